@@ -0,0 +1,206 @@
+use sony_wf1000xm5::command::{AncMode, Command, EqualizerPreset};
+
+pub const USAGE: &str = "usage: sonyctl [--device ADDRESS] [--json] [--read-only] COMMAND
+
+commands:
+  battery                   print the battery levels
+  codec                     print the Bluetooth codec in use
+  anc                       print the noise canceling mode
+  anc off|nc|ambient [--level 0-22] [--voice on|off]
+                            change it; what isn't given stays as it is
+  eq                        print the equalizer preset and bands
+  eq preset PRESET          change the preset, one of
+                            off, bright, excited, mellow, relaxed, vocal, treble-boost,
+                            bass-boost, speech, manual, custom1, custom2
+
+options:
+  --device ADDRESS          the headphones to use, by default the connected ones
+  --json                    print JSON, e.g. for waybar; see controller_cli::output
+  --read-only               refuse the commands which change something, e.g. to try a script";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Args {
+    /// The Bluetooth address of the headphones
+    pub device: Option<String>,
+    pub json: bool,
+    /// Refuse the commands which change something, see [sony_wf1000xm5::connection::ConnectionHandle::set_read_only]
+    pub read_only: bool,
+    pub action: Action,
+}
+
+/// What to do once connected
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    Battery,
+    Codec,
+    Anc,
+    SetAnc {
+        mode: AncMode,
+        level: Option<usize>,
+        voice_passthrough: Option<bool>,
+    },
+    Equalizer,
+    SetEqualizerPreset(EqualizerPreset),
+}
+
+/// The name of `preset` on the command line, e.g. `bass-boost`
+pub fn preset_name(preset: EqualizerPreset) -> String {
+    let mut name = String::new();
+    for c in preset.to_string().chars() {
+        if c.is_uppercase() && !name.is_empty() {
+            name.push('-');
+        }
+        name.push(c.to_ascii_lowercase());
+    }
+    name
+}
+
+/// The preset named `name` on the command line, see [preset_name]
+pub fn parse_preset(name: &str) -> Option<EqualizerPreset> {
+    EqualizerPreset::ALL
+        .into_iter()
+        .find(|preset| preset_name(*preset) == name)
+}
+
+/// The name of `mode` in JSON, e.g. `noise_canceling`
+pub fn anc_mode_name(mode: AncMode) -> &'static str {
+    match mode {
+        AncMode::Off => "off",
+        AncMode::ActiveNoiseCanceling => "noise_canceling",
+        AncMode::AmbientSound => "ambient_sound",
+    }
+}
+
+/// The mode named `name` in JSON, see [anc_mode_name]
+pub fn parse_anc_mode(name: &str) -> Option<AncMode> {
+    [
+        AncMode::Off,
+        AncMode::ActiveNoiseCanceling,
+        AncMode::AmbientSound,
+    ]
+    .into_iter()
+    .find(|mode| anc_mode_name(*mode) == name)
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut device = None;
+    let mut json = false;
+    let mut read_only = false;
+    let mut words = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--device" => device = Some(value()?),
+            "--json" => json = true,
+            "--read-only" => read_only = true,
+            // the options of the commands
+            "--level" | "--voice" => {
+                let value = value()?;
+                words.push(arg);
+                words.push(value);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {arg}")),
+            _ => words.push(arg),
+        }
+    }
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let action = match words[..] {
+        [] => return Err("no command given".to_string()),
+        ["battery"] => Action::Battery,
+        ["codec"] => Action::Codec,
+        ["anc"] => Action::Anc,
+        ["anc", mode, ref options @ ..] => parse_anc(mode, options)?,
+        ["eq"] => Action::Equalizer,
+        ["eq", "preset", preset] => Action::SetEqualizerPreset(
+            parse_preset(preset).ok_or(format!("unknown equalizer preset: {preset}"))?,
+        ),
+        _ => return Err(format!("unknown command: {}", words.join(" "))),
+    };
+    Ok(Args {
+        device,
+        json,
+        read_only,
+        action,
+    })
+}
+
+fn parse_anc(mode: &str, options: &[&str]) -> Result<Action, String> {
+    let mode = match mode {
+        "off" => AncMode::Off,
+        "nc" => AncMode::ActiveNoiseCanceling,
+        "ambient" => AncMode::AmbientSound,
+        _ => return Err(format!("unknown noise canceling mode: {mode}")),
+    };
+    let mut level = None;
+    let mut voice_passthrough = None;
+    for option in options.chunks(2) {
+        match option {
+            ["--level", value] => {
+                level = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|level| *level <= Command::MAX_AMBIENT_SOUND_LEVEL)
+                        .ok_or(format!("invalid ambient sound level: {value}"))?,
+                )
+            }
+            ["--voice", "on"] => voice_passthrough = Some(true),
+            ["--voice", "off"] => voice_passthrough = Some(false),
+            ["--voice", value] => return Err(format!("--voice is on or off, not {value}")),
+            _ => return Err(format!("unexpected: {}", option.join(" "))),
+        }
+    }
+    Ok(Action::SetAnc {
+        mode,
+        level,
+        voice_passthrough,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        parse_args(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            parse("--json battery").unwrap(),
+            Args {
+                device: None,
+                json: true,
+                read_only: false,
+                action: Action::Battery
+            }
+        );
+        assert_eq!(
+            parse("anc ambient --level 12 --device 00:11:22:33:44:55").unwrap(),
+            Args {
+                device: Some("00:11:22:33:44:55".to_string()),
+                json: false,
+                read_only: false,
+                action: Action::SetAnc {
+                    mode: AncMode::AmbientSound,
+                    level: Some(12),
+                    voice_passthrough: None,
+                }
+            }
+        );
+        assert_eq!(
+            parse("eq preset bass-boost").unwrap().action,
+            Action::SetEqualizerPreset(EqualizerPreset::BassBoost)
+        );
+        assert!(parse("--read-only eq preset bass-boost").unwrap().read_only);
+        assert_eq!(preset_name(EqualizerPreset::Custom1), "custom1");
+
+        assert!(parse("").is_err());
+        assert!(parse("anc ambient --level 23").is_err());
+        assert!(parse("anc loud").is_err());
+        assert!(parse("eq preset loud").is_err());
+        assert!(parse("--device").is_err());
+    }
+}
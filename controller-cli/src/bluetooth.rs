@@ -0,0 +1,63 @@
+//! Finding the headphones and opening their Sony service through BlueZ
+
+use bluer::{
+    Address, Device, Session, Uuid,
+    rfcomm::{Profile, ProfileHandle, Role, Stream},
+};
+use futures::StreamExt;
+use std::time::Duration;
+
+pub const SONY_SERVICE_UUID: Uuid = Uuid::from_u128(0x956C7B26_D49A_4BA8_B03F_B17D393CB6E2);
+
+/// Connect to the Sony service of the headphones at `address`, or of the connected ones offering it.
+///
+/// Keep the [ProfileHandle] for as long as the stream is used.
+pub async fn open(address: Option<&str>) -> Result<(Stream, ProfileHandle), String> {
+    let session = Session::new().await.map_err(|e| e.to_string())?;
+    let adapter = session.default_adapter().await.map_err(|e| e.to_string())?;
+    let device = match address {
+        Some(address) => {
+            let address: Address = address
+                .parse()
+                .map_err(|_| format!("invalid Bluetooth address: {address}"))?;
+            adapter.device(address).map_err(|e| e.to_string())?
+        }
+        None => find_connected(&adapter)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or("No connected headphones offer the Sony service; pass --device ADDRESS")?,
+    };
+    device.connect().await.map_err(|e| e.to_string())?;
+    let profile = Profile {
+        uuid: SONY_SERVICE_UUID,
+        role: Some(Role::Client),
+        auto_connect: Some(true),
+        ..Default::default()
+    };
+    let mut profile_handle = session
+        .register_profile(profile)
+        .await
+        .map_err(|e| e.to_string())?;
+    let request = tokio::time::timeout(Duration::from_secs(5), profile_handle.next())
+        .await
+        .ok()
+        .flatten()
+        .ok_or("Unable to connect to the Sony service. Another device (e.g. your phone) may be using it.")?;
+    let stream = request.accept().map_err(|e| e.to_string())?;
+    Ok((stream, profile_handle))
+}
+
+async fn find_connected(adapter: &bluer::Adapter) -> bluer::Result<Option<Device>> {
+    for address in adapter.device_addresses().await? {
+        let device = adapter.device(address)?;
+        if device.is_connected().await?
+            && device
+                .uuids()
+                .await?
+                .is_some_and(|uuids| uuids.contains(&SONY_SERVICE_UUID))
+        {
+            return Ok(Some(device));
+        }
+    }
+    Ok(None)
+}
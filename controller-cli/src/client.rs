@@ -0,0 +1,125 @@
+use futures::{FutureExt, future::Fuse};
+use sony_wf1000xm5::{
+    command::Command,
+    command_queue::CAPACITY,
+    connection::{
+        self, ConnectionError, ConnectionEvent, ConnectionHandle, EVENT_QUEUE_SIZE, RequestError,
+    },
+    payload::Payload,
+    session::Session,
+};
+use std::{pin::Pin, time::Duration};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::mpsc,
+};
+
+/// How long to wait for a reply, retransmissions included
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// [connection::run], boxed since its stream's type is the caller's
+type Connection = Pin<Box<dyn Future<Output = Result<(), ConnectionError>>>>;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+    #[error("{command:?}: {error}")]
+    Request {
+        command: Command,
+        error: RequestError,
+    },
+    #[error("The headphones answered {command:?} with {reply:?}")]
+    Unexpected {
+        command: Command,
+        reply: Option<Payload>,
+    },
+}
+
+/// One command at a time over the [connection], for scripts which do one thing and exit. The connection runs
+/// while the client waits for the headphones.
+pub struct Client {
+    handle: ConnectionHandle,
+    /// Pending once it ended, so the requests after that find the connection closed
+    connection: Fuse<Connection>,
+    /// Taken while waiting, so the connection doesn't wait for room for them
+    events: mpsc::Receiver<ConnectionEvent>,
+}
+
+impl Client {
+    /// Init the connection, which the headphones answer before anything else
+    pub async fn connect(
+        stream: impl AsyncRead + AsyncWrite + 'static,
+    ) -> Result<Self, ClientError> {
+        let (handle, commands) = ConnectionHandle::new(CAPACITY);
+        let (event_tx, events) = mpsc::channel(EVENT_QUEUE_SIZE);
+        // the session queues Init on creation
+        let connection = connection::run(stream, commands, event_tx, Session::new(), ());
+        let mut client = Self {
+            handle,
+            connection: (Box::pin(connection) as Connection).fuse(),
+            events,
+        };
+        loop {
+            tokio::select! {
+                event = client.events.recv() => match event {
+                    Some(ConnectionEvent::Payload(Payload::InitReply)) => return Ok(client),
+                    Some(_) => (),
+                    None => return Err(closed(Command::Init)),
+                },
+                result = &mut client.connection => {
+                    result?;
+                    return Err(closed(Command::Init));
+                }
+            }
+        }
+    }
+
+    /// Refuse the commands which change something, see [ConnectionHandle::set_read_only]
+    pub fn set_read_only(&self, read_only: bool) {
+        self.handle.set_read_only(read_only);
+    }
+
+    /// Send `command` and wait for its reply, see [Command::is_answered_by]
+    pub async fn request(&mut self, command: Command) -> Result<Payload, ClientError> {
+        match self.wait(command.clone()).await? {
+            Some(payload) => Ok(payload),
+            None => Err(ClientError::Unexpected {
+                command,
+                reply: None,
+            }),
+        }
+    }
+
+    /// Send `command` and wait for its Ack
+    pub async fn send(&mut self, command: Command) -> Result<(), ClientError> {
+        self.wait(command).await.map(drop)
+    }
+
+    /// Run the connection until it settled `command`, see [ConnectionHandle::request]
+    async fn wait(&mut self, command: Command) -> Result<Option<Payload>, ClientError> {
+        let reply = self.handle.request(command.clone(), REPLY_TIMEOUT);
+        tokio::pin!(reply);
+        loop {
+            tokio::select! {
+                reply = &mut reply => {
+                    return reply.map_err(|error| ClientError::Request { command, error });
+                }
+                result = &mut self.connection => {
+                    result?;
+                    return Err(closed(command));
+                }
+                Some(_) = self.events.recv() => (),
+            }
+        }
+    }
+}
+
+/// The connection ended without an error while `command` waited, which only stopping it does
+fn closed(command: Command) -> ClientError {
+    ClientError::Request {
+        command,
+        error: RequestError::Disconnected,
+    }
+}
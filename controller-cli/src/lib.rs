@@ -0,0 +1,179 @@
+//! `sonyctl`: the headphones from the command line, for scripts, status bars and key bindings
+
+pub mod args;
+#[cfg(target_os = "linux")]
+pub mod bluetooth;
+pub mod client;
+pub mod output;
+
+use args::Action;
+use client::{Client, ClientError};
+use sony_wf1000xm5::{
+    command::{AncMode, Command},
+    model::Model,
+    payload::{BatteryLevel, Payload},
+};
+
+/// Do `action`, returning the payloads to print
+pub async fn run(action: &Action, client: &mut Client) -> Result<Vec<Payload>, ClientError> {
+    Ok(match *action {
+        Action::Battery => {
+            // the batteries to ask for depend on the model; firmware which doesn't tell it (or not in time) still
+            // gets asked for the default model's
+            let model = match client.request(Command::GetModelName).await {
+                Ok(Payload::ModelName(name)) => Model::from_name(&name),
+                Ok(_) | Err(_) => None,
+            };
+            let mut batteries = Vec::new();
+            for battery_type in model.unwrap_or_default().battery_types() {
+                batteries.push(
+                    client
+                        .request(Command::GetBatteryStatus {
+                            battery_type: *battery_type,
+                        })
+                        .await?,
+                );
+            }
+            batteries
+        }
+        Action::Codec => vec![client.request(Command::GetCodec).await?],
+        Action::Anc => vec![client.request(Command::GetAncStatus).await?],
+        Action::SetAnc {
+            mode,
+            level,
+            voice_passthrough,
+        } => {
+            let (ambient_sound_voice_passthrough, ambient_sound_level) =
+                match client.request(Command::GetAncStatus).await? {
+                    Payload::AncStatus {
+                        ambient_sound_voice_passthrough,
+                        ambient_sound_level,
+                        ..
+                    } => (ambient_sound_voice_passthrough, ambient_sound_level),
+                    payload => {
+                        return Err(ClientError::Unexpected {
+                            command: Command::GetAncStatus,
+                            reply: Some(payload),
+                        });
+                    }
+                };
+            client
+                .send(Command::AncSet {
+                    dragging_ambient_sound_slider: false,
+                    mode,
+                    ambient_sound_voice_passthrough: voice_passthrough
+                        .unwrap_or(ambient_sound_voice_passthrough),
+                    ambient_sound_level: level.unwrap_or(ambient_sound_level as usize),
+                })
+                .await?;
+            Vec::new()
+        }
+        Action::Equalizer => vec![client.request(Command::GetEqualizerSettings).await?],
+        Action::SetEqualizerPreset(preset) => {
+            client
+                .send(Command::ChangeEqualizerPreset { preset })
+                .await?;
+            Vec::new()
+        }
+    })
+}
+
+/// The payloads on one line, e.g. `L 80% R 70% case 50%`
+pub fn describe(payloads: &[Payload]) -> String {
+    payloads
+        .iter()
+        .map(|payload| match payload {
+            Payload::BatteryLevel(BatteryLevel::Single(level)) => level.to_string(),
+            Payload::BatteryLevel(BatteryLevel::Headphones { left, right }) => {
+                format!("L {left} R {right}")
+            }
+            Payload::BatteryLevel(BatteryLevel::Case(level)) => format!("case {level}"),
+            Payload::Codec { codec } => format!("{codec:?}"),
+            Payload::AncStatus {
+                mode,
+                ambient_sound_voice_passthrough,
+                ambient_sound_level,
+            } => match mode {
+                AncMode::Off => "off".to_string(),
+                AncMode::ActiveNoiseCanceling => "noise canceling".to_string(),
+                AncMode::AmbientSound if *ambient_sound_voice_passthrough => {
+                    format!("ambient sound {ambient_sound_level} (voice)")
+                }
+                AncMode::AmbientSound => format!("ambient sound {ambient_sound_level}"),
+            },
+            Payload::Equalizer { preset, bands } => {
+                format!("{} {:?}", args::preset_name(*preset), bands.levels())
+            }
+            payload => format!("{payload:?}"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::{command::EqualizerPreset, connection::RequestError};
+
+    #[tokio::test]
+    async fn actions() {
+        let (stream, emulator) = emulator::duplex(Default::default());
+        let mut client = Client::connect(stream).await.unwrap();
+        assert_eq!(
+            describe(&run(&Action::Battery, &mut client).await.unwrap()),
+            "L 80% R 70% case 50%"
+        );
+        run(
+            &Action::SetAnc {
+                mode: AncMode::AmbientSound,
+                level: Some(12),
+                voice_passthrough: None,
+            },
+            &mut client,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            describe(&run(&Action::Anc, &mut client).await.unwrap()),
+            "ambient sound 12"
+        );
+        run(
+            &Action::SetEqualizerPreset(EqualizerPreset::BassBoost),
+            &mut client,
+        )
+        .await
+        .unwrap();
+        drop(client);
+        let state = emulator.await.unwrap().state;
+        assert_eq!(state.equalizer_preset, EqualizerPreset::BassBoost);
+        assert_eq!(state.anc_mode, AncMode::AmbientSound);
+    }
+
+    #[tokio::test]
+    async fn read_only() {
+        let (stream, emulator) = emulator::duplex(Default::default());
+        let mut client = Client::connect(stream).await.unwrap();
+        client.set_read_only(true);
+        assert!(matches!(
+            run(
+                &Action::SetEqualizerPreset(EqualizerPreset::BassBoost),
+                &mut client,
+            )
+            .await,
+            Err(ClientError::Request {
+                error: RequestError::ReadOnly(_),
+                ..
+            })
+        ));
+        // reading still works
+        assert_eq!(
+            describe(&run(&Action::Codec, &mut client).await.unwrap()),
+            "Ldac"
+        );
+        drop(client);
+        assert_eq!(
+            emulator.await.unwrap().state.equalizer_preset,
+            EqualizerPreset::Off
+        );
+    }
+}
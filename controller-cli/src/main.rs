@@ -0,0 +1,63 @@
+//! usage: see [controller_cli::args::USAGE], or run `sonyctl --help`
+
+#![cfg_attr(not(target_os = "linux"), allow(unused))]
+
+#[cfg(target_os = "linux")]
+use controller_cli::bluetooth;
+use controller_cli::{
+    args::{USAGE, parse_args},
+    client::Client,
+    describe,
+    output::Output,
+    run,
+};
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("sonyctl connects through BlueZ, so it only runs on Linux");
+    std::process::exit(1);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{USAGE}");
+        return;
+    }
+    let args = match parse_args(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    let (stream, _profile_handle) = match bluetooth::open(args.device.as_deref()).await {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+    let result = async {
+        let mut client = Client::connect(stream).await?;
+        client.set_read_only(args.read_only);
+        run(&args.action, &mut client).await
+    }
+    .await;
+    match result {
+        Ok(payloads) if args.json && !payloads.is_empty() => {
+            println!(
+                "{}",
+                serde_json::to_string(&Output::new(&payloads)).unwrap()
+            );
+        }
+        Ok(payloads) if payloads.is_empty() => (),
+        Ok(payloads) => println!("{}", describe(&payloads)),
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! What `--json` prints: one object per command, for status bars (waybar, polybar, i3blocks) to consume.
+//!
+//! The schema is stable: fields are only ever added, never renamed or removed, and ones the command didn't read are left out.
+//! `text` is what sonyctl prints without `--json`, which is also what waybar shows of a custom module.
+//!
+//! ```json
+//! {"text":"L 80% R 70% case 50%","battery":{"left":80,"right":70,"case":50}}
+//! {"text":"ambient sound 12","anc":{"mode":"ambient_sound","ambient_sound_level":12,"voice_passthrough":false}}
+//! {"text":"bass-boost [0, 0, 0, 0, 0, 0]","equalizer":{"preset":"bass-boost","bands":{"clear_bass":0,...}}}
+//! {"text":"Ldac","codec":"ldac"}
+//! ```
+
+use serde::Serialize;
+use sony_wf1000xm5::payload::{BatteryLevel, Payload};
+
+use crate::{
+    args::{anc_mode_name, preset_name},
+    describe,
+};
+
+#[derive(Debug, Default, Serialize)]
+pub struct Output {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery: Option<Battery>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anc: Option<Anc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub equalizer: Option<Equalizer>,
+    /// e.g. `ldac`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+}
+
+/// In percent. Earbuds have `left`, `right` and `case`, over-ear headphones `level`.
+#[derive(Debug, Default, Serialize)]
+pub struct Battery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub case: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<u8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Anc {
+    /// `off`, `noise_canceling` or `ambient_sound`
+    pub mode: &'static str,
+    pub ambient_sound_level: u8,
+    pub voice_passthrough: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Equalizer {
+    /// As given to `sonyctl eq preset`, e.g. `bass-boost`
+    pub preset: String,
+    pub bands: Bands,
+}
+
+/// From -10 to 10
+#[derive(Debug, Serialize)]
+pub struct Bands {
+    pub clear_bass: i8,
+    pub band_400: i8,
+    pub band_1000: i8,
+    pub band_2500: i8,
+    pub band_6300: i8,
+    pub band_16000: i8,
+}
+
+impl Output {
+    pub fn new(payloads: &[Payload]) -> Self {
+        let mut output = Self {
+            text: describe(payloads),
+            ..Default::default()
+        };
+        for payload in payloads {
+            match payload {
+                Payload::BatteryLevel(level) => {
+                    let battery = output.battery.get_or_insert_default();
+                    match level {
+                        BatteryLevel::Single(level) => battery.level = Some(level.get()),
+                        BatteryLevel::Headphones { left, right } => {
+                            battery.left = Some(left.get());
+                            battery.right = Some(right.get());
+                        }
+                        BatteryLevel::Case(level) => battery.case = Some(level.get()),
+                    }
+                }
+                Payload::AncStatus {
+                    mode,
+                    ambient_sound_voice_passthrough,
+                    ambient_sound_level,
+                } => {
+                    output.anc = Some(Anc {
+                        mode: anc_mode_name(*mode),
+                        ambient_sound_level: *ambient_sound_level,
+                        voice_passthrough: *ambient_sound_voice_passthrough,
+                    })
+                }
+                Payload::Equalizer { preset, bands } => {
+                    let [
+                        clear_bass,
+                        band_400,
+                        band_1000,
+                        band_2500,
+                        band_6300,
+                        band_16000,
+                    ] = bands.levels();
+                    output.equalizer = Some(Equalizer {
+                        preset: preset_name(*preset),
+                        bands: Bands {
+                            clear_bass,
+                            band_400,
+                            band_1000,
+                            band_2500,
+                            band_6300,
+                            band_16000,
+                        },
+                    })
+                }
+                Payload::Codec { codec } => {
+                    output.codec = Some(format!("{codec:?}").to_lowercase())
+                }
+                _ => (),
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::{
+        command::{AncMode, EqualizerBands, EqualizerPreset},
+        payload::{BatteryPercent, Codec},
+    };
+
+    fn json(payloads: &[Payload]) -> String {
+        serde_json::to_string(&Output::new(payloads)).unwrap()
+    }
+
+    #[test]
+    fn schema() {
+        assert_eq!(
+            json(&[
+                Payload::BatteryLevel(BatteryLevel::Headphones {
+                    left: BatteryPercent::new(80).unwrap(),
+                    right: BatteryPercent::new(70).unwrap(),
+                }),
+                Payload::BatteryLevel(BatteryLevel::Case(BatteryPercent::new(50).unwrap())),
+            ]),
+            r#"{"text":"L 80% R 70% case 50%","battery":{"left":80,"right":70,"case":50}}"#
+        );
+        assert_eq!(
+            json(&[Payload::AncStatus {
+                mode: AncMode::AmbientSound,
+                ambient_sound_voice_passthrough: false,
+                ambient_sound_level: 12,
+            }]),
+            r#"{"text":"ambient sound 12","anc":{"mode":"ambient_sound","ambient_sound_level":12,"voice_passthrough":false}}"#
+        );
+        assert_eq!(
+            json(&[Payload::Equalizer {
+                preset: EqualizerPreset::BassBoost,
+                bands: EqualizerBands::new(3, 0, 0, 0, 0, -2).unwrap(),
+            }]),
+            r#"{"text":"bass-boost [3, 0, 0, 0, 0, -2]","equalizer":{"preset":"bass-boost","bands":{"clear_bass":3,"band_400":0,"band_1000":0,"band_2500":0,"band_6300":0,"band_16000":-2}}}"#
+        );
+        assert_eq!(
+            json(&[Payload::Codec { codec: Codec::Ldac }]),
+            r#"{"text":"Ldac","codec":"ldac"}"#
+        );
+    }
+}
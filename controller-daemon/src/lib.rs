@@ -0,0 +1,8 @@
+//! Keeps the connection to the headphones and shares it over D-Bus, so several programs can use them at once
+//! (the Sony service only takes one connection), and over a Unix socket for those without D-Bus.
+
+pub mod media;
+pub mod mqtt;
+pub mod service;
+#[cfg(unix)]
+pub mod socket;
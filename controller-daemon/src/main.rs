@@ -0,0 +1,252 @@
+//! usage: controller-daemon [--device ADDRESS] [--when-playing MODE] [--when-paused MODE] [--socket PATH] [--no-bus]
+//!                          [--mqtt HOST[:PORT]]
+//!
+//! Connects to the headphones (by default the connected ones) and serves [controller_daemon::service::BUS_NAME]
+//! on the session bus until the connection drops.
+//!
+//! `--when-playing` and `--when-paused` switch the noise canceling mode (`off`, `noise_canceling` or `ambient_sound`)
+//! when media starts and stops playing, see [controller_daemon::media].
+//!
+//! `--socket` also serves the methods as JSON-RPC on a Unix socket, see [controller_daemon::socket], and with
+//! `--no-bus` only there, e.g. when there's no session bus without a desktop.
+//!
+//! `--mqtt` publishes the headphones to Home Assistant through the MQTT broker, see [controller_daemon::mqtt].
+//! The credentials, if it needs any, are taken from `SONYXM5_MQTT_USERNAME` and `SONYXM5_MQTT_PASSWORD`.
+
+#![cfg_attr(not(target_os = "linux"), allow(unused))]
+
+use controller_cli::args::parse_anc_mode;
+use controller_daemon::{
+    media::{self, MediaAnc},
+    mqtt,
+    service::{BUS_NAME, Controller, OBJECT_PATH, publish},
+};
+use sony_wf1000xm5::{
+    command_queue::CAPACITY,
+    connection::{self, ConnectionEvent, ConnectionHandle, EVENT_QUEUE_SIZE},
+    payload::Payload,
+    session::Session,
+};
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+use zbus::object_server::SignalEmitter;
+
+const USAGE: &str = "usage: controller-daemon [--device ADDRESS] [--when-playing MODE] [--when-paused MODE] [--socket PATH] [--no-bus] [--mqtt HOST[:PORT]]";
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    device: Option<String>,
+    /// What to do on playback
+    media_anc: MediaAnc,
+    socket: Option<PathBuf>,
+    /// The broker, as `host:port`
+    mqtt: Option<String>,
+    /// Whether to serve on the session bus
+    bus: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            device: None,
+            media_anc: MediaAnc::default(),
+            socket: None,
+            mqtt: None,
+            bus: true,
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        if arg == "--no-bus" {
+            options.bus = false;
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("{arg} needs a value"))?;
+        let mode = || parse_anc_mode(&value).ok_or_else(|| format!("unknown mode: {value}"));
+        match arg.as_str() {
+            "--device" => options.device = Some(value),
+            "--when-playing" => options.media_anc.playing = Some(mode()?),
+            "--when-paused" => options.media_anc.paused = Some(mode()?),
+            "--socket" => options.socket = Some(PathBuf::from(value)),
+            "--mqtt" if value.contains(':') => options.mqtt = Some(value),
+            "--mqtt" => options.mqtt = Some(format!("{value}:{}", mqtt::DEFAULT_PORT)),
+            _ => return Err(format!("unknown option: {arg}")),
+        }
+    }
+    if !options.bus && options.socket.is_none() && options.mqtt.is_none() {
+        return Err("--no-bus needs --socket or --mqtt, or there's nothing to serve".to_string());
+    }
+    // the players are found on the session bus
+    if !options.bus && options.media_anc.is_enabled() {
+        return Err("--when-playing and --when-paused need the session bus".to_string());
+    }
+    Ok(options)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("controller-daemon connects through BlueZ, so it only runs on Linux");
+    std::process::exit(1);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    env_logger::init();
+    let options = parse_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{e}\n{USAGE}");
+        std::process::exit(2);
+    });
+    if let Err(e) = serve(options).await {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(target_os = "linux")]
+async fn serve(options: Options) -> Result<(), Box<dyn std::error::Error>> {
+    use controller_daemon::socket;
+    use std::future::pending;
+
+    let (stream, _profile_handle) =
+        controller_cli::bluetooth::open(options.device.as_deref()).await?;
+    let (handle, commands) = ConnectionHandle::new(CAPACITY);
+    let (event_tx, mut events) = mpsc::channel::<ConnectionEvent>(EVENT_QUEUE_SIZE);
+    let (bus_payloads_tx, bus_payloads_rx) = mpsc::channel(EVENT_QUEUE_SIZE);
+    let (mqtt_payloads_tx, mqtt_payloads_rx) = mpsc::channel(EVENT_QUEUE_SIZE);
+    let bus = if options.bus {
+        let bus = zbus::connection::Builder::session()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, Controller::new(handle.clone()))?
+            .build()
+            .await?;
+        log::info!("serving {BUS_NAME}");
+        Some(bus)
+    } else {
+        None
+    };
+    let listener = options.socket.as_deref().map(socket::bind).transpose()?;
+    if let Some(path) = &options.socket {
+        log::info!("serving {}", path.display());
+    }
+
+    let mqtt_options = options.mqtt.map(|address| mqtt::MqttOptions {
+        address,
+        username: std::env::var("SONYXM5_MQTT_USERNAME").ok(),
+        password: std::env::var("SONYXM5_MQTT_PASSWORD").ok(),
+    });
+
+    // every payload goes to the D-Bus signals and MQTT, whichever are enabled; the connection ends first.
+    // They wait for replies from the connection while publishing, so it doesn't wait for them: what one of them
+    // is too far behind to take is dropped.
+    let forward = |payloads: &mpsc::Sender<Payload>, payload: Payload| {
+        if let Err(mpsc::error::TrySendError::Full(payload)) = payloads.try_send(payload) {
+            log::warn!("too many changes to publish; dropped {payload:?}");
+        }
+    };
+    let fan_out = async {
+        while let Some(event) = events.recv().await {
+            let ConnectionEvent::Payload(payload) = event else {
+                continue;
+            };
+            if bus.is_some() {
+                forward(&bus_payloads_tx, payload.clone());
+            }
+            if mqtt_options.is_some() {
+                forward(&mqtt_payloads_tx, payload);
+            }
+        }
+        pending().await
+    };
+    // the ones not enabled never finish
+    let signals = async {
+        match &bus {
+            Some(bus) => publish(SignalEmitter::new(bus, OBJECT_PATH)?, bus_payloads_rx).await,
+            None => pending().await,
+        }
+    };
+    let media = async {
+        match &bus {
+            Some(bus) if options.media_anc.is_enabled() => {
+                media::watch(bus, options.media_anc, handle.clone()).await
+            }
+            _ => pending().await,
+        }
+    };
+    let sockets = async {
+        match listener {
+            Some(listener) => socket::serve(listener, Controller::new(handle.clone())).await,
+            None => pending().await,
+        }
+    };
+    let home_assistant = async {
+        match &mqtt_options {
+            Some(mqtt_options) => {
+                let controller = Controller::new(handle.clone());
+                mqtt::run(mqtt_options, controller, mqtt_payloads_rx).await
+            }
+            None => pending().await,
+        }
+    };
+    let result: Result<(), Box<dyn std::error::Error>> = tokio::select! {
+        result = fan_out => result,
+        result = connection::run(stream, commands, event_tx, Session::new(), ()) => result.map_err(Into::into),
+        result = signals => result.map_err(Into::into),
+        result = media => result.map_err(Into::into),
+        result = sockets => result.map_err(Into::into),
+        result = home_assistant => result.map_err(Into::into),
+    };
+    if let Some(path) = &options.socket {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::command::AncMode;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn args() {
+        assert_eq!(parse(&[]), Ok(Options::default()));
+        assert_eq!(
+            parse(&["--when-playing", "noise_canceling", "--device", "AA:BB"]),
+            Ok(Options {
+                device: Some("AA:BB".to_string()),
+                media_anc: MediaAnc {
+                    playing: Some(AncMode::ActiveNoiseCanceling),
+                    paused: None,
+                },
+                ..Options::default()
+            })
+        );
+        assert!(parse(&["--when-paused", "loud"]).is_err());
+        assert!(parse(&["--when-paused"]).is_err());
+        assert_eq!(
+            parse(&["--no-bus", "--socket", "/run/user/1000/sonyxm5.sock"]),
+            Ok(Options {
+                socket: Some(PathBuf::from("/run/user/1000/sonyxm5.sock")),
+                bus: false,
+                ..Options::default()
+            })
+        );
+        assert!(parse(&["--no-bus"]).is_err());
+        assert_eq!(
+            parse(&["--no-bus", "--mqtt", "homeassistant.local"]),
+            Ok(Options {
+                mqtt: Some("homeassistant.local:1883".to_string()),
+                bus: false,
+                ..Options::default()
+            })
+        );
+        assert!(parse(&["--no-bus", "--socket", "x", "--when-paused", "off"]).is_err());
+    }
+}
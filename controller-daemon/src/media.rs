@@ -0,0 +1,172 @@
+//! Switch the noise canceling mode when media starts or stops playing, going by the MPRIS players on the session bus
+//! (the ones playerctl controls).
+
+use crate::service::REPLY_TIMEOUT;
+use futures::StreamExt;
+use sony_wf1000xm5::{
+    command::{AncMode, Command},
+    connection::ConnectionHandle,
+    payload::Payload,
+};
+use std::collections::{HashMap, HashSet};
+use zbus::{MatchRule, MessageStream, message::Type, zvariant::OwnedValue};
+
+const PLAYER_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Which mode to switch to; `None` leaves it as it is
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MediaAnc {
+    pub playing: Option<AncMode>,
+    /// Paused or stopped
+    pub paused: Option<AncMode>,
+}
+
+impl MediaAnc {
+    pub fn is_enabled(&self) -> bool {
+        self.playing.is_some() || self.paused.is_some()
+    }
+}
+
+/// Whether anything plays, over every player
+#[derive(Debug, Default)]
+pub struct Players {
+    /// By unique bus name
+    playing: HashSet<String>,
+}
+
+impl Players {
+    /// `player` reported `status` (`Playing`, `Paused` or `Stopped`); whether anything plays now, if that changed
+    pub fn update(&mut self, player: &str, status: &str) -> Option<bool> {
+        let was_playing = self.is_playing();
+        if status == "Playing" {
+            self.playing.insert(player.to_string());
+        } else {
+            self.playing.remove(player);
+        }
+        (self.is_playing() != was_playing).then_some(self.is_playing())
+    }
+
+    /// `player` left the bus; like [Players::update] otherwise
+    pub fn remove(&mut self, player: &str) -> Option<bool> {
+        self.update(player, "Stopped")
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.playing.is_empty()
+    }
+}
+
+/// Switch as `media_anc` says whenever playback starts or stops.
+///
+/// Players which were already playing count once they report a change.
+pub async fn watch(
+    bus: &zbus::Connection,
+    media_anc: MediaAnc,
+    connection: ConnectionHandle,
+) -> zbus::Result<()> {
+    let properties = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .interface("org.freedesktop.DBus.Properties")?
+        .member("PropertiesChanged")?
+        .path(PLAYER_PATH)?
+        .arg(0, PLAYER_INTERFACE)?
+        .build();
+    let owners = MatchRule::builder()
+        .msg_type(Type::Signal)
+        .sender("org.freedesktop.DBus")?
+        .interface("org.freedesktop.DBus")?
+        .member("NameOwnerChanged")?
+        .build();
+    let mut messages = futures::stream::select(
+        MessageStream::for_match_rule(properties, bus, None).await?,
+        MessageStream::for_match_rule(owners, bus, None).await?,
+    );
+    let mut players = Players::default();
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        let header = message.header();
+        let playing = if header.member().is_some_and(|m| m == "NameOwnerChanged") {
+            let (_, old_owner, new_owner): (String, String, String) =
+                message.body().deserialize()?;
+            if old_owner.is_empty() || !new_owner.is_empty() {
+                continue;
+            }
+            players.remove(&old_owner)
+        } else {
+            let Some(player) = header.sender() else {
+                continue;
+            };
+            let (_, changed, _): (String, HashMap<String, OwnedValue>, Vec<String>) =
+                message.body().deserialize()?;
+            let Some(status) = changed
+                .get("PlaybackStatus")
+                .and_then(|status| status.downcast_ref::<String>().ok())
+            else {
+                continue;
+            };
+            players.update(player.as_str(), &status)
+        };
+        let mode = match playing {
+            Some(true) => media_anc.playing,
+            Some(false) => media_anc.paused,
+            None => None,
+        };
+        if let Some(mode) = mode {
+            log::info!(
+                "media {}, switching to {mode:?}",
+                if players.is_playing() {
+                    "playing"
+                } else {
+                    "paused"
+                }
+            );
+            set_anc_mode(&connection, mode).await;
+        }
+    }
+    Ok(())
+}
+
+/// Change the mode, keeping the ambient sound settings
+async fn set_anc_mode(connection: &ConnectionHandle, mode: AncMode) {
+    let (ambient_sound_voice_passthrough, ambient_sound_level) = match connection
+        .request(Command::GetAncStatus, REPLY_TIMEOUT)
+        .await
+    {
+        Ok(Some(Payload::AncStatus {
+            ambient_sound_voice_passthrough,
+            ambient_sound_level,
+            ..
+        })) => (ambient_sound_voice_passthrough, ambient_sound_level),
+        reply => {
+            log::warn!("couldn't read the noise canceling mode: {reply:?}");
+            return;
+        }
+    };
+    let command = Command::AncSet {
+        dragging_ambient_sound_slider: false,
+        mode,
+        ambient_sound_voice_passthrough,
+        ambient_sound_level: ambient_sound_level as usize,
+    };
+    if let Err(e) = connection.request(command, REPLY_TIMEOUT).await {
+        log::warn!("couldn't switch to {mode:?}: {e}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn players() {
+        let mut players = Players::default();
+        assert_eq!(players.update(":1.10", "Paused"), None);
+        assert_eq!(players.update(":1.10", "Playing"), Some(true));
+        // another one starting doesn't change anything
+        assert_eq!(players.update(":1.20", "Playing"), None);
+        assert_eq!(players.update(":1.10", "Stopped"), None);
+        assert_eq!(players.remove(":1.20"), Some(false));
+        assert!(!players.is_playing());
+    }
+}
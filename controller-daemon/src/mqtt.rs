@@ -0,0 +1,533 @@
+//! Publishes the headphones to an MQTT broker so Home Assistant discovers them: the batteries and the codec as
+//! sensors, and the noise canceling mode and the equalizer preset as selects, which change them too.
+//!
+//! Only the part of MQTT 3.1.1 the bridge needs is implemented: QoS 0, retained messages and a last will,
+//! which marks the headphones unavailable when the daemon goes away.
+
+use crate::service::Controller;
+use controller_cli::args::{anc_mode_name, preset_name};
+use serde_json::{Value, json};
+use sony_wf1000xm5::{
+    command::{AncMode, EqualizerPreset},
+    payload::{BatteryLevel, Payload},
+};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    time::{Instant, interval_at},
+};
+
+pub const DEFAULT_PORT: u16 = 1883;
+const DISCOVERY_PREFIX: &str = "homeassistant";
+/// Of the state and command topics, and the id of the device in Home Assistant
+const NODE_ID: &str = "sonyxm5";
+const CLIENT_ID: &str = "sonyxm5-controller-daemon";
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MqttOptions {
+    /// `host:port`
+    pub address: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum MqttError {
+    #[error("Lost the connection to the MQTT broker: {0}")]
+    Transport(#[from] std::io::Error),
+    #[error("The MQTT broker refused the connection with code {0}")]
+    Refused(u8),
+    #[error("The MQTT broker sent a malformed packet")]
+    Malformed,
+    #[error("The MQTT broker closed the connection")]
+    Closed,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Packet {
+    ConnAck {
+        return_code: u8,
+    },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+    },
+    SubAck,
+    PingResp,
+    /// Something the bridge doesn't use
+    Other(u8),
+}
+
+fn push_length(out: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn push_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+/// The fixed header (the packet type, its `flags` and the length) followed by `body`
+fn packet(packet_type: u8, flags: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![packet_type << 4 | flags];
+    push_length(&mut packet, body.len());
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Connect with a clean session, leaving `will` to be published (retained) if the connection is lost
+fn connect_packet(options: &MqttOptions, will: (&str, &str)) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_bytes(&mut body, b"MQTT");
+    // protocol level 4 is MQTT 3.1.1
+    body.push(4);
+    // clean session, will, will retain
+    let mut flags = 0x02 | 0x04 | 0x20;
+    if options.username.is_some() {
+        flags |= 0x80;
+    }
+    if options.password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+    push_bytes(&mut body, CLIENT_ID.as_bytes());
+    push_bytes(&mut body, will.0.as_bytes());
+    push_bytes(&mut body, will.1.as_bytes());
+    for credential in [&options.username, &options.password].into_iter().flatten() {
+        push_bytes(&mut body, credential.as_bytes());
+    }
+    packet(CONNECT, 0, &body)
+}
+
+fn publish_packet(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_bytes(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(PUBLISH, retain as u8, &body)
+}
+
+fn subscribe_packet(packet_id: u16, topic: &str) -> Vec<u8> {
+    let mut body = packet_id.to_be_bytes().to_vec();
+    push_bytes(&mut body, topic.as_bytes());
+    // QoS 0
+    body.push(0);
+    packet(SUBSCRIBE, 0b0010, &body)
+}
+
+/// The first packet in `bytes` and its length, or `None` if it isn't all there yet
+fn parse_packet(bytes: &[u8]) -> Result<Option<(Packet, usize)>, MqttError> {
+    let Some(&first) = bytes.first() else {
+        return Ok(None);
+    };
+    let mut length = 0;
+    let mut header_length = 1;
+    loop {
+        // the length takes at most 4 bytes
+        if header_length > 4 {
+            return Err(MqttError::Malformed);
+        }
+        let Some(&byte) = bytes.get(header_length) else {
+            return Ok(None);
+        };
+        length |= ((byte & 0x7f) as usize) << (7 * (header_length - 1));
+        header_length += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let Some(body) = bytes.get(header_length..header_length + length) else {
+        return Ok(None);
+    };
+    let packet = match first >> 4 {
+        CONNACK => Packet::ConnAck {
+            return_code: *body.get(1).ok_or(MqttError::Malformed)?,
+        },
+        PUBLISH => {
+            let topic_length = u16::from_be_bytes(
+                body.get(..2)
+                    .ok_or(MqttError::Malformed)?
+                    .try_into()
+                    .unwrap(),
+            ) as usize;
+            let topic = body.get(2..2 + topic_length).ok_or(MqttError::Malformed)?;
+            let topic = String::from_utf8(topic.to_vec()).map_err(|_| MqttError::Malformed)?;
+            // QoS 1 and 2 have a packet identifier
+            let qos = (first >> 1) & 0b11;
+            let payload_start = 2 + topic_length + if qos > 0 { 2 } else { 0 };
+            Packet::Publish {
+                topic,
+                payload: body
+                    .get(payload_start..)
+                    .ok_or(MqttError::Malformed)?
+                    .to_vec(),
+            }
+        }
+        SUBACK => Packet::SubAck,
+        PINGRESP => Packet::PingResp,
+        packet_type => Packet::Other(packet_type),
+    };
+    Ok(Some((packet, header_length + length)))
+}
+
+fn state_topic(entity: &str) -> String {
+    format!("{NODE_ID}/{entity}")
+}
+
+fn command_topic(entity: &str) -> String {
+    format!("{NODE_ID}/{entity}/set")
+}
+
+fn availability_topic() -> String {
+    format!("{NODE_ID}/availability")
+}
+
+/// The states of the entities `payload` tells about
+fn states(payload: &Payload) -> Vec<(&'static str, String)> {
+    match payload {
+        Payload::BatteryLevel(BatteryLevel::Headphones { left, right }) => vec![
+            ("battery_left", left.get().to_string()),
+            ("battery_right", right.get().to_string()),
+        ],
+        Payload::BatteryLevel(BatteryLevel::Case(level)) => {
+            vec![("battery_case", level.get().to_string())]
+        }
+        Payload::AncStatus { mode, .. } => vec![("anc", anc_mode_name(*mode).to_string())],
+        Payload::Equalizer { preset, .. } => vec![("equalizer", preset_name(*preset))],
+        Payload::Codec { codec } => vec![("codec", format!("{codec:?}").to_lowercase())],
+        _ => vec![],
+    }
+}
+
+/// The discovery topics and configs of the entities of `model_name`
+fn discovery(model_name: &str) -> Vec<(String, Value)> {
+    let device = json!({
+        "identifiers": [NODE_ID],
+        "name": model_name,
+        "manufacturer": "Sony",
+        "model": model_name,
+    });
+    let availability = availability_topic();
+    let entity = |component: &str, entity: &str, name: &str, extra: Value| {
+        let mut config = json!({
+            "name": name,
+            "unique_id": format!("{NODE_ID}_{entity}"),
+            "state_topic": state_topic(entity),
+            "availability_topic": availability,
+            "device": device,
+        });
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        (
+            format!("{DISCOVERY_PREFIX}/{component}/{NODE_ID}/{entity}/config"),
+            config,
+        )
+    };
+    let battery = json!({"device_class": "battery", "unit_of_measurement": "%"});
+    let batteries = [
+        ("battery_left", "Left battery"),
+        ("battery_right", "Right battery"),
+        ("battery_case", "Case battery"),
+    ];
+    let mut configs: Vec<_> = batteries
+        .iter()
+        .map(|(id, name)| entity("sensor", id, name, battery.clone()))
+        .collect();
+    configs.push(entity("sensor", "codec", "Codec", json!({})));
+    // TODO: a "worn" binary_sensor, once the library decodes whether the earbuds are worn
+    let modes = [
+        AncMode::Off,
+        AncMode::ActiveNoiseCanceling,
+        AncMode::AmbientSound,
+    ];
+    configs.push(entity(
+        "select",
+        "anc",
+        "Noise canceling",
+        json!({
+            "command_topic": command_topic("anc"),
+            "options": modes.map(anc_mode_name),
+        }),
+    ));
+    configs.push(entity(
+        "select",
+        "equalizer",
+        "Equalizer preset",
+        json!({
+            "command_topic": command_topic("equalizer"),
+            "options": EqualizerPreset::ALL.map(preset_name),
+        }),
+    ));
+    configs
+}
+
+struct Bridge {
+    stream: TcpStream,
+    controller: Controller,
+    /// The ambient sound level and voice passthrough, which a new mode keeps
+    ambient_sound: (u8, bool),
+}
+
+impl Bridge {
+    async fn publish(&mut self, topic: &str, payload: &str) -> Result<(), MqttError> {
+        self.stream
+            .write_all(&publish_packet(topic, payload.as_bytes(), true))
+            .await?;
+        Ok(())
+    }
+
+    async fn publish_states(&mut self, payload: &Payload) -> Result<(), MqttError> {
+        if let Payload::AncStatus {
+            ambient_sound_voice_passthrough,
+            ambient_sound_level,
+            ..
+        } = payload
+        {
+            self.ambient_sound = (*ambient_sound_level, *ambient_sound_voice_passthrough);
+        }
+        for (entity, state) in states(payload) {
+            self.publish(&state_topic(entity), &state).await?;
+        }
+        Ok(())
+    }
+
+    /// Publish what the headphones are like now; the changes come as payloads afterwards
+    async fn publish_current(&mut self) -> Result<(), MqttError> {
+        match self.controller.battery().await {
+            Ok(levels) => {
+                for (battery, level) in levels {
+                    let entity = match battery.as_str() {
+                        "level" => "battery".to_string(),
+                        battery => format!("battery_{battery}"),
+                    };
+                    self.publish(&state_topic(&entity), &level.to_string())
+                        .await?;
+                }
+            }
+            Err(e) => log::warn!("couldn't get the batteries: {e}"),
+        }
+        match self.controller.anc().await {
+            Ok((mode, level, voice_passthrough)) => {
+                self.ambient_sound = (level, voice_passthrough);
+                self.publish(&state_topic("anc"), &mode).await?;
+            }
+            Err(e) => log::warn!("couldn't get the noise canceling mode: {e}"),
+        }
+        match self.controller.equalizer().await {
+            Ok((preset, _)) => self.publish(&state_topic("equalizer"), &preset).await?,
+            Err(e) => log::warn!("couldn't get the equalizer: {e}"),
+        }
+        match self.controller.codec().await {
+            Ok(codec) => self.publish(&state_topic("codec"), &codec).await?,
+            Err(e) => log::warn!("couldn't get the codec: {e}"),
+        }
+        Ok(())
+    }
+
+    async fn handle_command(&self, topic: &str, payload: &[u8]) {
+        let value = String::from_utf8_lossy(payload);
+        let result = if topic == command_topic("anc") {
+            let (level, voice_passthrough) = self.ambient_sound;
+            self.controller
+                .set_anc(&value, level, voice_passthrough)
+                .await
+        } else if topic == command_topic("equalizer") {
+            self.controller.set_equalizer_preset(&value).await
+        } else {
+            return;
+        };
+        if let Err(e) = result {
+            log::warn!("couldn't set {topic} to {value}: {e}");
+        }
+    }
+}
+
+/// Publish the headphones until the connection to the broker drops, or the one to the headphones does
+/// (`payloads` ends).
+pub async fn run(
+    options: &MqttOptions,
+    controller: Controller,
+    mut payloads: mpsc::Receiver<Payload>,
+) -> Result<(), MqttError> {
+    let mut stream = TcpStream::connect(&options.address).await?;
+    let availability = availability_topic();
+    stream
+        .write_all(&connect_packet(options, (&availability, "offline")))
+        .await?;
+    let mut received = Vec::new();
+    let mut buffer = [0; 1024];
+    let connack = loop {
+        if let Some((packet, length)) = parse_packet(&received)? {
+            received.drain(..length);
+            break packet;
+        }
+        match stream.read(&mut buffer).await? {
+            0 => return Err(MqttError::Closed),
+            read => received.extend_from_slice(&buffer[..read]),
+        }
+    };
+    match connack {
+        Packet::ConnAck { return_code: 0 } => (),
+        Packet::ConnAck { return_code } => return Err(MqttError::Refused(return_code)),
+        _ => return Err(MqttError::Malformed),
+    }
+    log::info!("connected to the MQTT broker at {}", options.address);
+
+    let model_name = controller.model_name().await.unwrap_or_else(|e| {
+        log::warn!("couldn't get the model name: {e}");
+        "Sony headphones".to_string()
+    });
+    let mut bridge = Bridge {
+        stream,
+        controller,
+        ambient_sound: (0, false),
+    };
+    for (topic, config) in discovery(&model_name) {
+        bridge.publish(&topic, &config.to_string()).await?;
+    }
+    bridge.publish(&availability, "online").await?;
+    for (packet_id, entity) in [(1, "anc"), (2, "equalizer")] {
+        bridge
+            .stream
+            .write_all(&subscribe_packet(packet_id, &command_topic(entity)))
+            .await?;
+    }
+    bridge.publish_current().await?;
+
+    let mut ping = interval_at(Instant::now() + KEEP_ALIVE / 2, KEEP_ALIVE / 2);
+    loop {
+        while let Some((packet, length)) = parse_packet(&received)? {
+            received.drain(..length);
+            match packet {
+                Packet::Publish { topic, payload } => bridge.handle_command(&topic, &payload).await,
+                Packet::ConnAck { .. } => return Err(MqttError::Malformed),
+                Packet::SubAck | Packet::PingResp | Packet::Other(_) => (),
+            }
+        }
+        tokio::select! {
+            read = bridge.stream.read(&mut buffer) => match read? {
+                0 => return Err(MqttError::Closed),
+                read => received.extend_from_slice(&buffer[..read]),
+            },
+            payload = payloads.recv() => {
+                let Some(payload) = payload else {
+                    bridge.publish(&availability, "offline").await?;
+                    return Ok(());
+                };
+                bridge.publish_states(&payload).await?;
+            }
+            _ = ping.tick() => bridge.stream.write_all(&packet(PINGREQ, 0, &[])).await?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::payload::BatteryPercent;
+
+    #[test]
+    fn packets() {
+        // a discovery config is longer than 127 bytes, so its length takes two bytes
+        let payload = vec![b'x'; 200];
+        let publish = publish_packet("sonyxm5/anc", &payload, true);
+        assert_eq!(&publish[..3], &[0x31, 213, 1]);
+        let mut received = publish.clone();
+        received.extend_from_slice(&packet(PINGRESP, 0, &[]));
+        assert_eq!(
+            parse_packet(&received).unwrap(),
+            Some((
+                Packet::Publish {
+                    topic: "sonyxm5/anc".to_string(),
+                    payload,
+                },
+                publish.len()
+            ))
+        );
+        assert_eq!(
+            parse_packet(&received[publish.len()..]).unwrap(),
+            Some((Packet::PingResp, 2))
+        );
+        // not all there yet
+        assert_eq!(parse_packet(&publish[..100]).unwrap(), None);
+        assert_eq!(
+            parse_packet(&[0x20, 2, 0, 5]).unwrap(),
+            Some((Packet::ConnAck { return_code: 5 }, 4))
+        );
+        assert!(parse_packet(&[0x30, 0xff, 0xff, 0xff, 0xff, 0x7f]).is_err());
+
+        let options = MqttOptions {
+            address: "localhost:1883".to_string(),
+            username: Some("user".to_string()),
+            password: None,
+        };
+        let connect = connect_packet(&options, ("sonyxm5/availability", "offline"));
+        assert_eq!(&connect[2..9], b"\0\x04MQTT\x04");
+        assert_eq!(connect[9], 0x80 | 0x20 | 0x04 | 0x02);
+        assert!(connect.ends_with(b"\0\x04user"));
+        assert_eq!(
+            subscribe_packet(1, "sonyxm5/anc/set"),
+            b"\x82\x14\0\x01\0\x0fsonyxm5/anc/set\0"
+        );
+    }
+
+    #[test]
+    fn home_assistant() {
+        let battery = Payload::BatteryLevel(BatteryLevel::Headphones {
+            left: BatteryPercent::new(80).unwrap(),
+            right: BatteryPercent::new(75).unwrap(),
+        });
+        assert_eq!(
+            states(&battery),
+            vec![
+                ("battery_left", "80".to_string()),
+                ("battery_right", "75".to_string())
+            ]
+        );
+        let anc = Payload::AncStatus {
+            mode: AncMode::AmbientSound,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: 10,
+        };
+        assert_eq!(states(&anc), vec![("anc", "ambient_sound".to_string())]);
+        assert_eq!(states(&Payload::InitReply), vec![]);
+
+        let earbuds = discovery("WF-1000XM5");
+        let (topic, config) = &earbuds[0];
+        assert_eq!(topic, "homeassistant/sensor/sonyxm5/battery_left/config");
+        assert_eq!(config["device_class"], "battery");
+        assert_eq!(config["state_topic"], "sonyxm5/battery_left");
+        assert_eq!(config["device"]["model"], "WF-1000XM5");
+        let (topic, config) = earbuds.last().unwrap();
+        assert_eq!(topic, "homeassistant/select/sonyxm5/equalizer/config");
+        assert_eq!(config["command_topic"], "sonyxm5/equalizer/set");
+        assert!(
+            config["options"]
+                .as_array()
+                .unwrap()
+                .contains(&json!("bass-boost"))
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! The D-Bus service, e.g. `busctl --user call org.sonyxm5.Controller /org/sonyxm5/Controller org.sonyxm5.Controller Battery`
+//!
+//! Mode and preset names are the ones of `sonyctl --json`.
+
+use controller_cli::args::{anc_mode_name, parse_anc_mode, parse_preset, preset_name};
+use sony_wf1000xm5::{
+    command::Command,
+    connection::{ConnectionHandle, RequestError},
+    model::Model,
+    payload::{BatteryLevel, Payload},
+    snapshot::HeadphoneSnapshot,
+};
+use std::{collections::HashMap, time::Duration};
+use tokio::sync::mpsc;
+use zbus::{fdo, object_server::SignalEmitter};
+
+pub const BUS_NAME: &str = "org.sonyxm5.Controller";
+pub const OBJECT_PATH: &str = "/org/sonyxm5/Controller";
+
+/// How long a method call waits for the headphones, retransmissions included
+pub(crate) const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct Controller {
+    connection: ConnectionHandle,
+}
+
+impl Controller {
+    pub fn new(connection: ConnectionHandle) -> Self {
+        Self { connection }
+    }
+
+    /// `command`'s reply, or `None` once the headphones acked a command which gets none
+    async fn request(&self, command: Command) -> fdo::Result<Option<Payload>> {
+        self.connection
+            .request(command, REPLY_TIMEOUT)
+            .await
+            .map_err(failed)
+    }
+
+    /// e.g. `WF-1000XM5`
+    pub async fn model_name(&self) -> fdo::Result<String> {
+        match self.request(Command::GetModelName).await? {
+            Some(Payload::ModelName(name)) => Ok(name),
+            payload => Err(unexpected(payload)),
+        }
+    }
+}
+
+fn failed(error: RequestError) -> fdo::Error {
+    match error {
+        RequestError::Invalid(e) => fdo::Error::InvalidArgs(e.to_string()),
+        e @ RequestError::Timeout => fdo::Error::TimedOut(e.to_string()),
+        e => fdo::Error::Failed(e.to_string()),
+    }
+}
+
+fn unexpected(payload: Option<Payload>) -> fdo::Error {
+    fdo::Error::Failed(format!("unexpected reply: {payload:?}"))
+}
+
+#[zbus::interface(name = "org.sonyxm5.Controller")]
+impl Controller {
+    /// In percent, by battery: `left`, `right` and `case` for earbuds, `level` for over-ear headphones
+    pub async fn battery(&self) -> fdo::Result<HashMap<String, u8>> {
+        let model = match self.request(Command::GetModelName).await? {
+            Some(Payload::ModelName(name)) => Model::from_name(&name),
+            _ => None,
+        };
+        let mut levels = HashMap::new();
+        for battery_type in model.unwrap_or_default().battery_types() {
+            let command = Command::GetBatteryStatus {
+                battery_type: *battery_type,
+            };
+            match self.request(command).await? {
+                Some(Payload::BatteryLevel(BatteryLevel::Single(level))) => {
+                    levels.insert("level".to_string(), level.get());
+                }
+                Some(Payload::BatteryLevel(BatteryLevel::Headphones { left, right })) => {
+                    levels.insert("left".to_string(), left.get());
+                    levels.insert("right".to_string(), right.get());
+                }
+                Some(Payload::BatteryLevel(BatteryLevel::Case(level))) => {
+                    levels.insert("case".to_string(), level.get());
+                }
+                payload => return Err(unexpected(payload)),
+            }
+        }
+        Ok(levels)
+    }
+
+    /// The mode, the ambient sound level and whether voices pass through
+    pub async fn anc(&self) -> fdo::Result<(String, u8, bool)> {
+        match self.request(Command::GetAncStatus).await? {
+            Some(Payload::AncStatus {
+                mode,
+                ambient_sound_voice_passthrough,
+                ambient_sound_level,
+            }) => Ok((
+                anc_mode_name(mode).to_string(),
+                ambient_sound_level,
+                ambient_sound_voice_passthrough,
+            )),
+            payload => Err(unexpected(payload)),
+        }
+    }
+
+    pub async fn set_anc(&self, mode: &str, level: u8, voice_passthrough: bool) -> fdo::Result<()> {
+        let mode = parse_anc_mode(mode)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("unknown mode: {mode}")))?;
+        self.request(Command::AncSet {
+            dragging_ambient_sound_slider: false,
+            mode,
+            ambient_sound_voice_passthrough: voice_passthrough,
+            ambient_sound_level: level as usize,
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// The preset and the levels of the bands, clear bass first
+    pub async fn equalizer(&self) -> fdo::Result<(String, Vec<i16>)> {
+        match self.request(Command::GetEqualizerSettings).await? {
+            Some(Payload::Equalizer { preset, bands }) => {
+                Ok((preset_name(preset), bands.levels().map(i16::from).to_vec()))
+            }
+            payload => Err(unexpected(payload)),
+        }
+    }
+
+    pub async fn set_equalizer_preset(&self, preset: &str) -> fdo::Result<()> {
+        let preset = parse_preset(preset)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("unknown preset: {preset}")))?;
+        self.request(Command::ChangeEqualizerPreset { preset })
+            .await?;
+        Ok(())
+    }
+
+    /// e.g. `ldac`
+    pub async fn codec(&self) -> fdo::Result<String> {
+        match self.request(Command::GetCodec).await? {
+            Some(Payload::Codec { codec }) => Ok(format!("{codec:?}").to_lowercase()),
+            payload => Err(unexpected(payload)),
+        }
+    }
+
+    /// Something changed on the headphones, whoever changed it; `value` is human readable, and empty if unknown.
+    /// `field` is one of the [sony_wf1000xm5::snapshot::SnapshotField]s, e.g. `Anc` or `LeftBattery`.
+    #[zbus(signal)]
+    async fn changed(emitter: &SignalEmitter<'_>, field: &str, value: &str) -> zbus::Result<()>;
+}
+
+/// Emit [Controller::changed] for whatever the `payloads` change, until the connection is gone
+pub async fn publish(
+    emitter: SignalEmitter<'_>,
+    mut payloads: mpsc::Receiver<Payload>,
+) -> zbus::Result<()> {
+    let mut snapshot = HeadphoneSnapshot::default();
+    while let Some(payload) = payloads.recv().await {
+        for change in snapshot.apply(&payload) {
+            Controller::changed(
+                &emitter,
+                &format!("{:?}", change.field),
+                change.new.as_deref().unwrap_or_default(),
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,261 @@
+//! The methods of the [crate::service] as JSON-RPC 2.0 over a Unix socket, for scripts and programs which
+//! can't or don't want to use D-Bus, e.g. without a desktop session. One request per line, one response per line:
+//!
+//! ```text
+//! $ echo '{"jsonrpc": "2.0", "id": 1, "method": "anc"}' | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/sonyxm5.sock
+//! {"id":1,"jsonrpc":"2.0","result":{"level":0,"mode":"noise_canceling","voice_passthrough":false}}
+//! ```
+//!
+//! The methods are `battery`, `anc`, `set_anc` (with `mode`, `level` and `voice_passthrough`), `equalizer`,
+//! `set_equalizer_preset` (with `preset`) and `codec`; the changes are only signaled on D-Bus.
+
+use crate::service::Controller;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use std::{
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::Path,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+use zbus::fdo;
+
+/// The codes of the JSON-RPC spec
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Anything the headphones or the connection did
+const HEADPHONES_ERROR: i64 = -32000;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    /// Requests without one are notifications, which get no response
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<fdo::Error> for RpcError {
+    fn from(error: fdo::Error) -> Self {
+        match error {
+            fdo::Error::InvalidArgs(message) => Self::new(INVALID_PARAMS, message),
+            fdo::Error::Failed(message) | fdo::Error::TimedOut(message) => {
+                Self::new(HEADPHONES_ERROR, message)
+            }
+            e => Self::new(HEADPHONES_ERROR, e.to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SetAncParams {
+    mode: String,
+    level: u8,
+    #[serde(default)]
+    voice_passthrough: bool,
+}
+
+#[derive(Deserialize)]
+struct SetEqualizerPresetParams {
+    preset: String,
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+async fn call(
+    controller: &Controller,
+    method: &str,
+    params_value: Value,
+) -> Result<Value, RpcError> {
+    Ok(match method {
+        "battery" => json!(controller.battery().await?),
+        "anc" => {
+            let (mode, level, voice_passthrough) = controller.anc().await?;
+            json!({"mode": mode, "level": level, "voice_passthrough": voice_passthrough})
+        }
+        "set_anc" => {
+            let SetAncParams {
+                mode,
+                level,
+                voice_passthrough,
+            } = params(params_value)?;
+            controller.set_anc(&mode, level, voice_passthrough).await?;
+            Value::Null
+        }
+        "equalizer" => {
+            let (preset, bands) = controller.equalizer().await?;
+            json!({"preset": preset, "bands": bands})
+        }
+        "set_equalizer_preset" => {
+            let SetEqualizerPresetParams { preset } = params(params_value)?;
+            controller.set_equalizer_preset(&preset).await?;
+            Value::Null
+        }
+        "codec" => json!(controller.codec().await?),
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method: {method}"),
+            ));
+        }
+    })
+}
+
+/// The response to one line of a client, if it gets one
+pub async fn respond(controller: &Controller, line: &str) -> Option<String> {
+    let (id, result) = match serde_json::from_str::<Value>(line) {
+        Err(e) => (Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string()))),
+        Ok(request) => {
+            let id = request.get("id").cloned().unwrap_or_default();
+            match serde_json::from_value::<RpcRequest>(request) {
+                Err(e) => (id, Err(RpcError::new(INVALID_REQUEST, e.to_string()))),
+                Ok(request) if request.jsonrpc != "2.0" => (
+                    id,
+                    Err(RpcError::new(
+                        INVALID_REQUEST,
+                        "only JSON-RPC 2.0 is supported",
+                    )),
+                ),
+                Ok(request) => {
+                    let result = call(controller, &request.method, request.params).await;
+                    (request.id?, result)
+                }
+            }
+        }
+    };
+    let response = match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(error) => json!({"jsonrpc": "2.0", "id": id, "error": error}),
+    };
+    Some(response.to_string())
+}
+
+/// Listen on `path`, replacing the socket a previous run left behind. Only the user can connect.
+pub fn bind(path: &Path) -> std::io::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        // binding fails on anything else, rather than deleting it
+        _ => (),
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Answer the clients of `listener`, each on its own task, until accepting fails
+pub async fn serve(listener: UnixListener, controller: Controller) -> std::io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let controller = controller.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer(stream, controller).await {
+                log::warn!("socket client: {e}");
+            }
+        });
+    }
+}
+
+async fn answer(stream: UnixStream, controller: Controller) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(mut response) = respond(&controller, &line).await {
+            response.push('\n');
+            writer.write_all(response.as_bytes()).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::{
+        command_queue::CAPACITY,
+        connection::{self, ConnectionEvent, ConnectionHandle, EVENT_QUEUE_SIZE},
+        session::Session,
+    };
+    use tokio::sync::mpsc;
+
+    fn error_code(response: &str) -> i64 {
+        let response: Value = serde_json::from_str(response).unwrap();
+        response["error"]["code"].as_i64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests() {
+        let (stream, _emulator) = emulator::duplex(Default::default());
+        let (handle, commands) = ConnectionHandle::new(CAPACITY);
+        let (event_tx, mut events) = mpsc::channel::<ConnectionEvent>(EVENT_QUEUE_SIZE);
+        tokio::spawn(connection::run(
+            stream,
+            commands,
+            event_tx,
+            Session::new(),
+            (),
+        ));
+        // what the headphones send, which only the D-Bus signals and MQTT use
+        tokio::spawn(async move { while events.recv().await.is_some() {} });
+        let controller = Controller::new(handle);
+
+        let set = r#"{"jsonrpc": "2.0", "id": 1, "method": "set_anc", "params": {"mode": "off", "level": 0}}"#;
+        let response: Value =
+            serde_json::from_str(&respond(&controller, set).await.unwrap()).unwrap();
+        assert_eq!(response, json!({"jsonrpc": "2.0", "id": 1, "result": null}));
+        let get = r#"{"jsonrpc": "2.0", "id": "anc", "method": "anc"}"#;
+        let response: Value =
+            serde_json::from_str(&respond(&controller, get).await.unwrap()).unwrap();
+        assert_eq!(response["id"], "anc");
+        assert_eq!(response["result"]["mode"], "off");
+
+        // notifications get no response
+        let notification =
+            r#"{"jsonrpc": "2.0", "method": "set_equalizer_preset", "params": {"preset": "off"}}"#;
+        assert_eq!(respond(&controller, notification).await, None);
+
+        assert_eq!(
+            error_code(&respond(&controller, "{").await.unwrap()),
+            PARSE_ERROR
+        );
+        let no_method = r#"{"jsonrpc": "2.0", "id": 2}"#;
+        assert_eq!(
+            error_code(&respond(&controller, no_method).await.unwrap()),
+            INVALID_REQUEST
+        );
+        let unknown = r#"{"jsonrpc": "2.0", "id": 3, "method": "power_off"}"#;
+        assert_eq!(
+            error_code(&respond(&controller, unknown).await.unwrap()),
+            METHOD_NOT_FOUND
+        );
+        let bad_mode = r#"{"jsonrpc": "2.0", "id": 4, "method": "set_anc", "params": {"mode": "loud", "level": 0}}"#;
+        assert_eq!(
+            error_code(&respond(&controller, bad_mode).await.unwrap()),
+            INVALID_PARAMS
+        );
+    }
+}
@@ -0,0 +1,279 @@
+# The English messages, which the other catalogues translate. See controller-gui/src/i18n.rs for the syntax.
+
+## shared
+ok = ok
+cancel = cancel
+retry = retry?
+yes = yes
+no = no
+refresh = refresh
+seconds-unit = s
+
+## device picker
+picker-known-failed = error while listing the known devices: { $error }
+picker-listing-known = Listing the known devices...
+picker-search = Search for other devices?
+picker-discovery-failed = error while discovering devices: { $error }
+picker-search-done = Search done.
+picker-search-again = Search again?
+picker-searching = Searching devices...
+picker-stop-searching = Stop searching?
+picker-waiting-for-last = Waiting for { $address } to connect...
+picker-pairing-failed = Pairing failed: { $error }
+picker-pairing = Pairing...
+picker-confirm-passkey = Does { $device } show { $passkey }?
+picker-pin-code = The PIN code of { $device }:
+picker-passkey = The passkey of { $device }:
+picker-display-code = Type { $code } on { $device }
+picker-bluetooth-enabled = Bluetooth enabled: { $enabled }
+picker-bluetooth-off = Bluetooth is not on. Turn it on and press refresh.
+picker-show-all = show all devices
+picker-show-all-hover = Include the devices which don't look like audio devices
+picker-audio = audio
+picker-connected = connected
+picker-new-headphones = New headphones? Put them in pairing mode (see their manual), search for them, then pick them and pair.
+picker-connect = connect?
+picker-pair-and-connect = pair and connect?
+picker-connect-automatically = Connect to this device automatically next time
+picker-bluetooth-failed = BtInfo: error: { $error }
+picker-getting-bluetooth = Getting BtInfo
+
+## app
+app-headphones = Headphones
+app-disconnected = Disconnected (buds in case?)
+app-error = Got an error: { $error }
+app-reconnecting = Reconnecting in { $seconds }s (attempt { $attempt })
+app-reconnect-automatically = reconnect automatically
+app-reconnect = reconnect
+app-close = close
+app-connecting = Connecting...
+last-state-stale = Last known state, from { $time } (stale)
+last-state-stale-hover = What the headphones reported when they were last connected; it may have changed since
+last-state-anc = Noise canceling: { $mode }
+last-state-equalizer = Equalizer: { $preset }
+app-stop = stop?
+app-tab-not-connected = { $title } (not connected)
+app-tab-connect = connect headphones
+app-tab-connect-other = + connect other headphones
+app-tab-settings = ⚙ settings
+app-compact = 🗗 compact
+app-compact-hover = A small window which stays on top, with the batteries and the noise canceling mode
+app-permissions-failed = Error while requesting permissions: { $error }
+app-pick-from-popup = Pick the headphones from the popup
+app-allow-connection = Allow connection to WF-1000XM5
+app-no-web-serial = Web Serial API is not implemented for this browser.
+app-request-port-failed = Couldn't request port: { $error }. Try reloading the page.
+
+## compact window
+compact-title = Sony headphones
+compact-not-connected = not connected
+compact-connected = connected
+compact-expand = back to the full window
+compact-noise-canceling = NC
+compact-ambient-sound = ambient
+compact-anc-off = off
+
+## status bar
+status-connecting = connecting
+status-connected = connected
+status-disconnected = disconnected
+status-rssi = { $rssi } dBm
+status-rssi-hover = The signal strength; audio starts dropping out below about -80 dBm
+status-last-payload = last heard from { $age } ago
+status-age-seconds = { $seconds } s
+status-age-minutes = { $minutes } min
+status-age-hours = { $hours } h
+
+## settings
+settings = Settings
+settings-language = language
+settings-language-system = the system's
+settings-theme = theme
+settings-theme-system = the system's
+settings-theme-light = light
+settings-theme-dark = dark
+settings-ui-scale = size
+settings-reconnect = reconnect automatically when the connection drops
+settings-sound-pressure-interval = read the sound pressure every
+settings-sound-pressure-interval-hover = while measuring; used from the next time measuring starts
+settings-power-saving = power saving: stop reading the sound pressure while the window is hidden or minimized
+settings-developer = developer console
+settings-developer-hover = send payloads typed in as hex, to find out what unknown commands do
+settings-guessed-commands = controls for guessed commands
+settings-guessed-commands-hover = The touch controls, speak-to-chat, the call settings, finding the earbuds, the ambient sound range and spatial audio, whose commands are guesses, not taken from captures of the Sony app. The headphones may take them for something else entirely. The low battery warnings and the rejected commands the headphones report are only shown with them too.
+settings-capture-frames = save messages the app doesn't understand
+settings-close-to-tray = close to tray
+settings-start-minimized = start minimized
+settings-autostart = start at login
+settings-autostart-hover = in the tray, connecting to the last headphones once the OS connects to them
+settings-autostart-failed = Couldn't change the autostart entry: { $error }
+settings-autostart-no-device = Connect to the headphones once, so the app knows which ones to connect to at login.
+settings-elsewhere = Notifications, shortcuts, rules, profiles and the history are set in the tab of the headphones.
+settings-saved-in = They're saved with these and the size of the window in { $directory }.
+settings-not-saved = The web version doesn't save them yet.
+
+## headphones
+dismiss = dismiss
+headphones-command-rejected = The headphones rejected a command (0x{ $opcode }, error 0x{ $code }). This firmware probably doesn't support it.
+headphones-disconnect = disconnect?
+headphones-read-only = read-only
+headphones-read-only-hover = Only read from the headphones; never change anything on them
+headphones-read-only-mode = Read-only mode: the settings are shown but can't be changed.
+headphones-batteries = 🇱 battery: { $left }, 🇷 battery: { $right }, case battery: { $case }
+headphones-battery = battery: { $battery }
+headphones-changed-externally = changed externally
+headphones-changed-externally-hover = Something else, e.g. the Sony app on a phone, changed this at { $time }
+headphones-queue-full = The headphones aren't keeping up; { $command } wasn't sent. Try again in a moment.
+headphones-pending = Waiting for the headphones to confirm the change
+headphones-not-confirmed = ⚠ The headphones didn't confirm the change of { $setting }, so it's shown as they last reported it
+headphones-conflict = ⚠ Another app (e.g. the Sony app on your phone) keeps changing the settings you change here. Close it to avoid fighting over them.
+headphones-low-battery = 🪫 The { $component } battery is low ({ $level })
+headphones-codec = Codec: { $codec }
+headphones-ears-measured = Spatial audio: ears measured
+headphones-ears-not-measured = Spatial audio: ears not measured (do it in the Sony app to personalize 360 Reality Audio)
+headphones-firmware = Firmware: { $version }
+headphones-sound-pressure = sound pressure: { $db } dB
+headphones-stop-measuring = stop?
+headphones-start-measuring = Start sound pressure measure?
+
+## firmware updates
+firmware-check = check for updates
+firmware-check-source = Compares with the versions listed by hand in this project's repository, not with Sony's update server: { $url }
+firmware-up-to-date = up to date, as far as this project's version list knows
+firmware-available = firmware { $version } is out, according to this project's version list; update with the Sony app
+firmware-notes = what's new
+firmware-unknown-model = no firmware versions of the { $model } known yet
+firmware-check-again = check again
+
+## sound exposure
+exposure = Sound exposure today
+exposure-open-failed = Couldn't open the exposure log: { $error }
+exposure-write-failed = Couldn't write the exposure log: { $error }
+exposure-average = average: { $db } dB over { $minutes } min measured
+exposure-dose = daily dose: { $percent }%
+exposure-dose-hover = Of the WHO's safe listening allowance, 80 dB for 40 hours a week, counting only the time the sound pressure was measured
+exposure-nothing-yet = Nothing measured yet today; start the sound pressure measure.
+
+## sound settings
+# the presets and bands keep the names the headphones know them by
+equalizer = Equalizer
+equalizer-preset = preset
+equalizer-clear-bass = clear bass
+equalizer-import = Import…
+equalizer-import-hover = AutoEq parametric or graphic EQ, Wavelet, or an export
+equalizer-export = Export…
+equalizer-imported = Imported the equalizer
+equalizer-exported = Exported to { $path }
+anc = ANC configuration:
+anc-off = Off
+anc-ambient-sound = Ambient Sounds
+anc-ambient-level = level
+anc-voice-passthrough = voice passthrough
+anc-noise-canceling = Active Noise Canceling
+touch-controls = Touch controls
+touch-controls-double-tap = double tap
+touch-controls-triple-tap = triple tap
+speak-to-chat = Speak-to-Chat
+speak-to-chat-timeout = resume music after you stop talking
+calls = Calls
+calls-voice-focus = focus ambient sound on voices during calls
+calls-sidetone = hear your own voice
+calls-sidetone-hover = How much of your own voice is played back to you during calls
+
+## find my buds
+find-my-buds = Find my buds
+find-my-buds-left = 🇱 play tone
+find-my-buds-right = 🇷 play tone
+find-my-buds-stop = stop
+
+## profiles, sharing and backups
+profiles = Profiles
+profiles-apply = Apply
+profiles-delete = Delete
+profiles-name-hint = e.g. Office
+profiles-save = Save current settings
+share = Share settings
+share-copy = Copy share string
+share-qr-code = show QR code
+share-apply = Apply
+backup = Backup
+backup-no-directory = Couldn't find a directory to save backups in.
+backup-save = Back up device settings
+backup-saved = Saved to { $path }
+backup-none = No backups yet.
+backup-restore = Restore
+backup-nothing-to-restore = The headphones already match the backup.
+backup-restored = Restored { $count } setting(s).
+backup-other-model = Note: the backup was made on a { $backed_up }, not a { $current }.
+backup-guessed-skipped = Skipped { $count } setting(s) which need the guessed commands.
+
+## development
+bug-reports = Bug reports
+bug-reports-attach = Attach { $path } to an issue to help support more of the headphones' features.
+bug-reports-export = Export diagnostic bundle…
+bug-reports-export-hover = A zip of the recent traffic, the parse warnings, the device info and the settings, with the Bluetooth addresses redacted
+bug-reports-exported = Exported to { $path }
+bug-reports-export-failed = Couldn't export the bundle: { $error }
+developer-console = Developer console
+
+## notifications, shortcuts and rules
+notifications = Notifications
+notifications-low-battery = low battery, at
+notifications-charged = fully charged
+notifications-codec = codec changes
+notifications-disconnect = lost connection
+hotkeys = Global shortcuts
+hotkeys-explanation = These work without focusing the window. Your desktop asks to confirm them, and may let you change them in its settings.
+hotkeys-none = none
+hotkeys-record = click to record
+hotkeys-recording = press a shortcut with Ctrl or Alt, or Backspace to remove it
+hotkeys-failed = Couldn't register the shortcuts: { $error }
+rules = Rules
+rules-notification = Rule: { $trigger }
+rules-when = When
+rules-trigger-at = at
+rules-trigger-codec = the codec switches to
+rules-trigger-battery = a battery drops below
+rules-for = for
+rules-battery = battery
+rules-then = then
+rules-action-anc = set noise canceling
+rules-action-equalizer = switch the equalizer
+rules-action-notify = notify
+rules-level = level
+rules-add = Add
+rules-saved-in = Saved in { $path }, which you can edit too; restart the app to load it.
+remove = remove
+
+## audio output
+audio-output = Audio output (PipeWire)
+audio-output-default = Default output: yes
+audio-output-not-default = Default output: no
+audio-output-profile = Profile: { $profile }
+audio-output-codec = Codec: { $codec }
+audio-output-set-default = Set as default output
+audio-output-playback = Switch to high quality playback
+audio-output-headset = Switch to headset profile for the mic
+app-anc = Noise canceling by application
+app-anc-enabled = switch the mode when these applications use audio
+app-anc-noise-canceling = noise canceling
+app-anc-ambient-sound = ambient sound
+app-anc-off = off
+app-anc-mapping = { $number }. when "{ $application }" { $kind }: { $mode }
+app-anc-when = when
+app-anc-application = application
+app-anc-add = Add
+app-anc-matching = The first match wins. Names are matched in part and ignoring case, e.g. "zoom" or "firefox"; `pw-dump` lists them as application.name.
+
+## history and logs
+days-unit = days
+history = State history
+history-save = save to disk
+history-retention-hover = How long the saved history is kept
+history-empty = Nothing changed yet.
+history-copy = Copy
+history-no-directory = Couldn't find a directory to save the history in
+history-write-failed = Couldn't write the history log: { $error }
+protocol-log = Protocol log
+protocol-log-explanation = What the app and the headphones said to each other lately, for bug reports.
+headphones-command-timed-out = The headphones didn't answer a command ({ $command }), even after retrying. Are they still in range?
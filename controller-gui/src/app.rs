@@ -1,46 +1,970 @@
+#[cfg(target_os = "linux")]
+use crate::app_anc::AppAncSettings;
 use crate::async_resource::ResourceStatus;
+#[cfg(target_os = "linux")]
+use crate::autostart;
+#[cfg(target_os = "linux")]
+use crate::compact_window::{CompactAction, CompactWindow};
 #[cfg(not(target_arch = "wasm32"))]
 use crate::device_picker::DevicePicker;
-use crate::headphone_thread;
-use crate::{async_resource::AsyncResource, headphone_ui::HeadphoneUi};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::frame_capture::FrameCapture;
+use crate::headphone_thread::{self, OpenConnections};
+#[cfg(target_os = "linux")]
+use crate::hotkeys::{HotkeySettings, Hotkeys};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::last_state::LastStates;
+#[cfg(target_os = "linux")]
+use crate::limited_mode::LimitedMode;
+#[cfg(target_os = "linux")]
+use crate::notifications::NotificationSettings;
+#[cfg(target_os = "linux")]
+use crate::rules::Rules;
+#[cfg(target_os = "linux")]
+use crate::status_bar::RssiMonitor;
+#[cfg(target_os = "linux")]
+use crate::tray::{Tray, TrayAction, TraySettings, TrayState};
+use crate::{
+    async_resource::AsyncResource,
+    headphone_ui::HeadphoneUi,
+    history::HistoryLogSettings,
+    i18n::{self, Language},
+    profiles::Profiles,
+    reconnect::Reconnect,
+    settings::{AppSettings, Theme},
+    status_bar::{self, LinkState, LinkStatus},
+    tr,
+};
 #[cfg(not(target_arch = "wasm32"))]
 use bluer::Device;
 use eframe::egui;
+use sony_wf1000xm5::{
+    command_queue,
+    connection::{self, ConnectionError, ConnectionHandle},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{Arc, atomic::AtomicBool};
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc, time::Duration};
 use tokio::sync::mpsc;
 #[cfg(target_arch = "wasm32")]
 use web_sys::SerialPort;
 
+/// Identifies a [Session] while the app runs; the tabs are in the order of their ids
+type SessionId = u64;
+
+/// The connection to one pair of headphones, with its own connection task and tab
+struct Session {
+    #[cfg(not(target_arch = "wasm32"))]
+    connection: Device,
+    #[cfg(target_arch = "wasm32")]
+    connection: SerialPort,
+    connection_task: AsyncResource<anyhow::Result<()>>,
+    headphone_ui: Option<HeadphoneUi>,
+    reconnect: Reconnect,
+    /// Shown when we couldn't get the Sony channel
+    #[cfg(target_os = "linux")]
+    limited_mode: Option<LimitedMode>,
+    /// Whether we told the user about the connection dropping, once per connection
+    #[cfg(target_os = "linux")]
+    notified_disconnect: bool,
+    /// For the status bar
+    #[cfg(target_os = "linux")]
+    rssi: RssiMonitor,
+}
+
+impl Session {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn new(connection: Device, reconnect: bool) -> Self {
+        Self {
+            connection,
+            connection_task: AsyncResource::default(),
+            headphone_ui: None,
+            reconnect: Reconnect::new(reconnect),
+            #[cfg(target_os = "linux")]
+            limited_mode: None,
+            #[cfg(target_os = "linux")]
+            notified_disconnect: false,
+            #[cfg(target_os = "linux")]
+            rssi: RssiMonitor::default(),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn new(connection: SerialPort, reconnect: bool) -> Self {
+        Self {
+            connection,
+            connection_task: AsyncResource::default(),
+            headphone_ui: None,
+            reconnect: Reconnect::new(reconnect),
+        }
+    }
+
+    /// Stop the connection: one which is open closes in order and ends on its own, see
+    /// [connection::SHUTDOWN_TIMEOUT]; one which is still opening is dropped
+    fn close(&self) {
+        match &self.headphone_ui {
+            Some(ui) if ui.is_connected() => ui.disconnect(),
+            _ => self.connection_task.cancel(),
+        }
+    }
+
+    /// The UI of the headphones, while they're connected
+    fn connected_ui(&self) -> Option<&HeadphoneUi> {
+        let running = matches!(self.connection_task.get(), ResourceStatus::Pending);
+        self.headphone_ui
+            .as_ref()
+            .filter(|headphone_ui| running && headphone_ui.is_connected())
+    }
+
+    /// What the status bar shows about the connection
+    fn link_status(&mut self, ctx: &egui::Context) -> LinkStatus {
+        let headphone_ui = self.connected_ui();
+        let state = match self.connection_task.get() {
+            ResourceStatus::Pending if headphone_ui.is_some() => LinkState::Connected,
+            ResourceStatus::Pending | ResourceStatus::NotInitialized => LinkState::Connecting,
+            ResourceStatus::Ready(_) => LinkState::Disconnected,
+        };
+        let codec = headphone_ui.and_then(|headphone_ui| headphone_ui.snapshot().codec);
+        let last_payload_at = headphone_ui.and_then(HeadphoneUi::last_payload_at);
+        #[cfg(target_os = "linux")]
+        let rssi = if state == LinkState::Connected {
+            self.rssi.update(ctx, &self.connection)
+        } else {
+            None
+        };
+        // the web doesn't tell us the signal strength
+        #[cfg(target_arch = "wasm32")]
+        let rssi = {
+            let _ = ctx;
+            None
+        };
+        LinkStatus {
+            state,
+            codec,
+            rssi,
+            last_payload_at,
+        }
+    }
+
+    /// The name of the tab: the model once the headphones told us, with the address to tell the same ones apart
+    fn title(&self) -> String {
+        let model_name = self
+            .headphone_ui
+            .as_ref()
+            .and_then(|headphone_ui| headphone_ui.snapshot().device_info.model_name.clone());
+        #[cfg(not(target_arch = "wasm32"))]
+        let address = self.connection.address().to_string();
+        // the web doesn't tell us the address of a serial port
+        #[cfg(target_arch = "wasm32")]
+        let address = tr!("app-headphones");
+        match model_name {
+            Some(model_name) => format!("{model_name} ({address})"),
+            None => address,
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct App {
     #[cfg(not(target_arch = "wasm32"))]
     pub picker: DevicePicker,
-    #[cfg(not(target_arch = "wasm32"))]
-    current_connection: Option<Device>,
-    #[cfg(target_arch = "wasm32")]
-    current_connection: Option<SerialPort>,
     #[cfg(target_arch = "wasm32")]
     picker: AsyncResource<anyhow::Result<SerialPort>>,
-    connection_task: AsyncResource<anyhow::Result<()>>,
-    headphone_ui: Option<HeadphoneUi>,
+    sessions: BTreeMap<SessionId, Session>,
+    next_session_id: SessionId,
+    /// The tab shown, `None` for the device picker
+    selected: Option<SessionId>,
+    /// The settings page is shown instead of the tab
+    settings_shown: bool,
+    pub settings: AppSettings,
+    pub history_settings: HistoryLogSettings,
+    /// Start new connections in read-only mode
+    pub read_only: bool,
+    /// Show the developer console during this run (`--developer`), whatever the settings say
+    pub developer: bool,
+    /// Save the frames we can't parse for a bug report, see [crate::frame_capture::FrameCapture]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub capture_frames: Arc<AtomicBool>,
+    /// Whether new connections reconnect when the link drops; each session has its own attempts
+    pub reconnect: Reconnect,
+    /// Shared with the headphone UI, which edits them
+    pub profiles: Rc<RefCell<Profiles>>,
+    #[cfg(target_os = "linux")]
+    pub notification_settings: NotificationSettings,
+    /// Spawned on the first update
+    #[cfg(target_os = "linux")]
+    tray: Option<Tray>,
+    #[cfg(target_os = "linux")]
+    pub tray_settings: TraySettings,
+    /// The window is hidden in the tray; shown again if there's no icon after all, or it goes away
+    #[cfg(target_os = "linux")]
+    pub hidden: bool,
+    /// Closing the window only hides it while the tray icon is shown, unless quitting from the tray
+    #[cfg(target_os = "linux")]
+    quitting: bool,
+    /// Spawned on the first update
+    #[cfg(target_os = "linux")]
+    hotkeys: Option<Hotkeys>,
+    #[cfg(target_os = "linux")]
+    pub hotkey_settings: HotkeySettings,
+    /// Saved by the headphone UI, which edits them
+    #[cfg(target_os = "linux")]
+    pub rules: Rc<RefCell<Rules>>,
+    #[cfg(target_os = "linux")]
+    pub app_anc_settings: AppAncSettings,
+    #[cfg(target_os = "linux")]
+    pub compact_window: CompactWindow,
+    /// Whether the app starts at login, see [crate::autostart]
+    #[cfg(target_os = "linux")]
+    pub autostart: bool,
+    /// Why the autostart entry couldn't be changed
+    #[cfg(target_os = "linux")]
+    autostart_error: Option<String>,
+    /// Another instance was started, which wants this one shown instead, see [crate::single_instance]
+    #[cfg(target_os = "linux")]
+    pub show_requests: Option<std::sync::mpsc::Receiver<()>>,
+    /// The connection tasks, which main lets close before exiting
+    pub open_connections: OpenConnections,
+    /// Shown while the headphones connect
+    #[cfg(not(target_arch = "wasm32"))]
+    pub last_states: LastStates,
+}
+
+fn theme_label(theme: Theme) -> String {
+    match theme {
+        Theme::System => tr!("settings-theme-system"),
+        Theme::Light => tr!("settings-theme-light"),
+        Theme::Dark => tr!("settings-theme-dark"),
+    }
+}
+
+/// The headphones the tray and the shortcuts control: the ones shown, or else the first ones connected
+#[cfg(target_os = "linux")]
+fn active_ui(
+    sessions: &BTreeMap<SessionId, Session>,
+    selected: Option<SessionId>,
+) -> Option<&HeadphoneUi> {
+    selected
+        .and_then(|id| sessions.get(&id))
+        .and_then(Session::connected_ui)
+        .or_else(|| sessions.values().find_map(Session::connected_ui))
 }
 
 impl App {
+    pub const NAME: &'static str = "Sony-WF1000XM5 GUI";
+
+    /// Show the state in the tray icon and do what was picked from its menu
+    #[cfg(target_os = "linux")]
+    fn update_tray(&mut self, ctx: &egui::Context) {
+        let tray = self
+            .tray
+            .get_or_insert_with(|| Tray::spawn(ctx, self.tray_settings));
+        if self.hidden && tray.is_unavailable() {
+            log::warn!("no tray icon to show instead of the window");
+            self.hidden = false;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+        }
+        let headphone_ui = active_ui(&self.sessions, self.selected);
+        tray.update(
+            headphone_ui
+                .map(|headphone_ui| TrayState::connected(headphone_ui.snapshot()))
+                .unwrap_or_default(),
+        );
+        while let Some(action) = tray.poll_action() {
+            match action {
+                TrayAction::ShowWindow => {
+                    self.hidden = false;
+                    self.compact_window.shown = false;
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+                }
+                TrayAction::SetAnc(mode) => {
+                    if let Some(headphone_ui) = headphone_ui {
+                        headphone_ui.set_anc_mode(mode);
+                    }
+                }
+                TrayAction::SetEqualizerPreset(preset) => {
+                    if let Some(headphone_ui) = headphone_ui {
+                        headphone_ui.set_equalizer_preset(preset);
+                    }
+                }
+                TrayAction::Disconnect => {
+                    if let Some(headphone_ui) = headphone_ui {
+                        headphone_ui.disconnect();
+                    }
+                }
+                TrayAction::Settings(settings) => self.tray_settings = settings,
+                TrayAction::Quit => {
+                    self.quitting = true;
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+                }
+            }
+        }
+        if ctx.input(|i| i.viewport().close_requested())
+            && self.tray_settings.close_to_tray
+            && !self.quitting
+            && tray.is_shown()
+        {
+            self.hidden = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(false));
+        }
+    }
+
+    /// Show the window when another instance was started, like the tray's "Show window"
+    #[cfg(target_os = "linux")]
+    fn update_show_requests(&mut self, ctx: &egui::Context) {
+        let Some(requests) = self.show_requests.as_ref() else {
+            return;
+        };
+        if requests.try_iter().count() > 0 {
+            self.hidden = false;
+            self.compact_window.shown = false;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+        }
+    }
+
+    /// Show the compact window instead of the main one, or the other way round
+    #[cfg(target_os = "linux")]
+    pub fn set_compact(&mut self, ctx: &egui::Context, compact: bool) {
+        self.compact_window.shown = compact;
+        ctx.send_viewport_cmd_to(
+            egui::ViewportId::ROOT,
+            egui::ViewportCommand::Minimized(compact),
+        );
+        if !compact {
+            ctx.send_viewport_cmd_to(egui::ViewportId::ROOT, egui::ViewportCommand::Focus);
+        }
+    }
+
+    /// Draw the compact window while it's shown and do what was clicked in it
+    #[cfg(target_os = "linux")]
+    fn update_compact_window(&mut self, ctx: &egui::Context) {
+        if !self.compact_window.shown {
+            return;
+        }
+        let headphone_ui = active_ui(&self.sessions, self.selected);
+        match self
+            .compact_window
+            .show(ctx, headphone_ui.map(HeadphoneUi::snapshot))
+        {
+            Some(CompactAction::SetAnc(mode)) => {
+                if let Some(headphone_ui) = headphone_ui {
+                    headphone_ui.set_anc_mode(mode);
+                }
+            }
+            Some(CompactAction::Expand) => self.set_compact(ctx, false),
+            None => (),
+        }
+    }
+
+    /// Register the shortcuts edited in the headphone UI and do what they're pressed for
+    #[cfg(target_os = "linux")]
+    fn update_hotkeys(&mut self, ctx: &egui::Context) {
+        let hotkeys = self
+            .hotkeys
+            .get_or_insert_with(|| Hotkeys::spawn(ctx, self.hotkey_settings.clone()));
+        if let Some(headphone_ui) = self
+            .selected
+            .and_then(|id| self.sessions.get_mut(&id))
+            .and_then(|session| session.headphone_ui.as_mut())
+        {
+            self.hotkey_settings
+                .clone_from(headphone_ui.hotkey_settings());
+            hotkeys.rebind(&self.hotkey_settings);
+            headphone_ui.set_hotkeys_error(hotkeys.error());
+        }
+        let headphone_ui = active_ui(&self.sessions, self.selected);
+        while let Some(action) = hotkeys.poll_action() {
+            if let Some(headphone_ui) = headphone_ui {
+                headphone_ui.on_hotkey(action);
+            }
+        }
+    }
+
+    /// Take the settings from the headphones shown, where they're edited
+    fn pull_settings(&mut self) {
+        let Some(headphone_ui) = self
+            .selected
+            .and_then(|id| self.sessions.get(&id))
+            .and_then(|session| session.headphone_ui.as_ref())
+        else {
+            return;
+        };
+        self.history_settings = headphone_ui.history_settings();
+        #[cfg(target_os = "linux")]
+        {
+            self.notification_settings = headphone_ui.notification_settings();
+            self.hotkey_settings
+                .clone_from(headphone_ui.hotkey_settings());
+            self.app_anc_settings = headphone_ui.app_anc_settings().clone();
+        }
+    }
+
+    /// Give the settings to `headphone_ui`, e.g. after they were edited with other headphones
+    fn push_settings(&self, headphone_ui: &mut HeadphoneUi) {
+        headphone_ui.set_history_settings(self.history_settings);
+        #[cfg(target_os = "linux")]
+        {
+            headphone_ui.set_notification_settings(self.notification_settings);
+            headphone_ui.set_hotkey_settings(self.hotkey_settings.clone());
+            headphone_ui.set_app_anc_settings(self.app_anc_settings.clone());
+        }
+    }
+
+    /// Show another tab, carrying the settings edited in the current one over
+    fn select(&mut self, id: Option<SessionId>) {
+        if id == self.selected {
+            return;
+        }
+        self.pull_settings();
+        self.selected = id;
+        if let Some(mut headphone_ui) = id
+            .and_then(|id| self.sessions.get_mut(&id))
+            .and_then(|session| session.headphone_ui.take())
+        {
+            self.push_settings(&mut headphone_ui);
+            if let Some(session) = id.and_then(|id| self.sessions.get_mut(&id)) {
+                session.headphone_ui = Some(headphone_ui);
+            }
+        }
+    }
+
+    /// Connect to the headphones picked, or show them if they already are
+    #[cfg(not(target_arch = "wasm32"))]
+    fn open_session(&mut self, device: Device) {
+        if let Some(id) = self
+            .sessions
+            .iter()
+            .find(|(_, session)| session.connection.address() == device.address())
+            .map(|(id, _)| *id)
+        {
+            self.select(Some(id));
+            return;
+        }
+        self.add_session(Session::new(device, self.reconnect.enabled));
+    }
+
     #[cfg(target_arch = "wasm32")]
-    fn pick_device_web(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        egui::CentralPanel::default().show(ctx, |ui| match self.picker.get() {
+    fn open_session(&mut self, port: SerialPort) {
+        self.add_session(Session::new(port, self.reconnect.enabled));
+    }
+
+    fn add_session(&mut self, session: Session) {
+        let id = self.next_session_id;
+        self.next_session_id += 1;
+        self.sessions.insert(id, session);
+        self.select(Some(id));
+    }
+
+    fn close_session(&mut self, id: SessionId) {
+        if self.selected == Some(id) {
+            self.pull_settings();
+            self.selected = None;
+        }
+        if let Some(session) = self.sessions.remove(&id) {
+            session.close();
+        }
+        #[cfg(target_arch = "wasm32")]
+        self.picker.clear();
+    }
+
+    /// Start the connection task of the session, with a new headphone UI
+    fn start_session(&mut self, id: SessionId, ctx: &egui::Context) {
+        self.pull_settings();
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return;
+        };
+        let (connection, commands) = ConnectionHandle::new(command_queue::CAPACITY);
+        let (event_tx, event_rx) = mpsc::channel(connection::EVENT_QUEUE_SIZE);
+        #[cfg(not(target_arch = "wasm32"))]
+        let device = session.connection.clone();
+        #[cfg(target_arch = "wasm32")]
+        let port = session.connection.clone();
+        let thread_ctx = ctx.clone();
+        #[cfg(not(target_arch = "wasm32"))]
+        let frame_capture = eframe::storage_dir(Self::NAME).map(|dir| {
+            FrameCapture::new(
+                dir.join(FrameCapture::FILE_NAME),
+                self.capture_frames.clone(),
+            )
+        });
+        #[cfg(not(target_arch = "wasm32"))]
+        session
+            .connection_task
+            .set(self.open_connections.track(headphone_thread::run(
+                device,
+                event_tx,
+                commands,
+                thread_ctx,
+                frame_capture,
+            )));
+        #[cfg(target_arch = "wasm32")]
+        session.connection_task.set(
+            self.open_connections
+                .track(headphone_thread::run(port, event_tx, commands, thread_ctx)),
+        );
+        // a reconnection keeps the read-only mode of the connection before
+        let read_only = session
+            .headphone_ui
+            .as_ref()
+            .map_or(self.read_only, HeadphoneUi::is_read_only);
+        let mut headphone_ui =
+            HeadphoneUi::new(connection, event_rx, self.history_settings, read_only);
+        headphone_ui.set_profiles(self.profiles.clone());
+        #[cfg(not(target_arch = "wasm32"))]
+        headphone_ui.set_frame_capture(self.capture_frames.clone());
+        headphone_ui.set_developer_mode(self.developer || self.settings.developer);
+        headphone_ui.set_guessed_commands(self.settings.guessed_commands);
+        headphone_ui.set_sound_pressure_interval(self.settings.sound_pressure_interval());
+        #[cfg(target_os = "linux")]
+        {
+            headphone_ui.set_notification_settings(self.notification_settings);
+            headphone_ui.set_hotkey_settings(self.hotkey_settings.clone());
+            headphone_ui.set_rules(self.rules.clone());
+            headphone_ui.set_app_anc_settings(self.app_anc_settings.clone());
+            headphone_ui.set_device_address(session.connection.address().to_string());
+            session.notified_disconnect = false;
+        }
+        session.headphone_ui = Some(headphone_ui);
+    }
+
+    /// Keep the connection of the session going, and draw it if it's the tab shown
+    fn update_session(&mut self, id: SessionId, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        let shown = !self.settings_shown && self.selected == Some(id);
+        let mut should_close = false;
+        let mut should_start = false;
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return;
+        };
+        // if it dies with Ok(()) it means the user disconnected by themselves
+        if matches!(session.connection_task.get(), ResourceStatus::Ready(result) if result.is_ok())
+        {
+            self.close_session(id);
+            return;
+        }
+        match session.connection_task.get() {
             ResourceStatus::Ready(result) => {
-                if let Err(e) = result.as_ref() {
-                    ui.label(format!("Error while requesting permissions: {e}"));
+                let now = ctx.input(|i| i.time);
+                let e = result.as_ref().expect_err("closed above");
+                #[cfg(target_os = "linux")]
+                if !session.notified_disconnect
+                    && let Some(headphone_ui) = session.headphone_ui.as_ref()
+                {
+                    session.notified_disconnect = true;
+                    headphone_ui.notify_disconnect(&e.to_string());
+                }
+                if let Some(headphone_ui) = session.headphone_ui.as_mut()
+                    && headphone_ui.is_connected()
+                {
+                    headphone_ui.set_disconnected();
+                }
+                // rather than an error
+                let disconnected = e
+                    .downcast_ref::<ConnectionError>()
+                    .is_some_and(ConnectionError::is_disconnect);
+                // the phone owns the channel, which won't change by trying again
+                #[cfg(not(target_arch = "wasm32"))]
+                let retry = e
+                    .downcast_ref::<headphone_thread::SonyServiceUnavailable>()
+                    .is_none();
+                #[cfg(target_arch = "wasm32")]
+                let retry = true;
+                let retry_at = if retry {
+                    session.reconnect.failed(now)
                 } else {
-                    self.current_connection = Some(result.as_ref().unwrap().clone());
+                    None
+                };
+                if let Some(retry_at) = retry_at {
+                    if session.reconnect.due(now) {
+                        should_start = true;
+                    } else if shown {
+                        // for the countdown
+                        ctx.request_repaint_after(
+                            Duration::from_secs(1).min(Duration::from_secs_f64(retry_at - now)),
+                        );
+                    } else {
+                        ctx.request_repaint_after(Duration::from_secs_f64(retry_at - now));
+                    }
+                }
+                if shown {
+                    egui::CentralPanel::default().show(ctx, |ui| {
+                        if disconnected {
+                            ui.heading(tr!("app-disconnected"));
+                            ui.label(e.to_string());
+                        } else {
+                            ui.label(tr!("app-error", error = e));
+                        }
+                        if let Some(retry_at) = retry_at {
+                            ui.horizontal(|ui| {
+                                ui.spinner();
+                                ui.label(tr!(
+                                    "app-reconnecting",
+                                    seconds = (retry_at - now).ceil(),
+                                    attempt = session.reconnect.attempts()
+                                ));
+                            });
+                        }
+                        if ui
+                            .checkbox(
+                                &mut session.reconnect.enabled,
+                                tr!("app-reconnect-automatically"),
+                            )
+                            .changed()
+                        {
+                            self.reconnect.enabled = session.reconnect.enabled;
+                        }
+                        #[cfg(target_os = "linux")]
+                        if e.downcast_ref::<headphone_thread::SonyServiceUnavailable>()
+                            .is_some()
+                        {
+                            ui.separator();
+                            session
+                                .limited_mode
+                                .get_or_insert_with(LimitedMode::new)
+                                .draw(ctx, ui, &session.connection);
+                            ui.separator();
+                        }
+                        let retry = if disconnected {
+                            tr!("app-reconnect")
+                        } else {
+                            tr!("retry")
+                        };
+                        if ui.button(retry).clicked() {
+                            session.reconnect.reset();
+                            should_start = true;
+                            #[cfg(target_os = "linux")]
+                            {
+                                session.limited_mode = None;
+                            }
+                        }
+                        if ui.button(tr!("app-close")).clicked() {
+                            should_close = true;
+                        }
+                    });
                 }
             }
+
+            ResourceStatus::Pending => match session.headphone_ui.as_mut() {
+                // start_session sets both, so this is only to be safe
+                None => should_start = true,
+                Some(headphone_ui) if headphone_ui.is_connected() => {
+                    session.reconnect.reset();
+                    #[cfg(not(target_arch = "wasm32"))]
+                    self.last_states.record(
+                        &session.connection.address().to_string(),
+                        headphone_ui.snapshot(),
+                    );
+                    if shown {
+                        eframe::App::update(headphone_ui, ctx, frame);
+                    } else {
+                        headphone_ui.update_in_background(ctx);
+                    }
+                }
+                Some(headphone_ui) => {
+                    headphone_ui.poll_events();
+                    if shown {
+                        egui::CentralPanel::default().show(ctx, |ui| {
+                            ui.label(tr!("app-connecting"));
+                            if ui.button(tr!("app-stop")).clicked() {
+                                should_close = true;
+                            }
+                            ui.spinner();
+                            #[cfg(not(target_arch = "wasm32"))]
+                            if let Some(last_state) = self
+                                .last_states
+                                .get(&session.connection.address().to_string())
+                            {
+                                ui.separator();
+                                last_state.draw(ui);
+                            }
+                        });
+                    }
+                }
+            },
+            ResourceStatus::NotInitialized => should_start = true,
+        }
+        if should_close {
+            self.close_session(id);
+        } else if should_start {
+            self.start_session(id, ctx);
+        }
+    }
+
+    /// A tab per headphones, one to connect to more, and the settings
+    fn draw_tabs(&mut self, ctx: &egui::Context) {
+        let mut selected = self.selected;
+        let mut settings_shown = self.settings_shown;
+        #[cfg(target_os = "linux")]
+        let mut compact = false;
+        egui::TopBottomPanel::top("headphone_tabs").show(ctx, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                let mut tab = |ui: &mut egui::Ui, id, title: String| {
+                    if ui
+                        .selectable_label(!settings_shown && selected == id, title)
+                        .clicked()
+                    {
+                        selected = id;
+                        settings_shown = false;
+                    }
+                };
+                for (id, session) in &self.sessions {
+                    let title = session.title();
+                    let title = if session.connected_ui().is_some() {
+                        title
+                    } else {
+                        tr!("app-tab-not-connected", title = title)
+                    };
+                    tab(ui, Some(*id), title);
+                }
+                let connect = if self.sessions.is_empty() {
+                    tr!("app-tab-connect")
+                } else {
+                    tr!("app-tab-connect-other")
+                };
+                tab(ui, None, connect);
+                if ui
+                    .selectable_label(settings_shown, tr!("app-tab-settings"))
+                    .clicked()
+                {
+                    settings_shown = true;
+                }
+                #[cfg(target_os = "linux")]
+                {
+                    compact = ui
+                        .button(tr!("app-compact"))
+                        .on_hover_text(tr!("app-compact-hover"))
+                        .clicked();
+                }
+            });
+        });
+        #[cfg(target_os = "linux")]
+        if compact {
+            self.set_compact(ctx, true);
+        }
+        self.settings_shown = settings_shown;
+        self.select(selected);
+    }
+
+    /// The settings of the app itself; the ones of a section of the headphones' tab are edited there
+    fn draw_settings(&mut self, ctx: &egui::Context) {
+        let before = (self.settings, self.reconnect.enabled);
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading(tr!("settings"));
+            ui.horizontal(|ui| {
+                ui.label(tr!("settings-language"));
+                let system = tr!("settings-language-system");
+                egui::ComboBox::from_id_salt("language")
+                    .selected_text(
+                        self.settings
+                            .language
+                            .map_or(system.clone(), |language| language.name().to_string()),
+                    )
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.settings.language, None, system);
+                        for language in Language::ALL {
+                            ui.selectable_value(
+                                &mut self.settings.language,
+                                Some(language),
+                                language.name(),
+                            );
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                ui.label(tr!("settings-theme"));
+                egui::ComboBox::from_id_salt("theme")
+                    .selected_text(theme_label(self.settings.theme))
+                    .show_ui(ui, |ui| {
+                        for theme in Theme::ALL {
+                            ui.selectable_value(
+                                &mut self.settings.theme,
+                                theme,
+                                theme_label(theme),
+                            );
+                        }
+                    });
+            });
+            ui.horizontal(|ui| {
+                ui.label(tr!("settings-ui-scale"));
+                // applied once let go of, or the value would run away from the pointer as everything resizes
+                let id = ui.id().with("ui_scale");
+                let mut percent = ui
+                    .data(|data| data.get_temp(id))
+                    .unwrap_or(self.settings.ui_scale_percent);
+                let response = ui.add(
+                    egui::DragValue::new(&mut percent)
+                        .range(AppSettings::UI_SCALES)
+                        .suffix(" %"),
+                );
+                if response.dragged() || response.has_focus() {
+                    ui.data_mut(|data| data.insert_temp(id, percent));
+                } else {
+                    ui.data_mut(|data| data.remove::<u32>(id));
+                    self.settings.ui_scale_percent = percent;
+                }
+            });
+            ui.checkbox(&mut self.reconnect.enabled, tr!("settings-reconnect"));
+            ui.horizontal(|ui| {
+                ui.label(tr!("settings-sound-pressure-interval"));
+                ui.add(
+                    egui::DragValue::new(&mut self.settings.sound_pressure_interval_secs)
+                        .range(AppSettings::SOUND_PRESSURE_INTERVALS)
+                        .suffix(format!(" {}", tr!("seconds-unit"))),
+                )
+                .on_hover_text(tr!("settings-sound-pressure-interval-hover"));
+            });
+            ui.checkbox(
+                &mut self.settings.power_saving,
+                tr!("settings-power-saving"),
+            );
+            ui.checkbox(&mut self.settings.developer, tr!("settings-developer"))
+                .on_hover_text(tr!("settings-developer-hover"));
+            ui.checkbox(
+                &mut self.settings.guessed_commands,
+                tr!("settings-guessed-commands"),
+            )
+            .on_hover_text(tr!("settings-guessed-commands-hover"));
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                use std::sync::atomic::Ordering;
+
+                let mut capture = self.capture_frames.load(Ordering::Relaxed);
+                if ui
+                    .checkbox(&mut capture, tr!("settings-capture-frames"))
+                    .changed()
+                {
+                    self.capture_frames.store(capture, Ordering::Relaxed);
+                }
+            }
+            #[cfg(target_os = "linux")]
+            {
+                let tray_settings = self.tray_settings;
+                ui.checkbox(
+                    &mut self.tray_settings.close_to_tray,
+                    tr!("settings-close-to-tray"),
+                );
+                ui.checkbox(
+                    &mut self.tray_settings.start_minimized,
+                    tr!("settings-start-minimized"),
+                );
+                if self.tray_settings != tray_settings
+                    && let Some(tray) = self.tray.as_ref()
+                {
+                    tray.set_settings(self.tray_settings);
+                }
+                self.draw_autostart(ui);
+            }
+            ui.separator();
+            ui.label(tr!("settings-elsewhere"));
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(dir) = eframe::storage_dir(Self::NAME) {
+                ui.label(tr!("settings-saved-in", directory = dir.display()));
+            }
+            #[cfg(target_arch = "wasm32")]
+            ui.label(tr!("settings-not-saved"));
+        });
+        if (self.settings, self.reconnect.enabled) != before {
+            self.apply_settings();
+            self.apply_appearance(ctx);
+        }
+    }
+
+    /// The toggle of the autostart entry, which also turns on connecting to the last headphones
+    #[cfg(target_os = "linux")]
+    fn draw_autostart(&mut self, ui: &mut egui::Ui) {
+        if ui
+            .checkbox(&mut self.autostart, tr!("settings-autostart"))
+            .on_hover_text(tr!("settings-autostart-hover"))
+            .changed()
+        {
+            let result = if self.autostart {
+                self.picker.connect_to_the_device_automatically_on_startup = true;
+                autostart::install()
+            } else {
+                autostart::remove()
+            };
+            self.autostart_error = None;
+            if let Err(e) = result {
+                self.autostart = autostart::is_installed();
+                self.autostart_error = Some(tr!("settings-autostart-failed", error = e));
+            }
+        }
+        if let Some(error) = &self.autostart_error {
+            ui.colored_label(egui::Color32::RED, error);
+        } else if self.autostart
+            && self.picker.last_device_addr.is_empty()
+            && self.sessions.is_empty()
+        {
+            ui.label(tr!("settings-autostart-no-device"));
+        }
+    }
+
+    /// The theme and the scale of the settings; Ctrl +/- still zoom until the next change
+    pub fn apply_appearance(&self, ctx: &egui::Context) {
+        ctx.set_theme(self.settings.theme);
+        ctx.set_zoom_factor(self.settings.zoom_factor());
+    }
+
+    /// Pause the polling of every session while the window is out of sight, in power saving mode
+    fn update_power_saving(&mut self, ctx: &egui::Context) {
+        let minimized = ctx.input(|i| i.viewport().minimized == Some(true));
+        #[cfg(target_os = "linux")]
+        let out_of_sight = minimized || self.hidden;
+        #[cfg(not(target_os = "linux"))]
+        let out_of_sight = minimized;
+        let paused = self.settings.power_saving && out_of_sight;
+        for session in self.sessions.values_mut() {
+            if let Some(headphone_ui) = session.headphone_ui.as_mut() {
+                headphone_ui.set_polling_paused(paused);
+            }
+        }
+    }
+
+    /// Give the settings edited on the settings page to every session
+    fn apply_settings(&mut self) {
+        i18n::set_language(self.settings.effective_language());
+        let developer = self.developer || self.settings.developer;
+        for session in self.sessions.values_mut() {
+            session.reconnect.enabled = self.reconnect.enabled;
+            if let Some(headphone_ui) = session.headphone_ui.as_mut() {
+                headphone_ui.set_developer_mode(developer);
+                headphone_ui.set_guessed_commands(self.settings.guessed_commands);
+                headphone_ui.set_sound_pressure_interval(self.settings.sound_pressure_interval());
+            }
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn pick_device_web(
+        &mut self,
+        ctx: &egui::Context,
+        _frame: &mut eframe::Frame,
+    ) -> Option<SerialPort> {
+        let mut picked = None;
+        egui::CentralPanel::default().show(ctx, |ui| match self.picker.get() {
+            ResourceStatus::Ready(result) => match result.as_ref() {
+                Err(e) => {
+                    ui.label(tr!("app-permissions-failed", error = e));
+                    if ui.button(tr!("retry")).clicked() {
+                        self.picker.clear();
+                    }
+                }
+                Ok(port) => {
+                    picked = Some(port.clone());
+                    // for the next headphones
+                    self.picker.clear();
+                }
+            },
             ResourceStatus::Pending => {
-                ui.label("Pick the headphones from the popup");
+                ui.label(tr!("app-pick-from-popup"));
                 ui.spinner();
             }
             ResourceStatus::NotInitialized => {
-                if ui.button("Allow connection to WF-1000XM5").clicked() {
+                if ui.button(tr!("app-allow-connection")).clicked() {
                     use eframe::wasm_bindgen::JsValue;
                     use wasm_bindgen_futures::JsFuture;
                     use web_sys::{
@@ -53,9 +977,8 @@ impl App {
                     let serial = navigator.serial();
 
                     if serial.is_undefined() {
-                        self.picker.set_resource(Err(anyhow::anyhow!(
-                            "Web Serial API is not implemented for this browser."
-                        )));
+                        self.picker
+                            .set_resource(Err(anyhow::anyhow!(tr!("app-no-web-serial"))));
                         return;
                     }
                     let options = SerialPortRequestOptions::new();
@@ -63,13 +986,14 @@ impl App {
                         r#"
                         [
                         {
-                            "bluetoothServiceClassId":  ["956c7b26-d49a-4ba8-b03f-b17d393cb6e2"] 
+                            "bluetoothServiceClassId":  ["956c7b26-d49a-4ba8-b03f-b17d393cb6e2"]
                         }
                         ]
                     "#,
                     )
                     .unwrap();
-                    options.set_filters(&filters);
+                    // set like the service ids below, since the typed setter changes between web-sys versions
+                    Reflect::set(&options, &JsValue::from_str("filters"), &filters).unwrap();
                     let uuid_array = Array::new();
                     uuid_array.push(&JsValue::from_str("956c7b26-d49a-4ba8-b03f-b17d393cb6e2"));
                     Reflect::set(
@@ -86,106 +1010,86 @@ impl App {
                             .await
                             .map(|port| port.dyn_into().unwrap())
                             .map_err(|e| {
-                                anyhow::anyhow!(
-                                    "Couldn't request port: {e:?}. Try reloading the page."
-                                )
+                                anyhow::anyhow!(tr!(
+                                    "app-request-port-failed",
+                                    error = format!("{e:?}")
+                                ))
                             })
                     });
                 }
             }
         });
+        picked
     }
 }
 impl eframe::App for App {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        if self.current_connection.is_none() {
+        #[cfg(target_os = "linux")]
+        self.update_tray(ctx);
+        #[cfg(target_os = "linux")]
+        self.update_hotkeys(ctx);
+        #[cfg(target_os = "linux")]
+        self.update_compact_window(ctx);
+        #[cfg(target_os = "linux")]
+        self.update_show_requests(ctx);
+        self.draw_tabs(ctx);
+        if !self.settings_shown
+            && let Some(session) = self.selected.and_then(|id| self.sessions.get_mut(&id))
+        {
+            status_bar::draw(ctx, &session.link_status(ctx));
+        }
+        self.update_power_saving(ctx);
+        // the sessions not shown keep their connections and automations going
+        let ids: Vec<SessionId> = self.sessions.keys().copied().collect();
+        for id in ids {
+            self.update_session(id, ctx, frame);
+        }
+        if self.settings_shown {
+            self.draw_settings(ctx);
+        } else if self.selected.is_none() {
             #[cfg(target_os = "linux")]
             {
                 self.picker.update(ctx, frame);
-                self.current_connection = self.picker.wants_connection();
-            }
-            #[cfg(target_arch = "wasm32")]
-            {
-                self.pick_device_web(ctx, frame);
-            }
-        } else {
-            let mut should_reset_connection = false;
-            match self.connection_task.get() {
-                ResourceStatus::Ready(result) => {
-                    egui::CentralPanel::default().show(ctx, |ui| {
-                        if let Err(e) = result.as_ref() {
-                            ui.label(format!("Got an error: {e}"));
-                        } else {
-                            // if it dies with Ok(()) it means the user disconnected by themselves
-                            should_reset_connection = true;
-                        }
-                        if ui.button("retry?").clicked() {
-                            self.connection_task.clear();
-                        }
-                        if ui.button("go back to device picker").clicked() {
-                            should_reset_connection = true;
-                        }
-                    });
-                }
-
-                ResourceStatus::Pending => {
-                    let headphone_ui = self.headphone_ui.as_mut().unwrap();
-                    if headphone_ui.is_connected() {
-                        headphone_ui.update(ctx, frame);
-                    } else {
-                        headphone_ui.poll_events();
-                        egui::CentralPanel::default().show(ctx, |ui| {
-                            ui.label("Connecting...");
-                            if ui.button("stop?").clicked() {
-                                should_reset_connection = true;
-                            }
-                            ui.spinner();
-                        });
-                    }
-                }
-                ResourceStatus::NotInitialized => {
-                    let (command_tx, command_rx) = mpsc::unbounded_channel();
-                    let (payload_tx, payload_rx) = mpsc::unbounded_channel();
-                    let (stop_tx, stop_rx) = mpsc::channel(1);
-                    #[cfg(not(target_arch = "wasm32"))]
-                    let device = self.current_connection.as_ref().unwrap().clone();
-                    #[cfg(target_arch = "wasm32")]
-                    let port = self.current_connection.as_ref().unwrap().clone();
-                    let ctx = ctx.clone();
-                    #[cfg(not(target_arch = "wasm32"))]
-                    self.connection_task.set(async move {
-                        tokio::task::spawn_blocking(move || {
-                            headphone_thread::thread_main(
-                                device, payload_tx, command_rx, stop_rx, ctx,
-                            )
-                        })
-                        .await?
-                    });
-                    #[cfg(target_arch = "wasm32")]
-                    self.connection_task.set(async move {
-                        headphone_thread::thread_main(port, payload_tx, command_rx, stop_rx, ctx)
-                            .await
-                    });
-                    self.headphone_ui = Some(HeadphoneUi::new(command_tx, payload_rx, stop_tx));
+                if let Some(device) = self.picker.wants_connection() {
+                    self.open_session(device);
                 }
             }
-            if should_reset_connection {
-                self.connection_task.clear();
-                self.current_connection = None;
-
-                #[cfg(target_arch = "wasm32")]
-                self.picker.clear();
+            #[cfg(target_arch = "wasm32")]
+            if let Some(port) = self.pick_device_web(ctx, frame) {
+                self.open_session(port);
             }
         }
     }
     #[cfg(not(target_arch = "wasm32"))]
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
-        // cancel the connection task and all communication to it, since it blocks up the UI on exit
-        self.connection_task.cancel();
+        // they close in order while main waits for them
+        for session in self.sessions.values() {
+            session.close();
+        }
     }
 
     #[cfg(not(target_arch = "wasm32"))]
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
         self.picker.save(storage);
+        self.pull_settings();
+        self.history_settings.save(storage);
+        self.settings.save(storage);
+        self.profiles.borrow().save(storage);
+        self.last_states.save(storage);
+        #[cfg(target_os = "linux")]
+        {
+            self.notification_settings.save(storage);
+            self.tray_settings.save(storage);
+            self.hotkey_settings.save(storage);
+            self.app_anc_settings.save(storage);
+            self.compact_window.save(storage);
+        }
+        storage.set_string(
+            FrameCapture::ENABLED_KEY,
+            self.capture_frames
+                .load(std::sync::atomic::Ordering::Relaxed)
+                .to_string(),
+        );
+        storage.set_string(Reconnect::ENABLED_KEY, self.reconnect.enabled.to_string());
     }
 }
@@ -0,0 +1,243 @@
+//! Switching the noise canceling mode by the applications using audio, e.g. ambient sound while a meeting app
+//! records from the microphone, and noise canceling while a music player plays.
+//!
+//! The audio streams come from PipeWire, through `pw-dump`, which most desktops with PipeWire have. Their
+//! application names are matched instead of the focused window, since Wayland doesn't tell other apps which
+//! window is focused.
+
+use crate::pipewire;
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+use sony_wf1000xm5::command::AncMode;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// How often the streams are looked at
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamKind {
+    /// Recording, e.g. a call
+    Microphone,
+    Playback,
+}
+
+impl std::fmt::Display for StreamKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Microphone => "records from the microphone",
+            Self::Playback => "plays audio",
+        })
+    }
+}
+
+/// An audio stream which is running
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stream {
+    pub application: String,
+    pub kind: StreamKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppAncMapping {
+    /// Matches the application names containing it, ignoring case
+    pub application: String,
+    pub kind: StreamKind,
+    pub mode: AncMode,
+}
+
+impl AppAncMapping {
+    fn matches(&self, stream: &Stream) -> bool {
+        stream.kind == self.kind
+            && stream
+                .application
+                .to_lowercase()
+                .contains(&self.application.to_lowercase())
+    }
+}
+
+/// Off by default
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AppAncSettings {
+    pub enabled: bool,
+    /// The first one matching a stream wins
+    pub mappings: Vec<AppAncMapping>,
+}
+
+impl AppAncSettings {
+    const ENABLED_KEY: &'static str = "APP_ANC_ENABLED";
+    const MAPPINGS_KEY: &'static str = "APP_ANC_MAPPINGS";
+
+    pub fn load(storage: &dyn eframe::Storage) -> Self {
+        Self {
+            enabled: storage
+                .get_string(Self::ENABLED_KEY)
+                .is_some_and(|enabled| enabled == "true"),
+            mappings: storage
+                .get_string(Self::MAPPINGS_KEY)
+                .and_then(|json| {
+                    serde_json::from_str(&json)
+                        .inspect_err(|e| log::warn!("couldn't read the application mappings: {e}"))
+                        .ok()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(Self::ENABLED_KEY, self.enabled.to_string());
+        storage.set_string(
+            Self::MAPPINGS_KEY,
+            serde_json::to_string(&self.mappings).expect("mappings are always serializable"),
+        );
+    }
+
+    /// The mode the running `streams` call for, if any
+    pub fn mode_for(&self, streams: &[Stream]) -> Option<AncMode> {
+        self.mappings
+            .iter()
+            .find(|mapping| streams.iter().any(|stream| mapping.matches(stream)))
+            .map(|mapping| mapping.mode)
+    }
+}
+
+/// The running streams in the output of `pw-dump`
+fn parse_streams(json: &str) -> Result<Vec<Stream>, serde_json::Error> {
+    let objects: Vec<serde_json::Value> = serde_json::from_str(json)?;
+    Ok(objects
+        .iter()
+        .filter(|object| object["type"] == "PipeWire:Interface:Node")
+        .filter(|object| object["info"]["state"] == "running")
+        .filter_map(|object| {
+            let props = &object["info"]["props"];
+            let kind = match props["media.class"].as_str()? {
+                "Stream/Input/Audio" => StreamKind::Microphone,
+                "Stream/Output/Audio" => StreamKind::Playback,
+                _ => return None,
+            };
+            Some(Stream {
+                application: props["application.name"].as_str()?.to_string(),
+                kind,
+            })
+        })
+        .collect())
+}
+
+async fn read_streams() -> Result<Vec<Stream>, String> {
+    parse_streams(&pipewire::pw_dump().await?)
+        .map_err(|e| format!("couldn't read the output of pw-dump: {e}"))
+}
+
+/// Looks at the streams every few seconds, until dropped
+pub struct StreamWatcher {
+    streams: watch::Receiver<Result<Vec<Stream>, String>>,
+}
+
+impl StreamWatcher {
+    pub fn spawn(ctx: &egui::Context) -> Self {
+        let (tx, streams) = watch::channel(Ok(Vec::new()));
+        let ctx = ctx.clone();
+        tokio::task::spawn_local(async move {
+            while !tx.is_closed() {
+                let result = read_streams().await;
+                let changed = tx.send_if_modified(|streams| {
+                    let changed = *streams != result;
+                    *streams = result;
+                    changed
+                });
+                if changed {
+                    // to switch even with the window hidden
+                    ctx.request_repaint();
+                }
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        });
+        Self { streams }
+    }
+
+    /// The streams, if they changed since the last call
+    pub fn changed(&mut self) -> Option<Result<Vec<Stream>, String>> {
+        self.streams
+            .has_changed()
+            .unwrap_or(false)
+            .then(|| self.streams.borrow_and_update().clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pw_dump() {
+        let json = r#"[
+            {"id": 30, "type": "PipeWire:Interface:Node", "info": {"state": "suspended",
+                "props": {"media.class": "Audio/Sink", "node.name": "bluez_output"}}},
+            {"id": 71, "type": "PipeWire:Interface:Node", "info": {"state": "running",
+                "props": {"media.class": "Stream/Output/Audio", "application.name": "Spotify"}}},
+            {"id": 72, "type": "PipeWire:Interface:Node", "info": {"state": "running",
+                "props": {"media.class": "Stream/Input/Audio", "application.name": "Zoom"}}},
+            {"id": 73, "type": "PipeWire:Interface:Node", "info": {"state": "idle",
+                "props": {"media.class": "Stream/Output/Audio", "application.name": "Firefox"}}},
+            {"id": 80, "type": "PipeWire:Interface:Link", "info": {"state": "active"}}
+        ]"#;
+        assert_eq!(
+            parse_streams(json).unwrap(),
+            [
+                Stream {
+                    application: "Spotify".to_string(),
+                    kind: StreamKind::Playback,
+                },
+                Stream {
+                    application: "Zoom".to_string(),
+                    kind: StreamKind::Microphone,
+                },
+            ]
+        );
+        assert!(parse_streams("{").is_err());
+    }
+
+    #[test]
+    fn mappings() {
+        let settings = AppAncSettings {
+            enabled: true,
+            mappings: vec![
+                AppAncMapping {
+                    application: "zoom".to_string(),
+                    kind: StreamKind::Microphone,
+                    mode: AncMode::AmbientSound,
+                },
+                AppAncMapping {
+                    application: "spotify".to_string(),
+                    kind: StreamKind::Playback,
+                    mode: AncMode::ActiveNoiseCanceling,
+                },
+            ],
+        };
+        let spotify = Stream {
+            application: "Spotify".to_string(),
+            kind: StreamKind::Playback,
+        };
+        let zoom = Stream {
+            application: "ZOOM VoiceEngine".to_string(),
+            kind: StreamKind::Microphone,
+        };
+        assert_eq!(settings.mode_for(&[]), None);
+        assert_eq!(
+            settings.mode_for(std::slice::from_ref(&spotify)),
+            Some(AncMode::ActiveNoiseCanceling)
+        );
+        // the meeting comes first
+        assert_eq!(
+            settings.mode_for(&[spotify, zoom.clone()]),
+            Some(AncMode::AmbientSound)
+        );
+        // zoom playing isn't zoom recording
+        let zoom_playback = Stream {
+            kind: StreamKind::Playback,
+            ..zoom
+        };
+        assert_eq!(settings.mode_for(&[zoom_playback]), None);
+    }
+}
@@ -0,0 +1,112 @@
+//! Starting the app at login, with an XDG autostart entry: GNOME, KDE and the other desktops start what's in
+//! `~/.config/autostart`, and systemd's xdg-autostart-generator does for the rest. The entry starts the app in
+//! the tray (`--minimized`), and it connects to the last headphones once the OS does, see
+//! [crate::device_picker::DevicePicker::connect_to_the_device_automatically_on_startup].
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+const FILE_NAME: &str = "sony-wf1000xm5-controller.desktop";
+
+/// Where the entry is, e.g. `~/.config/autostart/sony-wf1000xm5-controller.desktop`
+pub fn path() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("autostart").join(FILE_NAME))
+}
+
+pub fn is_installed() -> bool {
+    path().is_some_and(|path| path.exists())
+}
+
+/// The program to start: the AppImage rather than where it's mounted this time, or else this executable
+fn program() -> io::Result<PathBuf> {
+    match std::env::var_os("APPIMAGE") {
+        Some(appimage) => Ok(appimage.into()),
+        None => std::env::current_exe(),
+    }
+}
+
+/// The entry which starts `program` in the tray
+fn entry(program: &Path) -> String {
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name={}\n\
+         Comment=Control the Sony WF-1000XM5 from the tray\n\
+         Exec={} --minimized\n\
+         Terminal=false\n\
+         X-GNOME-Autostart-enabled=true\n",
+        crate::app::App::NAME,
+        quote_argument(&program.to_string_lossy()),
+    )
+}
+
+/// `argument` as the `Exec` key of a desktop entry takes it: quoted if it has to be, with what's special in
+/// quotes escaped, and then escaped again as the value of a key
+fn quote_argument(argument: &str) -> String {
+    const RESERVED: &[char] = &[
+        ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(',
+        ')', '`',
+    ];
+    let argument = argument.replace('%', "%%");
+    let quoted = if argument.contains(RESERVED) {
+        let mut quoted = String::from("\"");
+        for char in argument.chars() {
+            if matches!(char, '"' | '`' | '$' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(char);
+        }
+        quoted.push('"');
+        quoted
+    } else {
+        argument
+    };
+    quoted.replace('\\', "\\\\")
+}
+
+/// Start the app at login from now on
+pub fn install() -> io::Result<()> {
+    let path = path().ok_or_else(|| io::Error::other("no home directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, entry(&program()?))
+}
+
+/// Don't start the app at login anymore
+pub fn remove() -> io::Result<()> {
+    let Some(path) = path() else {
+        return Ok(());
+    };
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn desktop_entry() {
+        let entry = entry(Path::new("/usr/bin/controller-gui"));
+        assert!(entry.starts_with("[Desktop Entry]\n"));
+        assert!(entry.contains("\nExec=/usr/bin/controller-gui --minimized\n"));
+
+        assert_eq!(
+            quote_argument("/home/me/My Apps/gui"),
+            "\"/home/me/My Apps/gui\""
+        );
+        assert_eq!(
+            quote_argument("/opt/$weird/100%"),
+            r#""/opt/\\$weird/100%%""#
+        );
+    }
+}
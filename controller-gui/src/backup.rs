@@ -0,0 +1,315 @@
+use crate::share::{
+    SharedAnc, SharedConfig, SharedEqualizer, anc_mode_from_byte, anc_mode_to_byte,
+};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sony_wf1000xm5::{
+    command::{Command, EqualizerBands, EqualizerPreset, QuickAccessApp, SpeakToChatTimeout},
+    snapshot::{AncSnapshot, EqualizerSnapshot, HeadphoneSnapshot},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+/// Bumped whenever the format below changes in a way older versions can't read
+const BACKUP_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupEqualizer {
+    pub preset: u8,
+    /// `[clear bass, 400 Hz, 1000 Hz, 2500 Hz, 6300 Hz, 16000 Hz]`
+    pub levels: [i8; 6],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupAnc {
+    pub mode: u8,
+    pub ambient_sound_level: u8,
+    pub voice_passthrough: bool,
+}
+
+/// Everything we can read from the headphones and write back, saved as JSON.
+/// Settings the headphones didn't report are left out, and aren't touched on restore.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceBackup {
+    pub version: u32,
+    pub created: DateTime<Local>,
+    /// Informational only; it can't be written back
+    pub model_name: Option<String>,
+    /// Informational only; it can't be written back
+    pub firmware_version: Option<String>,
+    pub equalizer: Option<BackupEqualizer>,
+    pub anc: Option<BackupAnc>,
+    /// `(double tap, triple tap)`
+    pub quick_access: Option<(u8, u8)>,
+    pub call_voice_focus: Option<bool>,
+    pub sidetone_level: Option<u8>,
+    pub speak_to_chat_timeout: Option<u8>,
+}
+
+#[derive(Debug, Error)]
+pub enum BackupError {
+    #[error("The backup is not valid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("The backup was made by a newer version of the app (version {version})")]
+    UnsupportedVersion { version: u32 },
+    #[error("Unknown equalizer preset in the backup: 0x{preset:x}")]
+    UnknownEqualizerPreset { preset: u8 },
+    #[error("Invalid equalizer level in the backup: {level}")]
+    InvalidEqualizerLevel { level: i16 },
+    #[error("Unknown ANC mode in the backup: {mode}")]
+    UnknownAncMode { mode: u8 },
+    #[error("Unknown quick access app in the backup: 0x{app:x}")]
+    UnknownQuickAccessApp { app: u8 },
+    #[error("Invalid sidetone level in the backup: {level}")]
+    InvalidSidetoneLevel { level: u8 },
+    #[error("Unknown Speak-to-Chat timeout in the backup: {timeout}")]
+    UnknownSpeakToChatTimeout { timeout: u8 },
+    #[error("Couldn't access the backup file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl DeviceBackup {
+    pub fn from_snapshot(snapshot: &HeadphoneSnapshot) -> Self {
+        Self {
+            version: BACKUP_VERSION,
+            created: Local::now(),
+            model_name: snapshot.device_info.model_name.clone(),
+            firmware_version: snapshot.device_info.firmware_version.clone(),
+            equalizer: snapshot.equalizer.map(|eq| BackupEqualizer {
+                preset: eq.preset as u8,
+                levels: eq.bands.levels(),
+            }),
+            anc: snapshot.anc.map(|anc| BackupAnc {
+                mode: anc_mode_to_byte(anc.mode),
+                ambient_sound_level: anc.ambient_sound_level,
+                voice_passthrough: anc.ambient_sound_voice_passthrough,
+            }),
+            quick_access: snapshot
+                .quick_access
+                .map(|(double_tap, triple_tap)| (double_tap as u8, triple_tap as u8)),
+            call_voice_focus: snapshot.call_voice_focus,
+            sidetone_level: snapshot.sidetone_level,
+            speak_to_chat_timeout: snapshot.speak_to_chat_timeout.map(|timeout| timeout as u8),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a backup is always serializable")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, BackupError> {
+        let backup: Self = serde_json::from_str(json)?;
+        if backup.version > BACKUP_VERSION {
+            return Err(BackupError::UnsupportedVersion {
+                version: backup.version,
+            });
+        }
+        Ok(backup)
+    }
+
+    /// e.g. `xm5-backup-2025-10-16_14-02-00.json`
+    pub fn file_name(&self) -> String {
+        format!(
+            "xm5-backup-{}.json",
+            self.created.format("%Y-%m-%d_%H-%M-%S")
+        )
+    }
+
+    /// The commands which bring headphones in the `current` state to the backed up state.
+    /// Settings which already match are skipped.
+    pub fn restore_commands(
+        &self,
+        current: &HeadphoneSnapshot,
+    ) -> Result<Vec<Command>, BackupError> {
+        let target = self.to_snapshot(current)?;
+        let mut shared = SharedConfig::default();
+        if target.equalizer != current.equalizer
+            && let Some(eq) = target.equalizer
+        {
+            shared.equalizer = Some(SharedEqualizer {
+                preset: eq.preset,
+                bands: eq.bands,
+            });
+        }
+        if target.anc != current.anc
+            && let Some(anc) = target.anc
+        {
+            shared.anc = Some(SharedAnc {
+                mode: anc.mode,
+                ambient_sound_level: anc.ambient_sound_level as usize,
+                voice_passthrough: anc.ambient_sound_voice_passthrough,
+            });
+        }
+        let mut commands = shared.to_commands();
+        if target.quick_access != current.quick_access
+            && let Some((double_tap, triple_tap)) = target.quick_access
+        {
+            commands.push(Command::SetQuickAccess {
+                double_tap,
+                triple_tap,
+            });
+        }
+        if target.call_voice_focus != current.call_voice_focus
+            && let Some(on) = target.call_voice_focus
+        {
+            commands.push(Command::SetCallVoiceFocus { on });
+        }
+        if target.sidetone_level != current.sidetone_level
+            && let Some(level) = target.sidetone_level
+        {
+            commands.push(Command::SetSidetoneLevel { level });
+        }
+        if target.speak_to_chat_timeout != current.speak_to_chat_timeout
+            && let Some(timeout) = target.speak_to_chat_timeout
+        {
+            commands.push(Command::SetSpeakToChatTimeout { timeout });
+        }
+        Ok(commands)
+    }
+
+    /// `current` with the backed up settings applied
+    fn to_snapshot(&self, current: &HeadphoneSnapshot) -> Result<HeadphoneSnapshot, BackupError> {
+        let mut snapshot = current.clone();
+        if let Some(eq) = self.equalizer {
+            snapshot.equalizer = Some(EqualizerSnapshot {
+                preset: EqualizerPreset::from_byte(eq.preset)
+                    .ok_or(BackupError::UnknownEqualizerPreset { preset: eq.preset })?,
+                bands: EqualizerBands::from_levels(eq.levels)
+                    .map_err(|e| BackupError::InvalidEqualizerLevel { level: e.level })?,
+            });
+        }
+        if let Some(anc) = self.anc {
+            snapshot.anc = Some(AncSnapshot {
+                mode: anc_mode_from_byte(anc.mode)
+                    .ok_or(BackupError::UnknownAncMode { mode: anc.mode })?,
+                ambient_sound_voice_passthrough: anc.voice_passthrough,
+                ambient_sound_level: anc.ambient_sound_level,
+            });
+        }
+        if let Some((double_tap, triple_tap)) = self.quick_access {
+            let app = |app| {
+                QuickAccessApp::from_byte(app).ok_or(BackupError::UnknownQuickAccessApp { app })
+            };
+            snapshot.quick_access = Some((app(double_tap)?, app(triple_tap)?));
+        }
+        if self.call_voice_focus.is_some() {
+            snapshot.call_voice_focus = self.call_voice_focus;
+        }
+        if let Some(level) = self.sidetone_level {
+            if level > Command::MAX_SIDETONE_LEVEL {
+                return Err(BackupError::InvalidSidetoneLevel { level });
+            }
+            snapshot.sidetone_level = Some(level);
+        }
+        if let Some(timeout) = self.speak_to_chat_timeout {
+            snapshot.speak_to_chat_timeout = Some(
+                SpeakToChatTimeout::from_byte(timeout)
+                    .ok_or(BackupError::UnknownSpeakToChatTimeout { timeout })?,
+            );
+        }
+        Ok(snapshot)
+    }
+
+    /// Save the backup in `dir`, returning the path of the file
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, BackupError> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(self.file_name());
+        fs::write(&path, self.to_json())?;
+        Ok(path)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &Path) -> Result<Self, BackupError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+/// The backups in `dir`, newest first
+#[cfg(not(target_arch = "wasm32"))]
+pub fn list_backups(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut backups: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    // the timestamp in the name sorts chronologically
+    backups.sort();
+    backups.reverse();
+    backups
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::command::AncMode;
+
+    fn snapshot() -> HeadphoneSnapshot {
+        HeadphoneSnapshot {
+            equalizer: Some(EqualizerSnapshot {
+                preset: EqualizerPreset::Custom1,
+                bands: EqualizerBands::new(1, 2, 3, 4, 5, 6).unwrap(),
+            }),
+            anc: Some(AncSnapshot {
+                mode: AncMode::AmbientSound,
+                ambient_sound_voice_passthrough: true,
+                ambient_sound_level: 12,
+            }),
+            quick_access: Some((QuickAccessApp::Spotify, QuickAccessApp::None)),
+            call_voice_focus: Some(true),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let backup = DeviceBackup::from_snapshot(&snapshot());
+        let restored = DeviceBackup::from_json(&backup.to_json()).unwrap();
+        assert_eq!(restored, backup);
+        // nothing to do on the same headphones
+        assert!(restored.restore_commands(&snapshot()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn restore_only_what_changed() {
+        let backup = DeviceBackup::from_snapshot(&snapshot());
+        let mut current = snapshot();
+        current.call_voice_focus = Some(false);
+        current.quick_access = None;
+        let commands = backup.restore_commands(&current).unwrap();
+        assert_eq!(commands.len(), 2);
+        assert!(matches!(
+            commands[0],
+            Command::SetQuickAccess {
+                double_tap: QuickAccessApp::Spotify,
+                triple_tap: QuickAccessApp::None
+            }
+        ));
+        assert!(matches!(
+            commands[1],
+            Command::SetCallVoiceFocus { on: true }
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_backups() {
+        let mut backup = DeviceBackup::from_snapshot(&snapshot());
+        backup.version = BACKUP_VERSION + 1;
+        assert!(matches!(
+            DeviceBackup::from_json(&backup.to_json()),
+            Err(BackupError::UnsupportedVersion { .. })
+        ));
+        backup.version = BACKUP_VERSION;
+        backup.equalizer.as_mut().unwrap().levels[0] = 11;
+        assert!(matches!(
+            backup.restore_commands(&HeadphoneSnapshot::default()),
+            Err(BackupError::InvalidEqualizerLevel { level: 11 })
+        ));
+    }
+}
@@ -0,0 +1,357 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use chrono::{DateTime, Local, TimeDelta};
+use sony_wf1000xm5::{
+    command::Command,
+    command_queue::QueueError,
+    connection::{ConnectionHandle, RequestError},
+    payload::Payload,
+    snapshot::{SnapshotChange, SnapshotField},
+};
+
+/// A change which arrives this long after we sent a command for the same field is considered ours
+const OWN_CHANGE_WINDOW: TimeDelta = TimeDelta::seconds(3);
+/// A change we sent which the headphones didn't report back within this long didn't take
+const CONFIRM_TIMEOUT: TimeDelta = TimeDelta::seconds(10);
+/// An external change this long after we changed the same field overwrote our change
+const OVERWRITE_WINDOW: TimeDelta = TimeDelta::seconds(15);
+/// How long a section shows that another controller changed its setting
+const EXTERNAL_CHANGE_SHOWN: TimeDelta = TimeDelta::seconds(60);
+
+/// Sends commands to the connection task, remembering which fields we changed and when,
+/// so changes can be told apart from external ones, and which changes the headphones didn't confirm yet.
+///
+/// In read-only mode, the connection's queue refuses the commands which would change anything on the headphones,
+/// so no part of the UI can get one through, not even with [Self::sender].
+pub struct CommandSender {
+    tx: ConnectionHandle,
+    changed_by_us: RefCell<HashMap<SnapshotField, DateTime<Local>>>,
+    /// When we last changed each field the headphones didn't report since
+    pending: RefCell<HashMap<SnapshotField, DateTime<Local>>>,
+    /// When something else last changed each field, until we change it again
+    changed_externally: RefCell<HashMap<SnapshotField, DateTime<Local>>>,
+    /// Changes which didn't fit in the queue, until the UI takes them
+    refused: RefCell<Vec<Command>>,
+}
+
+impl CommandSender {
+    pub fn new(tx: ConnectionHandle, read_only: bool) -> Self {
+        tx.set_read_only(read_only);
+        Self {
+            tx,
+            changed_by_us: RefCell::new(HashMap::new()),
+            pending: RefCell::new(HashMap::new()),
+            changed_externally: RefCell::new(HashMap::new()),
+            refused: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.tx.is_read_only()
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.tx.set_read_only(read_only);
+    }
+
+    /// Send `command`, unless it's a write in read-only mode. A change the queue is too full for is left for
+    /// [Self::take_refused], and a closed connection shows in [Self::is_closed], for the UI to handle both.
+    pub fn send(&self, command: Command) {
+        match self.tx.send(command.clone().into()) {
+            Ok(()) => self.remember(&command),
+            Err(QueueError::ReadOnly(_)) => log::warn!("read-only mode: not sending {command:?}"),
+            Err(QueueError::Full(_)) => {
+                log::warn!("the queue is full; not sending {command:?}");
+                self.refused.borrow_mut().push(command);
+            }
+            Err(QueueError::Closed(_)) => {
+                log::warn!("the connection is closed; not sending {command:?}");
+            }
+        }
+    }
+
+    /// Have the connection send `command` every `interval`, or stop with `None`, see
+    /// [ConnectionHandle::set_periodic]. Only for reads, which read-only mode lets through.
+    pub fn set_periodic(&self, command: Command, interval: Option<std::time::Duration>) {
+        debug_assert!(
+            !command.is_write(),
+            "{command:?} would change something periodically"
+        );
+        if let Err(e) = self.tx.set_periodic(command, interval) {
+            log::debug!("not changing the periodic commands: {e}");
+        }
+    }
+
+    /// Whether the connection task ended, see [ConnectionHandle::is_closed]
+    pub fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+
+    /// Ask the connection to close, see [ConnectionHandle::stop]
+    pub fn stop(&self) -> Result<(), RequestError> {
+        self.tx.stop()
+    }
+
+    /// The changes which weren't sent since the queue was full, see [sony_wf1000xm5::command_queue]
+    pub fn take_refused(&self) -> Vec<Command> {
+        std::mem::take(&mut self.refused.borrow_mut())
+    }
+
+    /// Send `command` and wait for its reply, see [ConnectionHandle::request].
+    /// The returned future doesn't borrow the sender, so it can be spawned.
+    pub fn request(
+        &self,
+        command: Command,
+        timeout: std::time::Duration,
+    ) -> impl Future<Output = Result<Option<Payload>, RequestError>> + 'static {
+        if self.tx.allows(&command) {
+            self.remember(&command);
+        }
+        self.tx.request(command, timeout)
+    }
+
+    /// Remember the field `command` changes, which was queued
+    fn remember(&self, command: &Command) {
+        if let Some(field) = SnapshotField::changed_by(command) {
+            let now = Local::now();
+            self.changed_by_us.borrow_mut().insert(field, now);
+            self.pending.borrow_mut().insert(field, now);
+            self.changed_externally.borrow_mut().remove(&field);
+        }
+    }
+
+    /// Whether we changed `field` and the headphones didn't confirm it yet
+    pub fn is_pending(&self, field: SnapshotField) -> bool {
+        self.pending.borrow().contains_key(&field)
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.borrow().is_empty()
+    }
+
+    /// The headphones sent `payload`, which confirms the change to the field it reports
+    pub fn confirm(&self, payload: &Payload) {
+        if let Some(field) = SnapshotField::reported_by(payload) {
+            self.pending.borrow_mut().remove(&field);
+        }
+    }
+
+    /// The fields whose changes weren't confirmed in time, which are no longer pending
+    pub fn take_unconfirmed(&self, now: DateTime<Local>) -> Vec<SnapshotField> {
+        let mut unconfirmed = Vec::new();
+        self.pending.borrow_mut().retain(|field, sent| {
+            let expired = now - *sent > CONFIRM_TIMEOUT;
+            if expired {
+                unconfirmed.push(*field);
+            }
+            !expired
+        });
+        unconfirmed
+    }
+
+    /// Another controller changed `field` at `time`, see [Self::is_ours]
+    pub fn record_external(&self, field: SnapshotField, time: DateTime<Local>) {
+        self.changed_externally.borrow_mut().insert(field, time);
+    }
+
+    /// When another controller changed `field`, if it did recently and we didn't change it since
+    pub fn changed_externally(
+        &self,
+        field: SnapshotField,
+        now: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        self.changed_externally
+            .borrow()
+            .get(&field)
+            .copied()
+            .filter(|time| now - *time <= EXTERNAL_CHANGE_SHOWN)
+    }
+
+    /// The raw sender, for tasks which only poll the headphones
+    pub fn sender(&self) -> ConnectionHandle {
+        self.tx.clone()
+    }
+
+    /// Whether `change` was (likely) caused by a command we sent
+    pub fn is_ours(&self, change: &SnapshotChange, time: DateTime<Local>) -> bool {
+        self.changed_by_us
+            .borrow()
+            .get(&change.field)
+            .is_some_and(|sent| time - *sent <= OWN_CHANGE_WINDOW)
+    }
+
+    /// Whether the external `change` undid a change we made shortly before
+    pub fn is_overwrite(&self, change: &SnapshotChange, time: DateTime<Local>) -> bool {
+        self.changed_by_us
+            .borrow()
+            .get(&change.field)
+            .is_some_and(|sent| time - *sent <= OVERWRITE_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::{
+        command::AncMode,
+        command_queue::CAPACITY,
+        connection::{Commands, Request},
+    };
+
+    /// A connection whose task takes nothing from the queue
+    fn connection(capacity: usize) -> (ConnectionHandle, Commands) {
+        ConnectionHandle::new(capacity)
+    }
+
+    fn anc_change() -> SnapshotChange {
+        SnapshotChange {
+            field: SnapshotField::Anc,
+            old: Some("Ambient (level 10)".to_string()),
+            new: Some("Off".to_string()),
+        }
+    }
+
+    #[test]
+    fn attribution() {
+        let (tx, _rx) = connection(CAPACITY);
+        let sender = CommandSender::new(tx, false);
+        let now = Local::now();
+        assert!(!sender.is_ours(&anc_change(), now));
+        sender.send(Command::AncSet {
+            dragging_ambient_sound_slider: false,
+            mode: AncMode::Off,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: 0,
+        });
+        assert!(sender.is_ours(&anc_change(), Local::now()));
+        assert!(!sender.is_ours(&anc_change(), Local::now() + TimeDelta::minutes(1)));
+    }
+
+    #[test]
+    fn confirmation() {
+        let (tx, _rx) = connection(CAPACITY);
+        let sender = CommandSender::new(tx, false);
+        let off = Command::AncSet {
+            dragging_ambient_sound_slider: false,
+            mode: AncMode::Off,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: 0,
+        };
+        sender.send(off.clone());
+        assert!(sender.is_pending(SnapshotField::Anc));
+        assert!(sender.take_unconfirmed(Local::now()).is_empty());
+        sender.confirm(&Payload::AncStatus {
+            mode: AncMode::Off,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: 0,
+        });
+        assert!(!sender.has_pending());
+
+        sender.send(off);
+        // other payloads don't confirm it
+        sender.confirm(&Payload::InitReply);
+        assert_eq!(
+            sender.take_unconfirmed(Local::now() + TimeDelta::minutes(1)),
+            vec![SnapshotField::Anc]
+        );
+        assert!(!sender.is_pending(SnapshotField::Anc));
+
+        // nothing is sent in read-only mode, so nothing waits for confirmation
+        let (tx, _rx) = connection(CAPACITY);
+        let read_only = CommandSender::new(tx, true);
+        read_only.send(Command::SetCallVoiceFocus { on: true });
+        assert!(!read_only.has_pending());
+    }
+
+    #[test]
+    fn read_only() {
+        let (tx, mut rx) = connection(CAPACITY);
+        let mut sender = CommandSender::new(tx, true);
+        sender.send(Command::SetCallVoiceFocus { on: true });
+        sender.send(Command::GetCallVoiceFocus);
+        assert!(matches!(
+            rx.try_recv(),
+            Some(Request {
+                command: Command::GetCallVoiceFocus,
+                ..
+            })
+        ));
+        assert!(rx.try_recv().is_none());
+        assert!(sender.changed_by_us.borrow().is_empty());
+        // nor through the connection's handle, like the UI's tasks use
+        assert!(matches!(
+            sender
+                .sender()
+                .send(Command::SetCallVoiceFocus { on: true }.into()),
+            Err(QueueError::ReadOnly(_))
+        ));
+
+        sender.set_read_only(false);
+        sender.send(Command::SetCallVoiceFocus { on: true });
+        assert!(matches!(
+            rx.try_recv(),
+            Some(Request {
+                command: Command::SetCallVoiceFocus { on: true },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn refused() {
+        let (tx, _rx) = connection(1);
+        let sender = CommandSender::new(tx, false);
+        sender.send(Command::SetCallVoiceFocus { on: true });
+        sender.send(Command::SetSidetoneLevel { level: 3 });
+        assert_eq!(
+            sender.take_refused(),
+            [Command::SetSidetoneLevel { level: 3 }]
+        );
+        assert!(sender.take_refused().is_empty());
+        // nothing waits for a confirmation of what wasn't sent
+        assert!(sender.is_pending(SnapshotField::CallVoiceFocus));
+        assert!(!sender.is_pending(SnapshotField::SidetoneLevel));
+    }
+
+    #[test]
+    fn closed() {
+        let (connection, rx) = connection(CAPACITY);
+        let sender = CommandSender::new(connection, false);
+        assert!(!sender.is_closed());
+        drop(rx);
+        // the UI keeps going, and finds out from is_closed
+        sender.send(Command::SetCallVoiceFocus { on: true });
+        assert!(sender.is_closed());
+        assert!(!sender.has_pending());
+        assert!(sender.take_refused().is_empty());
+    }
+
+    #[test]
+    fn external_changes() {
+        let (tx, _rx) = connection(CAPACITY);
+        let sender = CommandSender::new(tx, false);
+        let start = Local::now();
+        sender.record_external(SnapshotField::Anc, start);
+        assert_eq!(
+            sender.changed_externally(SnapshotField::Anc, start + TimeDelta::seconds(5)),
+            Some(start)
+        );
+        assert_eq!(
+            sender.changed_externally(SnapshotField::Equalizer, start),
+            None
+        );
+        assert_eq!(
+            sender.changed_externally(SnapshotField::Anc, start + TimeDelta::minutes(2)),
+            None
+        );
+
+        // changing it ourselves takes the indicator away
+        sender.send(Command::AncSet {
+            dragging_ambient_sound_slider: false,
+            mode: AncMode::Off,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: 0,
+        });
+        assert_eq!(sender.changed_externally(SnapshotField::Anc, start), None);
+    }
+}
@@ -0,0 +1,139 @@
+//! The compact window: the batteries and the noise canceling mode of the headphones the tray controls, in a
+//! small frameless window which stays on top, for keeping in a corner of the screen. It's a second egui
+//! viewport; the main window is minimized while it's shown, and comes back with the compact window's button.
+
+use crate::{tr, tray::battery_summary};
+use eframe::egui::{self, ViewportBuilder, ViewportClass, ViewportCommand, ViewportId};
+use sony_wf1000xm5::{command::AncMode, snapshot::HeadphoneSnapshot};
+
+const ANC_MODES: [AncMode; 3] = [
+    AncMode::ActiveNoiseCanceling,
+    AncMode::AmbientSound,
+    AncMode::Off,
+];
+
+/// What was clicked in the compact window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactAction {
+    SetAnc(AncMode),
+    /// Back to the main window
+    Expand,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactWindow {
+    /// Instead of the main window
+    pub shown: bool,
+}
+
+impl CompactWindow {
+    const SHOWN_KEY: &'static str = "COMPACT_WINDOW";
+    const SIZE: [f32; 2] = [240.0, 64.0];
+
+    pub fn load(storage: &dyn eframe::Storage) -> Self {
+        Self {
+            shown: storage
+                .get_string(Self::SHOWN_KEY)
+                .is_some_and(|shown| shown == "true"),
+        }
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(Self::SHOWN_KEY, self.shown.to_string());
+    }
+
+    /// Draw the window for this frame, with the headphones of `snapshot` or none connected
+    pub fn show(
+        &self,
+        ctx: &egui::Context,
+        snapshot: Option<&HeadphoneSnapshot>,
+    ) -> Option<CompactAction> {
+        let builder = ViewportBuilder::default()
+            .with_title(tr!("compact-title"))
+            .with_inner_size(Self::SIZE)
+            .with_decorations(false)
+            .with_resizable(false)
+            .with_always_on_top();
+        ctx.show_viewport_immediate(
+            ViewportId::from_hash_of("compact window"),
+            builder,
+            |ctx, class| {
+                // e.g. closed with the keyboard, which only takes it back to the main window
+                let mut action = ctx
+                    .input(|i| i.viewport().close_requested())
+                    .then_some(CompactAction::Expand);
+                let mut contents = |ui: &mut egui::Ui| {
+                    if let Some(clicked) = draw(ui, snapshot) {
+                        action = Some(clicked);
+                    }
+                };
+                match class {
+                    // the backend can't open more windows, so it's shown in the main one
+                    ViewportClass::Embedded => {
+                        egui::Window::new(tr!("compact-title"))
+                            .resizable(false)
+                            .show(ctx, |ui| contents(ui));
+                    }
+                    _ => {
+                        egui::CentralPanel::default().show(ctx, |ui| {
+                            // it has no title bar, so it's moved by dragging anywhere but the buttons
+                            let background = ui.interact(
+                                ui.max_rect(),
+                                ui.id().with("drag"),
+                                egui::Sense::drag(),
+                            );
+                            if background.drag_started() {
+                                ctx.send_viewport_cmd(ViewportCommand::StartDrag);
+                            }
+                            contents(ui);
+                        });
+                    }
+                }
+                action
+            },
+        )
+    }
+}
+
+fn anc_label(mode: AncMode) -> String {
+    match mode {
+        AncMode::ActiveNoiseCanceling => tr!("compact-noise-canceling"),
+        AncMode::AmbientSound => tr!("compact-ambient-sound"),
+        AncMode::Off => tr!("compact-anc-off"),
+    }
+}
+
+fn draw(ui: &mut egui::Ui, snapshot: Option<&HeadphoneSnapshot>) -> Option<CompactAction> {
+    let mut action = None;
+    ui.horizontal(|ui| {
+        let battery = snapshot.map(battery_summary);
+        ui.label(match battery.as_deref() {
+            None => tr!("compact-not-connected"),
+            Some("") => tr!("compact-connected"),
+            Some(battery) => battery.to_string(),
+        });
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            if ui
+                .small_button("⤢")
+                .on_hover_text(tr!("compact-expand"))
+                .clicked()
+            {
+                action = Some(CompactAction::Expand);
+            }
+        });
+    });
+    if let Some(snapshot) = snapshot {
+        let current = snapshot.anc.map(|anc| anc.mode);
+        ui.horizontal(|ui| {
+            for mode in ANC_MODES {
+                if ui
+                    .selectable_label(current == Some(mode), anc_label(mode))
+                    .clicked()
+                {
+                    action = Some(CompactAction::SetAnc(mode));
+                }
+            }
+        });
+    }
+    action
+}
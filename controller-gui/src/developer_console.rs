@@ -0,0 +1,138 @@
+//! A console for reverse engineering new features: send a payload typed in as hex, and watch the answer in the
+//! [crate::protocol_log]. Hidden unless turned on in the settings (or with `--developer` for one run), since a
+//! payload nobody knows can change anything on the headphones.
+
+use eframe::egui::{self, RichText, Ui};
+use sony_wf1000xm5::{
+    MessageType,
+    command::{Command, build_command},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("\"{0}\" isn't hex bytes, e.g. 12 02, 0x12 0x02 or 1202")]
+pub struct ParseHexError(String);
+
+/// Bytes typed in as hex, separated by spaces or commas or not at all, with or without `0x`
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, ParseHexError> {
+    let mut bytes = Vec::new();
+    for word in text
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|word| !word.is_empty())
+    {
+        let digits = word
+            .strip_prefix("0x")
+            .or_else(|| word.strip_prefix("0X"))
+            .unwrap_or(word);
+        if digits.is_empty() || digits.len() % 2 != 0 || !digits.is_ascii() {
+            return Err(ParseHexError(word.to_string()));
+        }
+        for pair in digits.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).expect("ascii");
+            bytes.push(u8::from_str_radix(pair, 16).map_err(|_| ParseHexError(word.to_string()))?);
+        }
+    }
+    Ok(bytes)
+}
+
+/// e.g. `3e 0c 00`
+fn spaced_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub struct DeveloperConsole {
+    input: String,
+    message_type: MessageType,
+}
+
+impl Default for DeveloperConsole {
+    fn default() -> Self {
+        Self {
+            input: String::new(),
+            message_type: MessageType::Command1,
+        }
+    }
+}
+
+impl DeveloperConsole {
+    /// Returns the command to send, once it was sent from the console
+    pub fn draw(&mut self, ui: &mut Ui, writable: bool) -> Option<Command> {
+        let mut send = None;
+        ui.label(
+            "Send a payload (the payload type first, then its data) and watch what the headphones answer under \"Protocol log\". \
+             Unknown payloads can change anything on the headphones, even their pairings.",
+        );
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("raw message type")
+                .selected_text(format!("{:?}", self.message_type))
+                .show_ui(ui, |ui| {
+                    for message_type in [MessageType::Command1, MessageType::Command2] {
+                        ui.selectable_value(
+                            &mut self.message_type,
+                            message_type,
+                            format!("{message_type:?}"),
+                        );
+                    }
+                });
+            ui.add(
+                egui::TextEdit::singleline(&mut self.input)
+                    .font(egui::TextStyle::Monospace)
+                    .hint_text("e.g. 12 02 (get the codec)"),
+            );
+        });
+        let command = parse_hex(&self.input)
+            .map_err(|e| e.to_string())
+            .and_then(|payload| {
+                let command = Command::Raw {
+                    message_type: self.message_type,
+                    payload,
+                };
+                // the session puts in the actual sequence number
+                let framed = build_command(&command, 0).map_err(|e| e.to_string())?;
+                Ok((command, framed))
+            });
+        match command {
+            Ok((command, framed)) => {
+                ui.horizontal(|ui| {
+                    ui.label("framed:");
+                    ui.label(RichText::new(spaced_hex(&framed)).monospace())
+                        .on_hover_text("with sequence number 0; the session puts in its own");
+                });
+                if ui
+                    .add_enabled(writable, egui::Button::new("send"))
+                    .on_disabled_hover_text("read-only")
+                    .clicked()
+                {
+                    send = Some(command);
+                }
+            }
+            Err(e) if !self.input.trim().is_empty() => {
+                ui.label(RichText::new(e).color(egui::Color32::YELLOW));
+            }
+            Err(_) => (),
+        }
+
+        send
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse_hex("12 02"), Ok(vec![0x12, 0x02]));
+        assert_eq!(parse_hex("0x58,0x03, 0X01"), Ok(vec![0x58, 0x03, 0x01]));
+        assert_eq!(parse_hex("5803 01"), Ok(vec![0x58, 0x03, 0x01]));
+        assert_eq!(parse_hex("  "), Ok(vec![]));
+        assert!(parse_hex("123").is_err());
+        assert!(parse_hex("0x").is_err());
+        assert!(parse_hex("zz").is_err());
+        assert!(parse_hex("é1").is_err());
+    }
+}
@@ -1,24 +1,114 @@
 use crate::async_resource::AsyncResource;
 use crate::async_resource::ResourceStatus;
-use bluer::{Adapter, AdapterEvent, Device, Session};
-use eframe::egui::{self, Context, ScrollArea, Ui};
+use crate::headphone_thread::SONY_SERVICE_UUID;
+use crate::pairing::{self, PairingPrompt, PairingRequest};
+use crate::tr;
+use bluer::{Adapter, AdapterEvent, Address, Device, DeviceEvent, DeviceProperty, Session, Uuid};
+use eframe::egui::{self, Context, RichText, ScrollArea, Ui};
 use futures::StreamExt;
 use futures::pin_mut;
+use sony_wf1000xm5::model::Model;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// The audio profiles headphones have: A2DP sink, headset and hands-free
+const AUDIO_UUIDS: [Uuid; 3] = [
+    Uuid::from_u128(0x0000110B_0000_1000_8000_00805F9B34FB),
+    Uuid::from_u128(0x00001108_0000_1000_8000_00805F9B34FB),
+    Uuid::from_u128(0x0000111E_0000_1000_8000_00805F9B34FB),
+];
+/// The major device class of audio and video devices, in bits 8 to 12 of the class of device
+const AUDIO_VIDEO_MAJOR_CLASS: u32 = 0x04;
+
+/// How likely a device is to be headphones we can control, the likeliest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Relevance {
+    /// Has the Sony service, or the name of a model we know
+    Sony,
+    Audio,
+    Other,
+}
+
+impl Relevance {
+    /// From what BlueZ knows of the device without connecting: the UUIDs it advertised or were found
+    /// over SDP when pairing, and its class
+    fn of(name: &str, class: Option<u32>, uuids: Option<&HashSet<Uuid>>) -> Self {
+        let sony_name = Model::from_name(name).is_some()
+            || ["WF-", "WH-", "LinkBuds"]
+                .iter()
+                .any(|prefix| name.trim().starts_with(prefix));
+        if sony_name || uuids.is_some_and(|uuids| uuids.contains(&SONY_SERVICE_UUID)) {
+            Self::Sony
+        } else if class.is_some_and(|class| (class >> 8) & 0x1f == AUDIO_VIDEO_MAJOR_CLASS)
+            || uuids.is_some_and(|uuids| AUDIO_UUIDS.iter().any(|uuid| uuids.contains(uuid)))
+        {
+            Self::Audio
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// A device BlueZ knew already, or found by the discovery
+struct Candidate {
+    device: Device,
+    relevance: Relevance,
+    /// The signal strength when it was found, in dBm; known devices which don't advertise have none
+    rssi: Option<i16>,
+    paired: bool,
+    connected: bool,
+    /// BlueZ knew it before searching, so a new search keeps it
+    known: bool,
+}
+
+impl Candidate {
+    async fn new(device: Device, name: &str, known: bool) -> bluer::Result<Self> {
+        let uuids = device.uuids().await?;
+        let relevance = Relevance::of(name, device.class().await?, uuids.as_ref());
+        Ok(Self {
+            rssi: device.rssi().await?,
+            paired: device.is_paired().await?,
+            connected: device.is_connected().await?,
+            device,
+            relevance,
+            known,
+        })
+    }
+}
 
 // Might get more info in the future
 struct BtInfo {
     is_powered: bool,
 }
 
+#[derive(Default)]
+struct PairingState {
+    task: AsyncResource<bluer::Result<Device>>,
+    prompts: Option<mpsc::UnboundedReceiver<PairingPrompt>>,
+    /// The prompt waiting for an answer, or the code to type in on the headphones
+    prompt: Option<PairingPrompt>,
+    /// The code typed in for the prompt
+    input: String,
+}
+
 #[derive(Default)]
 pub struct DevicePicker {
     bt_info: AsyncResource<bluer::Result<BtInfo>>,
-    bt_devices: Rc<RefCell<HashMap<String, Device>>>,
+    /// By name
+    bt_devices: Rc<RefCell<HashMap<String, Candidate>>>,
+    /// Show the devices which don't look like audio devices as well
+    show_all_devices: bool,
+    pairing: PairingState,
+    known_devices_task: AsyncResource<bluer::Result<()>>,
+    /// Discovery was asked for, by the user or because none of the known devices are headphones
+    search: bool,
     bt_devices_task: AsyncResource<anyhow::Result<()>>,
+    last_device_watch_task: AsyncResource<anyhow::Result<()>>,
+    /// Set by the watch task once the OS connects to the last device
+    connected_last_device: Rc<RefCell<Option<Device>>>,
     adapter: Rc<RefCell<Option<Adapter>>>,
     device: String,
     device_addr: String,
@@ -48,17 +138,64 @@ impl DevicePicker {
         self.bt_devices_task.set_resource(Ok(()));
     }
 
+    /// List the devices BlueZ knows already (paired, or seen before) with their names and whether they're
+    /// connected. Paired headphones usually don't advertise, so discovery wouldn't find them. Once listed, tells
+    /// whether to search anyway, because none of the known devices are headphones.
+    fn start_known_devices_task(&self, ctx: &Context, ui: &mut Ui) -> bool {
+        match self.known_devices_task.get() {
+            ResourceStatus::Ready(result) => {
+                if let Err(e) = result.as_ref() {
+                    ui.label(tr!("picker-known-failed", error = e));
+                    if ui.button(tr!("retry")).clicked() {
+                        self.known_devices_task.clear();
+                    }
+                }
+                !self
+                    .bt_devices
+                    .borrow()
+                    .values()
+                    .any(|candidate| candidate.relevance == Relevance::Sony)
+            }
+
+            ResourceStatus::Pending => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(tr!("picker-listing-known"));
+                });
+                false
+            }
+
+            ResourceStatus::NotInitialized => {
+                let adapter = self.adapter.borrow().clone().unwrap();
+                let map = self.bt_devices.clone();
+                let ctx = ctx.clone();
+                self.known_devices_task.set(async move {
+                    for addr in adapter.device_addresses().await? {
+                        let device = adapter.device(addr)?;
+                        if let Some(name) = device.name().await? {
+                            let candidate = Candidate::new(device, &name, true).await?;
+                            map.borrow_mut().insert(name, candidate);
+                        }
+                    }
+                    ctx.request_repaint();
+                    Ok(())
+                });
+                false
+            }
+        }
+    }
+
     fn start_device_discovery_task(&self, ctx: &Context, ui: &mut Ui) {
         match self.bt_devices_task.get() {
             ResourceStatus::Ready(result) => {
                 if let Err(e) = result.as_ref() {
-                    ui.label(format!("error while discovering devices: {e}"));
-                    if ui.button("retry?").clicked() {
+                    ui.label(tr!("picker-discovery-failed", error = e));
+                    if ui.button(tr!("retry")).clicked() {
                         self.bt_devices_task.clear();
                     }
                 } else {
-                    ui.label("Search done.");
-                    if ui.button("Search again?").clicked() {
+                    ui.label(tr!("picker-search-done"));
+                    if ui.button(tr!("picker-search-again")).clicked() {
                         self.bt_devices_task.clear();
                     }
                 }
@@ -66,8 +203,8 @@ impl DevicePicker {
 
             ResourceStatus::Pending => {
                 ui.horizontal(|ui| {
-                    ui.label("Searching devices...");
-                    if ui.button("Stop searching?").clicked() {
+                    ui.label(tr!("picker-searching"));
+                    if ui.button(tr!("picker-stop-searching")).clicked() {
                         self.stop_discovery_task();
                     }
                 });
@@ -77,8 +214,10 @@ impl DevicePicker {
             ResourceStatus::NotInitialized => {
                 {
                     let adapter = self.adapter.borrow().clone().unwrap();
-                    // clear the map if we have something in it
-                    self.bt_devices.take();
+                    // forget what the last search found, but not the known devices
+                    self.bt_devices
+                        .borrow_mut()
+                        .retain(|_, candidate| candidate.known);
                     let map = self.bt_devices.clone();
                     let ctx = ctx.clone();
                     let timeout = Duration::from_secs(30);
@@ -91,14 +230,25 @@ impl DevicePicker {
                                     AdapterEvent::DeviceAdded(addr) => {
                                         let device = adapter.device(addr)?;
                                         if let Some(name) = device.name().await? {
-                                            map.borrow_mut().insert(name, device);
+                                            let known = map
+                                                .borrow()
+                                                .get(&name)
+                                                .is_some_and(|candidate| candidate.known);
+                                            let candidate =
+                                                Candidate::new(device, &name, known).await?;
+                                            map.borrow_mut().insert(name, candidate);
                                             ctx.request_repaint();
                                         }
                                     }
 
                                     AdapterEvent::DeviceRemoved(addr) => {
                                         let device = adapter.device(addr)?;
-                                        if let Some(name) = device.name().await? {
+                                        if let Some(name) = device.name().await?
+                                            && map
+                                                .borrow()
+                                                .get(&name)
+                                                .is_some_and(|candidate| !candidate.known)
+                                        {
                                             map.borrow_mut().remove(&name);
                                             ctx.request_repaint();
                                         }
@@ -119,28 +269,243 @@ impl DevicePicker {
         }
     }
 
+    /// Wait for the OS to connect to the last device (e.g. when the case is opened) and connect to it as soon as it does.
+    /// This way we don't need to wait for discovery to find it.
+    fn start_last_device_watch_task(&self, ctx: &Context, ui: &mut Ui) {
+        if !self.connect_to_the_device_automatically_on_startup
+            || self.tried_connecting_to_last_device
+        {
+            return;
+        }
+        let Some(addr) = self.last_connected_addr().cloned() else {
+            return;
+        };
+        match self.last_device_watch_task.get() {
+            ResourceStatus::Ready(result) => {
+                if let Err(e) = result.as_ref() {
+                    log::warn!("couldn't watch the last device: {e}");
+                }
+            }
+
+            ResourceStatus::Pending => {
+                ui.label(tr!("picker-waiting-for-last", address = addr));
+            }
+
+            ResourceStatus::NotInitialized => {
+                let adapter = self.adapter.borrow().clone().unwrap();
+                let connected_last_device = self.connected_last_device.clone();
+                let ctx = ctx.clone();
+                self.last_device_watch_task.set(async move {
+                    let addr: Address = addr.parse()?;
+                    let device = adapter.device(addr)?;
+                    if !device.is_connected().await? {
+                        let events = device.events().await?;
+                        pin_mut!(events);
+                        loop {
+                            match events.next().await {
+                                Some(DeviceEvent::PropertyChanged(DeviceProperty::Connected(
+                                    true,
+                                ))) => break,
+                                Some(_) => (),
+                                None => return Ok(()),
+                            }
+                        }
+                    }
+                    *connected_last_device.borrow_mut() = Some(device);
+                    ctx.request_repaint();
+                    Ok(())
+                });
+            }
+        }
+    }
+
+    fn start_pairing(&mut self, ctx: &Context) {
+        let (Some(adapter), Some(candidate)) = (
+            self.adapter.borrow().clone(),
+            self.bt_devices
+                .borrow()
+                .get(&self.device)
+                .map(|c| c.device.clone()),
+        ) else {
+            return;
+        };
+        // discovery slows pairing down
+        self.stop_discovery_task();
+        let (prompts_tx, prompts) = mpsc::unbounded_channel();
+        self.pairing.prompts = Some(prompts);
+        self.pairing.prompt = None;
+        let ctx = ctx.clone();
+        self.pairing
+            .task
+            .set(pairing::pair(adapter, candidate, prompts_tx, ctx));
+    }
+
+    /// The pairing going on, and what it asks of the user
+    fn draw_pairing(&mut self, ui: &mut Ui) {
+        let state = &mut self.pairing;
+        let paired = match state.task.get() {
+            ResourceStatus::NotInitialized => return,
+            ResourceStatus::Ready(result) => match result.as_ref() {
+                Ok(device) => Some(device.clone()),
+                Err(e) => {
+                    ui.label(tr!("picker-pairing-failed", error = e));
+                    if ui.button(tr!("ok")).clicked() {
+                        state.task.clear();
+                    }
+                    None
+                }
+            },
+            ResourceStatus::Pending => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(tr!("picker-pairing"));
+                    if ui.button(tr!("cancel")).clicked() {
+                        state.task.cancel();
+                    }
+                });
+                None
+            }
+        };
+        if let Some(device) = paired {
+            state.task.clear();
+            state.prompt = None;
+            if let Some(candidate) = self.bt_devices.borrow_mut().get_mut(&self.device) {
+                candidate.paired = true;
+            }
+            self.tried_connecting_to_last_device = true;
+            self.is_connected = false;
+            self.wants_connection = Some(device);
+            return;
+        }
+        // a new prompt replaces the last code shown
+        if let Some(prompt) = state.prompts.as_mut().and_then(|p| p.try_recv().ok()) {
+            state.prompt = Some(prompt);
+            state.input.clear();
+        }
+        let Some(PairingPrompt { device, request }) = state.prompt.take() else {
+            return;
+        };
+        // put back until answered
+        let request = match request {
+            PairingRequest::Confirm { passkey, reply } => {
+                ui.label(tr!(
+                    "picker-confirm-passkey",
+                    device = device,
+                    passkey = format!("{passkey:06}")
+                ));
+                let (yes, no) = ui
+                    .horizontal(|ui| {
+                        (
+                            ui.button(tr!("yes")).clicked(),
+                            ui.button(tr!("no")).clicked(),
+                        )
+                    })
+                    .inner;
+                if yes || no {
+                    let _ = reply.send(yes.then_some(()));
+                    return;
+                }
+                PairingRequest::Confirm { passkey, reply }
+            }
+            PairingRequest::PinCode { reply } => {
+                ui.label(tr!("picker-pin-code", device = device));
+                match code_input(ui, &mut state.input) {
+                    Some(answer) => {
+                        let _ = reply.send(answer.then(|| state.input.clone()));
+                        return;
+                    }
+                    None => PairingRequest::PinCode { reply },
+                }
+            }
+            PairingRequest::Passkey { reply } => {
+                ui.label(tr!("picker-passkey", device = device));
+                match code_input(ui, &mut state.input) {
+                    Some(answer) => {
+                        let passkey = state.input.trim().parse().ok();
+                        let _ = reply.send(passkey.filter(|_| answer));
+                        return;
+                    }
+                    None => PairingRequest::Passkey { reply },
+                }
+            }
+            PairingRequest::Display(code) => {
+                ui.label(tr!("picker-display-code", code = code, device = device));
+                PairingRequest::Display(code)
+            }
+        };
+        state.prompt = Some(PairingPrompt { device, request });
+    }
+
     pub fn wants_connection(&mut self) -> Option<Device> {
         self.wants_connection.take()
     }
 }
 
+/// A text field for a code, with `Some(true)` once it's confirmed and `Some(false)` if it's canceled
+fn code_input(ui: &mut Ui, input: &mut String) -> Option<bool> {
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(input);
+        if ui.button(tr!("ok")).clicked() {
+            Some(true)
+        } else if ui.button(tr!("cancel")).clicked() {
+            Some(false)
+        } else {
+            None
+        }
+    })
+    .inner
+}
+
 impl eframe::App for DevicePicker {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ScrollArea::vertical().show(ui, |ui| {
+                self.draw_pairing(ui);
+                let mut pair = false;
                 match self.bt_info.get() {
                     ResourceStatus::Ready(bt_info_result) => match bt_info_result.as_ref() {
                         Ok(bt_info) => {
-                            ui.label(format!("Bluetooth enabled: {}", bt_info.is_powered));
-                            if ui.button("refresh").clicked() {
+                            ui.label(tr!(
+                                "picker-bluetooth-enabled",
+                                enabled = bt_info.is_powered
+                            ));
+                            if ui.button(tr!("refresh")).clicked() {
                                 self.bt_info.clear();
+                                self.known_devices_task.clear();
                             }
                             if !bt_info.is_powered {
-                                ui.label("Bluetooth is not on. Turn it on and press refresh.");
+                                ui.label(tr!("picker-bluetooth-off"));
                             } else {
-                                self.start_device_discovery_task(ctx, ui);
-                                for (device, dev) in self.bt_devices.borrow().iter() {
-                                    ui.radio_value(&mut self.device, device.clone(), device);
+                                self.start_last_device_watch_task(ctx, ui);
+                                if !self.tried_connecting_to_last_device
+                                    && let Some(device) = self.connected_last_device.take()
+                                {
+                                    self.tried_connecting_to_last_device = true;
+                                    self.device_addr = device.address().to_string();
+                                    self.wants_connection = Some(device);
+                                }
+                                if self.start_known_devices_task(ctx, ui) {
+                                    self.search = true;
+                                }
+                                if self.search {
+                                    self.start_device_discovery_task(ctx, ui);
+                                } else if ui.button(tr!("picker-search")).clicked() {
+                                    self.search = true;
+                                }
+                                ui.checkbox(&mut self.show_all_devices, tr!("picker-show-all"))
+                                    .on_hover_text(tr!("picker-show-all-hover"));
+                                let bt_devices = self.bt_devices.borrow();
+                                let mut candidates: Vec<_> = bt_devices.iter().collect();
+                                candidates.sort_by_key(|(name, candidate)| {
+                                    (
+                                        candidate.relevance,
+                                        !candidate.connected,
+                                        std::cmp::Reverse(candidate.rssi),
+                                        name.as_str(),
+                                    )
+                                });
+                                for (device, candidate) in candidates {
+                                    let dev = &candidate.device;
                                     if self.device.is_empty()
                                         && let Some(addr) = self.last_connected_addr()
                                         && dev.address().to_string() == *addr
@@ -152,11 +517,39 @@ impl eframe::App for DevicePicker {
                                     if self.device == *device {
                                         self.device_addr = dev.address().to_string();
                                     }
+                                    if candidate.relevance == Relevance::Other
+                                        && !self.show_all_devices
+                                        && self.device != *device
+                                    {
+                                        continue;
+                                    }
+                                    ui.horizontal(|ui| {
+                                        ui.radio_value(&mut self.device, device.clone(), device);
+                                        match candidate.relevance {
+                                            Relevance::Sony => {
+                                                ui.label(RichText::new("Sony").strong());
+                                            }
+                                            Relevance::Audio => {
+                                                ui.label(tr!("picker-audio"));
+                                            }
+                                            Relevance::Other => (),
+                                        }
+                                        let mut details = dev.address().to_string();
+                                        if let Some(rssi) = candidate.rssi {
+                                            details += &format!(", {rssi} dBm");
+                                        }
+                                        if candidate.connected {
+                                            details += &format!(", {}", tr!("picker-connected"));
+                                        }
+                                        ui.weak(details);
+                                    });
                                 }
+                                drop(bt_devices);
 
+                                ui.label(tr!("picker-new-headphones"));
                                 if !self.device.is_empty() {
                                     #[allow(clippy::collapsible_if)]
-                                    if ui.button("connect?").clicked()
+                                    if ui.button(tr!("picker-connect")).clicked()
                                         || (self.found_last_device
                                             && !self.tried_connecting_to_last_device)
                                     {
@@ -169,27 +562,41 @@ impl eframe::App for DevicePicker {
                                                 .borrow()
                                                 .get(&self.device)
                                                 .unwrap()
+                                                .device
                                                 .clone(),
                                         );
                                     }
 
+                                    if self
+                                        .bt_devices
+                                        .borrow()
+                                        .get(&self.device)
+                                        .is_some_and(|candidate| !candidate.paired)
+                                        && matches!(
+                                            self.pairing.task.get(),
+                                            ResourceStatus::NotInitialized
+                                        )
+                                        && ui.button(tr!("picker-pair-and-connect")).clicked()
+                                    {
+                                        pair = true;
+                                    }
                                     ui.checkbox(
                                         &mut self.connect_to_the_device_automatically_on_startup,
-                                        "Connect to this device automatically next time",
+                                        tr!("picker-connect-automatically"),
                                     );
                                 }
                             }
                         }
                         Err(e) => {
-                            ui.label(format!("BtInfo: error: {e}"));
-                            if ui.button("retry?").clicked() {
+                            ui.label(tr!("picker-bluetooth-failed", error = e));
+                            if ui.button(tr!("retry")).clicked() {
                                 self.bt_info.clear();
                             }
                         }
                     },
 
                     ResourceStatus::Pending => {
-                        ui.label("Getting BtInfo");
+                        ui.label(tr!("picker-getting-bluetooth"));
                         ui.spinner();
                     }
 
@@ -213,6 +620,9 @@ impl eframe::App for DevicePicker {
                         });
                     }
                 }
+                if pair {
+                    self.start_pairing(ctx);
+                }
             });
         });
     }
@@ -226,3 +636,31 @@ impl eframe::App for DevicePicker {
         storage.set_string(Self::LAST_ADDR_KEY, device);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn relevance() {
+        let sony = HashSet::from([SONY_SERVICE_UUID]);
+        let a2dp = HashSet::from([AUDIO_UUIDS[0]]);
+        assert_eq!(Relevance::of("WF-1000XM5", None, None), Relevance::Sony);
+        assert_eq!(
+            Relevance::of("LE_WF-1000XM5", None, Some(&sony)),
+            Relevance::Sony
+        );
+        // a speaker, by its class (major class audio/video, minor class loudspeaker)
+        assert_eq!(
+            Relevance::of("Speaker", Some(0x240414), None),
+            Relevance::Audio
+        );
+        assert_eq!(Relevance::of("Car", None, Some(&a2dp)), Relevance::Audio);
+        // a phone
+        assert_eq!(
+            Relevance::of("Pixel", Some(0x5a020c), None),
+            Relevance::Other
+        );
+        assert!(Relevance::Sony < Relevance::Audio);
+    }
+}
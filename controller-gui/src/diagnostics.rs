@@ -0,0 +1,214 @@
+//! A diagnostic bundle to attach to bug reports: the recent protocol traffic, the parse warnings, what the
+//! headphones said about themselves and the app's settings, as one zip. Bluetooth addresses are redacted from
+//! everything that goes in, since the settings and the frames can contain them.
+//!
+//! The zip is written without compression, which every unzip reads and which takes no compression library;
+//! the files are small text anyway.
+
+use chrono::{DateTime, Datelike, Local, Timelike};
+
+/// The files of a bundle, by their name in the zip
+#[derive(Debug, Default)]
+pub struct Bundle {
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    /// Add `text` as `name`, with the addresses in it redacted
+    pub fn add(&mut self, name: impl Into<String>, text: &str) {
+        self.files
+            .push((name.into(), redact_addresses(text).into_bytes()));
+    }
+
+    /// The bundle as a zip, with `time` as the modification time of the files
+    pub fn to_zip(&self, time: DateTime<Local>) -> Vec<u8> {
+        let (dos_time, dos_date) = dos_date_time(time);
+        let mut zip = Vec::new();
+        let mut directory = Vec::new();
+        for (name, data) in &self.files {
+            let offset = zip.len() as u32;
+            let crc = crc32(data);
+            // the fields the local header and the central directory share, from the version needed on
+            let mut common = Vec::new();
+            common.extend(20u16.to_le_bytes());
+            // the names are UTF-8
+            common.extend(0x0800u16.to_le_bytes());
+            // stored
+            common.extend(0u16.to_le_bytes());
+            common.extend(dos_time.to_le_bytes());
+            common.extend(dos_date.to_le_bytes());
+            common.extend(crc.to_le_bytes());
+            common.extend((data.len() as u32).to_le_bytes());
+            common.extend((data.len() as u32).to_le_bytes());
+            common.extend((name.len() as u16).to_le_bytes());
+            // no extra field
+            common.extend(0u16.to_le_bytes());
+
+            zip.extend(0x04034b50u32.to_le_bytes());
+            zip.extend(&common);
+            zip.extend(name.as_bytes());
+            zip.extend(data);
+
+            directory.extend(0x02014b50u32.to_le_bytes());
+            // made by
+            directory.extend(20u16.to_le_bytes());
+            directory.extend(&common);
+            // no comment, disk 0, no internal or external attributes
+            directory.extend([0; 10]);
+            directory.extend(offset.to_le_bytes());
+            directory.extend(name.as_bytes());
+        }
+        let directory_offset = zip.len() as u32;
+        let count = self.files.len() as u16;
+        zip.extend(&directory);
+        zip.extend(0x06054b50u32.to_le_bytes());
+        // this disk and the one the directory starts on
+        zip.extend([0; 4]);
+        zip.extend(count.to_le_bytes());
+        zip.extend(count.to_le_bytes());
+        zip.extend((directory.len() as u32).to_le_bytes());
+        zip.extend(directory_offset.to_le_bytes());
+        // no comment
+        zip.extend(0u16.to_le_bytes());
+        zip
+    }
+}
+
+/// The time and the date as the zip headers have them, in 2 second steps and from 1980
+fn dos_date_time(time: DateTime<Local>) -> (u16, u16) {
+    let dos_time = ((time.hour() << 11) | (time.minute() << 5) | (time.second() / 2)) as u16;
+    let year = time.year().clamp(1980, 2107) as u32 - 1980;
+    let dos_date = ((year << 9) | (time.month() << 5) | time.day()) as u16;
+    (dos_time, dos_date)
+}
+
+/// The CRC-32 zip checks the files with
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// `text` with every Bluetooth address, e.g. `AC:80:0A:12:34:56`, `ac_80_0a_12_34_56` as BlueZ has them in
+/// paths or `AC-80-0A-12-34-56`, replaced by `XX:XX:XX:XX:XX:XX`
+pub fn redact_addresses(text: &str) -> String {
+    const ADDRESS_LEN: usize = 17;
+    const REDACTED: &str = "XX:XX:XX:XX:XX:XX";
+    let bytes = text.as_bytes();
+    let is_address = |start: usize| {
+        let candidate = &bytes[start..start + ADDRESS_LEN];
+        let separator = candidate[2];
+        [b':', b'_', b'-'].contains(&separator)
+            && candidate.iter().enumerate().all(|(i, byte)| {
+                if i % 3 == 2 {
+                    *byte == separator
+                } else {
+                    byte.is_ascii_hexdigit()
+                }
+            })
+            // not a part of something longer, e.g. a hex dump
+            && (start == 0 || !bytes[start - 1].is_ascii_hexdigit())
+            && bytes
+                .get(start + ADDRESS_LEN)
+                .is_none_or(|byte| !byte.is_ascii_hexdigit())
+    };
+    let mut redacted = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut start = 0;
+    while start + ADDRESS_LEN <= bytes.len() {
+        if is_address(start) {
+            redacted.push_str(&text[copied..start]);
+            redacted.push_str(REDACTED);
+            start += ADDRESS_LEN;
+            copied = start;
+        } else {
+            start += 1;
+        }
+    }
+    redacted.push_str(&text[copied..]);
+    redacted
+}
+
+/// Pick where to save the `zip` of a bundle; the path it was saved at, or `None` if none was picked
+pub async fn save_with_dialog(zip: Vec<u8>) -> Option<Result<std::path::PathBuf, std::io::Error>> {
+    let file = rfd::AsyncFileDialog::new()
+        .set_title("Export diagnostic bundle")
+        .set_file_name(format!(
+            "sony-headphones-diagnostics-{}.zip",
+            Local::now().format("%Y%m%d-%H%M%S")
+        ))
+        .add_filter("Zip", &["zip"])
+        .save_file()
+        .await?;
+    let path = file.path().to_path_buf();
+    Some(std::fs::write(&path, zip).map(|()| path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            redact_addresses("last_address: Some(\"AC:80:0A:12:34:56\"),"),
+            "last_address: Some(\"XX:XX:XX:XX:XX:XX\"),"
+        );
+        assert_eq!(
+            redact_addresses("/org/bluez/hci0/dev_ac_80_0a_12_34_56 and ac-80-0a-12-34-5f"),
+            "/org/bluez/hci0/dev_XX:XX:XX:XX:XX:XX and XX:XX:XX:XX:XX:XX"
+        );
+        // mixed separators, too short and hex dumps stay
+        for text in [
+            "AC:80_0A:12:34:56",
+            "AC:80:0A:12:34",
+            "0AC:80:0A:12:34:56",
+            "12:00:01.250 Command1",
+            "ünïcödé",
+        ] {
+            assert_eq!(redact_addresses(text), text);
+        }
+    }
+
+    #[test]
+    fn zip() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+
+        let mut bundle = Bundle::default();
+        bundle.add("about.txt", "version 0.1.2");
+        bundle.add("settings.ron", "AC:80:0A:12:34:56");
+        let time = Local.with_ymd_and_hms(2024, 5, 17, 13, 45, 30).unwrap();
+        let zip = bundle.to_zip(time);
+
+        assert_eq!(&zip[..4], b"PK\x03\x04");
+        // the first file, stored as it is
+        assert_eq!(&zip[30..39], b"about.txt");
+        assert_eq!(&zip[39..52], b"version 0.1.2");
+        let second = 52;
+        assert_eq!(&zip[second..second + 4], b"PK\x03\x04");
+        assert_eq!(
+            &zip[second + 30 + "settings.ron".len()..][..17],
+            b"XX:XX:XX:XX:XX:XX"
+        );
+        // the end of the central directory, pointing at its 2 entries
+        let end = &zip[zip.len() - 22..];
+        assert_eq!(&end[..4], b"PK\x05\x06");
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+        let directory_offset = u32::from_le_bytes(end[16..20].try_into().unwrap()) as usize;
+        assert_eq!(&zip[directory_offset..directory_offset + 4], b"PK\x01\x02");
+        assert_eq!(
+            dos_date_time(time),
+            ((13 << 11) | (45 << 5) | 15, (44 << 9) | (5 << 5) | 17)
+        );
+    }
+}
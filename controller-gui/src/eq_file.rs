@@ -0,0 +1,358 @@
+//! Equalizer files: import of the tunings the community shares, and export of ours.
+//!
+//! Import reads
+//! - AutoEq's parametric EQ (`ParametricEQ.txt`, also what Equalizer APO reads):
+//!   `Filter 1: ON PK Fc 105 Hz Gain 4.1 dB Q 0.70`, with peaking (`PK`) and shelf (`LSC`, `HSC`) filters
+//! - graphic EQs as Wavelet and AutoEq's `GraphicEQ.txt` have them: `GraphicEQ: 20 -1.2; 21 -1.3; ...`
+//! - our own JSON export
+//!
+//! The response of the file is sampled at the frequencies of the bands (and at 60 Hz for Clear Bass),
+//! then rounded and clamped to what the headphones take. The preamp is ignored, since the bands are relative anyway.
+//!
+//! The other way round, [response_db] approximates what our bands do, for the equalizer's curve.
+
+use serde::{Deserialize, Serialize};
+use sony_wf1000xm5::command::EqualizerBands;
+use std::f64::consts::PI;
+use thiserror::Error;
+
+/// Where each band is sampled, in Hz, in the order of [EqualizerBands::levels]
+const BAND_FREQUENCIES: [f64; 6] = [60.0, 400.0, 1000.0, 2500.0, 6300.0, 16000.0];
+/// The sample rate to evaluate the parametric filters at
+const SAMPLE_RATE: f64 = 48000.0;
+/// The Q of filters which don't give one
+const DEFAULT_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+/// Of the peaking filters [response_db] models the middle bands with, about an octave wide
+const BAND_Q: f64 = 1.41;
+
+#[derive(Debug, Error)]
+pub enum EqFileError {
+    #[error("This isn't an equalizer file we know (AutoEq parametric or graphic EQ, or JSON)")]
+    UnknownFormat,
+    #[error("Couldn't read line {line}: {text}")]
+    InvalidLine { line: usize, text: String },
+    #[error("The JSON isn't an equalizer export: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid equalizer level: {level}")]
+    InvalidLevel { level: i16 },
+    #[error("Couldn't access the file: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// What [export] writes, named like the bands in `sonyctl --json`
+#[derive(Debug, Serialize, Deserialize)]
+struct BandsFile {
+    clear_bass: i8,
+    band_400: i8,
+    band_1000: i8,
+    band_2500: i8,
+    band_6300: i8,
+    band_16000: i8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FilterKind {
+    Peaking,
+    LowShelf,
+    HighShelf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Filter {
+    kind: FilterKind,
+    frequency: f64,
+    gain: f64,
+    q: f64,
+}
+
+impl Filter {
+    /// The gain in dB at `frequency`, from the biquad of the Audio EQ Cookbook
+    fn gain_at(&self, frequency: f64) -> f64 {
+        let a = 10f64.powf(self.gain / 40.0);
+        let w0 = 2.0 * PI * self.frequency / SAMPLE_RATE;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * self.q);
+        let sqrt_a_alpha = 2.0 * a.sqrt() * alpha;
+        let (b, den) = match self.kind {
+            FilterKind::Peaking => (
+                [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+                [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+            ),
+            FilterKind::LowShelf => (
+                [
+                    a * ((a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha),
+                    2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                    a * ((a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha),
+                ],
+                [
+                    (a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha,
+                    -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                    (a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha,
+                ],
+            ),
+            FilterKind::HighShelf => (
+                [
+                    a * ((a + 1.0) + (a - 1.0) * cos + sqrt_a_alpha),
+                    -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                    a * ((a + 1.0) + (a - 1.0) * cos - sqrt_a_alpha),
+                ],
+                [
+                    (a + 1.0) - (a - 1.0) * cos + sqrt_a_alpha,
+                    2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                    (a + 1.0) - (a - 1.0) * cos - sqrt_a_alpha,
+                ],
+            ),
+        };
+        let w = 2.0 * PI * frequency / SAMPLE_RATE;
+        let magnitude = |[c0, c1, c2]: [f64; 3]| {
+            let re = c0 + c1 * w.cos() + c2 * (2.0 * w).cos();
+            let im = -(c1 * w.sin() + c2 * (2.0 * w).sin());
+            re.hypot(im)
+        };
+        20.0 * (magnitude(b) / magnitude(den)).log10()
+    }
+}
+
+/// The gain in dB at `frequency` of `bands`, taking Clear Bass and the 16000 Hz band as shelves and the others
+/// as peaking filters, each a level a dB. Sony doesn't say what the headphones do, so it's only an approximation.
+pub fn response_db(bands: &EqualizerBands, frequency: f64) -> f64 {
+    let levels = bands.levels();
+    let last = levels.len() - 1;
+    BAND_FREQUENCIES
+        .iter()
+        .zip(levels)
+        .enumerate()
+        .map(|(index, (&band_frequency, level))| {
+            let (kind, q) = match index {
+                0 => (FilterKind::LowShelf, DEFAULT_Q),
+                _ if index == last => (FilterKind::HighShelf, DEFAULT_Q),
+                _ => (FilterKind::Peaking, BAND_Q),
+            };
+            Filter {
+                kind,
+                frequency: band_frequency,
+                gain: level as f64,
+                q,
+            }
+            .gain_at(frequency)
+        })
+        .sum()
+}
+
+/// Read an equalizer file, see the module docs for the formats
+pub fn import(text: &str) -> Result<EqualizerBands, EqFileError> {
+    if text.trim_start().starts_with('{') {
+        let file: BandsFile = serde_json::from_str(text)?;
+        return EqualizerBands::new(
+            file.clear_bass,
+            file.band_400,
+            file.band_1000,
+            file.band_2500,
+            file.band_6300,
+            file.band_16000,
+        )
+        .map_err(|e| EqFileError::InvalidLevel { level: e.level });
+    }
+    let mut filters = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let invalid = || EqFileError::InvalidLine {
+            line: index + 1,
+            text: line.to_string(),
+        };
+        let line = line.trim();
+        if let Some(points) = line.strip_prefix("GraphicEQ:") {
+            return graphic(points).ok_or_else(invalid).map(bands);
+        }
+        if line.starts_with("Filter")
+            && let Some(filter) = parametric(line).map_err(|()| invalid())?
+        {
+            filters.push(filter);
+        }
+    }
+    if filters.is_empty() {
+        return Err(EqFileError::UnknownFormat);
+    }
+    Ok(bands(BAND_FREQUENCIES.map(|frequency| {
+        filters.iter().map(|filter| filter.gain_at(frequency)).sum()
+    })))
+}
+
+/// Our own format, see [import]
+pub fn export(bands: &EqualizerBands) -> String {
+    let [
+        clear_bass,
+        band_400,
+        band_1000,
+        band_2500,
+        band_6300,
+        band_16000,
+    ] = bands.levels();
+    serde_json::to_string_pretty(&BandsFile {
+        clear_bass,
+        band_400,
+        band_1000,
+        band_2500,
+        band_6300,
+        band_16000,
+    })
+    .expect("bands are always serializable")
+}
+
+/// One `Filter N: ON PK Fc 105 Hz Gain 4.1 dB Q 0.70` line; `None` if it's off
+fn parametric(line: &str) -> Result<Option<Filter>, ()> {
+    let (_, filter) = line.split_once(':').ok_or(())?;
+    let tokens: Vec<&str> = filter.split_whitespace().collect();
+    if tokens.first() != Some(&"ON") {
+        return Ok(None);
+    }
+    let kind = match tokens.get(1).copied() {
+        Some("PK" | "PEQ") => FilterKind::Peaking,
+        Some("LSC" | "LS" | "LSQ") => FilterKind::LowShelf,
+        Some("HSC" | "HS" | "HSQ") => FilterKind::HighShelf,
+        _ => return Err(()),
+    };
+    let value = |name| {
+        tokens
+            .iter()
+            .position(|token| *token == name)
+            .and_then(|index| tokens.get(index + 1))
+            .map(|value| value.parse::<f64>().map_err(|_| ()))
+            .transpose()
+    };
+    Ok(Some(Filter {
+        kind,
+        frequency: value("Fc")?.ok_or(())?,
+        gain: value("Gain")?.ok_or(())?,
+        q: value("Q")?.unwrap_or(DEFAULT_Q),
+    }))
+}
+
+/// `20 -1.2; 21 -1.3; ...`, interpolated on a logarithmic frequency scale
+fn graphic(points: &str) -> Option<[f64; 6]> {
+    let mut curve = Vec::new();
+    for point in points.split(';').filter(|point| !point.trim().is_empty()) {
+        let (frequency, gain) = point.trim().split_once(char::is_whitespace)?;
+        let frequency: f64 = frequency.parse().ok()?;
+        if frequency <= 0.0 {
+            return None;
+        }
+        curve.push((frequency.ln(), gain.trim().parse::<f64>().ok()?));
+    }
+    curve.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    let (first, last) = (*curve.first()?, *curve.last()?);
+    Some(BAND_FREQUENCIES.map(|frequency| {
+        let x = frequency.ln();
+        if x <= first.0 {
+            return first.1;
+        }
+        if x >= last.0 {
+            return last.1;
+        }
+        let next = curve.iter().position(|(f, _)| *f >= x).unwrap();
+        let ((x0, y0), (x1, y1)) = (curve[next - 1], curve[next]);
+        y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+    }))
+}
+
+/// Pick a file and [import] it; `None` if no file was picked
+#[cfg(target_os = "linux")]
+pub async fn import_with_dialog() -> Option<Result<EqualizerBands, EqFileError>> {
+    let file = rfd::AsyncFileDialog::new()
+        .set_title("Import equalizer")
+        .add_filter("Equalizer", &["txt", "json"])
+        .pick_file()
+        .await?;
+    Some(
+        std::fs::read_to_string(file.path())
+            .map_err(EqFileError::from)
+            .and_then(|text| import(&text)),
+    )
+}
+
+/// Pick where to [export] `bands` to; the path it was saved at, or `None` if none was picked
+#[cfg(target_os = "linux")]
+pub async fn export_with_dialog(
+    bands: EqualizerBands,
+) -> Option<Result<std::path::PathBuf, EqFileError>> {
+    let file = rfd::AsyncFileDialog::new()
+        .set_title("Export equalizer")
+        .set_file_name("equalizer.json")
+        .add_filter("JSON", &["json"])
+        .save_file()
+        .await?;
+    let path = file.path().to_path_buf();
+    Some(
+        std::fs::write(&path, export(&bands))
+            .map(|()| path)
+            .map_err(EqFileError::from),
+    )
+}
+
+fn bands(gains: [f64; 6]) -> EqualizerBands {
+    let levels = gains.map(|gain| {
+        gain.round()
+            .clamp(EqualizerBands::MIN as f64, EqualizerBands::MAX as f64) as i8
+    });
+    EqualizerBands::from_levels(levels).expect("the levels are clamped")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn autoeq_parametric() {
+        let bands = import(
+            "Preamp: -6.2 dB
+Filter 1: ON LSC Fc 105 Hz Gain 6.0 dB Q 0.70
+Filter 2: ON PK Fc 2500 Hz Gain -4.0 dB Q 1.41
+Filter 3: OFF PK Fc 6300 Hz Gain 9.0 dB Q 1.00
+Filter 4: ON HSC Fc 10000 Hz Gain 20.0 dB Q 0.70
+",
+        )
+        .unwrap();
+        // the shelves haven't fully risen at 60 Hz and 6300 Hz, and the 16000 Hz band is clamped
+        assert_eq!(bands.levels(), [5, 0, 0, -4, 3, EqualizerBands::MAX]);
+    }
+
+    #[test]
+    fn graphic_eq() {
+        let bands =
+            import("GraphicEQ: 20 4; 100 4; 400 2; 1000 0; 2000 -2; 4000 -4; 20000 1").unwrap();
+        assert_eq!(bands.levels(), [4, 2, 0, -3, -3, 0]);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let bands = EqualizerBands::new(3, -2, 0, 1, 0, -10).unwrap();
+        assert_eq!(import(&export(&bands)).unwrap(), bands);
+    }
+
+    #[test]
+    fn response() {
+        let flat = EqualizerBands::default();
+        assert!(response_db(&flat, 1000.0).abs() < 1e-9);
+        let mid = EqualizerBands::new(0, 0, 6, 0, 0, 0).unwrap();
+        assert!((response_db(&mid, 1000.0) - 6.0).abs() < 1e-6);
+        assert!(response_db(&mid, 60.0) < 0.5);
+        assert!(response_db(&mid, 16000.0) < 0.5);
+        let bass = EqualizerBands::new(10, 0, 0, 0, 0, 0).unwrap();
+        assert!(response_db(&bass, 20.0) > 9.0);
+        assert!(response_db(&bass, 6300.0) < 0.5);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(matches!(import("hello"), Err(EqFileError::UnknownFormat)));
+        assert!(matches!(
+            import("Filter 1: ON PK Fc abc Hz Gain 1 dB"),
+            Err(EqFileError::InvalidLine { line: 1, .. })
+        ));
+        assert!(matches!(
+            import(
+                r#"{"clear_bass":11,"band_400":0,"band_1000":0,"band_2500":0,"band_6300":0,"band_16000":0}"#
+            ),
+            Err(EqFileError::InvalidLevel { level: 11 })
+        ));
+    }
+}
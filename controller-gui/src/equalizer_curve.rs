@@ -0,0 +1,91 @@
+//! The curve of the equalizer above its sliders, so it's easier to tell what the settings do. It's the
+//! approximation of [crate::eq_file::response_db], on a logarithmic frequency axis like the bands.
+
+use crate::eq_file::response_db;
+use eframe::egui::{self, Color32, Pos2, Stroke, Ui};
+use sony_wf1000xm5::command::EqualizerBands;
+
+const MIN_HZ: f64 = 20.0;
+const MAX_HZ: f64 = 20000.0;
+/// A bit more than the bands go, for where they add up
+const MAX_DB: f64 = 12.0;
+/// Where the curve is evaluated, spaced evenly on the axis
+const POINTS: usize = 120;
+/// With a line across
+const GRID_HZ: [(f64, &str); 3] = [(100.0, "100 Hz"), (1000.0, "1 kHz"), (10000.0, "10 kHz")];
+
+/// Where `frequency` is along the axis, from 0 to 1
+fn axis_position(frequency: f64) -> f64 {
+    (frequency / MIN_HZ).log10() / (MAX_HZ / MIN_HZ).log10()
+}
+
+pub fn plot(ui: &mut Ui, bands: &EqualizerBands) {
+    let (response, painter) = ui.allocate_painter(
+        egui::vec2(ui.available_width().min(500.0), 100.0),
+        egui::Sense::hover(),
+    );
+    let rect = response.rect;
+    let grid = ui.visuals().widgets.noninteractive.bg_stroke;
+    painter.rect_stroke(rect, 0.0, grid, egui::StrokeKind::Inside);
+    let x = |frequency: f64| rect.left() + axis_position(frequency) as f32 * rect.width();
+    let y = |db: f64| {
+        let position = (db.clamp(-MAX_DB, MAX_DB) + MAX_DB) / (2.0 * MAX_DB);
+        rect.bottom() - position as f32 * rect.height()
+    };
+    let text = |pos, anchor, text: &str| {
+        painter.text(
+            pos,
+            anchor,
+            text,
+            egui::FontId::proportional(11.0),
+            ui.visuals().weak_text_color(),
+        );
+    };
+    for (frequency, label) in GRID_HZ {
+        painter.vline(x(frequency), rect.y_range(), grid);
+        text(
+            Pos2::new(x(frequency), rect.bottom()),
+            egui::Align2::LEFT_BOTTOM,
+            label,
+        );
+    }
+    painter.hline(rect.x_range(), y(0.0), grid);
+    text(
+        rect.left_top(),
+        egui::Align2::LEFT_TOP,
+        &format!("+{MAX_DB} dB"),
+    );
+    text(
+        Pos2::new(rect.left(), y(0.0)),
+        egui::Align2::LEFT_BOTTOM,
+        "0 dB",
+    );
+
+    let curve = (0..=POINTS)
+        .map(|point| {
+            let frequency = MIN_HZ * (MAX_HZ / MIN_HZ).powf(point as f64 / POINTS as f64);
+            Pos2::new(x(frequency), y(response_db(bands, frequency)))
+        })
+        .collect();
+    painter.line(curve, Stroke::new(2.0, Color32::LIGHT_BLUE));
+    if let Some(pointer) = response.hover_pos() {
+        let position = ((pointer.x - rect.left()) / rect.width()) as f64;
+        let frequency = MIN_HZ * (MAX_HZ / MIN_HZ).powf(position.clamp(0.0, 1.0));
+        response.on_hover_text(format!(
+            "{frequency:.0} Hz: {:+.1} dB",
+            response_db(bands, frequency)
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn axis() {
+        assert_eq!(axis_position(MIN_HZ), 0.0);
+        assert!((axis_position(MAX_HZ) - 1.0).abs() < 1e-9);
+        assert!((axis_position(632.0) - 0.5).abs() < 0.01);
+    }
+}
@@ -0,0 +1,283 @@
+//! Sound exposure from the sound pressure measurements: the time-weighted average level and the daily dose.
+//!
+//! The dose follows the WHO's safe listening standard (H.870): 80 dB for 40 hours a week, so 40/7 hours a day,
+//! with every 3 dB more halving the allowed time. The headphones don't say how they weight the level, so this
+//! is an estimate, not a dosimeter.
+
+use chrono::{DateTime, Local, NaiveDate};
+use eframe::egui::{self, Color32, Pos2, Stroke, Ui};
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+/// The level which is safe for [REFERENCE_HOURS] a day
+const REFERENCE_DB: f64 = 80.0;
+const REFERENCE_HOURS: f64 = 40.0 / 7.0;
+/// Every this many dB more halves the allowed time
+const EXCHANGE_RATE_DB: f64 = 3.0;
+/// How long a sample counts for at most, the longest interval the headphones are polled at
+/// (see [crate::settings::AppSettings::SOUND_PRESSURE_INTERVALS]); longer gaps are time we didn't measure
+const MAX_SAMPLE_SECS: f64 = 5.0;
+/// How long the first sample after a gap counts for, the default interval the headphones are polled at
+const SAMPLE_SECS: f64 = 1.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub time: DateTime<Local>,
+    pub db: f64,
+}
+
+/// The samples of one day
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exposure {
+    day: NaiveDate,
+    samples: Vec<Sample>,
+}
+
+impl Exposure {
+    /// `samples` of any day, keeping the ones of `day`
+    pub fn new(day: NaiveDate, samples: impl IntoIterator<Item = Sample>) -> Self {
+        let mut samples: Vec<Sample> = samples
+            .into_iter()
+            .filter(|sample| sample.time.date_naive() == day)
+            .collect();
+        samples.sort_by_key(|sample| sample.time);
+        Self { day, samples }
+    }
+
+    /// Add `sample`, starting over if it's the first one of a new day
+    pub fn record(&mut self, sample: Sample) {
+        let day = sample.time.date_naive();
+        if day != self.day {
+            *self = Self::new(day, []);
+        }
+        self.samples.push(sample);
+    }
+
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    /// Each level with how many seconds it counts for
+    fn durations(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        let previous = std::iter::once(None).chain(self.samples.iter().map(Some));
+        self.samples.iter().zip(previous).map(|(sample, previous)| {
+            let secs = previous
+                .map(|previous| (sample.time - previous.time).as_seconds_f64())
+                .filter(|secs| *secs <= MAX_SAMPLE_SECS)
+                .unwrap_or(SAMPLE_SECS);
+            (sample.db, secs)
+        })
+    }
+
+    /// How long was measured, in seconds
+    pub fn measured_secs(&self) -> f64 {
+        self.durations().map(|(_, secs)| secs).sum()
+    }
+
+    /// The equivalent continuous level (Leq) over the measured time, `None` before any sample
+    pub fn average_db(&self) -> Option<f64> {
+        let secs = self.measured_secs();
+        let energy: f64 = self
+            .durations()
+            .map(|(db, secs)| secs * 10f64.powf(db / 10.0))
+            .sum();
+        (secs > 0.0).then(|| 10.0 * (energy / secs).log10())
+    }
+
+    /// The share of the daily allowance used up, 1.0 being all of it
+    pub fn daily_dose(&self) -> f64 {
+        self.durations()
+            .map(|(db, secs)| {
+                let allowed_secs =
+                    REFERENCE_HOURS * 3600.0 / 2f64.powf((db - REFERENCE_DB) / EXCHANGE_RATE_DB);
+                secs / allowed_secs
+            })
+            .sum()
+    }
+
+    /// The level over the day, with the safe level as a line
+    pub fn plot(&self, ui: &mut Ui) {
+        const MIN_DB: f64 = 30.0;
+        const MAX_DB: f64 = 110.0;
+        let (Some(first), Some(last)) = (self.samples.first(), self.samples.last()) else {
+            return;
+        };
+        let (response, painter) = ui.allocate_painter(
+            egui::vec2(ui.available_width().min(500.0), 120.0),
+            egui::Sense::hover(),
+        );
+        let rect = response.rect;
+        painter.rect_stroke(
+            rect,
+            0.0,
+            ui.visuals().widgets.noninteractive.bg_stroke,
+            egui::StrokeKind::Inside,
+        );
+        let span = (last.time - first.time).as_seconds_f64().max(1.0);
+        let point = |sample: &Sample| {
+            let x = (sample.time - first.time).as_seconds_f64() / span;
+            let y = (sample.db.clamp(MIN_DB, MAX_DB) - MIN_DB) / (MAX_DB - MIN_DB);
+            Pos2::new(
+                rect.left() + x as f32 * rect.width(),
+                rect.bottom() - y as f32 * rect.height(),
+            )
+        };
+        let safe_y =
+            rect.bottom() - ((REFERENCE_DB - MIN_DB) / (MAX_DB - MIN_DB)) as f32 * rect.height();
+        painter.hline(rect.x_range(), safe_y, Stroke::new(1.0, Color32::YELLOW));
+        // one line per stretch of measuring
+        let stroke = Stroke::new(1.5, ui.visuals().text_color());
+        let mut line = Vec::new();
+        for (sample, (_, secs)) in self.samples.iter().zip(self.durations()) {
+            if secs == SAMPLE_SECS && !line.is_empty() {
+                painter.line(std::mem::take(&mut line), stroke);
+            }
+            line.push(point(sample));
+        }
+        painter.line(line, stroke);
+        let text = |pos, anchor, text: String| {
+            painter.text(
+                pos,
+                anchor,
+                text,
+                egui::FontId::proportional(11.0),
+                ui.visuals().weak_text_color(),
+            );
+        };
+        text(
+            rect.left_top(),
+            egui::Align2::LEFT_TOP,
+            format!("{MAX_DB} dB"),
+        );
+        text(
+            rect.left_bottom(),
+            egui::Align2::LEFT_BOTTOM,
+            first.time.format("%H:%M").to_string(),
+        );
+        text(
+            rect.right_bottom(),
+            egui::Align2::RIGHT_BOTTOM,
+            last.time.format("%H:%M").to_string(),
+        );
+        text(
+            Pos2::new(rect.right(), safe_y),
+            egui::Align2::RIGHT_BOTTOM,
+            format!("{REFERENCE_DB} dB"),
+        );
+    }
+}
+
+/// The samples of the last days, one JSON object per line
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ExposureLog {
+    file: File,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ExposureLog {
+    pub const FILE_NAME: &'static str = "exposure.jsonl";
+    /// How many days of samples are kept
+    const RETENTION_DAYS: i64 = 7;
+
+    /// Open (or create) the log at `path`, with the samples of the last days
+    pub fn open(path: &Path) -> io::Result<(Self, Vec<Sample>)> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut samples = Vec::new();
+        if path.exists() {
+            let cutoff = Local::now() - chrono::TimeDelta::days(Self::RETENTION_DAYS);
+            samples = fs::read_to_string(path)?
+                .lines()
+                // e.g. a line cut short by a crash
+                .filter_map(|line| serde_json::from_str::<Sample>(line).ok())
+                .filter(|sample| sample.time >= cutoff)
+                .collect();
+            let kept = samples
+                .iter()
+                .map(|sample| serde_json::to_string(sample).map(|line| line + "\n"))
+                .collect::<Result<String, _>>()?;
+            let tmp_path = path.with_extension("jsonl.tmp");
+            fs::write(&tmp_path, kept)?;
+            fs::rename(&tmp_path, path)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok((Self { file }, samples))
+    }
+
+    pub fn append(&mut self, sample: &Sample) -> io::Result<()> {
+        let mut line = serde_json::to_string(sample)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{TimeDelta, TimeZone};
+
+    fn samples(start: DateTime<Local>, db: f64, secs: i64) -> impl Iterator<Item = Sample> {
+        (0..secs).map(move |i| Sample {
+            time: start + TimeDelta::seconds(i),
+            db,
+        })
+    }
+
+    #[test]
+    fn dose() {
+        let morning = Local.with_ymd_and_hms(2024, 5, 1, 9, 0, 0).unwrap();
+        let mut exposure = Exposure::new(morning.date_naive(), []);
+        assert_eq!(exposure.average_db(), None);
+        // an hour at 83 dB uses up half as much as two hours at 80 dB
+        for sample in samples(morning, 83.0, 3600) {
+            exposure.record(sample);
+        }
+        assert!((exposure.measured_secs() - 3600.0).abs() < 1e-6);
+        assert!((exposure.daily_dose() - 2.0 * 7.0 / 40.0).abs() < 1e-3);
+
+        // the gap over lunch doesn't count, and 60 dB barely adds to the average
+        let afternoon = morning + TimeDelta::hours(4);
+        for sample in samples(afternoon, 60.0, 3600) {
+            exposure.record(sample);
+        }
+        assert!((exposure.measured_secs() - 7200.0).abs() < 1e-6);
+        assert!((exposure.average_db().unwrap() - 80.0).abs() < 0.1);
+
+        // a new day starts over
+        exposure.record(Sample {
+            time: morning + TimeDelta::days(1),
+            db: 70.0,
+        });
+        assert_eq!(exposure.samples().len(), 1);
+    }
+
+    #[test]
+    fn log() {
+        let dir = std::env::temp_dir().join(format!("xm5-exposure-test-{}", std::process::id()));
+        let path = dir.join(ExposureLog::FILE_NAME);
+        let now = Local::now();
+        let old = Sample {
+            time: now - TimeDelta::days(30),
+            db: 50.0,
+        };
+        let new = Sample {
+            time: now,
+            db: 70.0,
+        };
+        {
+            let (mut log, samples) = ExposureLog::open(&path).unwrap();
+            assert!(samples.is_empty());
+            log.append(&old).unwrap();
+            log.append(&new).unwrap();
+        }
+        let (_, samples) = ExposureLog::open(&path).unwrap();
+        assert_eq!(samples, [new]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+//! Checking whether newer firmware is out for the headphones.
+//!
+//! Sony's update server, which the Sound Connect app asks, serves its metadata encrypted, and that isn't
+//! decoded yet; until it is, the newest versions come from `controller-gui/firmware.json` in this repository,
+//! kept up to date by hand. Installing an update still takes the Sony app: the image would be sent over the
+//! Sony channel in chunks of the size the headphones allow, which isn't reverse engineered either. That
+//! transfer belongs here, as what to do about an [UpdateStatus::Available].
+
+use serde::Deserialize;
+use sony_wf1000xm5::compatibility::compare_versions;
+use std::{cmp::Ordering, collections::HashMap, time::Duration};
+
+pub const METADATA_URL: &str = "https://raw.githubusercontent.com/usering-around/sony-wf1000xm5-controller/main/controller-gui/firmware.json";
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct LatestFirmware {
+    pub version: String,
+    /// What changed, e.g. Sony's support page of the update
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+/// The newest firmware of each model, by the model name the headphones report
+pub type FirmwareMetadata = HashMap<String, LatestFirmware>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UpdateStatus {
+    UpToDate,
+    Available(LatestFirmware),
+    /// Nothing is known about the model's firmware
+    UnknownModel,
+}
+
+/// Compare the `installed` firmware of `model_name` with the newest one in `metadata`
+pub fn check(metadata: &FirmwareMetadata, model_name: &str, installed: &str) -> UpdateStatus {
+    match metadata.get(model_name) {
+        None => UpdateStatus::UnknownModel,
+        Some(latest) => match compare_versions(installed, &latest.version) {
+            Ordering::Less => UpdateStatus::Available(latest.clone()),
+            // newer than we know of, e.g. before firmware.json was updated
+            Ordering::Equal | Ordering::Greater => UpdateStatus::UpToDate,
+        },
+    }
+}
+
+pub fn parse_metadata(json: &str) -> Result<FirmwareMetadata, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+/// Download the metadata on a blocking thread, so the UI doesn't wait for it
+async fn fetch_metadata() -> Result<FirmwareMetadata, String> {
+    let json = tokio::task::spawn_blocking(|| {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_global(Some(FETCH_TIMEOUT))
+            .build()
+            .into();
+        agent.get(METADATA_URL).call()?.body_mut().read_to_string()
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("couldn't download the firmware versions: {e}"))?;
+    parse_metadata(&json).map_err(|e| format!("couldn't read the firmware versions: {e}"))
+}
+
+/// Whether newer firmware than `installed` is out for `model_name`
+pub async fn check_for_update(
+    model_name: String,
+    installed: String,
+) -> Result<UpdateStatus, String> {
+    let metadata = fetch_metadata().await?;
+    Ok(check(&metadata, &model_name, &installed))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn versions() {
+        let metadata = parse_metadata(
+            r#"{"WF-1000XM5": {"version": "3.1.2"}, "WH-1000XM5": {"version": "2.2.0", "notes": "https://example.com"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            check(&metadata, "WF-1000XM5", "3.1.2"),
+            UpdateStatus::UpToDate
+        );
+        assert_eq!(
+            check(&metadata, "WF-1000XM5", "3.10.0"),
+            UpdateStatus::UpToDate
+        );
+        assert_eq!(
+            check(&metadata, "WH-1000XM5", "2.1.9"),
+            UpdateStatus::Available(LatestFirmware {
+                version: "2.2.0".to_string(),
+                notes: Some("https://example.com".to_string()),
+            })
+        );
+        assert_eq!(
+            check(&metadata, "LinkBuds S", "1.0"),
+            UpdateStatus::UnknownModel
+        );
+    }
+
+    #[test]
+    fn repository_metadata() {
+        let metadata = parse_metadata(include_str!("../firmware.json")).unwrap();
+        assert!(metadata.contains_key("WF-1000XM5"));
+    }
+}
@@ -0,0 +1,155 @@
+use chrono::Local;
+use sony_wf1000xm5::{MessageType, payload::ParsePayloadError};
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// The report file keeps at most this many frames
+const MAX_FRAMES: usize = 50;
+/// Frames which come in faster than this after the last captured one are dropped
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Saves the payloads we couldn't parse to a small report file which can be attached to an issue.
+///
+/// Opt-in, and bounded: only new frames are written, at most one per [MIN_INTERVAL],
+/// and never more than [MAX_FRAMES] in total.
+pub struct FrameCapture {
+    enabled: Arc<AtomicBool>,
+    path: PathBuf,
+    /// `None` until the existing report was read
+    seen: Option<HashSet<String>>,
+    last_capture: Option<Instant>,
+}
+
+impl FrameCapture {
+    pub const FILE_NAME: &str = "unparsed-frames.txt";
+    pub const ENABLED_KEY: &str = "FRAME_CAPTURE_ENABLED";
+
+    /// `enabled` can be flipped at any time, e.g. from the UI thread
+    pub fn new(path: PathBuf, enabled: Arc<AtomicBool>) -> Self {
+        Self {
+            enabled,
+            path,
+            seen: None,
+            last_capture: None,
+        }
+    }
+
+    /// Append the frame to the report, unless capturing is off or the frame is rate limited or already there.
+    /// Returns whether it was written.
+    pub fn capture(
+        &mut self,
+        message_type: MessageType,
+        payload: &[u8],
+        error: &ParsePayloadError,
+        now: Instant,
+    ) -> io::Result<bool> {
+        if !self.enabled.load(Ordering::Relaxed)
+            || self
+                .last_capture
+                .is_some_and(|last| now.duration_since(last) < MIN_INTERVAL)
+        {
+            return Ok(false);
+        }
+        let seen = match self.seen.as_mut() {
+            Some(seen) => seen,
+            None => self.seen.insert(read_report(&self.path)?),
+        };
+        let key = format!("{message_type:?} {}", to_hex(payload));
+        if seen.len() >= MAX_FRAMES || seen.contains(&key) {
+            return Ok(false);
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        // time, message type, payload, error
+        writeln!(file, "{} {key} {error}", Local::now().to_rfc3339())?;
+        seen.insert(key);
+        self.last_capture = Some(now);
+        Ok(true)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The message type and payload of every frame in the report
+fn read_report(path: &Path) -> io::Result<HashSet<String>> {
+    let report = match fs::read_to_string(path) {
+        Ok(report) => report,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(report
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ').skip(1);
+            Some(format!("{} {}", fields.next()?, fields.next()?))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bounded() {
+        let dir = std::env::temp_dir().join(format!("xm5-capture-test-{}", std::process::id()));
+        let path = dir.join(FrameCapture::FILE_NAME);
+        let _ = fs::remove_dir_all(&dir);
+        let enabled = Arc::new(AtomicBool::new(false));
+        let mut capture = FrameCapture::new(path.clone(), enabled.clone());
+        let error = ParsePayloadError::UnknownPayloadType { kind: 0x70 };
+        let now = std::cell::Cell::new(Instant::now());
+        let frame = |capture: &mut FrameCapture, payload: &[u8]| {
+            now.set(now.get() + MIN_INTERVAL);
+            capture
+                .capture(MessageType::Command1, payload, &error, now.get())
+                .unwrap()
+        };
+
+        // opt-in
+        assert!(!frame(&mut capture, &[0x70]));
+        enabled.store(true, Ordering::Relaxed);
+        assert!(frame(&mut capture, &[0x70]));
+        // repeats aren't written again, even after a restart
+        assert!(!frame(&mut capture, &[0x70]));
+        let mut capture = FrameCapture::new(path.clone(), enabled);
+        assert!(!frame(&mut capture, &[0x70]));
+        // rate limited
+        assert!(frame(&mut capture, &[0x71]));
+        assert!(
+            !capture
+                .capture(MessageType::Command1, &[0x72], &error, now.get())
+                .unwrap()
+        );
+
+        for kind in 0..=u8::MAX {
+            frame(&mut capture, &[0x80, kind]);
+        }
+        let report = fs::read_to_string(&path).unwrap();
+        assert_eq!(report.lines().count(), MAX_FRAMES);
+        assert!(
+            report
+                .lines()
+                .next()
+                .unwrap()
+                .ends_with(" Command1 70 Unknown payload type: 0x70")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -6,20 +6,30 @@ use bluer::{
 use eframe::egui::Context;
 #[cfg(not(target_arch = "wasm32"))]
 use futures::StreamExt;
-use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, pin_mut};
+#[cfg(target_arch = "wasm32")]
+use futures::{AsyncRead, AsyncWrite};
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::frame_capture::FrameCapture;
+use crate::protocol_log::ProtocolEvent;
+#[cfg(target_arch = "wasm32")]
 use anyhow::bail;
 use log::debug;
+#[cfg(not(target_arch = "wasm32"))]
+use sony_wf1000xm5::{MessageType, payload::ParsePayloadError};
 use sony_wf1000xm5::{
-    MessageType,
     command::Command,
-    frame_parser::{FrameParser, FrameParserResult},
+    connection::{self, Commands, Frontend},
     payload::Payload,
+    session::Session as HeadphoneSession,
+    trace::TracedFrame,
 };
 #[cfg(target_arch = "wasm32")]
 use std::pin::Pin;
-use std::time::Duration;
-use tokio::sync::mpsc;
+use std::{cell::Cell, rc::Rc, time::Duration};
+#[cfg(not(target_arch = "wasm32"))]
+use thiserror::Error;
+use tokio::sync::{Notify, mpsc};
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen_futures::JsFuture;
 #[cfg(target_arch = "wasm32")]
@@ -29,19 +39,27 @@ use wasm_streams::{
 #[cfg(target_arch = "wasm32")]
 use web_sys::SerialPort;
 #[cfg(not(target_arch = "wasm32"))]
-const SONY_SERVICE_UUID: Uuid = Uuid::from_u128(0x956C7B26_D49A_4BA8_B03F_B17D393CB6E2);
+pub(crate) const SONY_SERVICE_UUID: Uuid = Uuid::from_u128(0x956C7B26_D49A_4BA8_B03F_B17D393CB6E2);
 
+/// The Sony service didn't accept our connection, either because the device isn't a WF-1000XM5
+/// or because another device (usually the phone) owns the channel.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Error)]
+#[error(
+    "Unable to connect to sony service. Are you sure it's a WF-1000XM5? Another device (e.g. your phone) may be using it."
+)]
+pub struct SonyServiceUnavailable;
+
+/// Connect to `device` and run the connection (see [connection::run]) until it's stopped or it ends. It's a local
+/// task on the UI's runtime like the others, since the stream is async anyway.
 #[cfg(not(target_arch = "wasm32"))]
-#[tokio::main(flavor = "current_thread")]
-pub async fn thread_main(
+pub async fn run(
     device: Device,
-    payload_tx: mpsc::UnboundedSender<Payload>,
-    command_rx: mpsc::UnboundedReceiver<Command>,
-    mut stop_rx: mpsc::Receiver<()>,
+    event_tx: mpsc::Sender<ConnectionEvent>,
+    commands: Commands,
     ctx: Context,
+    frame_capture: Option<FrameCapture>,
 ) -> anyhow::Result<()> {
-    use tokio_util::compat::TokioAsyncReadCompatExt;
-
     debug!("attempting to connect...");
     device.connect().await?;
     debug!("connected!");
@@ -54,7 +72,7 @@ pub async fn thread_main(
     let session = Session::new().await?;
     let mut profile_handle = session.register_profile(profile).await?;
     let connection = tokio::select! {
-        _ = stop_rx.recv() => {
+        _ = commands.stopped() => {
             return Ok(());
         }
         Some(connection_request) = profile_handle.next() => {
@@ -63,25 +81,27 @@ pub async fn thread_main(
 
         _ = tokio::time::sleep(Duration::from_secs(5)) => {
             debug!("(exiting with an error)");
-            bail!("Unable to connect to sony service. Are you sure it's a WF-1000XM5?");
+            return Err(SonyServiceUnavailable.into());
         }
     };
     debug!("connection request: {:?}", connection);
     let stream = connection.accept()?;
-    let stream = stream.compat();
-    connect(stream, payload_tx, command_rx, stop_rx, ctx).await?;
+    let session = session_for(&event_tx, &ctx);
+    let ui = Ui { ctx, frame_capture };
+    connection::run(stream, commands, event_tx, session, ui).await?;
 
     Ok(())
 }
 
+/// Open `port` and run the connection until it's stopped or it ends
 #[cfg(target_arch = "wasm32")]
-pub async fn thread_main(
+pub async fn run(
     port: SerialPort,
-    payload_tx: mpsc::UnboundedSender<Payload>,
-    command_rx: mpsc::UnboundedReceiver<Command>,
-    stop_rx: mpsc::Receiver<()>,
+    event_tx: mpsc::Sender<ConnectionEvent>,
+    commands: Commands,
     ctx: Context,
 ) -> anyhow::Result<()> {
+    use tokio_util::compat::FuturesAsyncReadCompatExt;
     use web_sys::SerialOptions;
 
     if let Err(e) = JsFuture::from(port.open(&SerialOptions::new(9600))).await {
@@ -94,10 +114,14 @@ pub async fn thread_main(
         writeable_stream,
     };
     let ctxx = ctx.clone();
-    connect(web_stream, payload_tx, command_rx, stop_rx, ctx).await?;
+    let session = session_for(&event_tx, &ctx);
+    let result =
+        connection::run(web_stream.compat(), commands, event_tx, session, Ui { ctx }).await;
+    // also when the connection failed, so the port can be opened again to reconnect
     if let Err(e) = JsFuture::from(port.close()).await {
         bail!("Couldn't close serial port: {e:?}");
     };
+    result?;
     debug!("thread main died peacefully");
     // notify the GUI about it
     ctxx.request_repaint();
@@ -147,142 +171,144 @@ impl AsyncWrite for WebSerialStream {
     }
 }
 
-async fn connect(
-    stream: impl AsyncRead + AsyncWrite,
-    payload_tx: mpsc::UnboundedSender<Payload>,
-    mut command_rx: mpsc::UnboundedReceiver<Command>,
-    mut stop_rx: mpsc::Receiver<()>,
-    ctx: Context,
-) -> anyhow::Result<()> {
-    let mut frame_parser = FrameParser::new();
-    let mut seq_number = 0;
-    let init_command = sony_wf1000xm5::command::build_command(&Command::Init, seq_number);
-    debug!("init_command: {:x?}", init_command);
-    let mut tries = 3;
-    pin_mut!(stream);
-    stream.write_all(&init_command).await?;
-    let mut buffer = [0];
-    let sleep = async |duration| {
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            tokio::time::sleep(duration).await
-        }
-        #[cfg(target_arch = "wasm32")]
-        {
-            gloo_timers::future::sleep(duration).await
-        }
-    };
+/// Counts the connection tasks which still run, so the app can let them close in order before it exits
+#[derive(Clone, Default)]
+pub struct OpenConnections(Rc<OpenConnectionsInner>);
 
-    loop {
-        tokio::select! {
-            _ = stop_rx.recv() => {
-                return Ok(());
-            }
+#[derive(Default)]
+struct OpenConnectionsInner {
+    count: Cell<usize>,
+    closed: Notify,
+}
 
-            Ok(_) = stream.read(&mut buffer) => {
-                // stream is alive
-                break;
-            }
-
-            _ =  sleep(Duration::from_millis(1500)) => {
-                if tries == 0 {
-                    anyhow::bail!("max retries failed; try connecting again");
-                }
-                debug!("init failed; retrying...");
-                stream.write_all(&init_command).await?;
-                tries -= 1;
-            }
+/// Dropped when its connection task ends, however it ends
+struct OpenConnection(OpenConnections);
 
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        let inner = &self.0.0;
+        inner.count.set(inner.count.get() - 1);
+        inner.closed.notify_waiters();
+    }
+}
 
+impl OpenConnections {
+    /// `connection`, counted until it ends
+    pub fn track<F: Future + 'static>(
+        &self,
+        connection: F,
+    ) -> impl Future<Output = F::Output> + 'static {
+        self.0.count.set(self.0.count.get() + 1);
+        let open = OpenConnection(self.clone());
+        async move {
+            let _open = open;
+            connection.await
         }
     }
-    // feed the 1 byte we read
-    frame_parser.parse(&buffer);
 
-    // communication must be done sequentially, so after a command we must wait for an Ack
-    // (we start with true because we wait for Ack for our init)
-    let mut waiting_for_ack = true;
-    'eventloop: loop {
-        tokio::select! {
-
-            _ = stop_rx.recv() => {
-                debug!("event loop received stop");
-                return Ok(());
+    /// Once every tracked connection ended
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn closed(&self) {
+        loop {
+            let closed = self.0.closed.notified();
+            if self.0.count.get() == 0 {
+                return;
             }
-            Ok(n) = stream.read(&mut buffer) => {
-                let mut offset = 0;
-                loop {
-                    match frame_parser.parse(&buffer[offset..n]) {
-
-                        FrameParserResult::Ready { msg, consumed} => {
-                            if let Err(e) = msg.kind {
-                                log::warn!("unknown message type: {e}; ignoring");
-                                continue;
-                            }
-                            if let Err(e) = msg.checksum.as_ref() {
-                                log::warn!("bad checksum: {e}; ignoring");
-                                continue;
-                            }
-                            debug!("msg: {msg:x?}");
-                            if msg.kind == Ok(MessageType::Ack) {
-                                seq_number = msg.seq_num;
-                                waiting_for_ack = false;
-                            } else if msg.kind == Ok(MessageType::Command1) || msg.kind == Ok(MessageType::Command2) {
-                                let payload = sony_wf1000xm5::payload::parse_payload(msg.payload, msg.kind.unwrap());
-                                debug!("payload: {:x?}", payload);
-
-                                let command = sony_wf1000xm5::command::build_command(&Command::Ack, msg.seq_num);
-                                debug!("responding: {:x?}", command);
-                                stream.write_all(&command).await?;
-
-                                match payload {
-                                    Ok(payload) => {
-                                        if payload_tx.send(payload).is_err() {
-                                            break 'eventloop;
-                                        }
-                                        ctx.request_repaint();
-                                    }
-
-                                    Err(e) => {
-                                        log::warn!("bad payload: {e}");
-                                    }
+            closed.await;
+        }
+    }
+}
 
-                                }
-                            }
+/// What the connection tells the UI
+#[derive(Debug)]
+pub enum ConnectionEvent {
+    Payload(Payload),
+    /// The headphones never acked the command, even after it was retransmitted
+    CommandTimedOut(Command),
+    /// Every frame sent and received, and what couldn't be parsed, for the protocol log
+    Protocol(ProtocolEvent),
+}
 
-                            offset += consumed;
-                            if offset >=  n {
-                                // we're done reading
-                                break;
-                            }
-                        }
+impl From<connection::ConnectionEvent> for ConnectionEvent {
+    fn from(event: connection::ConnectionEvent) -> Self {
+        match event {
+            connection::ConnectionEvent::Payload(payload) => Self::Payload(payload),
+            connection::ConnectionEvent::CommandTimedOut(command) => Self::CommandTimedOut(command),
+            connection::ConnectionEvent::Warning(warning) => {
+                Self::Protocol(ProtocolEvent::warning(warning))
+            }
+        }
+    }
+}
 
-                        FrameParserResult::Incomplete { .. } => {
-                            // we read more bytes
-                            break;
-                        }
+/// Repaints caused by payloads which stream in periodically are coalesced into one per this delay
+const STREAMING_REPAINT_DELAY: Duration = Duration::from_millis(100);
 
-                        FrameParserResult::Error { err, consumed } => {
-                            log::warn!("frame parser returned an error: {err}, consumed: {consumed}");
-                            anyhow::bail!("FrameParser failed. It is likely that the headphone sent a malformed request. Reconnect.");
-                        }
+/// The UI as the connection sees it, which takes the events when it repaints
+struct Ui {
+    ctx: Context,
+    #[cfg(not(target_arch = "wasm32"))]
+    frame_capture: Option<FrameCapture>,
+}
 
+impl Frontend for Ui {
+    fn wake(&mut self) {
+        self.ctx.request_repaint();
+    }
 
-                    }
-                }
+    fn wake_later(&mut self) {
+        self.ctx.request_repaint_after(STREAMING_REPAINT_DELAY);
+    }
 
+    // there's no file to save unparsed frames to on the web
+    #[cfg(not(target_arch = "wasm32"))]
+    fn invalid_payload(
+        &mut self,
+        message_type: MessageType,
+        payload: &[u8],
+        error: &ParsePayloadError,
+    ) {
+        if let Some(capture) = self.frame_capture.as_mut()
+            && let Err(e) = capture.capture(message_type, payload, error, std::time::Instant::now())
+        {
+            log::warn!("couldn't save the unparsed frame: {e}");
         }
+    }
+}
 
-            Some(command) = command_rx.recv(), if !waiting_for_ack => {
-                let command_bytes = sony_wf1000xm5::command::build_command(&command, seq_number);
-                debug!("sending: {:?}, raw: {:x?}", command, command_bytes);
-                stream
-                .write_all(&command_bytes)
-                .await?;
-                waiting_for_ack = true;
-            }
+/// A new session, which queued the Init command, and whose frames also go to the protocol log
+fn session_for(event_tx: &mpsc::Sender<ConnectionEvent>, ctx: &Context) -> HeadphoneSession {
+    let mut session = HeadphoneSession::new();
+    let frames_tx = event_tx.clone();
+    let ctx = ctx.clone();
+    // RUST_LOG=controller_gui=trace shows every frame, and so does the protocol log
+    session.set_tracer(move |frame: &TracedFrame| {
+        log::trace!("{frame}");
+        // read along with the next payload, which repaints anyway, unless the UI has to make room first
+        let event = ConnectionEvent::Protocol(ProtocolEvent::frame(frame));
+        if let Err(mpsc::error::TrySendError::Full(_)) = frames_tx.try_send(event) {
+            ctx.request_repaint();
         }
-    }
+    });
+    session
+}
 
-    Ok(())
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn open_connections() {
+        let open = OpenConnections::default();
+        let connection = open.track(std::future::ready(()));
+        // aborted, like a session's task when it's cancelled
+        drop(open.track(std::future::pending::<()>()));
+        assert!(
+            tokio::time::timeout(Duration::ZERO, open.closed())
+                .await
+                .is_err()
+        );
+        connection.await;
+        open.closed().await;
+    }
 }
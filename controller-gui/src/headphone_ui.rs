@@ -1,115 +1,941 @@
+#[cfg(target_os = "linux")]
+use crate::app_anc::{AppAncMapping, AppAncSettings, StreamKind, StreamWatcher};
 use crate::async_resource::AsyncResource;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::backup::{self, DeviceBackup};
+use crate::command_sender::CommandSender;
+use crate::developer_console::DeveloperConsole;
+#[cfg(target_os = "linux")]
+use crate::diagnostics;
+use crate::equalizer_curve;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::exposure::ExposureLog;
+use crate::exposure::{Exposure, Sample};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::frame_capture::FrameCapture;
+use crate::headphone_thread::ConnectionEvent;
+use crate::history::{ConflictDetector, HistoryEntry, HistoryLogSettings, LogEvent, StateHistory};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::history::{HistoryLog, LogRecord};
+#[cfg(target_os = "linux")]
+use crate::hotkeys::{self, HotkeyAction, HotkeySettings};
+#[cfg(target_os = "linux")]
+use crate::notifications::{Notice, NotificationSettings, Notifier};
+#[cfg(target_os = "linux")]
+use crate::pipewire;
+use crate::profiles::Profiles;
+use crate::protocol_log::ProtocolLog;
+#[cfg(target_os = "linux")]
+use crate::rules::{Action, Battery, Rule, RuleEngine, Rules, Trigger};
+use crate::settings::AppSettings;
+use crate::share::{self, SharedAnc, SharedConfig, SharedEqualizer};
+use crate::tr;
+use crate::widgets::{
+    AncControl, AncState, BatteryRow, DEFAULT_AMBIENT_RANGE, EqualizerSliders, PresetPicker,
+};
+#[cfg(target_os = "linux")]
+use crate::{
+    async_resource::ResourceStatus,
+    eq_file::{self, EqFileError},
+    firmware_update,
+};
 use eframe::egui::{self, RichText, Slider, Ui};
-#[cfg(target_arch = "wasm32")]
-use futures::StreamExt;
 use sony_wf1000xm5::{
-    command::{AncMode, BatteryType, Command, EqualizerPreset},
-    payload::{BatteryLevel, Codec, Payload},
+    command::{AncMode, Command, EqualizerPreset, QuickAccessApp, SpeakToChatTimeout},
+    compatibility::{DeviceInfo, compatibility_report},
+    connection::{self, ConnectionHandle},
+    model::Model,
+    payload::{BatteryComponent, BatteryPercent, Capabilities, Codec, Payload},
+    snapshot::{EqualizerSnapshot, HeadphoneSnapshot, SnapshotField},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use std::{
+    cell::{Cell, RefCell},
+    ops::RangeInclusive,
+    rc::Rc,
+    time::Duration,
 };
 use tokio::sync::mpsc;
 
-#[derive(PartialEq, Eq)]
-struct Equalizer {
-    preset: EqualizerPreset,
-    clear_bass: i8,
-    band_400: i8,
-    band_1000: i8,
-    band_2500: i8,
-    band_6300: i8,
-    band_16000: i8,
+/// How long to wait for the reply to a command, including its retransmissions
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// The headphones report the batteries when they change, so they're only asked again after this long without
+/// a report, in case one got lost
+const BATTERY_FALLBACK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// Of the section labels, in points; the UI scale setting scales it along with everything else
+const SECTION_TEXT_SIZE: f32 = 25.0;
+/// The least height of the controls of the headphones, in points
+const MIN_HIT_TARGET_SIZE: f32 = 28.0;
+/// Asked for on connecting, like the others, but only while the guessed commands are allowed
+const GUESSED_QUERIES: [Command; 7] = [
+    Command::GetSupportedFunctions,
+    Command::GetAmbientSoundRange,
+    Command::GetCallVoiceFocus,
+    Command::GetSidetoneLevel,
+    Command::GetQuickAccess,
+    Command::GetSpatialAudioStatus,
+    Command::GetSpeakToChatTimeout,
+];
+
+struct QuickAccess {
+    double_tap: QuickAccessApp,
+    triple_tap: QuickAccessApp,
 }
 
 #[derive(Default)]
 struct HeadphoneState {
-    case_battery: Option<usize>,
-    left_ear_battery: Option<usize>,
-    right_ear_battery: Option<usize>,
-    equalizer: Option<Equalizer>,
-    anc_mode: Option<AncMode>,
-    ambient_slider: Option<usize>,
-    voice_passthrough: Option<bool>,
+    equalizer: Option<EqualizerSnapshot>,
+    anc: Option<AncState>,
+    /// Reported by the headphones, since it differs between firmware versions
+    ambient_range: Option<RangeInclusive<usize>>,
     codec: Option<Codec>,
     sound_pressure_db: Option<usize>,
-    sound_pressure_poll_task: AsyncResource<()>,
+    /// The headphones confirmed they're measuring, so there's something to read
+    measuring_sound_pressure: bool,
+    call_voice_focus: Option<bool>,
+    sidetone_level: Option<u8>,
+    spatial_audio_ear_measured: Option<bool>,
+    speak_to_chat_timeout: Option<SpeakToChatTimeout>,
+    /// What the headphones reported they support. Only for bug reports: the bit positions are guesses, and a
+    /// wrong one would hide a section the headphones do have.
+    capabilities: Option<Capabilities>,
+    device_info: DeviceInfo,
+    quick_access: Option<QuickAccess>,
+    /// Offer the controls which send [Command::is_guessed] commands
+    guessed_commands: bool,
+    /// The last low battery warning the headphones sent, until dismissed
+    low_battery: Option<(BatteryComponent, BatteryPercent)>,
+    /// Asks for the batteries of the model, once it's known, and again when they weren't reported for long
+    battery_query_task: AsyncResource<()>,
+    /// When the headphones last reported a battery, asked or not
+    battery_reported_at: Rc<Cell<Option<chrono::DateTime<chrono::Local>>>>,
+}
+
+impl HeadphoneState {
+    /// Whether to show the control which sends `command`
+    fn offers(&self, command: &Command) -> bool {
+        self.guessed_commands || !command.is_guessed()
+    }
+
+    fn shared_config(&self) -> SharedConfig {
+        SharedConfig {
+            equalizer: self.equalizer.as_ref().map(|eq| SharedEqualizer {
+                preset: eq.preset,
+                bands: eq.bands,
+            }),
+            anc: self.anc.map(|anc| SharedAnc {
+                mode: anc.mode,
+                ambient_sound_level: anc.ambient_level,
+                voice_passthrough: anc.voice_passthrough,
+            }),
+        }
+    }
+}
+
+/// The label of a section, with a spinner while a change made in it waits for the headphones to confirm it
+fn section_label(ui: &mut Ui, text: String, sender: &CommandSender, fields: &[SnapshotField]) {
+    let now = chrono::Local::now();
+    let pending = fields.iter().any(|field| sender.is_pending(*field));
+    let changed_externally = fields
+        .iter()
+        .filter_map(|field| sender.changed_externally(*field, now))
+        .max();
+    ui.horizontal(|ui| {
+        ui.label(RichText::new(text).strong().size(SECTION_TEXT_SIZE));
+        if pending {
+            ui.spinner().on_hover_text(tr!("headphones-pending"));
+        } else if let Some(time) = changed_externally {
+            ui.label(
+                RichText::new(tr!("headphones-changed-externally")).color(egui::Color32::YELLOW),
+            )
+            .on_hover_text(tr!(
+                "headphones-changed-externally-hover",
+                time = time.format("%H:%M:%S")
+            ));
+            // to take it away once it's old
+            ui.ctx().request_repaint_after(Duration::from_secs(1));
+        }
+    });
+}
+
+/// A warning and the button to dismiss it, which Tab reaches next; true once clicked
+fn dismissable_warning(ui: &mut Ui, text: String) -> bool {
+    ui.horizontal(|ui| {
+        ui.label(RichText::new(text).color(egui::Color32::YELLOW));
+        ui.button(tr!("dismiss")).clicked()
+    })
+    .inner
+}
+
+/// Makes the controls in `ui` easier to hit, with a pointer or a finger: buttons, sliders and combo boxes are
+/// taller, and the radio buttons and checkboxes bigger
+fn enlarge_hit_targets(ui: &mut Ui) {
+    let spacing = ui.spacing_mut();
+    spacing.interact_size.y = spacing.interact_size.y.max(MIN_HIT_TARGET_SIZE);
+    spacing.button_padding.y = spacing.button_padding.y.max(4.0);
+    spacing.icon_width = spacing.icon_width.max(18.0);
+    spacing.icon_width_inner = spacing.icon_width_inner.max(10.0);
+    spacing.item_spacing.y = spacing.item_spacing.y.max(6.0);
+}
+
+/// `preset` if its bands can be changed, or else the manual one
+fn adjustable_preset(preset: EqualizerPreset) -> EqualizerPreset {
+    if matches!(
+        preset,
+        EqualizerPreset::Manual | EqualizerPreset::Custom1 | EqualizerPreset::Custom2
+    ) {
+        preset
+    } else {
+        // we shouldn't (can't?) change non-custom/manual presets
+        EqualizerPreset::Manual
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct EqFileState {
+    /// `None` when no file was picked
+    import: AsyncResource<Option<Result<sony_wf1000xm5::command::EqualizerBands, EqFileError>>>,
+    export: AsyncResource<Option<Result<std::path::PathBuf, EqFileError>>>,
+    /// The result of the last import or export
+    status: Option<String>,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct HotkeysState {
+    /// Kept by the app, which registers them
+    settings: HotkeySettings,
+    /// The action whose shortcut is being recorded
+    recording: Option<HotkeyAction>,
+    /// Why the shortcuts couldn't be registered
+    error: Option<String>,
+}
+
+#[cfg(target_os = "linux")]
+struct RulesState {
+    /// Shared with the app, which loads them
+    rules: Rc<RefCell<Rules>>,
+    engine: RuleEngine,
+    /// The rule being added
+    trigger: Trigger,
+    action: Action,
+    /// Why the rules couldn't be saved
+    error: Option<String>,
+}
+
+#[cfg(target_os = "linux")]
+impl Default for RulesState {
+    fn default() -> Self {
+        Self {
+            rules: Rc::default(),
+            engine: RuleEngine::default(),
+            trigger: TRIGGERS[0].1,
+            action: ACTIONS[0].1.clone(),
+            error: None,
+        }
+    }
+}
+
+/// The kinds of rule triggers, with what they start out as when picked; the labels are message keys
+#[cfg(target_os = "linux")]
+const TRIGGERS: [(&str, Trigger); 3] = [
+    (
+        "rules-trigger-at",
+        Trigger::At(chrono::NaiveTime::from_hms_opt(22, 0, 0).unwrap()),
+    ),
+    ("rules-trigger-codec", Trigger::Codec(Codec::Sbc)),
+    (
+        "rules-trigger-battery",
+        Trigger::BatteryBelow {
+            battery: Battery::Any,
+            percent: 20,
+        },
+    ),
+];
+
+/// The kinds of rule actions, with what they start out as when picked; the labels are message keys
+#[cfg(target_os = "linux")]
+const ACTIONS: [(&str, Action); 3] = [
+    (
+        "rules-action-anc",
+        Action::SetAnc {
+            mode: AncMode::AmbientSound,
+            ambient_level: None,
+        },
+    ),
+    (
+        "rules-action-equalizer",
+        Action::SetEqualizerPreset(EqualizerPreset::Off),
+    ),
+    ("rules-action-notify", Action::Notify(String::new())),
+];
+
+#[cfg(target_os = "linux")]
+struct AppAncState {
+    settings: AppAncSettings,
+    /// Running while enabled
+    watcher: Option<StreamWatcher>,
+    /// The mode the streams last called for, to only switch when that changes
+    wanted: Option<AncMode>,
+    error: Option<String>,
+    /// The mapping being added
+    new: AppAncMapping,
+}
+
+#[cfg(target_os = "linux")]
+impl Default for AppAncState {
+    fn default() -> Self {
+        Self {
+            settings: AppAncSettings::default(),
+            watcher: None,
+            wanted: None,
+            error: None,
+            new: AppAncMapping {
+                application: String::new(),
+                kind: StreamKind::Microphone,
+                mode: AncMode::AmbientSound,
+            },
+        }
+    }
+}
+
+/// The last check for newer firmware, only done when asked for
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct FirmwareUpdateState {
+    check: AsyncResource<Result<firmware_update::UpdateStatus, String>>,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct DiagnosticsState {
+    /// `None` when no file was picked
+    export: AsyncResource<Option<Result<std::path::PathBuf, std::io::Error>>>,
+    /// The result of the last export
+    status: Option<String>,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct AudioOutputState {
+    /// The Bluetooth address of the headphones, to find them in PipeWire
+    address: Option<String>,
+    status: AsyncResource<Result<pipewire::AudioStatus, String>>,
+    /// The last change asked of PipeWire
+    change: AsyncResource<Result<(), String>>,
+}
+
+#[derive(Default)]
+struct ExposureState {
+    /// Today's samples, read from the log on the first one
+    exposure: Option<Exposure>,
+    #[cfg(not(target_arch = "wasm32"))]
+    log: Option<ExposureLog>,
+    error: Option<String>,
+}
+
+impl ExposureState {
+    fn exposure(&mut self) -> &mut Exposure {
+        let today = chrono::Local::now().date_naive();
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.exposure.is_none() {
+            let samples = match eframe::storage_dir(crate::app::App::NAME) {
+                Some(dir) => match ExposureLog::open(&dir.join(ExposureLog::FILE_NAME)) {
+                    Ok((log, samples)) => {
+                        self.log = Some(log);
+                        samples
+                    }
+                    Err(e) => {
+                        log::warn!("couldn't open the exposure log: {e}");
+                        self.error = Some(tr!("exposure-open-failed", error = e));
+                        Vec::new()
+                    }
+                },
+                None => Vec::new(),
+            };
+            self.exposure = Some(Exposure::new(today, samples));
+        }
+        self.exposure
+            .get_or_insert_with(|| Exposure::new(today, []))
+    }
+
+    fn record(&mut self, db: usize) {
+        let sample = Sample {
+            time: chrono::Local::now(),
+            db: db as f64,
+        };
+        self.exposure().record(sample);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(log) = self.log.as_mut()
+            && let Err(e) = log.append(&sample)
+        {
+            log::warn!("couldn't write the exposure log: {e}");
+            self.error = Some(tr!("exposure-write-failed", error = e));
+            self.log = None;
+        }
+    }
+}
+
+#[derive(Default)]
+struct ProfilesState {
+    /// Shared with the app, which saves them
+    profiles: Rc<RefCell<Profiles>>,
+    /// The name to save the current settings under
+    name: String,
+}
+
+#[derive(Default)]
+struct ShareState {
+    input: String,
+    error: Option<String>,
+    show_qr: bool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+struct BackupState {
+    /// The result of the last backup or restore
+    status: Option<String>,
+    /// Read from the backup directory when the section is first shown
+    saved: Option<Vec<std::path::PathBuf>>,
 }
 
 pub struct HeadphoneUi {
-    request_send: mpsc::UnboundedSender<Command>,
-    payload_recv: mpsc::UnboundedReceiver<Payload>,
-    stop_connection: mpsc::Sender<()>,
+    request_send: CommandSender,
+    event_recv: mpsc::Receiver<ConnectionEvent>,
     headphone_state: HeadphoneState,
+    share: ShareState,
+    profiles: ProfilesState,
+    exposure: ExposureState,
+    #[cfg(target_os = "linux")]
+    eq_file: EqFileState,
+    #[cfg(not(target_arch = "wasm32"))]
+    backup: BackupState,
+    /// The last command the headphones rejected, until dismissed
+    command_error: Option<String>,
+    /// Mirrors the state from the payloads, to compute the history
+    snapshot: HeadphoneSnapshot,
+    /// When the headphones last sent anything, for the status bar
+    last_payload_at: Option<chrono::DateTime<chrono::Local>>,
+    history: StateHistory,
+    conflicts: ConflictDetector,
+    history_settings: HistoryLogSettings,
+    /// Opened on the first record after the history log was enabled
+    #[cfg(not(target_arch = "wasm32"))]
+    history_log: Option<HistoryLog>,
+    history_log_error: Option<String>,
+    /// Shared with the connection task, which does the capturing
+    #[cfg(not(target_arch = "wasm32"))]
+    frame_capture: Arc<AtomicBool>,
+    protocol_log: ProtocolLog,
+    /// How often the sound pressure is read while it's measured
+    sound_pressure_interval: Duration,
+    /// Nothing is polled, see [Self::set_polling_paused]
+    polling_paused: bool,
+    /// Only in developer mode
+    developer_console: Option<DeveloperConsole>,
+    #[cfg(target_os = "linux")]
+    notifier: Notifier,
+    #[cfg(target_os = "linux")]
+    hotkeys: HotkeysState,
+    #[cfg(target_os = "linux")]
+    rules: RulesState,
+    #[cfg(target_os = "linux")]
+    app_anc: AppAncState,
+    #[cfg(target_os = "linux")]
+    audio_output: AudioOutputState,
+    #[cfg(target_os = "linux")]
+    firmware_update: FirmwareUpdateState,
+    #[cfg(target_os = "linux")]
+    diagnostics: DiagnosticsState,
     is_connected: bool,
 }
 
 impl HeadphoneUi {
     pub fn new(
-        request_send: mpsc::UnboundedSender<Command>,
-        payload_recv: mpsc::UnboundedReceiver<Payload>,
-        stop_connection: mpsc::Sender<()>,
+        connection: ConnectionHandle,
+        event_recv: mpsc::Receiver<ConnectionEvent>,
+        history_settings: HistoryLogSettings,
+        read_only: bool,
     ) -> Self {
         Self {
-            request_send,
-            payload_recv,
-            stop_connection,
+            request_send: CommandSender::new(connection, read_only),
+            event_recv,
             headphone_state: HeadphoneState::default(),
+            share: ShareState::default(),
+            profiles: ProfilesState::default(),
+            exposure: ExposureState::default(),
+            #[cfg(target_os = "linux")]
+            eq_file: EqFileState::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            backup: BackupState::default(),
+            command_error: None,
+            snapshot: HeadphoneSnapshot::default(),
+            last_payload_at: None,
+            history: StateHistory::new(),
+            conflicts: ConflictDetector::default(),
+            history_settings,
+            #[cfg(not(target_arch = "wasm32"))]
+            history_log: None,
+            history_log_error: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            frame_capture: Arc::default(),
+            protocol_log: ProtocolLog::default(),
+            sound_pressure_interval: AppSettings::default().sound_pressure_interval(),
+            polling_paused: false,
+            developer_console: None,
+            #[cfg(target_os = "linux")]
+            notifier: Notifier::default(),
+            #[cfg(target_os = "linux")]
+            hotkeys: HotkeysState::default(),
+            #[cfg(target_os = "linux")]
+            rules: RulesState::default(),
+            #[cfg(target_os = "linux")]
+            app_anc: AppAncState::default(),
+            #[cfg(target_os = "linux")]
+            audio_output: AudioOutputState::default(),
+            #[cfg(target_os = "linux")]
+            firmware_update: FirmwareUpdateState::default(),
+            #[cfg(target_os = "linux")]
+            diagnostics: DiagnosticsState::default(),
             is_connected: false,
         }
     }
 
+    pub fn history_settings(&self) -> HistoryLogSettings {
+        self.history_settings
+    }
+
+    /// The settings edited with other headphones
+    pub fn set_history_settings(&mut self, settings: HistoryLogSettings) {
+        if settings != self.history_settings {
+            self.history_settings = settings;
+            // opened again with the new retention
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                self.history_log = None;
+            }
+        }
+    }
+
+    /// The flag which turns on saving unparsed frames in the connection task
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_frame_capture(&mut self, enabled: Arc<AtomicBool>) {
+        self.frame_capture = enabled;
+    }
+
+    /// Takes effect right away if the sound pressure is being read
+    pub fn set_sound_pressure_interval(&mut self, interval: Duration) {
+        self.sound_pressure_interval = interval;
+        if self.headphone_state.measuring_sound_pressure && !self.polling_paused {
+            self.poll_sound_pressure(true);
+        }
+    }
+
+    /// Have the connection read the sound pressure every [Self::set_sound_pressure_interval], or stop it
+    fn poll_sound_pressure(&self, on: bool) {
+        self.request_send.set_periodic(
+            Command::GetSoundPressure,
+            on.then_some(self.sound_pressure_interval),
+        );
+    }
+
+    /// Stop reading the sound pressure while the window is out of sight, in power saving mode.
+    /// The headphones keep measuring, and the reading goes on once the window is back.
+    pub fn set_polling_paused(&mut self, paused: bool) {
+        if paused == self.polling_paused {
+            return;
+        }
+        self.polling_paused = paused;
+        if self.headphone_state.measuring_sound_pressure {
+            self.poll_sound_pressure(!paused);
+        }
+    }
+
+    /// Show the developer console, see [crate::developer_console]
+    pub fn set_developer_mode(&mut self, enabled: bool) {
+        if enabled != self.developer_console.is_some() {
+            self.developer_console = enabled.then(DeveloperConsole::default);
+        }
+    }
+
+    /// Offer the controls which send commands whose bytes are guesses, see [Command::is_guessed]
+    pub fn set_guessed_commands(&mut self, enabled: bool) {
+        let was_enabled = self.headphone_state.guessed_commands;
+        self.headphone_state.guessed_commands = enabled;
+        if enabled && !was_enabled && self.is_connected {
+            self.query_guessed();
+        }
+    }
+
+    /// Ask for what only the guessed commands read, while they're allowed, see [Command::is_guessed]
+    fn query_guessed(&mut self) {
+        for command in GUESSED_QUERIES {
+            if self.headphone_state.offers(&command) {
+                self.request_send.send(command);
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn notification_settings(&self) -> NotificationSettings {
+        self.notifier.settings
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set_notification_settings(&mut self, settings: NotificationSettings) {
+        self.notifier.settings = settings;
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn hotkey_settings(&self) -> &HotkeySettings {
+        &self.hotkeys.settings
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set_hotkey_settings(&mut self, settings: HotkeySettings) {
+        self.hotkeys.settings = settings;
+    }
+
+    /// Shown with the shortcuts, see [crate::hotkeys::Hotkeys::error]
+    #[cfg(target_os = "linux")]
+    pub fn set_hotkeys_error(&mut self, error: Option<String>) {
+        self.hotkeys.error = error;
+    }
+
+    /// Do what the shortcut of `action` was pressed for
+    #[cfg(target_os = "linux")]
+    pub fn on_hotkey(&self, action: HotkeyAction) {
+        match action {
+            HotkeyAction::CycleAnc => {
+                if let Some(anc) = self.snapshot.anc {
+                    self.set_anc_mode(hotkeys::next_anc_mode(anc.mode));
+                }
+            }
+            HotkeyAction::NextEqualizerPreset | HotkeyAction::PreviousEqualizerPreset => {
+                if let Some(equalizer) = self.snapshot.equalizer.as_ref() {
+                    self.set_equalizer_preset(hotkeys::step_equalizer_preset(
+                        equalizer.preset,
+                        action == HotkeyAction::NextEqualizerPreset,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// The Bluetooth address of the headphones, for the PipeWire panel
+    #[cfg(target_os = "linux")]
+    pub fn set_device_address(&mut self, address: String) {
+        self.audio_output.address = Some(address);
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn app_anc_settings(&self) -> &AppAncSettings {
+        &self.app_anc.settings
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set_app_anc_settings(&mut self, settings: AppAncSettings) {
+        self.app_anc.settings = settings;
+    }
+
+    /// Switch the noise canceling mode when the streams call for another one
+    #[cfg(target_os = "linux")]
+    fn update_app_anc(&mut self, ctx: &egui::Context) {
+        let state = &mut self.app_anc;
+        if !state.settings.enabled {
+            state.watcher = None;
+            state.wanted = None;
+            state.error = None;
+            return;
+        }
+        let Some(streams) = state
+            .watcher
+            .get_or_insert_with(|| StreamWatcher::spawn(ctx))
+            .changed()
+        else {
+            return;
+        };
+        let streams = match streams {
+            Ok(streams) => streams,
+            Err(e) => {
+                state.error = Some(e);
+                return;
+            }
+        };
+        state.error = None;
+        let wanted = state.settings.mode_for(&streams);
+        if wanted != state.wanted {
+            state.wanted = wanted;
+            if let Some(mode) = wanted
+                && self.snapshot.anc.is_some_and(|anc| anc.mode != mode)
+            {
+                log::info!("switching to {mode:?} for {streams:?}");
+                self.set_anc_mode(mode);
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set_rules(&mut self, rules: Rc<RefCell<Rules>>) {
+        self.rules.rules = rules;
+    }
+
+    /// Run the time rules which are due
+    #[cfg(target_os = "linux")]
+    fn tick_rules(&mut self, ctx: &egui::Context) {
+        let rules = self.rules.rules.clone();
+        let rules = rules.borrow();
+        let now = chrono::Local::now().naive_local();
+        for rule in self.rules.engine.on_tick(&rules, now) {
+            self.run_rule(rule);
+        }
+        if rules
+            .rules
+            .iter()
+            .any(|rule| rule.enabled && matches!(rule.trigger, Trigger::At(_)))
+        {
+            // also while the window is hidden in the tray
+            ctx.request_repaint_after(std::time::Duration::from_secs(10));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn run_rule(&self, rule: Rule) {
+        log::info!("running rule {rule}");
+        match rule.action {
+            Action::SetAnc {
+                mode,
+                ambient_level,
+            } => self.set_anc(mode, ambient_level),
+            Action::SetEqualizerPreset(preset) => self.set_equalizer_preset(preset),
+            Action::Notify(message) => Notice {
+                summary: message,
+                body: tr!("rules-notification", trigger = rule.trigger),
+                urgent: false,
+            }
+            .show(),
+        }
+    }
+
+    /// Tell the user the connection dropped with `error`, if it was up
+    #[cfg(target_os = "linux")]
+    pub fn notify_disconnect(&self, error: &str) {
+        if self.is_connected
+            && let Some(notice) = self.notifier.on_disconnect(error)
+        {
+            notice.show();
+        }
+    }
+
+    /// The connection ended: forget the state of the headphones, which may change before they're back
+    pub fn set_disconnected(&mut self) {
+        if self.is_connected {
+            self.log(LogEvent::Disconnected);
+        }
+        self.is_connected = false;
+        self.poll_sound_pressure(false);
+        self.headphone_state.battery_query_task.cancel();
+        self.headphone_state = HeadphoneState::default();
+        // they may come back updated
+        #[cfg(target_os = "linux")]
+        self.firmware_update.check.clear();
+        // the model and firmware stay, to tell which headphones they were
+        self.snapshot = HeadphoneSnapshot {
+            device_info: std::mem::take(&mut self.snapshot.device_info),
+            ..Default::default()
+        };
+    }
+
+    pub fn set_profiles(&mut self, profiles: Rc<RefCell<Profiles>>) {
+        self.profiles.profiles = profiles;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.request_send.is_read_only()
+    }
+
+    /// Save `event` to the history file, if enabled
+    fn log(&mut self, event: LogEvent) {
+        if !self.history_settings.enabled {
+            return;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let record = LogRecord {
+                time: chrono::Local::now(),
+                event,
+            };
+            let result = match self.history_log.as_mut() {
+                Some(log) => log.append(&record),
+                None => {
+                    let Some(dir) = eframe::storage_dir(crate::app::App::NAME) else {
+                        self.history_log_error = Some(tr!("history-no-directory"));
+                        self.history_settings.enabled = false;
+                        return;
+                    };
+                    HistoryLog::open(
+                        &dir.join(HistoryLog::FILE_NAME),
+                        self.history_settings.retention(),
+                    )
+                    .and_then(|log| self.history_log.insert(log).append(&record))
+                }
+            };
+            if let Err(e) = result {
+                log::warn!("couldn't write the history log: {e}");
+                self.history_log_error = Some(tr!("history-write-failed", error = e));
+                self.history_settings.enabled = false;
+                self.history_log = None;
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = event;
+    }
+
     pub fn is_connected(&self) -> bool {
         self.is_connected
     }
+
+    /// What the headphones told us so far
+    pub fn snapshot(&self) -> &HeadphoneSnapshot {
+        &self.snapshot
+    }
+
+    pub fn last_payload_at(&self) -> Option<chrono::DateTime<chrono::Local>> {
+        self.last_payload_at
+    }
+
+    /// Switch the noise canceling mode, keeping the ambient sound settings
+    pub fn set_anc_mode(&self, mode: AncMode) {
+        self.set_anc(mode, None);
+    }
+
+    /// Like [Self::set_anc_mode], with another ambient sound level if there's one
+    fn set_anc(&self, mode: AncMode, ambient_level: Option<usize>) {
+        self.request_send.send(Command::AncSet {
+            dragging_ambient_sound_slider: false,
+            mode,
+            ambient_sound_voice_passthrough: self
+                .headphone_state
+                .anc
+                .is_some_and(|anc| anc.voice_passthrough),
+            ambient_sound_level: ambient_level
+                .or(self.headphone_state.anc.map(|anc| anc.ambient_level))
+                .unwrap_or(0),
+        });
+    }
+
+    pub fn set_equalizer_preset(&self, preset: EqualizerPreset) {
+        self.request_send
+            .send(Command::ChangeEqualizerPreset { preset });
+    }
+
+    /// Like the disconnect button
+    pub fn disconnect(&self) {
+        let _ = self.request_send.stop();
+    }
     fn handle_payload(&mut self, payload: Payload) {
+        let now = chrono::Local::now();
+        self.last_payload_at = Some(now);
+        self.request_send.confirm(&payload);
+        for change in self.snapshot.apply(&payload) {
+            // the first value we get is just us reading the state
+            let external = change.old.is_some() && !self.request_send.is_ours(&change, now);
+            if external {
+                self.request_send.record_external(change.field, now);
+            }
+            if external && self.request_send.is_overwrite(&change, now) {
+                self.conflicts.record_overwrite(now);
+            }
+            let entry = HistoryEntry {
+                time: now,
+                change,
+                external,
+            };
+            self.log(LogEvent::from(&entry));
+            self.history.push(entry);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            for notice in self.notifier.on_payload(&payload) {
+                notice.show();
+            }
+            let rules = self.rules.rules.clone();
+            for rule in self.rules.engine.on_payload(&rules.borrow(), &payload) {
+                self.run_rule(rule);
+            }
+        }
         match payload {
             Payload::InitReply => {
                 self.is_connected = true;
+                self.log(LogEvent::Connected);
                 // get all information
-                self.request_send
-                    .send(Command::GetBatteryStatus {
-                        battery_type: BatteryType::Headphones,
-                    })
-                    .unwrap();
-                self.request_send
-                    .send(Command::GetBatteryStatus {
-                        battery_type: BatteryType::Case,
-                    })
-                    .unwrap();
-                self.request_send
-                    .send(Command::GetEqualizerSettings)
-                    .unwrap();
-                self.request_send.send(Command::GetAncStatus).unwrap();
-                self.request_send.send(Command::GetCodec).unwrap();
+                self.query_guessed();
+                // the batteries to ask for depend on the model
+                let model_name = self
+                    .request_send
+                    .request(Command::GetModelName, REPLY_TIMEOUT);
+                let request_send = self.request_send.sender();
+                let battery_reported_at = self.headphone_state.battery_reported_at.clone();
+                self.headphone_state.battery_query_task.set(async move {
+                    let model = match model_name.await {
+                        Ok(Some(Payload::ModelName(model_name))) => Model::from_name(&model_name),
+                        Ok(_) => None,
+                        Err(e) => {
+                            log::warn!("couldn't get the model name: {e}");
+                            None
+                        }
+                    };
+                    loop {
+                        for battery_type in model.unwrap_or_default().battery_types() {
+                            let command = Command::GetBatteryStatus {
+                                battery_type: *battery_type,
+                            };
+                            if request_send.send(command.into()).is_err() {
+                                return;
+                            }
+                        }
+                        // until nothing was reported for the whole interval
+                        let mut wait = BATTERY_FALLBACK_INTERVAL;
+                        loop {
+                            connection::sleep(wait).await;
+                            let since_report = battery_reported_at.get().map(|reported| {
+                                (chrono::Local::now() - reported)
+                                    .to_std()
+                                    .unwrap_or_default()
+                            });
+                            match since_report {
+                                Some(since) if since < BATTERY_FALLBACK_INTERVAL => {
+                                    wait = BATTERY_FALLBACK_INTERVAL - since;
+                                }
+                                _ => break,
+                            }
+                        }
+                        log::debug!(
+                            "no battery reported for {BATTERY_FALLBACK_INTERVAL:?}; asking"
+                        );
+                    }
+                });
+                self.request_send.send(Command::GetFirmwareVersion);
+                self.request_send.send(Command::GetEqualizerSettings);
+                self.request_send.send(Command::GetAncStatus);
+                self.request_send.send(Command::GetCodec);
             }
 
-            Payload::BatteryLevel(battery) => match battery {
-                BatteryLevel::Case(battery) => {
-                    self.headphone_state.case_battery = Some(battery);
-                }
-
-                BatteryLevel::Headphones { left, right } => {
-                    self.headphone_state.left_ear_battery = Some(left);
-                    self.headphone_state.right_ear_battery = Some(right);
-                }
-            },
+            // shown from the snapshot
+            Payload::BatteryLevel(_) => {
+                self.headphone_state
+                    .battery_reported_at
+                    .set(Some(chrono::Local::now()));
+            }
 
-            Payload::Equalizer {
-                preset,
-                clear_bass,
-                band_400,
-                band_1000,
-                band_2500,
-                band_6300,
-                band_16000,
-            } => {
-                self.headphone_state.equalizer = Some(Equalizer {
-                    preset,
-                    clear_bass,
-                    band_400,
-                    band_1000,
-                    band_2500,
-                    band_6300,
-                    band_16000,
-                });
+            Payload::Equalizer { preset, bands } => {
+                self.headphone_state.equalizer = Some(EqualizerSnapshot { preset, bands });
             }
 
             Payload::AncStatus {
@@ -117,9 +943,11 @@ impl HeadphoneUi {
                 ambient_sound_voice_passthrough,
                 ambient_sound_level,
             } => {
-                self.headphone_state.anc_mode = Some(mode);
-                self.headphone_state.ambient_slider = Some(ambient_sound_level as usize);
-                self.headphone_state.voice_passthrough = Some(ambient_sound_voice_passthrough);
+                self.headphone_state.anc = Some(AncState {
+                    mode,
+                    ambient_level: ambient_sound_level as usize,
+                    voice_passthrough: ambient_sound_voice_passthrough,
+                });
             }
 
             Payload::Codec { codec } => {
@@ -128,293 +956,1384 @@ impl HeadphoneUi {
 
             Payload::SoundPressureMeasureReply { is_on } => {
                 if is_on {
-                    self.request_send.send(Command::GetSoundPressure).unwrap();
-                    let request_send = self.request_send.clone();
-                    // we create the polling task in another thread since the GUI thread sleeps when there is no user interaction
-                    #[cfg(not(target_arch = "wasm32"))]
-                    self.headphone_state
-                        .sound_pressure_poll_task
-                        .set(async move {
-                            let (stop_tx, mut stop_rx) = mpsc::channel(1);
-                            let _ = tokio::task::spawn_blocking(move || {
-                                tokio::runtime::Builder::new_current_thread()
-                                    .enable_time()
-                                    .build()
-                                    .unwrap()
-                                    .block_on(async move {
-                                        loop {
-                                            use std::time::Duration;
-
-                                            tokio::select! {
-                                                _ = stop_rx.recv() => {
-                                                    break;
-                                                }
-
-                                                _ = tokio::time::sleep(Duration::from_secs(1)) => {
-                                                    if request_send.send(Command::GetSoundPressure).is_err()
-                                                    {
-                                                        break;
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        log::debug!("sound pressure task dead");
-                                    });
-                            })
-                            .await;
-                            let _ = stop_tx.send(()).await;
-                        });
-
-                    #[cfg(target_arch = "wasm32")]
-                    self.headphone_state
-                        .sound_pressure_poll_task
-                        .set(async move {
-                            let mut interval = gloo_timers::future::IntervalStream::new(1000);
-                            while let Some(_) = interval.next().await {
-                                if request_send.send(Command::GetSoundPressure).is_err() {
-                                    break;
-                                }
-                            }
-                        });
+                    self.headphone_state.measuring_sound_pressure = true;
+                    if !self.polling_paused {
+                        self.poll_sound_pressure(true);
+                    }
                 } else {
+                    self.headphone_state.measuring_sound_pressure = false;
                     self.headphone_state.sound_pressure_db = None;
-                    self.headphone_state.sound_pressure_poll_task.cancel();
+                    self.poll_sound_pressure(false);
                 }
             }
 
             Payload::SoundPressure { db } => {
                 self.headphone_state.sound_pressure_db = Some(db);
+                self.exposure.record(db);
+            }
+
+            // both guessed payload types, which may be something else entirely
+            Payload::BatteryLow { .. } | Payload::CommandError { .. }
+                if !self.headphone_state.guessed_commands =>
+            {
+                log::debug!("not shown without the guessed commands: {payload:?}");
+            }
+
+            Payload::BatteryLow { component, level } => {
+                self.headphone_state.low_battery = Some((component, level));
+            }
+
+            Payload::CallVoiceFocus { on } => {
+                self.headphone_state.call_voice_focus = Some(on);
+            }
+
+            Payload::SidetoneLevel { level } => {
+                self.headphone_state.sidetone_level = Some(level);
+            }
+
+            Payload::SupportedFunctions(capabilities) => {
+                self.headphone_state.capabilities = Some(capabilities);
+            }
+
+            Payload::QuickAccess {
+                double_tap,
+                triple_tap,
+            } => {
+                self.headphone_state.quick_access = Some(QuickAccess {
+                    double_tap,
+                    triple_tap,
+                });
+            }
+
+            Payload::SpeakToChatTimeout { timeout } => {
+                self.headphone_state.speak_to_chat_timeout = Some(timeout);
+            }
+
+            Payload::SpatialAudioStatus { ear_measured } => {
+                self.headphone_state.spatial_audio_ear_measured = Some(ear_measured);
+            }
+
+            Payload::AmbientSoundRange { min, max } => {
+                let range = min as usize..=max as usize;
+                if let Some(anc) = self.headphone_state.anc.as_mut() {
+                    anc.ambient_level = anc.ambient_level.clamp(*range.start(), *range.end());
+                }
+                self.headphone_state.ambient_range = Some(range);
+            }
+
+            Payload::CommandError { opcode, code } => {
+                log::warn!("command 0x{opcode:x} was rejected with error 0x{code:x}");
+                self.command_error = Some(tr!(
+                    "headphones-command-rejected",
+                    opcode = format!("{opcode:x}"),
+                    code = format!("{code:x}")
+                ));
+            }
+
+            Payload::ModelName(model_name) => {
+                if Model::from_name(&model_name).is_none() {
+                    log::warn!("unknown model {model_name}; assuming it's like the WF-1000XM5");
+                }
+                self.headphone_state.device_info.model_name = Some(model_name);
+            }
+
+            Payload::FirmwareVersion(firmware_version) => {
+                self.headphone_state.device_info.firmware_version = Some(firmware_version);
             }
+
+            // the connection saved it for a bug report, see FrameCapture
+            Payload::Unknown { .. } => (),
+
+            // payloads added to the library after this frontend
+            _ => log::debug!("not shown: {payload:?}"),
         }
     }
 
     fn draw_headphones_info(&mut self, ui: &mut Ui) {
-        let size = 25.0;
+        let size = SECTION_TEXT_SIZE;
 
-        if ui.button("disconnect?").clicked() {
-            self.stop_connection.try_send(()).unwrap();
+        ui.horizontal(|ui| {
+            if ui.button(tr!("headphones-disconnect")).clicked() {
+                // the connection closing on its own meanwhile is just as good
+                let _ = self.request_send.stop();
+            }
+            let mut read_only = self.request_send.is_read_only();
+            if ui
+                .checkbox(&mut read_only, tr!("headphones-read-only"))
+                .on_hover_text(tr!("headphones-read-only-hover"))
+                .changed()
+            {
+                self.request_send.set_read_only(read_only);
+            }
+        });
+        if self.request_send.is_read_only() {
+            ui.label(tr!("headphones-read-only-mode"));
         }
-        if let Some(left_battery) = self.headphone_state.left_ear_battery
-            && let Some(right_battery) = self.headphone_state.right_ear_battery
-            && let Some(case_battery) = self.headphone_state.case_battery
+        ui.add(BatteryRow::new(&self.snapshot).text_size(size));
+        if self.conflicts.is_conflicting(chrono::Local::now())
+            && dismissable_warning(ui, tr!("headphones-conflict"))
         {
-            ui.label(
-                RichText::from(format!(
-                    "🇱 battery: {}, 🇷 battery: {}, case battery: {}",
-                    left_battery, right_battery, case_battery
-                ))
-                .size(size)
-                .strong(),
-            );
+            self.conflicts.dismiss(chrono::Local::now());
+        }
+        if let Some(error) = self.command_error.as_ref()
+            && dismissable_warning(ui, format!("⚠ {error}"))
+        {
+            self.command_error = None;
+        }
+        if let Some((component, level)) = self.headphone_state.low_battery
+            && dismissable_warning(
+                ui,
+                tr!(
+                    "headphones-low-battery",
+                    component = component,
+                    level = level
+                ),
+            )
+        {
+            self.headphone_state.low_battery = None;
         }
         ui.separator();
         if let Some(codec) = self.headphone_state.codec {
             ui.label(
-                RichText::new(format!("Codec: {}", codec.as_str()))
+                RichText::new(tr!("headphones-codec", codec = codec.as_str()))
                     .size(size)
                     .strong(),
             );
         }
-        ui.separator();
-        if let Some(sound_pressure) = self.headphone_state.sound_pressure_db {
-            ui.label(
-                RichText::new(format!("sound pressure: {sound_pressure} dB"))
+        if let Some(ear_measured) = self.headphone_state.spatial_audio_ear_measured
+            && self.headphone_state.offers(&Command::GetSpatialAudioStatus)
+        {
+            ui.label(if ear_measured {
+                tr!("headphones-ears-measured")
+            } else {
+                tr!("headphones-ears-not-measured")
+            });
+        }
+        if let Some(firmware_version) = self.headphone_state.device_info.firmware_version.as_ref() {
+            ui.label(tr!("headphones-firmware", version = firmware_version));
+            for warning in compatibility_report(&self.headphone_state.device_info).warnings {
+                ui.label(RichText::new(format!("⚠ {warning}")).color(egui::Color32::YELLOW));
+            }
+            #[cfg(target_os = "linux")]
+            self.draw_firmware_update(ui);
+        }
+        ui.separator();
+        if let Some(sound_pressure) = self.headphone_state.sound_pressure_db {
+            ui.label(
+                RichText::new(tr!("headphones-sound-pressure", db = sound_pressure))
                     .strong()
                     .size(size),
             );
-            if ui.button("stop?").clicked() {
+            if ui.button(tr!("headphones-stop-measuring")).clicked() {
                 self.request_send
-                    .send(Command::SoundPressureMeasure { on: false })
-                    .unwrap();
+                    .send(Command::SoundPressureMeasure { on: false });
             }
-        } else if ui.button("Start sound pressure measure?").clicked() {
+        } else if ui.button(tr!("headphones-start-measuring")).clicked() {
             self.request_send
-                .send(Command::SoundPressureMeasure { on: true })
-                .unwrap();
+                .send(Command::SoundPressureMeasure { on: true });
         }
+        self.draw_exposure(ui);
+    }
+
+    #[cfg(target_os = "linux")]
+    fn draw_firmware_update(&mut self, ui: &mut Ui) {
+        let device_info = &self.headphone_state.device_info;
+        let (Some(model_name), Some(installed)) = (
+            device_info.model_name.clone(),
+            device_info.firmware_version.clone(),
+        ) else {
+            return;
+        };
+        let check = &self.firmware_update.check;
+        ui.horizontal(|ui| match check.get() {
+            ResourceStatus::NotInitialized => {
+                if ui
+                    .small_button(tr!("firmware-check"))
+                    .on_hover_text(tr!(
+                        "firmware-check-source",
+                        url = firmware_update::METADATA_URL
+                    ))
+                    .clicked()
+                {
+                    check.set(firmware_update::check_for_update(model_name, installed));
+                }
+            }
+            ResourceStatus::Pending => {
+                ui.spinner();
+            }
+            ResourceStatus::Ready(result) => {
+                match result.as_ref() {
+                    Ok(firmware_update::UpdateStatus::UpToDate) => {
+                        ui.label(tr!("firmware-up-to-date"));
+                    }
+                    Ok(firmware_update::UpdateStatus::Available(latest)) => {
+                        ui.label(
+                            RichText::new(tr!("firmware-available", version = latest.version))
+                                .color(egui::Color32::YELLOW),
+                        );
+                        if let Some(notes) = latest.notes.as_ref() {
+                            ui.hyperlink_to(tr!("firmware-notes"), notes);
+                        }
+                    }
+                    Ok(firmware_update::UpdateStatus::UnknownModel) => {
+                        ui.label(tr!("firmware-unknown-model", model = model_name));
+                    }
+                    Err(e) => {
+                        ui.label(RichText::new(e).color(egui::Color32::YELLOW));
+                    }
+                }
+                if ui.small_button(tr!("firmware-check-again")).clicked() {
+                    check.clear();
+                }
+            }
+        });
+    }
+
+    fn draw_exposure(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("exposure"), |ui| {
+            let exposure = self.exposure.exposure();
+            match exposure.average_db() {
+                Some(average) => {
+                    let minutes = (exposure.measured_secs() / 60.0).round();
+                    ui.label(tr!(
+                        "exposure-average",
+                        db = format!("{average:.0}"),
+                        minutes = minutes
+                    ));
+                    let dose = exposure.daily_dose() * 100.0;
+                    let text = RichText::new(tr!("exposure-dose", percent = format!("{dose:.0}")));
+                    ui.label(if dose >= 100.0 {
+                        text.color(egui::Color32::YELLOW)
+                    } else {
+                        text
+                    })
+                    .on_hover_text(tr!("exposure-dose-hover"));
+                    exposure.plot(ui);
+                }
+                None => {
+                    ui.label(tr!("exposure-nothing-yet"));
+                }
+            }
+            if let Some(error) = self.exposure.error.as_ref() {
+                ui.label(RichText::new(error).color(egui::Color32::RED));
+            }
+        });
+    }
 
+    fn draw_sound_settings(&mut self, ui: &mut Ui) {
         ui.separator();
         if let Some(equalizer) = self.headphone_state.equalizer.as_mut() {
-            ui.label(RichText::new("Equalizer").strong().size(size));
-
-            ui.menu_button(equalizer.preset.to_string(), |ui| {
-                let responses = [
-                    ui.selectable_value(&mut equalizer.preset, EqualizerPreset::Off, "Off"),
-                    ui.selectable_value(&mut equalizer.preset, EqualizerPreset::Bright, "Bright"),
-                    ui.selectable_value(&mut equalizer.preset, EqualizerPreset::Excited, "Excited"),
-                    ui.selectable_value(&mut equalizer.preset, EqualizerPreset::Mellow, "Mellow"),
-                    ui.selectable_value(&mut equalizer.preset, EqualizerPreset::Relaxed, "Relaxed"),
-                    ui.selectable_value(&mut equalizer.preset, EqualizerPreset::Vocal, "Vocal"),
-                    ui.selectable_value(
-                        &mut equalizer.preset,
-                        EqualizerPreset::TrebleBoost,
-                        "Treble Boost",
-                    ),
-                    ui.selectable_value(
-                        &mut equalizer.preset,
-                        EqualizerPreset::BassBoost,
-                        "Bass Boost",
-                    ),
-                    ui.selectable_value(&mut equalizer.preset, EqualizerPreset::Speech, "Speech"),
-                    ui.selectable_value(&mut equalizer.preset, EqualizerPreset::Manual, "Manual"),
-                    ui.selectable_value(&mut equalizer.preset, EqualizerPreset::Custom1, "Custom1"),
-                    ui.selectable_value(&mut equalizer.preset, EqualizerPreset::Custom2, "Custom2"),
-                ];
-                if responses.iter().any(|r| r.clicked()) {
-                    self.request_send
-                        .send(Command::ChangeEqualizerPreset {
-                            preset: equalizer.preset,
-                        })
-                        .unwrap();
+            section_label(
+                ui,
+                tr!("equalizer"),
+                &self.request_send,
+                &[SnapshotField::Equalizer],
+            );
+
+            if ui.add(PresetPicker::new(&mut equalizer.preset)).changed() {
+                self.request_send.send(Command::ChangeEqualizerPreset {
+                    preset: equalizer.preset,
+                });
+            }
+
+            equalizer_curve::plot(ui, &equalizer.bands);
+            if ui
+                .add(EqualizerSliders::new(&mut equalizer.bands))
+                .changed()
+            {
+                self.request_send.send(Command::ChangeEqualizerSetting {
+                    preset: adjustable_preset(equalizer.preset),
+                    bands: equalizer.bands,
+                });
+            }
+            #[cfg(target_os = "linux")]
+            {
+                let state = &mut self.eq_file;
+                let read_only = self.request_send.is_read_only();
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(!read_only, egui::Button::new(tr!("equalizer-import")))
+                        .on_hover_text(tr!("equalizer-import-hover"))
+                        .clicked()
+                    {
+                        state.import.set(eq_file::import_with_dialog());
+                    }
+                    if ui.button(tr!("equalizer-export")).clicked() {
+                        state
+                            .export
+                            .set(eq_file::export_with_dialog(equalizer.bands));
+                    }
+                });
+                if let ResourceStatus::Ready(result) = state.import.get() {
+                    match result.as_ref() {
+                        Some(Ok(bands)) => {
+                            equalizer.bands = *bands;
+                            self.request_send.send(Command::ChangeEqualizerSetting {
+                                preset: adjustable_preset(equalizer.preset),
+                                bands: *bands,
+                            });
+                            state.status = Some(tr!("equalizer-imported"));
+                        }
+                        Some(Err(e)) => state.status = Some(e.to_string()),
+                        None => (),
+                    }
+                    state.import.clear();
+                }
+                if let ResourceStatus::Ready(result) = state.export.get() {
+                    match result.as_ref() {
+                        Some(Ok(path)) => {
+                            state.status = Some(tr!("equalizer-exported", path = path.display()))
+                        }
+                        Some(Err(e)) => state.status = Some(e.to_string()),
+                        None => (),
+                    }
+                    state.export.clear();
+                }
+                if let Some(status) = state.status.as_ref() {
+                    ui.label(status);
+                }
+            }
+        }
+        ui.separator();
+        // what the firmware before range discovery used
+        let ambient_range = self
+            .headphone_state
+            .ambient_range
+            .clone()
+            .unwrap_or(DEFAULT_AMBIENT_RANGE);
+        if let Some(anc) = self.headphone_state.anc.as_mut() {
+            section_label(ui, tr!("anc"), &self.request_send, &[SnapshotField::Anc]);
+            if let Some(command) = AncControl::new(anc, ambient_range).show(ui) {
+                self.request_send.send(command);
+            }
+        }
+    }
+    fn draw_touch_controls(&mut self, ui: &mut Ui) {
+        if !self.headphone_state.offers(&Command::GetQuickAccess) {
+            return;
+        }
+        if let Some(quick_access) = self.headphone_state.quick_access.as_mut() {
+            section_label(
+                ui,
+                tr!("touch-controls"),
+                &self.request_send,
+                &[SnapshotField::QuickAccess],
+            );
+            let mut changed = false;
+            for (label, app) in [
+                (
+                    tr!("touch-controls-double-tap"),
+                    &mut quick_access.double_tap,
+                ),
+                (
+                    tr!("touch-controls-triple-tap"),
+                    &mut quick_access.triple_tap,
+                ),
+            ] {
+                egui::ComboBox::from_label(label)
+                    .selected_text(app.to_string())
+                    .show_ui(ui, |ui| {
+                        for choice in [
+                            QuickAccessApp::None,
+                            QuickAccessApp::Spotify,
+                            QuickAccessApp::Endel,
+                        ] {
+                            changed |= ui
+                                .selectable_value(app, choice, choice.to_string())
+                                .clicked();
+                        }
+                    });
+            }
+            if changed {
+                self.request_send.send(Command::SetQuickAccess {
+                    double_tap: quick_access.double_tap,
+                    triple_tap: quick_access.triple_tap,
+                });
+            }
+            ui.separator();
+        }
+    }
+
+    fn draw_speak_to_chat(&mut self, ui: &mut Ui) {
+        if !self.headphone_state.offers(&Command::GetSpeakToChatTimeout) {
+            return;
+        }
+        if let Some(timeout) = self.headphone_state.speak_to_chat_timeout.as_mut() {
+            section_label(
+                ui,
+                tr!("speak-to-chat"),
+                &self.request_send,
+                &[SnapshotField::SpeakToChatTimeout],
+            );
+            let mut changed = false;
+            egui::ComboBox::from_label(tr!("speak-to-chat-timeout"))
+                .selected_text(timeout.to_string())
+                .show_ui(ui, |ui| {
+                    for choice in SpeakToChatTimeout::ALL {
+                        changed |= ui
+                            .selectable_value(timeout, choice, choice.to_string())
+                            .clicked();
+                    }
+                });
+            if changed {
+                self.request_send
+                    .send(Command::SetSpeakToChatTimeout { timeout: *timeout });
+            }
+            ui.separator();
+        }
+    }
+
+    fn draw_calls(&mut self, ui: &mut Ui) {
+        if !self.headphone_state.offers(&Command::GetCallVoiceFocus) {
+            return;
+        }
+        let state = &mut self.headphone_state;
+        if state.call_voice_focus.is_none() && state.sidetone_level.is_none() {
+            return;
+        }
+        section_label(
+            ui,
+            tr!("calls"),
+            &self.request_send,
+            &[SnapshotField::CallVoiceFocus, SnapshotField::SidetoneLevel],
+        );
+        if let Some(call_voice_focus) = state.call_voice_focus.as_mut()
+            && ui
+                .checkbox(call_voice_focus, tr!("calls-voice-focus"))
+                .clicked()
+        {
+            self.request_send.send(Command::SetCallVoiceFocus {
+                on: *call_voice_focus,
+            });
+        }
+        if let Some(sidetone_level) = state.sidetone_level.as_mut()
+            && ui
+                .add(
+                    Slider::new(sidetone_level, 0..=Command::MAX_SIDETONE_LEVEL)
+                        .text(tr!("calls-sidetone")),
+                )
+                .on_hover_text(tr!("calls-sidetone-hover"))
+                .drag_stopped()
+        {
+            self.request_send.send(Command::SetSidetoneLevel {
+                level: *sidetone_level,
+            });
+        }
+        ui.separator();
+    }
+
+    fn draw_find_my_buds(&mut self, ui: &mut Ui) {
+        if !self.headphone_state.offers(&Command::StopLocatorTone) {
+            return;
+        }
+        ui.collapsing(tr!("find-my-buds"), |ui| {
+            ui.horizontal(|ui| {
+                if ui.button(tr!("find-my-buds-left")).clicked() {
+                    self.request_send.send(Command::PlayLocatorTone {
+                        left: true,
+                        right: false,
+                    });
+                }
+                if ui.button(tr!("find-my-buds-right")).clicked() {
+                    self.request_send.send(Command::PlayLocatorTone {
+                        left: false,
+                        right: true,
+                    });
+                }
+                if ui.button(tr!("find-my-buds-stop")).clicked() {
+                    self.request_send.send(Command::StopLocatorTone);
                 }
             });
+        });
+    }
 
+    fn draw_profiles(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("profiles"), |ui| {
+            let read_only = self.request_send.is_read_only();
+            let mut remove = None;
+            let mut applied = None;
+            let profiles = self.profiles.profiles.borrow();
+            for profile in profiles.iter() {
+                ui.horizontal(|ui| {
+                    let selected = profiles.selected() == Some(profile.name.as_str());
+                    if ui
+                        .add_enabled(
+                            !read_only,
+                            egui::Button::selectable(selected, &profile.name),
+                        )
+                        .on_hover_text(tr!("profiles-apply"))
+                        .clicked()
+                    {
+                        for command in profile.config.to_commands() {
+                            self.request_send.send(command);
+                        }
+                        applied = Some(profile.name.clone());
+                    }
+                    if ui
+                        .small_button("🗑")
+                        .on_hover_text(tr!("profiles-delete"))
+                        .clicked()
+                    {
+                        remove = Some(profile.name.clone());
+                    }
+                });
+            }
+            drop(profiles);
+            if let Some(name) = applied {
+                self.profiles.profiles.borrow_mut().select(&name);
+            }
+            if let Some(name) = remove {
+                self.profiles.profiles.borrow_mut().remove(&name);
+            }
             ui.horizontal(|ui| {
-                let responses = [
-                    ui.add(
-                        Slider::new(&mut equalizer.clear_bass, -10..=10)
-                            .vertical()
-                            .text(RichText::new("clear bass").strong()),
-                    ),
-                    ui.add(
-                        Slider::new(&mut equalizer.band_400, -10..=10)
-                            .vertical()
-                            .text(RichText::new("400 Hz").strong()),
-                    ),
-                    ui.add(
-                        Slider::new(&mut equalizer.band_1000, -10..=10)
-                            .vertical()
-                            .text(RichText::new("1000 Hz").strong()),
-                    ),
-                    ui.add(
-                        Slider::new(&mut equalizer.band_2500, -10..=10)
-                            .vertical()
-                            .text(RichText::new("2500 Hz").strong()),
-                    ),
-                    ui.add(
-                        Slider::new(&mut equalizer.band_6300, -10..=10)
-                            .vertical()
-                            .text(RichText::new("6300 Hz").strong()),
-                    ),
-                    ui.add(
-                        Slider::new(&mut equalizer.band_16000, -10..=10)
-                            .vertical()
-                            .text(RichText::new("16000 Hz").strong()),
-                    ),
-                ];
-                if responses.iter().any(|r| r.changed()) {
-                    let preset = if matches!(
-                        equalizer.preset,
-                        EqualizerPreset::Manual
-                            | EqualizerPreset::Custom1
-                            | EqualizerPreset::Custom2
-                    ) {
-                        equalizer.preset
-                    } else {
-                        // we shouldn't (can't?) change non-custom/manual presets
-                        EqualizerPreset::Manual
-                    };
-                    self.request_send
-                        .send(Command::ChangeEqualizerSetting {
-                            preset,
-                            bass_level: equalizer.clear_bass,
-                            band_400: equalizer.band_400,
-                            band_1000: equalizer.band_1000,
-                            band_2500: equalizer.band_2500,
-                            band_6300: equalizer.band_6300,
-                            band_16000: equalizer.band_16000,
-                        })
-                        .unwrap();
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.profiles.name)
+                        .hint_text(tr!("profiles-name-hint"))
+                        .desired_width(120.0),
+                );
+                let name = self.profiles.name.trim();
+                if ui
+                    .add_enabled(!name.is_empty(), egui::Button::new(tr!("profiles-save")))
+                    .clicked()
+                {
+                    self.profiles
+                        .profiles
+                        .borrow_mut()
+                        .insert(name, self.headphone_state.shared_config());
+                    self.profiles.name.clear();
                 }
             });
+        });
+    }
+
+    fn draw_share(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("share"), |ui| {
+            let share_string = self.headphone_state.shared_config().encode();
+            ui.horizontal(|ui| {
+                if ui.button(tr!("share-copy")).clicked() {
+                    ui.ctx().copy_text(share_string.clone());
+                }
+                ui.checkbox(&mut self.share.show_qr, tr!("share-qr-code"));
+            });
+            if self.share.show_qr {
+                share::draw_qr_code(ui, &share_string, 4.0);
+            }
+
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.share.input);
+                if ui
+                    .add_enabled(
+                        !self.request_send.is_read_only(),
+                        egui::Button::new(tr!("share-apply")),
+                    )
+                    .clicked()
+                {
+                    match SharedConfig::decode(&self.share.input) {
+                        Ok(config) => {
+                            self.share.error = None;
+                            for command in config.to_commands() {
+                                self.request_send.send(command);
+                            }
+                        }
+                        Err(e) => self.share.error = Some(e.to_string()),
+                    }
+                }
+            });
+            if let Some(error) = self.share.error.as_ref() {
+                ui.label(error);
+            }
+        });
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn draw_backup(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("backup"), |ui| {
+            let Some(dir) =
+                eframe::storage_dir(crate::app::App::NAME).map(|dir| dir.join("backups"))
+            else {
+                ui.label(tr!("backup-no-directory"));
+                return;
+            };
+            if ui.button(tr!("backup-save")).clicked() {
+                let backup = DeviceBackup::from_snapshot(&self.snapshot);
+                self.backup.status = Some(match backup.save(&dir) {
+                    Ok(path) => tr!("backup-saved", path = path.display()),
+                    Err(e) => e.to_string(),
+                });
+                self.backup.saved = None;
+            }
+            if let Some(status) = self.backup.status.as_ref() {
+                ui.label(status);
+            }
+            let saved = self
+                .backup
+                .saved
+                .get_or_insert_with(|| backup::list_backups(&dir));
+            if saved.is_empty() {
+                ui.label(tr!("backup-none"));
+                return;
+            }
+            let read_only = self.request_send.is_read_only();
+            let mut restore = None;
+            egui::ScrollArea::vertical()
+                .max_height(150.0)
+                .show(ui, |ui| {
+                    for path in saved.iter() {
+                        ui.horizontal(|ui| {
+                            ui.label(path.file_name().unwrap_or_default().to_string_lossy());
+                            if ui
+                                .add_enabled(!read_only, egui::Button::new(tr!("backup-restore")))
+                                .clicked()
+                            {
+                                restore = Some(path.clone());
+                            }
+                        });
+                    }
+                });
+            if let Some(path) = restore {
+                self.backup.status = Some(match self.restore_backup(&path) {
+                    Ok(status) => status,
+                    Err(e) => e.to_string(),
+                });
+            }
+        });
+    }
+
+    /// Send the commands to restore the backup at `path`, returning what happened
+    #[cfg(not(target_arch = "wasm32"))]
+    fn restore_backup(&mut self, path: &std::path::Path) -> Result<String, backup::BackupError> {
+        let backup = DeviceBackup::load(path)?;
+        let (commands, skipped): (Vec<_>, Vec<_>) = backup
+            .restore_commands(&self.snapshot)?
+            .into_iter()
+            .partition(|command| self.headphone_state.guessed_commands || !command.is_guessed());
+        let count = commands.len();
+        for command in commands {
+            self.request_send.send(command);
         }
-        ui.separator();
-        if let Some(anc_mode) = self.headphone_state.anc_mode.as_mut()
-            && let Some(ambient_slider) = self.headphone_state.ambient_slider.as_mut()
-            && let Some(voice_passthrough) = self.headphone_state.voice_passthrough.as_mut()
+        let mut status = match count {
+            0 => tr!("backup-nothing-to-restore"),
+            _ => tr!("backup-restored", count = count),
+        };
+        if !skipped.is_empty() {
+            status.push(' ');
+            status += &tr!("backup-guessed-skipped", count = skipped.len());
+        }
+        if let (Some(backed_up), Some(current)) = (
+            backup.model_name.as_ref(),
+            self.snapshot.device_info.model_name.as_ref(),
+        ) && backed_up != current
         {
-            ui.label(RichText::new("ANC configuration:").strong().size(size));
+            status.push(' ');
+            status += &tr!(
+                "backup-other-model",
+                backed_up = backed_up,
+                current = current
+            );
+        }
+        Ok(status)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn draw_bug_reports(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("bug-reports"), |ui| {
+            let mut enabled = self.frame_capture.load(Ordering::Relaxed);
             if ui
-                .radio_value(anc_mode, AncMode::Off, RichText::new("Off").strong())
-                .clicked()
+                .checkbox(&mut enabled, tr!("settings-capture-frames"))
+                .changed()
             {
-                self.request_send
-                    .send(Command::AncSet {
-                        dragging_ambient_sound_slider: false,
-                        mode: AncMode::Off,
-                        ambient_sound_voice_passthrough: false,
-                        ambient_sound_level: 0,
-                    })
-                    .unwrap();
+                self.frame_capture.store(enabled, Ordering::Relaxed);
             }
-            if ui
-                .radio_value(
-                    anc_mode,
-                    AncMode::AmbientSound,
-                    RichText::new("Ambient Sounds").strong(),
-                )
-                .clicked()
+            if let Some(dir) = eframe::storage_dir(crate::app::App::NAME) {
+                ui.label(tr!(
+                    "bug-reports-attach",
+                    path = dir.join(FrameCapture::FILE_NAME).display()
+                ));
+            }
+            #[cfg(target_os = "linux")]
             {
-                self.request_send
-                    .send(Command::AncSet {
-                        dragging_ambient_sound_slider: false,
-                        mode: AncMode::AmbientSound,
-                        ambient_sound_voice_passthrough: true,
-                        ambient_sound_level: *ambient_slider,
+                if ui
+                    .button(tr!("bug-reports-export"))
+                    .on_hover_text(tr!("bug-reports-export-hover"))
+                    .clicked()
+                {
+                    let zip = self.diagnostic_bundle().to_zip(chrono::Local::now());
+                    self.diagnostics
+                        .export
+                        .set(diagnostics::save_with_dialog(zip));
+                }
+                let state = &mut self.diagnostics;
+                if let ResourceStatus::Ready(result) = state.export.get() {
+                    match result.as_ref() {
+                        Some(Ok(path)) => {
+                            state.status = Some(tr!("bug-reports-exported", path = path.display()))
+                        }
+                        Some(Err(e)) => {
+                            state.status = Some(tr!("bug-reports-export-failed", error = e))
+                        }
+                        None => (),
+                    }
+                    state.export.clear();
+                }
+                if let Some(status) = state.status.as_ref() {
+                    ui.label(status);
+                }
+            }
+        });
+    }
+
+    /// What [Self::draw_bug_reports] exports: the recent traffic and warnings of the protocol log, what the
+    /// headphones reported, the history and the settings of the app
+    #[cfg(target_os = "linux")]
+    fn diagnostic_bundle(&self) -> diagnostics::Bundle {
+        use crate::protocol_log::ProtocolEventKind;
+
+        let mut bundle = diagnostics::Bundle::default();
+        bundle.add(
+            "about.txt",
+            &format!(
+                "controller-gui {}\n{} {}\nexported {}\nconnected: {}\nread only: {}\n",
+                env!("CARGO_PKG_VERSION"),
+                std::env::consts::OS,
+                std::env::consts::ARCH,
+                chrono::Local::now().to_rfc3339(),
+                self.is_connected,
+                self.request_send.is_read_only(),
+            ),
+        );
+        let events_of = |kinds: &[ProtocolEventKind]| {
+            self.protocol_log
+                .events()
+                .filter(|event| kinds.contains(&event.kind))
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        bundle.add(
+            "traffic.txt",
+            &events_of(&[ProtocolEventKind::Sent, ProtocolEventKind::Received]),
+        );
+        bundle.add("warnings.txt", &events_of(&[ProtocolEventKind::Warning]));
+
+        let device_info = &self.headphone_state.device_info;
+        let warnings = compatibility_report(device_info)
+            .warnings
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let device = serde_json::json!({
+            "model_name": device_info.model_name,
+            "firmware_version": device_info.firmware_version,
+            "compatibility_warnings": warnings,
+            "capabilities": self.headphone_state.capabilities.map(|capabilities| format!("{capabilities:?}")),
+            "snapshot": self.snapshot,
+        });
+        bundle.add(
+            "device.json",
+            &serde_json::to_string_pretty(&device).expect("the JSON is valid"),
+        );
+        bundle.add("history.txt", &self.history.export());
+        if let Some(dir) = eframe::storage_dir(crate::app::App::NAME) {
+            for (file, name) in [
+                ("app.ron", "settings.ron"),
+                (FrameCapture::FILE_NAME, FrameCapture::FILE_NAME),
+            ] {
+                if let Ok(text) = std::fs::read_to_string(dir.join(file)) {
+                    bundle.add(name, &text);
+                }
+            }
+        }
+        bundle
+    }
+
+    fn draw_developer_console(&mut self, ui: &mut Ui) {
+        let Some(console) = self.developer_console.as_mut() else {
+            return;
+        };
+        ui.collapsing(tr!("developer-console"), |ui| {
+            if let Some(command) = console.draw(ui, !self.request_send.is_read_only()) {
+                self.request_send.send(command);
+            }
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    fn draw_notifications(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("notifications"), |ui| {
+            let settings = &mut self.notifier.settings;
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.low_battery, tr!("notifications-low-battery"));
+                ui.add_enabled(
+                    settings.low_battery,
+                    egui::DragValue::new(&mut settings.low_battery_threshold)
+                        .range(1..=99)
+                        .suffix("%"),
+                );
+            });
+            ui.checkbox(&mut settings.charged, tr!("notifications-charged"));
+            ui.checkbox(&mut settings.codec, tr!("notifications-codec"));
+            ui.checkbox(&mut settings.disconnect, tr!("notifications-disconnect"));
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    fn draw_hotkeys(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("hotkeys"), |ui| {
+            ui.label(tr!("hotkeys-explanation"));
+            let state = &mut self.hotkeys;
+            for action in HotkeyAction::ALL {
+                ui.horizontal(|ui| {
+                    ui.label(action.description());
+                    if state.recording != Some(action) {
+                        let trigger = state
+                            .settings
+                            .trigger(action)
+                            .map_or_else(|| tr!("hotkeys-none"), str::to_string);
+                        if ui
+                            .button(trigger)
+                            .on_hover_text(tr!("hotkeys-record"))
+                            .clicked()
+                        {
+                            state.recording = Some(action);
+                        }
+                        return;
+                    }
+                    if ui.button(tr!("cancel")).clicked() {
+                        state.recording = None;
+                    }
+                    ui.label(tr!("hotkeys-recording"));
+                    let pressed = ui.input(|i| {
+                        i.events.iter().find_map(|event| match event {
+                            egui::Event::Key {
+                                key,
+                                pressed: true,
+                                modifiers,
+                                ..
+                            } => Some((*key, *modifiers)),
+                            _ => None,
+                        })
+                    });
+                    match pressed {
+                        Some((egui::Key::Escape, _)) => state.recording = None,
+                        Some((egui::Key::Backspace, modifiers)) if modifiers.is_none() => {
+                            state.settings.set_trigger(action, None);
+                            state.recording = None;
+                        }
+                        Some((key, modifiers)) => {
+                            if let Some(trigger) = hotkeys::trigger(modifiers, key) {
+                                state.settings.set_trigger(action, Some(trigger));
+                                state.recording = None;
+                            }
+                        }
+                        None => (),
+                    }
+                });
+            }
+            if let Some(error) = state.error.as_ref() {
+                ui.label(tr!("hotkeys-failed", error = error));
+            }
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    fn draw_rules(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("rules"), |ui| {
+            let state = &mut self.rules;
+            let mut rules = state.rules.borrow_mut();
+            let mut changed = false;
+            let mut removed = None;
+            for (index, rule) in rules.rules.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    let text = rule.to_string();
+                    changed |= ui.checkbox(&mut rule.enabled, text).changed();
+                    if ui.button(tr!("remove")).clicked() {
+                        removed = Some(index);
+                    }
+                });
+            }
+            if let Some(index) = removed {
+                rules.rules.remove(index);
+                changed = true;
+            }
+
+            ui.horizontal(|ui| {
+                ui.label(tr!("rules-when"));
+                let selected = TRIGGERS
+                    .iter()
+                    .find(|(_, trigger)| {
+                        std::mem::discriminant(trigger) == std::mem::discriminant(&state.trigger)
                     })
-                    .unwrap();
+                    .map_or("", |(label, _)| label);
+                egui::ComboBox::from_id_salt("rule trigger")
+                    .selected_text(crate::i18n::translate(selected, &[]))
+                    .show_ui(ui, |ui| {
+                        for (label, trigger) in TRIGGERS {
+                            if ui
+                                .selectable_label(
+                                    selected == label,
+                                    crate::i18n::translate(label, &[]),
+                                )
+                                .clicked()
+                            {
+                                state.trigger = trigger;
+                            }
+                        }
+                    });
+                match &mut state.trigger {
+                    Trigger::At(time) => {
+                        use chrono::Timelike;
+                        let (mut hour, mut minute) = (time.hour(), time.minute());
+                        ui.add(egui::DragValue::new(&mut hour).range(0..=23));
+                        ui.label(":");
+                        ui.add(egui::DragValue::new(&mut minute).range(0..=59));
+                        *time = chrono::NaiveTime::from_hms_opt(hour, minute, 0).unwrap_or(*time);
+                    }
+                    Trigger::Codec(codec) => {
+                        egui::ComboBox::from_id_salt("rule codec")
+                            .selected_text(format!("{codec:?}"))
+                            .show_ui(ui, |ui| {
+                                for choice in [
+                                    Codec::Sbc,
+                                    Codec::Aac,
+                                    Codec::Ldac,
+                                    Codec::Aptx,
+                                    Codec::AptxHd,
+                                ] {
+                                    ui.selectable_value(codec, choice, format!("{choice:?}"));
+                                }
+                            });
+                    }
+                    Trigger::BatteryBelow { battery, percent } => {
+                        ui.add(egui::DragValue::new(percent).range(1..=100).suffix("%"));
+                        ui.label(tr!("rules-for"));
+                        egui::ComboBox::from_id_salt("rule battery")
+                            .selected_text(battery.to_string())
+                            .show_ui(ui, |ui| {
+                                for choice in Battery::ALL {
+                                    ui.selectable_value(battery, choice, choice.to_string());
+                                }
+                            });
+                        ui.label(tr!("rules-battery"));
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label(tr!("rules-then"));
+                let selected = ACTIONS
+                    .iter()
+                    .find(|(_, action)| {
+                        std::mem::discriminant(action) == std::mem::discriminant(&state.action)
+                    })
+                    .map_or("", |(label, _)| label);
+                egui::ComboBox::from_id_salt("rule action")
+                    .selected_text(crate::i18n::translate(selected, &[]))
+                    .show_ui(ui, |ui| {
+                        for (label, action) in ACTIONS {
+                            if ui
+                                .selectable_label(
+                                    selected == label,
+                                    crate::i18n::translate(label, &[]),
+                                )
+                                .clicked()
+                            {
+                                state.action = action;
+                            }
+                        }
+                    });
+                match &mut state.action {
+                    Action::SetAnc {
+                        mode,
+                        ambient_level,
+                    } => {
+                        egui::ComboBox::from_id_salt("rule anc")
+                            .selected_text(format!("{mode:?}"))
+                            .show_ui(ui, |ui| {
+                                for choice in [
+                                    AncMode::ActiveNoiseCanceling,
+                                    AncMode::AmbientSound,
+                                    AncMode::Off,
+                                ] {
+                                    ui.selectable_value(mode, choice, format!("{choice:?}"));
+                                }
+                            });
+                        if *mode == AncMode::AmbientSound {
+                            let mut set_level = ambient_level.is_some();
+                            ui.checkbox(&mut set_level, tr!("rules-level"));
+                            let range = self
+                                .headphone_state
+                                .ambient_range
+                                .clone()
+                                .unwrap_or(DEFAULT_AMBIENT_RANGE);
+                            match (set_level, ambient_level.as_mut()) {
+                                (true, Some(level)) => {
+                                    ui.add(egui::DragValue::new(level).range(range));
+                                }
+                                (true, None) => *ambient_level = Some(*range.end() / 2),
+                                (false, _) => *ambient_level = None,
+                            }
+                        } else {
+                            *ambient_level = None;
+                        }
+                    }
+                    Action::SetEqualizerPreset(preset) => {
+                        egui::ComboBox::from_id_salt("rule preset")
+                            .selected_text(preset.to_string())
+                            .show_ui(ui, |ui| {
+                                for choice in EqualizerPreset::ALL {
+                                    ui.selectable_value(preset, choice, choice.to_string());
+                                }
+                            });
+                    }
+                    Action::Notify(message) => {
+                        ui.text_edit_singleline(message);
+                    }
+                }
+                if ui.button(tr!("rules-add")).clicked() {
+                    rules.rules.push(Rule {
+                        enabled: true,
+                        trigger: state.trigger,
+                        action: state.action.clone(),
+                    });
+                    changed = true;
+                }
+            });
+
+            let path = Rules::path();
+            if changed && let Some(path) = path.as_ref() {
+                state.error = rules.save(path).err().map(|e| e.to_string());
+            }
+            if let Some(error) = state.error.as_ref() {
+                ui.label(error);
+            }
+            if let Some(path) = path {
+                ui.label(tr!("rules-saved-in", path = path.display()));
+            }
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    fn draw_audio_output(&mut self, ui: &mut Ui) {
+        let state = &mut self.audio_output;
+        let Some(address) = state.address.clone() else {
+            return;
+        };
+        ui.collapsing(tr!("audio-output"), |ui| {
+            if let ResourceStatus::Ready(result) = state.change.get() {
+                // whatever changed, show how things are now
+                if let Err(e) = result.as_ref() {
+                    ui.label(e);
+                } else {
+                    state.status.set(pipewire::status(address.clone()));
+                    state.change.clear();
+                }
+            }
+            let mut refresh = ui.button(tr!("refresh")).clicked();
+            let protocol_codec = self
+                .headphone_state
+                .codec
+                .filter(|codec| *codec != Codec::Unknown)
+                .map(|codec| format!("{codec:?}"));
+            match state.status.get() {
+                ResourceStatus::NotInitialized => refresh = true,
+                ResourceStatus::Pending => {
+                    ui.spinner();
+                }
+                ResourceStatus::Ready(result) => match result.as_ref() {
+                    Err(e) => {
+                        ui.label(e.as_str());
+                    }
+                    Ok(status) => {
+                        ui.label(if status.is_default {
+                            tr!("audio-output-default")
+                        } else {
+                            tr!("audio-output-not-default")
+                        });
+                        if let Some(profile) = status.profile.as_ref() {
+                            ui.label(tr!("audio-output-profile", profile = profile.description));
+                        }
+                        if let Some(codec) = status.codec.as_deref() {
+                            ui.label(tr!("audio-output-codec", codec = codec));
+                        }
+                        ui.horizontal(|ui| {
+                            if let Some(sink) = status.sink
+                                && !status.is_default
+                                && ui.button(tr!("audio-output-set-default")).clicked()
+                            {
+                                state.change.set(pipewire::set_default(sink));
+                            }
+                            let is_headset =
+                                status.profile.as_ref().is_some_and(|p| p.is_headset());
+                            let (label, target) = if is_headset {
+                                (tr!("audio-output-playback"), status.find_profile(false))
+                            } else {
+                                (tr!("audio-output-headset"), status.find_profile(true))
+                            };
+                            if let (Some(device), Some(target)) = (status.device, target)
+                                && ui
+                                    .button(label)
+                                    .on_hover_text(&target.description)
+                                    .clicked()
+                            {
+                                state
+                                    .change
+                                    .set(pipewire::set_profile(device, target.index));
+                            }
+                        });
+                        for advice in status.advice(protocol_codec.as_deref()) {
+                            ui.label(advice);
+                        }
+                    }
+                },
+            }
+            if refresh {
+                state.change.clear();
+                state.status.set(pipewire::status(address.clone()));
             }
-            if *anc_mode == AncMode::AmbientSound {
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    fn draw_app_anc(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("app-anc"), |ui| {
+            let state = &mut self.app_anc;
+            ui.checkbox(&mut state.settings.enabled, tr!("app-anc-enabled"));
+            let mode_text = |mode: AncMode| match mode {
+                AncMode::ActiveNoiseCanceling => tr!("app-anc-noise-canceling"),
+                AncMode::AmbientSound => tr!("app-anc-ambient-sound"),
+                AncMode::Off => tr!("app-anc-off"),
+            };
+            let mut removed = None;
+            for (index, mapping) in state.settings.mappings.iter().enumerate() {
                 ui.horizontal(|ui| {
-                    let mut should_update = false;
-                    should_update |= ui.add(Slider::new(ambient_slider, 0..=20)).drag_stopped();
-                    should_update |= ui
-                        .checkbox(voice_passthrough, "voice passthrough")
-                        .clicked();
-
-                    if should_update {
-                        self.request_send
-                            .send(Command::AncSet {
-                                dragging_ambient_sound_slider: false,
-                                mode: AncMode::AmbientSound,
-                                ambient_sound_voice_passthrough: *voice_passthrough,
-                                ambient_sound_level: *ambient_slider,
-                            })
-                            .unwrap();
+                    ui.label(tr!(
+                        "app-anc-mapping",
+                        number = index + 1,
+                        application = mapping.application,
+                        kind = mapping.kind,
+                        mode = mode_text(mapping.mode)
+                    ));
+                    if ui.button(tr!("remove")).clicked() {
+                        removed = Some(index);
                     }
                 });
             }
-            if ui
-                .radio_value(
-                    anc_mode,
-                    AncMode::ActiveNoiseCanceling,
-                    RichText::new("Active Noise Canceling").strong(),
+            if let Some(index) = removed {
+                state.settings.mappings.remove(index);
+            }
+            ui.horizontal(|ui| {
+                let new = &mut state.new;
+                ui.label(tr!("app-anc-when"));
+                ui.add(
+                    egui::TextEdit::singleline(&mut new.application)
+                        .hint_text(tr!("app-anc-application"))
+                        .desired_width(120.0),
+                );
+                egui::ComboBox::from_id_salt("app anc kind")
+                    .selected_text(new.kind.to_string())
+                    .show_ui(ui, |ui| {
+                        for kind in [StreamKind::Microphone, StreamKind::Playback] {
+                            ui.selectable_value(&mut new.kind, kind, kind.to_string());
+                        }
+                    });
+                egui::ComboBox::from_id_salt("app anc mode")
+                    .selected_text(mode_text(new.mode))
+                    .show_ui(ui, |ui| {
+                        for mode in [
+                            AncMode::ActiveNoiseCanceling,
+                            AncMode::AmbientSound,
+                            AncMode::Off,
+                        ] {
+                            ui.selectable_value(&mut new.mode, mode, mode_text(mode));
+                        }
+                    });
+                if ui
+                    .add_enabled(
+                        !new.application.trim().is_empty(),
+                        egui::Button::new(tr!("app-anc-add")),
+                    )
+                    .clicked()
+                {
+                    state.settings.mappings.push(AppAncMapping {
+                        application: new.application.trim().to_string(),
+                        ..new.clone()
+                    });
+                    new.application.clear();
+                }
+            });
+            ui.label(tr!("app-anc-matching"));
+            if let Some(error) = state.error.as_ref() {
+                ui.label(error);
+            }
+        });
+    }
+
+    fn draw_history(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("history"), |ui| {
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.horizontal(|ui| {
+                if ui
+                    .checkbox(&mut self.history_settings.enabled, tr!("history-save"))
+                    .changed()
+                {
+                    self.history_log = None;
+                    self.history_log_error = None;
+                }
+                ui.add(
+                    egui::DragValue::new(&mut self.history_settings.retention_days)
+                        .range(1..=365)
+                        .suffix(format!(" {}", tr!("days-unit"))),
                 )
-                .clicked()
-            {
-                self.request_send
-                    .send(Command::AncSet {
-                        dragging_ambient_sound_slider: false,
-                        mode: AncMode::ActiveNoiseCanceling,
-                        ambient_sound_voice_passthrough: true,
-                        ambient_sound_level: *ambient_slider,
-                    })
-                    .unwrap();
+                .on_hover_text(tr!("history-retention-hover"));
+            });
+            if let Some(error) = self.history_log_error.as_ref() {
+                ui.label(RichText::new(error).color(egui::Color32::YELLOW));
+            }
+            if self.history.is_empty() {
+                ui.label(tr!("history-empty"));
+                return;
+            }
+            if ui.button(tr!("history-copy")).clicked() {
+                ui.ctx().copy_text(self.history.export());
+            }
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    for entry in self.history.iter() {
+                        ui.label(entry.to_string());
+                    }
+                });
+        });
+    }
+
+    fn draw_protocol_log(&mut self, ui: &mut Ui) {
+        ui.collapsing(tr!("protocol-log"), |ui| {
+            ui.label(tr!("protocol-log-explanation"));
+            self.protocol_log.draw(ui);
+        });
+    }
+
+    /// Handle the payloads and run the automations, for headphones whose tab isn't shown
+    pub fn update_in_background(&mut self, ctx: &egui::Context) {
+        self.poll_events();
+        // the connection task ended, e.g. the headphones went away, before the app got its result
+        if self.is_connected && self.request_send.is_closed() {
+            self.set_disconnected();
+        }
+        self.check_confirmations(ctx);
+        #[cfg(target_os = "linux")]
+        self.tick_rules(ctx);
+        #[cfg(target_os = "linux")]
+        self.update_app_anc(ctx);
+    }
+
+    /// Revert the changes the headphones didn't confirm in time, checking again while some are pending
+    fn check_confirmations(&mut self, ctx: &egui::Context) {
+        for field in self.request_send.take_unconfirmed(chrono::Local::now()) {
+            self.revert(field);
+            self.command_error = Some(tr!("headphones-not-confirmed", setting = field));
+        }
+        for command in self.request_send.take_refused() {
+            if let Some(field) = SnapshotField::changed_by(&command) {
+                self.revert(field);
             }
+            self.command_error = Some(tr!(
+                "headphones-queue-full",
+                command = format!("{command:?}")
+            ));
+        }
+        if self.request_send.has_pending() {
+            ctx.request_repaint_after(Duration::from_secs(1));
         }
     }
+
+    /// Show the value the headphones last reported for `field` again, since our change to it didn't take or
+    /// wasn't sent
+    fn revert(&mut self, field: SnapshotField) {
+        let snapshot = &self.snapshot;
+        let state = &mut self.headphone_state;
+        match field {
+            SnapshotField::Equalizer => state.equalizer = snapshot.equalizer,
+            SnapshotField::Anc => {
+                if let Some(anc) = snapshot.anc {
+                    state.anc = Some(anc.into());
+                }
+            }
+            SnapshotField::CallVoiceFocus => state.call_voice_focus = snapshot.call_voice_focus,
+            SnapshotField::SidetoneLevel => state.sidetone_level = snapshot.sidetone_level,
+            SnapshotField::QuickAccess => {
+                state.quick_access =
+                    snapshot
+                        .quick_access
+                        .map(|(double_tap, triple_tap)| QuickAccess {
+                            double_tap,
+                            triple_tap,
+                        })
+            }
+            SnapshotField::SpeakToChatTimeout => {
+                state.speak_to_chat_timeout = snapshot.speak_to_chat_timeout
+            }
+            _ => (),
+        }
+    }
+
     pub fn poll_events(&mut self) {
-        while let Ok(payload) = self.payload_recv.try_recv() {
-            self.handle_payload(payload);
+        while let Ok(event) = self.event_recv.try_recv() {
+            match event {
+                ConnectionEvent::Payload(payload) => self.handle_payload(payload),
+                ConnectionEvent::CommandTimedOut(command) => {
+                    self.command_error = Some(tr!(
+                        "headphones-command-timed-out",
+                        command = format!("{command:?}")
+                    ));
+                }
+                ConnectionEvent::Protocol(event) => self.protocol_log.record(event),
+            }
+        }
+    }
+}
+
+impl Drop for HeadphoneUi {
+    fn drop(&mut self) {
+        if self.is_connected {
+            self.log(LogEvent::Disconnected);
         }
     }
 }
 
 impl eframe::App for HeadphoneUi {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        self.poll_events();
+        self.update_in_background(ctx);
         egui::CentralPanel::default().show(ctx, |ui| {
             self.draw_headphones_info(ui);
+            let writable = !self.request_send.is_read_only();
+            ui.add_enabled_ui(writable, |ui| {
+                enlarge_hit_targets(ui);
+                self.draw_sound_settings(ui);
+                ui.separator();
+                self.draw_touch_controls(ui);
+                self.draw_speak_to_chat(ui);
+                self.draw_calls(ui);
+                self.draw_find_my_buds(ui);
+            });
+            self.draw_profiles(ui);
+            self.draw_share(ui);
+            #[cfg(not(target_arch = "wasm32"))]
+            self.draw_backup(ui);
+            self.draw_history(ui);
+            self.draw_protocol_log(ui);
+            #[cfg(target_os = "linux")]
+            self.draw_notifications(ui);
+            #[cfg(target_os = "linux")]
+            self.draw_hotkeys(ui);
+            #[cfg(target_os = "linux")]
+            self.draw_rules(ui);
+            #[cfg(target_os = "linux")]
+            self.draw_audio_output(ui);
+            #[cfg(target_os = "linux")]
+            self.draw_app_anc(ui);
+            #[cfg(not(target_arch = "wasm32"))]
+            self.draw_bug_reports(ui);
+            self.draw_developer_console(ui);
         });
     }
 }
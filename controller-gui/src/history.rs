@@ -0,0 +1,334 @@
+use std::collections::VecDeque;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+use sony_wf1000xm5::snapshot::SnapshotChange;
+
+/// How many changes we keep around
+const HISTORY_CAPACITY: usize = 100;
+/// This many overwrites within [CONFLICT_WINDOW] means another controller is fighting us
+const CONFLICT_OVERWRITES: usize = 2;
+const CONFLICT_WINDOW: TimeDelta = TimeDelta::seconds(60);
+
+pub struct HistoryEntry {
+    pub time: DateTime<Local>,
+    pub change: SnapshotChange,
+    /// The change wasn't made by us (e.g. it was made with the touch sensors or the official app)
+    pub external: bool,
+}
+
+impl std::fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.time.format("%H:%M"), self.change)?;
+        if self.external {
+            write!(f, " (external)")?;
+        }
+        Ok(())
+    }
+}
+
+/// The last changes to the state of the headphones, for support purposes
+pub struct StateHistory {
+    entries: VecDeque<HistoryEntry>,
+}
+
+impl StateHistory {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::with_capacity(HISTORY_CAPACITY),
+        }
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.entries.len() == HISTORY_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Newest first
+    pub fn iter(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter().rev()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The whole history as text, oldest first, one change per line
+    pub fn export(&self) -> String {
+        self.entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{} {}{}",
+                    entry.time.format("%Y-%m-%d %H:%M:%S"),
+                    entry.change,
+                    if entry.external { " (external)" } else { "" }
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Default for StateHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Detects another controller (e.g. the Sony app on the phone) changing the same settings we do,
+/// by counting how often external changes overwrite ours.
+#[derive(Default)]
+pub struct ConflictDetector {
+    overwrites: VecDeque<DateTime<Local>>,
+    dismissed_at: Option<DateTime<Local>>,
+}
+
+impl ConflictDetector {
+    pub fn record_overwrite(&mut self, time: DateTime<Local>) {
+        self.overwrites.push_back(time);
+        while self
+            .overwrites
+            .front()
+            .is_some_and(|first| time - *first > CONFLICT_WINDOW)
+        {
+            self.overwrites.pop_front();
+        }
+    }
+
+    /// Whether we're in a tug-of-war with another controller (and the user didn't dismiss it since)
+    pub fn is_conflicting(&self, now: DateTime<Local>) -> bool {
+        let recent = self
+            .overwrites
+            .iter()
+            .filter(|time| now - **time <= CONFLICT_WINDOW)
+            .filter(|time| self.dismissed_at.is_none_or(|dismissed| **time > dismissed))
+            .count();
+        recent >= CONFLICT_OVERWRITES
+    }
+
+    pub fn dismiss(&mut self, now: DateTime<Local>) {
+        self.dismissed_at = Some(now);
+    }
+}
+
+/// Something worth remembering across app restarts
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LogEvent {
+    Connected,
+    Disconnected,
+    Change {
+        field: String,
+        old: Option<String>,
+        new: Option<String>,
+        external: bool,
+    },
+}
+
+impl From<&HistoryEntry> for LogEvent {
+    fn from(entry: &HistoryEntry) -> Self {
+        Self::Change {
+            field: format!("{:?}", entry.change.field),
+            old: entry.change.old.clone(),
+            new: entry.change.new.clone(),
+            external: entry.external,
+        }
+    }
+}
+
+/// One line of the history file
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRecord {
+    pub time: DateTime<Local>,
+    #[serde(flatten)]
+    pub event: LogEvent,
+}
+
+/// Whether and for how long the history is saved to disk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HistoryLogSettings {
+    pub enabled: bool,
+    pub retention_days: u32,
+}
+
+impl Default for HistoryLogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_days: 30,
+        }
+    }
+}
+
+impl HistoryLogSettings {
+    const ENABLED_KEY: &'static str = "HISTORY_LOG_ENABLED";
+    const RETENTION_DAYS_KEY: &'static str = "HISTORY_LOG_RETENTION_DAYS";
+
+    pub fn load(storage: &dyn eframe::Storage) -> Self {
+        let default = Self::default();
+        Self {
+            enabled: storage
+                .get_string(Self::ENABLED_KEY)
+                .is_some_and(|enabled| enabled == "true"),
+            retention_days: storage
+                .get_string(Self::RETENTION_DAYS_KEY)
+                .and_then(|days| days.parse().ok())
+                .unwrap_or(default.retention_days),
+        }
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(Self::ENABLED_KEY, self.enabled.to_string());
+        storage.set_string(Self::RETENTION_DAYS_KEY, self.retention_days.to_string());
+    }
+
+    pub fn retention(&self) -> TimeDelta {
+        TimeDelta::days(self.retention_days as i64)
+    }
+}
+
+/// Appends [LogRecord]s to a JSONL file, one record per line
+#[cfg(not(target_arch = "wasm32"))]
+pub struct HistoryLog {
+    file: File,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl HistoryLog {
+    pub const FILE_NAME: &'static str = "history.jsonl";
+
+    /// Open (or create) the log at `path`, dropping the records older than `retention`
+    pub fn open(path: &Path, retention: TimeDelta) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if path.exists() {
+            let cutoff = Local::now() - retention;
+            let kept = read_log(path)?
+                .into_iter()
+                .filter(|record| record.time >= cutoff)
+                .map(|record| serde_json::to_string(&record).map(|line| line + "\n"))
+                .collect::<Result<String, _>>()?;
+            // write the pruned log next to the old one, so we don't lose it if we crash midway
+            let tmp_path = path.with_extension("jsonl.tmp");
+            fs::write(&tmp_path, kept)?;
+            fs::rename(&tmp_path, path)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn append(&mut self, record: &LogRecord) -> io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())
+    }
+}
+
+/// Every record in the log at `path`. Lines which can't be parsed (e.g. a line cut short by a crash) are skipped.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_log(path: &Path) -> io::Result<Vec<LogRecord>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::snapshot::SnapshotField;
+
+    fn anc_change() -> SnapshotChange {
+        SnapshotChange {
+            field: SnapshotField::Anc,
+            old: Some("Ambient (level 10)".to_string()),
+            new: Some("Off".to_string()),
+        }
+    }
+
+    #[test]
+    fn conflict() {
+        let mut detector = ConflictDetector::default();
+        let start = Local::now();
+        detector.record_overwrite(start);
+        assert!(!detector.is_conflicting(start));
+        detector.record_overwrite(start + TimeDelta::seconds(10));
+        assert!(detector.is_conflicting(start + TimeDelta::seconds(10)));
+        // it calms down on its own
+        assert!(!detector.is_conflicting(start + TimeDelta::minutes(5)));
+
+        detector.dismiss(start + TimeDelta::seconds(11));
+        assert!(!detector.is_conflicting(start + TimeDelta::seconds(12)));
+        detector.record_overwrite(start + TimeDelta::seconds(20));
+        detector.record_overwrite(start + TimeDelta::seconds(25));
+        assert!(detector.is_conflicting(start + TimeDelta::seconds(25)));
+    }
+
+    #[test]
+    fn bounded() {
+        let mut history = StateHistory::new();
+        for _ in 0..HISTORY_CAPACITY + 5 {
+            history.push(HistoryEntry {
+                time: Local::now(),
+                change: anc_change(),
+                external: true,
+            });
+        }
+        assert_eq!(history.iter().count(), HISTORY_CAPACITY);
+        assert!(
+            history
+                .iter()
+                .next()
+                .unwrap()
+                .to_string()
+                .ends_with("ANC: Ambient (level 10)→Off (external)")
+        );
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn log_retention() {
+        let dir = std::env::temp_dir().join(format!("xm5-history-test-{}", std::process::id()));
+        let path = dir.join(HistoryLog::FILE_NAME);
+        let _ = fs::remove_dir_all(&dir);
+
+        let old = LogRecord {
+            time: Local::now() - TimeDelta::days(10),
+            event: LogEvent::Connected,
+        };
+        let new = LogRecord {
+            time: Local::now(),
+            event: LogEvent::Change {
+                field: "Anc".to_string(),
+                old: Some("Off".to_string()),
+                new: None,
+                external: true,
+            },
+        };
+        let mut log = HistoryLog::open(&path, TimeDelta::days(30)).unwrap();
+        log.append(&old).unwrap();
+        log.append(&new).unwrap();
+        drop(log);
+        // a truncated line is skipped
+        fs::write(
+            &path,
+            fs::read_to_string(&path).unwrap() + "{\"time\": \"2025",
+        )
+        .unwrap();
+        assert_eq!(read_log(&path).unwrap(), vec![old, new.clone()]);
+
+        HistoryLog::open(&path, TimeDelta::days(5)).unwrap();
+        assert_eq!(read_log(&path).unwrap(), vec![new]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
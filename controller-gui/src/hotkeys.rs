@@ -0,0 +1,309 @@
+//! Global shortcuts, which work without focusing the window (or with it hidden in the tray).
+//!
+//! They go through the GlobalShortcuts portal, which KDE and GNOME implement on Wayland and X11 alike; the desktop
+//! asks the user to confirm them the first time, and may let them pick other keys in its settings.
+//! Speak-to-Chat can't be toggled yet, since the library only knows its timeout.
+
+use crate::async_resource::{AsyncResource, ResourceStatus};
+use ashpd::desktop::global_shortcuts::{GlobalShortcuts, NewShortcut};
+use eframe::egui::{self, Key, Modifiers};
+use futures::StreamExt;
+use sony_wf1000xm5::command::{AncMode, EqualizerPreset};
+use tokio::sync::{mpsc, watch};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyAction {
+    CycleAnc,
+    NextEqualizerPreset,
+    PreviousEqualizerPreset,
+}
+
+impl HotkeyAction {
+    pub const ALL: [Self; 3] = [
+        Self::CycleAnc,
+        Self::NextEqualizerPreset,
+        Self::PreviousEqualizerPreset,
+    ];
+
+    /// What the portal knows it by
+    fn id(self) -> &'static str {
+        match self {
+            Self::CycleAnc => "cycle-anc",
+            Self::NextEqualizerPreset => "next-equalizer-preset",
+            Self::PreviousEqualizerPreset => "previous-equalizer-preset",
+        }
+    }
+
+    fn storage_key(self) -> &'static str {
+        match self {
+            Self::CycleAnc => "HOTKEY_CYCLE_ANC",
+            Self::NextEqualizerPreset => "HOTKEY_NEXT_EQUALIZER_PRESET",
+            Self::PreviousEqualizerPreset => "HOTKEY_PREVIOUS_EQUALIZER_PRESET",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::CycleAnc => "Cycle noise canceling, ambient sound and off",
+            Self::NextEqualizerPreset => "Next equalizer preset",
+            Self::PreviousEqualizerPreset => "Previous equalizer preset",
+        }
+    }
+}
+
+/// The mode [HotkeyAction::CycleAnc] switches to from `mode`
+pub fn next_anc_mode(mode: AncMode) -> AncMode {
+    match mode {
+        AncMode::ActiveNoiseCanceling => AncMode::AmbientSound,
+        AncMode::AmbientSound => AncMode::Off,
+        AncMode::Off => AncMode::ActiveNoiseCanceling,
+    }
+}
+
+/// The preset after (or before) `preset` in [EqualizerPreset::ALL], wrapping around
+pub fn step_equalizer_preset(preset: EqualizerPreset, forward: bool) -> EqualizerPreset {
+    let all = EqualizerPreset::ALL;
+    let index = all.iter().position(|p| *p == preset).unwrap_or(0);
+    let step = if forward { 1 } else { all.len() - 1 };
+    all[(index + step) % all.len()]
+}
+
+/// The trigger of `key` pressed with `modifiers`, as the XDG shortcuts spec writes them, e.g. `CTRL+ALT+n`.
+/// `None` for keys it has no name for, and for keys without Ctrl or Alt (but F1 and the like), which would
+/// keep the key from typing anywhere else.
+pub fn trigger(modifiers: Modifiers, key: Key) -> Option<String> {
+    let name = key.name();
+    let function_key = name.len() > 1 && name.starts_with('F') && name[1..].parse::<u8>().is_ok();
+    if !(modifiers.ctrl || modifiers.alt || function_key) {
+        return None;
+    }
+    let keysym = match key {
+        // letters and digits
+        _ if name.len() == 1 && name.chars().all(|c| c.is_ascii_alphanumeric()) => {
+            name.to_ascii_lowercase()
+        }
+        _ if function_key => name.to_string(),
+        Key::ArrowDown | Key::ArrowLeft | Key::ArrowRight | Key::ArrowUp => name.to_string(),
+        Key::Space => "space".to_string(),
+        Key::Enter => "Return".to_string(),
+        Key::Insert | Key::Delete | Key::Home | Key::End => name.to_string(),
+        Key::PageUp => "Prior".to_string(),
+        Key::PageDown => "Next".to_string(),
+        Key::Comma => "comma".to_string(),
+        Key::Minus => "minus".to_string(),
+        Key::Period => "period".to_string(),
+        Key::Equals => "equal".to_string(),
+        Key::Semicolon => "semicolon".to_string(),
+        Key::Slash => "slash".to_string(),
+        Key::Backslash => "backslash".to_string(),
+        Key::OpenBracket => "bracketleft".to_string(),
+        Key::CloseBracket => "bracketright".to_string(),
+        Key::Quote => "apostrophe".to_string(),
+        Key::Backtick => "grave".to_string(),
+        _ => return None,
+    };
+    let modifiers = [
+        (modifiers.ctrl, "CTRL"),
+        (modifiers.alt, "ALT"),
+        (modifiers.shift, "SHIFT"),
+    ];
+    Some(
+        modifiers
+            .into_iter()
+            .filter_map(|(pressed, name)| pressed.then_some(name))
+            .chain([keysym.as_str()])
+            .collect::<Vec<_>>()
+            .join("+"),
+    )
+}
+
+/// The trigger of each [HotkeyAction]; none are bound by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HotkeySettings {
+    /// In the order of [HotkeyAction::ALL]
+    triggers: [Option<String>; HotkeyAction::ALL.len()],
+}
+
+impl HotkeySettings {
+    pub fn load(storage: &dyn eframe::Storage) -> Self {
+        Self {
+            triggers: HotkeyAction::ALL.map(|action| {
+                storage
+                    .get_string(action.storage_key())
+                    .filter(|trigger| !trigger.is_empty())
+            }),
+        }
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        for action in HotkeyAction::ALL {
+            storage.set_string(
+                action.storage_key(),
+                self.trigger(action).unwrap_or_default().to_string(),
+            );
+        }
+    }
+
+    pub fn trigger(&self, action: HotkeyAction) -> Option<&str> {
+        self.triggers[action as usize].as_deref()
+    }
+
+    /// Bind `action` to `trigger`, or unbind it
+    pub fn set_trigger(&mut self, action: HotkeyAction, trigger: Option<String>) {
+        self.triggers[action as usize] = trigger;
+    }
+
+    fn shortcuts(&self) -> Vec<NewShortcut> {
+        HotkeyAction::ALL
+            .into_iter()
+            .filter_map(|action| {
+                self.trigger(action).map(|trigger| {
+                    NewShortcut::new(action.id(), action.description()).preferred_trigger(trigger)
+                })
+            })
+            .collect()
+    }
+}
+
+/// The shortcuts registered with the portal
+pub struct Hotkeys {
+    task: AsyncResource<ashpd::Result<()>>,
+    settings: watch::Sender<HotkeySettings>,
+    actions: mpsc::UnboundedReceiver<HotkeyAction>,
+    ctx: egui::Context,
+}
+
+impl Hotkeys {
+    pub fn spawn(ctx: &egui::Context, settings: HotkeySettings) -> Self {
+        let (actions_tx, actions) = mpsc::unbounded_channel();
+        let (settings, settings_rx) = watch::channel(settings);
+        let task = AsyncResource::default();
+        let task_ctx = ctx.clone();
+        task.set(async move {
+            let result = bind(settings_rx, actions_tx, task_ctx).await;
+            if let Err(e) = result.as_ref() {
+                log::warn!("no global shortcuts: {e}");
+            }
+            result
+        });
+        Self {
+            task,
+            settings,
+            actions,
+            ctx: ctx.clone(),
+        }
+    }
+
+    /// Register `settings` instead, if they changed; tries the portal again if it failed
+    pub fn rebind(&mut self, settings: &HotkeySettings) {
+        if *self.settings.borrow() != *settings && self.error().is_some() {
+            *self = Self::spawn(&self.ctx, settings.clone());
+            return;
+        }
+        self.settings.send_if_modified(|current| {
+            let changed = current != settings;
+            current.clone_from(settings);
+            changed
+        });
+    }
+
+    /// Why the shortcuts couldn't be registered, e.g. because the desktop has no portal for them
+    pub fn error(&self) -> Option<String> {
+        match self.task.get() {
+            ResourceStatus::Ready(result) => result.as_ref().err().map(|e| e.to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn poll_action(&mut self) -> Option<HotkeyAction> {
+        self.actions.try_recv().ok()
+    }
+}
+
+/// Keep the shortcuts of `settings` registered, sending the actions of the ones pressed.
+/// Nothing is asked of the portal until there's a shortcut.
+async fn bind(
+    mut settings: watch::Receiver<HotkeySettings>,
+    actions: mpsc::UnboundedSender<HotkeyAction>,
+    ctx: egui::Context,
+) -> ashpd::Result<()> {
+    let mut portal = None;
+    loop {
+        let shortcuts = settings.borrow_and_update().shortcuts();
+        if shortcuts.is_empty() {
+            if settings.changed().await.is_err() {
+                return Ok(());
+            }
+            continue;
+        }
+        let portal = match portal.as_mut() {
+            Some(portal) => portal,
+            None => portal.insert(GlobalShortcuts::new().await?),
+        };
+        let mut activated = std::pin::pin!(portal.receive_activated().await?);
+        let session = portal.create_session().await?;
+        portal
+            .bind_shortcuts(&session, &shortcuts, None)
+            .await?
+            .response()?;
+        let closed = loop {
+            tokio::select! {
+                Some(activation) = activated.next() => {
+                    if let Some(action) = HotkeyAction::ALL
+                        .into_iter()
+                        .find(|action| action.id() == activation.shortcut_id())
+                    {
+                        let _ = actions.send(action);
+                        // to run the app's update even with the window hidden
+                        ctx.request_repaint();
+                    }
+                }
+                changed = settings.changed() => break changed.is_err(),
+            }
+        };
+        session.close().await?;
+        if closed {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn triggers() {
+        let ctrl_alt = Modifiers {
+            ctrl: true,
+            alt: true,
+            ..Default::default()
+        };
+        assert_eq!(trigger(ctrl_alt, Key::N).as_deref(), Some("CTRL+ALT+n"));
+        assert_eq!(
+            trigger(Modifiers::SHIFT | Modifiers::ALT, Key::PageUp).as_deref(),
+            Some("ALT+SHIFT+Prior")
+        );
+        assert_eq!(trigger(Modifiers::NONE, Key::F9).as_deref(), Some("F9"));
+        // would keep the key from typing
+        assert_eq!(trigger(Modifiers::SHIFT, Key::N), None);
+        assert_eq!(trigger(ctrl_alt, Key::Copy), None);
+    }
+
+    #[test]
+    fn cycling() {
+        assert_eq!(next_anc_mode(AncMode::Off), AncMode::ActiveNoiseCanceling);
+        assert_eq!(
+            step_equalizer_preset(EqualizerPreset::Custom2, true),
+            EqualizerPreset::Off
+        );
+        assert_eq!(
+            step_equalizer_preset(EqualizerPreset::Off, false),
+            EqualizerPreset::Custom2
+        );
+        assert_eq!(
+            step_equalizer_preset(EqualizerPreset::Bright, true),
+            EqualizerPreset::Excited
+        );
+    }
+}
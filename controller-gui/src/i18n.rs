@@ -0,0 +1,169 @@
+//! The translations of the user-facing strings. Each language has a catalogue in `controller-gui/i18n`, in a
+//! subset of the Fluent syntax: one `key = text` per line, with `{ $name }` for what [tr!] passes in and `#`
+//! comments. Adding a language takes its catalogue and a [Language] variant; the layout code only knows the keys.
+//! What a catalogue lacks is shown in English.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{
+        LazyLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Language {
+    English,
+}
+
+impl Language {
+    pub const ALL: [Self; 1] = [Self::English];
+
+    /// e.g. `en`, as in `LANG` and in the settings
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::English => "en",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|language| language.code() == code)
+    }
+
+    /// In the language itself
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::English => "English",
+        }
+    }
+
+    fn catalogue(self) -> &'static str {
+        match self {
+            Self::English => include_str!("../i18n/en.ftl"),
+        }
+    }
+
+    /// The language of the system, from `LC_ALL`, `LC_MESSAGES` or `LANG` (e.g. `de_DE.UTF-8`), or English
+    pub fn detect() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        for variable in ["LC_ALL", "LC_MESSAGES", "LANG"] {
+            let Ok(locale) = std::env::var(variable) else {
+                continue;
+            };
+            if locale.is_empty() {
+                continue;
+            }
+            let code = locale.split(['_', '.', '@']).next().unwrap_or_default();
+            return Self::from_code(code).unwrap_or(Self::English);
+        }
+        Self::English
+    }
+}
+
+/// The messages of each language, in the order of [Language::ALL]
+static CATALOGUES: LazyLock<Vec<HashMap<String, String>>> = LazyLock::new(|| {
+    Language::ALL
+        .iter()
+        .map(|language| parse_catalogue(language.catalogue()))
+        .collect()
+});
+/// The index of the current language in [Language::ALL]
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+fn parse_catalogue(catalogue: &str) -> HashMap<String, String> {
+    catalogue
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (key, text) = line.split_once('=')?;
+            Some((key.trim().to_string(), text.trim().to_string()))
+        })
+        .collect()
+}
+
+pub fn set_language(language: Language) {
+    let index = Language::ALL
+        .iter()
+        .position(|known| *known == language)
+        .expect("every language is in ALL");
+    CURRENT.store(index, Ordering::Relaxed);
+}
+
+pub fn language() -> Language {
+    Language::ALL[CURRENT.load(Ordering::Relaxed)]
+}
+
+/// The message `key` in the current language with `args` put in, or the key itself if no catalogue has it
+pub fn translate(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    let text = CATALOGUES[CURRENT.load(Ordering::Relaxed)]
+        .get(key)
+        .or_else(|| CATALOGUES[0].get(key));
+    let Some(text) = text else {
+        log::warn!("no translation for {key}");
+        return key.to_string();
+    };
+    let mut translated = text.clone();
+    for (name, value) in args {
+        translated = translated.replace(&format!("{{ ${name} }}"), &value.to_string());
+    }
+    translated
+}
+
+/// The translation of a message, e.g. `tr!("picker-pairing-failed", error = e)` for
+/// `picker-pairing-failed = Pairing failed: { $error }`
+#[macro_export]
+macro_rules! tr {
+    ($key:literal) => {
+        $crate::i18n::translate($key, &[])
+    };
+    ($key:literal, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::translate(
+            $key,
+            &[$((stringify!($name), &$value as &dyn std::fmt::Display)),+],
+        )
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn catalogues() {
+        let english = parse_catalogue(Language::English.catalogue());
+        assert_eq!(
+            translate("picker-pairing-failed", &[("error", &"timed out")]),
+            "Pairing failed: timed out"
+        );
+        assert_eq!(translate("no-such-message", &[]), "no-such-message");
+
+        // every message the layout code asks for is in the English catalogue
+        for source in [
+            include_str!("app.rs"),
+            include_str!("compact_window.rs"),
+            include_str!("device_picker.rs"),
+            include_str!("headphone_ui.rs"),
+            include_str!("last_state.rs"),
+            include_str!("status_bar.rs"),
+            include_str!("widgets.rs"),
+        ] {
+            for usage in source.split("tr!(\"").skip(1) {
+                let key = usage.split('"').next().unwrap();
+                assert!(english.contains_key(key), "{key} isn't in en.ftl");
+            }
+        }
+        // and the others don't have any English lacks, e.g. after a key was renamed
+        for language in Language::ALL {
+            for key in parse_catalogue(language.catalogue()).keys() {
+                assert!(
+                    english.contains_key(key),
+                    "{key} of {language:?} isn't in en.ftl"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! The state the headphones were in when they were last connected, kept per address, so their tab shows it right
+//! away (marked stale) while the connection is being set up, instead of an empty panel.
+
+use crate::{
+    tr,
+    widgets::{BatteryRow, anc_label, preset_label},
+};
+use chrono::{DateTime, Local};
+use eframe::egui::{self, RichText};
+use serde::{Deserialize, Serialize};
+use sony_wf1000xm5::{
+    payload::{BatteryPercent, BatteryStatus, Codec},
+    snapshot::{AncSnapshot, EqualizerSnapshot, HeadphoneSnapshot},
+};
+use std::collections::HashMap;
+
+/// What the headphones last reported of what the tab shows first
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LastState {
+    /// When they were still connected
+    pub seen_at: DateTime<Local>,
+    pub battery: Option<BatteryPercent>,
+    pub battery_status: BatteryStatus,
+    pub equalizer: Option<EqualizerSnapshot>,
+    pub anc: Option<AncSnapshot>,
+    pub codec: Option<Codec>,
+}
+
+impl LastState {
+    /// The part of `snapshot` to keep, or `None` if the headphones didn't report any of it yet
+    pub fn from_snapshot(snapshot: &HeadphoneSnapshot, seen_at: DateTime<Local>) -> Option<Self> {
+        let state = Self {
+            seen_at,
+            battery: snapshot.battery,
+            battery_status: snapshot.battery_status,
+            equalizer: snapshot.equalizer,
+            anc: snapshot.anc,
+            codec: snapshot.codec,
+        };
+        let empty = state.battery.is_none()
+            && state.battery_status == BatteryStatus::default()
+            && state.equalizer.is_none()
+            && state.anc.is_none()
+            && state.codec.is_none();
+        (!empty).then_some(state)
+    }
+
+    /// Greyed out, under a label telling when it's from
+    pub fn draw(&self, ui: &mut egui::Ui) {
+        ui.label(RichText::new(tr!(
+            "last-state-stale",
+            time = self.seen_at.format("%Y-%m-%d %H:%M").to_string()
+        )))
+        .on_hover_text(tr!("last-state-stale-hover"));
+        ui.add_enabled_ui(false, |ui| {
+            ui.add(BatteryRow {
+                status: self.battery_status,
+                battery: self.battery,
+                text_size: None,
+            });
+            if let Some(anc) = self.anc {
+                ui.label(tr!("last-state-anc", mode = anc_label(anc.mode)));
+            }
+            if let Some(equalizer) = self.equalizer {
+                ui.label(tr!(
+                    "last-state-equalizer",
+                    preset = preset_label(equalizer.preset)
+                ));
+            }
+            if let Some(codec) = self.codec {
+                ui.label(tr!("headphones-codec", codec = codec.as_str()));
+            }
+        });
+    }
+}
+
+/// The last state of every pair of headphones which was connected, by address
+#[derive(Debug, Default)]
+pub struct LastStates {
+    states: HashMap<String, LastState>,
+}
+
+impl LastStates {
+    const KEY: &'static str = "LAST_STATES";
+
+    /// Kept states which can't be read (e.g. saved by a newer version of the app) are forgotten
+    pub fn load(storage: &dyn eframe::Storage) -> Self {
+        let states = storage
+            .get_string(Self::KEY)
+            .map(|json| {
+                serde_json::from_str(&json).unwrap_or_else(|e| {
+                    log::warn!("couldn't read the last states of the headphones: {e}");
+                    HashMap::new()
+                })
+            })
+            .unwrap_or_default();
+        Self { states }
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(
+            Self::KEY,
+            serde_json::to_string(&self.states).expect("the states are always serializable"),
+        );
+    }
+
+    pub fn get(&self, address: &str) -> Option<&LastState> {
+        self.states.get(address)
+    }
+
+    /// Keep what the connected headphones at `address` reported so far
+    pub fn record(&mut self, address: &str, snapshot: &HeadphoneSnapshot) {
+        if let Some(state) = LastState::from_snapshot(snapshot, Local::now()) {
+            self.states.insert(address.to_string(), state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::payload::Payload;
+
+    #[test]
+    fn kept_per_address() {
+        let mut snapshot = HeadphoneSnapshot::default();
+        let mut states = LastStates::default();
+        // nothing reported yet
+        states.record("AC:80:0A:12:34:56", &snapshot);
+        assert!(states.get("AC:80:0A:12:34:56").is_none());
+
+        snapshot.apply(&Payload::Codec { codec: Codec::Ldac });
+        states.record("AC:80:0A:12:34:56", &snapshot);
+        let json = serde_json::to_string(&states.states).unwrap();
+        let loaded: HashMap<String, LastState> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded["AC:80:0A:12:34:56"].codec, Some(Codec::Ldac));
+        assert!(states.get("AC:80:0A:12:34:57").is_none());
+    }
+}
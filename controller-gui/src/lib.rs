@@ -1,6 +1,53 @@
 pub mod app;
+#[cfg(target_os = "linux")]
+pub mod app_anc;
 pub mod async_resource;
 #[cfg(target_os = "linux")]
+pub mod autostart;
+pub mod backup;
+pub mod command_sender;
+#[cfg(target_os = "linux")]
+pub mod compact_window;
+pub mod developer_console;
+#[cfg(target_os = "linux")]
 pub mod device_picker;
+#[cfg(target_os = "linux")]
+pub mod diagnostics;
+pub mod eq_file;
+pub mod equalizer_curve;
+pub mod exposure;
+#[cfg(target_os = "linux")]
+pub mod firmware_update;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod frame_capture;
 pub mod headphone_thread;
 pub mod headphone_ui;
+pub mod history;
+#[cfg(target_os = "linux")]
+pub mod hotkeys;
+pub mod i18n;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod last_state;
+#[cfg(target_os = "linux")]
+pub mod limited_mode;
+#[cfg(target_os = "linux")]
+pub mod notifications;
+#[cfg(target_os = "linux")]
+pub mod pairing;
+#[cfg(target_os = "linux")]
+pub mod pipewire;
+pub mod profiles;
+pub mod protocol_log;
+pub mod reconnect;
+#[cfg(target_os = "linux")]
+pub mod rules;
+pub mod settings;
+pub mod share;
+#[cfg(target_os = "linux")]
+pub mod single_instance;
+pub mod status_bar;
+#[cfg(target_os = "linux")]
+pub mod tray;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod wakeup_audit;
+pub mod widgets;
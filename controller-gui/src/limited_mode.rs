@@ -0,0 +1,78 @@
+use crate::async_resource::{AsyncResource, ResourceStatus};
+use bluer::{Device, DeviceEvent, DeviceProperty};
+use eframe::egui::{Context, RichText, Ui};
+use futures::{StreamExt, pin_mut};
+use sony_wf1000xm5::{
+    payload::BatteryPercent,
+    snapshot::{FallbackReading, HeadphoneSnapshot},
+};
+use std::{cell::RefCell, rc::Rc};
+
+/// What we can still show when the Sony channel is owned by another device (usually the phone):
+/// the battery level BlueZ gets through the standard Battery1 interface.
+#[derive(Default)]
+pub struct LimitedMode {
+    snapshot: Rc<RefCell<HeadphoneSnapshot>>,
+    battery_task: AsyncResource<anyhow::Result<()>>,
+}
+
+impl LimitedMode {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn draw(&self, ctx: &Context, ui: &mut Ui, device: &Device) {
+        ui.label(
+            RichText::new("Limited mode")
+                .strong()
+                .size(25.0)
+                .color(eframe::egui::Color32::YELLOW),
+        );
+        ui.label(
+            "Another device is using the headphones' control channel, so only the battery level is available.",
+        );
+        match self.battery_task.get() {
+            ResourceStatus::Ready(result) => {
+                if let Err(e) = result.as_ref() {
+                    ui.label(format!("Couldn't read the battery level: {e}"));
+                }
+            }
+            ResourceStatus::Pending => (),
+            ResourceStatus::NotInitialized => {
+                let device = device.clone();
+                let snapshot = self.snapshot.clone();
+                let ctx = ctx.clone();
+                self.battery_task.set(async move {
+                    let update = |level: u8| {
+                        let Ok(level) = BatteryPercent::new(level) else {
+                            log::warn!("BlueZ reported a battery level of {level}%; ignoring");
+                            return;
+                        };
+                        snapshot
+                            .borrow_mut()
+                            .apply_fallback(&FallbackReading::Battery(level));
+                        ctx.request_repaint();
+                    };
+                    if let Some(level) = device.battery_percentage().await? {
+                        update(level);
+                    }
+                    let events = device.events().await?;
+                    pin_mut!(events);
+                    while let Some(event) = events.next().await {
+                        if let DeviceEvent::PropertyChanged(DeviceProperty::BatteryPercentage(
+                            level,
+                        )) = event
+                        {
+                            update(level);
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+        match self.snapshot.borrow().battery {
+            Some(battery) => ui.label(RichText::new(format!("battery: {battery}%")).size(25.0)),
+            None => ui.label("BlueZ doesn't know the battery level (yet)."),
+        };
+    }
+}
@@ -1,38 +1,144 @@
 use controller_gui::app::App;
 #[cfg(not(target_arch = "wasm32"))]
+use controller_gui::app_anc::AppAncSettings;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::compact_window::CompactWindow;
+#[cfg(not(target_arch = "wasm32"))]
 use controller_gui::device_picker::DevicePicker;
 #[cfg(not(target_arch = "wasm32"))]
+use controller_gui::frame_capture::FrameCapture;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::headphone_thread::OpenConnections;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::history::HistoryLogSettings;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::hotkeys::HotkeySettings;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::last_state::LastStates;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::notifications::NotificationSettings;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::profiles::Profiles;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::reconnect::Reconnect;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::rules::Rules;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::settings::AppSettings;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::single_instance::SingleInstance;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::tray::TraySettings;
+#[cfg(not(target_arch = "wasm32"))]
+use controller_gui::wakeup_audit::{Wakeup, WakeupAudit};
+#[cfg(not(target_arch = "wasm32"))]
 use eframe::{EframePumpStatus, UserEvent, egui};
 #[cfg(not(target_arch = "wasm32"))]
-use std::{io, os::fd::AsRawFd};
+use sony_wf1000xm5::connection::SHUTDOWN_TIMEOUT;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    cell::RefCell,
+    io,
+    os::fd::AsRawFd,
+    rc::Rc,
+    sync::{Arc, atomic::AtomicBool},
+    time::Duration,
+};
 #[cfg(not(target_arch = "wasm32"))]
 use tokio::task::LocalSet;
 #[cfg(not(target_arch = "wasm32"))]
 use winit::event_loop::{ControlFlow, EventLoop};
 
+/// Never change anything on the headphones, see [controller_gui::command_sender::CommandSender]
+#[cfg(not(target_arch = "wasm32"))]
+const READ_ONLY_FLAG: &str = "--read-only";
+/// Start with only the tray icon, see [TraySettings::start_minimized]
+#[cfg(not(target_arch = "wasm32"))]
+const MINIMIZED_FLAG: &str = "--minimized";
+/// Show the developer console for this run, see [controller_gui::developer_console]
+#[cfg(not(target_arch = "wasm32"))]
+const DEVELOPER_FLAG: &str = "--developer";
+
 #[cfg(not(target_arch = "wasm32"))]
 pub fn main() -> io::Result<()> {
     env_logger::init();
+    let read_only = std::env::args().skip(1).any(|arg| arg == READ_ONLY_FLAG);
+    let minimized = std::env::args().skip(1).any(|arg| arg == MINIMIZED_FLAG);
+    let developer = std::env::args().skip(1).any(|arg| arg == DEVELOPER_FLAG);
+    // started minimized, e.g. at login, the running instance stays where it is
+    let instance = match SingleInstance::acquire(!minimized) {
+        Ok(Some(instance)) => Some(instance),
+        Ok(None) => {
+            log::info!("already running");
+            return Ok(());
+        }
+        Err(e) => {
+            log::warn!("couldn't check for another instance: {e}");
+            None
+        }
+    };
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([320.0, 240.0]),
+        viewport: egui::ViewportBuilder::default().with_inner_size([400.0, 520.0]),
         ..Default::default()
     };
 
     let mut eventloop = EventLoop::<UserEvent>::with_user_event().build().unwrap();
     eventloop.set_control_flow(ControlFlow::Poll);
 
+    let open_connections = OpenConnections::default();
     let mut winit_app = eframe::create_native(
-        "Sony-WF1000XM5 GUI",
+        App::NAME,
         options,
         Box::new(|cc| {
             let mut app = App::default();
+            app.read_only = read_only;
+            app.developer = developer;
 
-            if let Some(storage) = cc.storage
-                && let Some(addr) = storage.get_string(DevicePicker::LAST_ADDR_KEY)
-                && !addr.is_empty()
-            {
-                app.picker.last_device_addr = addr;
-                app.picker.connect_to_the_device_automatically_on_startup = true;
+            if let Some(storage) = cc.storage {
+                if let Some(addr) = storage.get_string(DevicePicker::LAST_ADDR_KEY)
+                    && !addr.is_empty()
+                {
+                    app.picker.last_device_addr = addr;
+                    app.picker.connect_to_the_device_automatically_on_startup = true;
+                }
+                app.history_settings = HistoryLogSettings::load(storage);
+                app.settings = AppSettings::load(storage);
+                controller_gui::i18n::set_language(app.settings.effective_language());
+                app.apply_appearance(&cc.egui_ctx);
+                app.profiles = Rc::new(RefCell::new(Profiles::load(storage)));
+                app.last_states = LastStates::load(storage);
+                app.notification_settings = NotificationSettings::load(storage);
+                app.capture_frames = Arc::new(AtomicBool::new(
+                    storage
+                        .get_string(FrameCapture::ENABLED_KEY)
+                        .is_some_and(|enabled| enabled == "true"),
+                ));
+                app.reconnect.enabled = storage
+                    .get_string(Reconnect::ENABLED_KEY)
+                    .is_none_or(|enabled| enabled == "true");
+                app.tray_settings = TraySettings::load(storage);
+                app.hotkey_settings = HotkeySettings::load(storage);
+                app.app_anc_settings = AppAncSettings::load(storage);
+                app.compact_window = CompactWindow::load(storage);
+            }
+            app.autostart = controller_gui::autostart::is_installed();
+            app.open_connections = open_connections.clone();
+            app.show_requests = instance.map(|instance| instance.listen(&cc.egui_ctx));
+            if let Some(path) = Rules::path() {
+                match Rules::load(&path) {
+                    Ok(rules) => app.rules = Rc::new(RefCell::new(rules)),
+                    Err(e) => log::warn!("no rules: {e}"),
+                }
+            }
+            if app.compact_window.shown {
+                app.set_compact(&cc.egui_ctx, true);
+            }
+            if minimized || app.tray_settings.start_minimized {
+                // eframe shows the window once the first frame is painted, and handles this right after;
+                // the app shows it again if there's no tray icon to click
+                app.hidden = true;
+                cc.egui_ctx
+                    .send_viewport_cmd(egui::ViewportCommand::Visible(false));
             }
             Ok(Box::new(app))
         }),
@@ -48,20 +154,38 @@ pub fn main() -> io::Result<()> {
     local.block_on(&rt, async {
         let eventloop_fd = tokio::io::unix::AsyncFd::new(eventloop.as_raw_fd())?;
         let mut control_flow = ControlFlow::Poll;
+        let mut audit = WakeupAudit::from_env();
 
         loop {
+            // all the waiting happens here, so the tasks on the LocalSet get to run in the meantime
             let mut guard = match control_flow {
-                ControlFlow::Poll => None,
-                ControlFlow::Wait => Some(eventloop_fd.readable().await?),
+                ControlFlow::Poll => {
+                    tokio::task::yield_now().await;
+                    audit.record(Wakeup::Poll);
+                    None
+                }
+                ControlFlow::Wait => {
+                    let guard = eventloop_fd.readable().await?;
+                    audit.record(Wakeup::Event);
+                    Some(guard)
+                }
+                // egui's next requested repaint
                 ControlFlow::WaitUntil(deadline) => {
-                    tokio::time::timeout_at(deadline.into(), eventloop_fd.readable())
+                    let guard = tokio::time::timeout_at(deadline.into(), eventloop_fd.readable())
                         .await
                         .ok()
-                        .transpose()?
+                        .transpose()?;
+                    audit.record(if guard.is_some() {
+                        Wakeup::Event
+                    } else {
+                        Wakeup::Deadline
+                    });
+                    guard
                 }
             };
 
-            match winit_app.pump_eframe_app(&mut eventloop, None) {
+            // never let winit block; we already waited above
+            match winit_app.pump_eframe_app(&mut eventloop, Some(Duration::ZERO)) {
                 EframePumpStatus::Continue(next) => control_flow = next,
                 EframePumpStatus::Exit(_code) => {
                     break;
@@ -73,6 +197,13 @@ pub fn main() -> io::Result<()> {
             }
         }
 
+        // the app asked the connections to close in order on exit
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT * 2, open_connections.closed())
+            .await
+            .is_err()
+        {
+            log::warn!("the connections didn't close in time");
+        }
         Ok::<_, io::Error>(())
     })
 }
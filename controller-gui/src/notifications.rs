@@ -0,0 +1,244 @@
+//! Desktop notifications for what happens while the window is out of sight: low and full batteries,
+//! codec changes and lost connections.
+
+use sony_wf1000xm5::payload::{BatteryLevel, BatteryPercent, Codec, Payload};
+
+/// Which notifications to show
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotificationSettings {
+    pub low_battery: bool,
+    /// In percent; a battery is low once it's at or below it
+    pub low_battery_threshold: u8,
+    pub charged: bool,
+    pub codec: bool,
+    pub disconnect: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            low_battery: true,
+            low_battery_threshold: 20,
+            charged: true,
+            codec: false,
+            disconnect: true,
+        }
+    }
+}
+
+impl NotificationSettings {
+    const LOW_BATTERY_KEY: &'static str = "NOTIFY_LOW_BATTERY";
+    const LOW_BATTERY_THRESHOLD_KEY: &'static str = "NOTIFY_LOW_BATTERY_THRESHOLD";
+    const CHARGED_KEY: &'static str = "NOTIFY_CHARGED";
+    const CODEC_KEY: &'static str = "NOTIFY_CODEC";
+    const DISCONNECT_KEY: &'static str = "NOTIFY_DISCONNECT";
+
+    pub fn load(storage: &dyn eframe::Storage) -> Self {
+        let default = Self::default();
+        let flag = |key, default| {
+            storage
+                .get_string(key)
+                .map_or(default, |enabled| enabled == "true")
+        };
+        Self {
+            low_battery: flag(Self::LOW_BATTERY_KEY, default.low_battery),
+            low_battery_threshold: storage
+                .get_string(Self::LOW_BATTERY_THRESHOLD_KEY)
+                .and_then(|threshold| threshold.parse().ok())
+                .unwrap_or(default.low_battery_threshold),
+            charged: flag(Self::CHARGED_KEY, default.charged),
+            codec: flag(Self::CODEC_KEY, default.codec),
+            disconnect: flag(Self::DISCONNECT_KEY, default.disconnect),
+        }
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(Self::LOW_BATTERY_KEY, self.low_battery.to_string());
+        storage.set_string(
+            Self::LOW_BATTERY_THRESHOLD_KEY,
+            self.low_battery_threshold.to_string(),
+        );
+        storage.set_string(Self::CHARGED_KEY, self.charged.to_string());
+        storage.set_string(Self::CODEC_KEY, self.codec.to_string());
+        storage.set_string(Self::DISCONNECT_KEY, self.disconnect.to_string());
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Notice {
+    pub summary: String,
+    pub body: String,
+    pub urgent: bool,
+}
+
+impl Notice {
+    /// Show it on the desktop; failures are only logged, e.g. when there's no notification daemon
+    pub fn show(self) {
+        tokio::task::spawn_local(async move {
+            let result = notify_rust::Notification::new()
+                .appname(crate::app::App::NAME)
+                .icon("audio-headphones")
+                .summary(&self.summary)
+                .body(&self.body)
+                .urgency(if self.urgent {
+                    notify_rust::Urgency::Critical
+                } else {
+                    notify_rust::Urgency::Normal
+                })
+                .show_async()
+                .await;
+            if let Err(e) = result {
+                log::warn!("couldn't show a notification: {e}");
+            }
+        });
+    }
+}
+
+/// Decides which notices the payloads of one connection call for
+#[derive(Debug, Default)]
+pub struct Notifier {
+    pub settings: NotificationSettings,
+    /// The last level of each battery, by name
+    batteries: Vec<(&'static str, BatteryPercent)>,
+    codec: Option<Codec>,
+}
+
+impl Notifier {
+    pub fn new(settings: NotificationSettings) -> Self {
+        Self {
+            settings,
+            ..Default::default()
+        }
+    }
+
+    pub fn on_payload(&mut self, payload: &Payload) -> Vec<Notice> {
+        match payload {
+            Payload::BatteryLevel(BatteryLevel::Single(level)) => {
+                self.on_battery("Battery", *level).into_iter().collect()
+            }
+            Payload::BatteryLevel(BatteryLevel::Headphones { left, right }) => self
+                .on_battery("Left earbud", *left)
+                .into_iter()
+                .chain(self.on_battery("Right earbud", *right))
+                .collect(),
+            Payload::BatteryLevel(BatteryLevel::Case(level)) => {
+                self.on_battery("Case", *level).into_iter().collect()
+            }
+            Payload::Codec { codec } => {
+                let old = self.codec.replace(*codec);
+                if self.settings.codec
+                    && let Some(old) = old
+                    && old != *codec
+                {
+                    vec![Notice {
+                        summary: format!("Now using {codec:?}"),
+                        body: format!("The headphones switched from {old:?} to {codec:?}."),
+                        urgent: false,
+                    }]
+                } else {
+                    vec![]
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    /// The connection ended with `error`
+    pub fn on_disconnect(&self, error: &str) -> Option<Notice> {
+        self.settings.disconnect.then(|| Notice {
+            summary: "Lost the connection to the headphones".to_string(),
+            body: error.to_string(),
+            urgent: false,
+        })
+    }
+
+    fn on_battery(&mut self, name: &'static str, level: BatteryPercent) -> Option<Notice> {
+        let old = match self.batteries.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, old)) => Some(std::mem::replace(old, level)),
+            None => {
+                self.batteries.push((name, level));
+                None
+            }
+        };
+        let threshold = self.settings.low_battery_threshold;
+        let low = |level: BatteryPercent| level.get() <= threshold;
+        if self.settings.low_battery && low(level) && !old.is_some_and(low) {
+            Some(Notice {
+                summary: format!("{name}: low battery"),
+                body: format!("{name} is at {level}."),
+                urgent: true,
+            })
+        } else if self.settings.charged
+            && level.get() == 100
+            && old.is_some_and(|old| old.get() < 100)
+        {
+            Some(Notice {
+                summary: format!("{name}: fully charged"),
+                body: format!("{name} is at {level}."),
+                urgent: false,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn single(level: u8) -> Payload {
+        Payload::BatteryLevel(BatteryLevel::Single(BatteryPercent::new(level).unwrap()))
+    }
+
+    #[test]
+    fn battery() {
+        let mut notifier = Notifier::new(NotificationSettings::default());
+        assert!(notifier.on_payload(&single(50)).is_empty());
+        let notices = notifier.on_payload(&single(20));
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].summary, "Battery: low battery");
+        // only once per crossing
+        assert!(notifier.on_payload(&single(15)).is_empty());
+        assert!(notifier.on_payload(&single(99)).is_empty());
+        assert_eq!(
+            notifier.on_payload(&single(100))[0].summary,
+            "Battery: fully charged"
+        );
+
+        // low from the start
+        let mut notifier = Notifier::new(NotificationSettings::default());
+        let notices = notifier.on_payload(&Payload::BatteryLevel(BatteryLevel::Headphones {
+            left: BatteryPercent::new(10).unwrap(),
+            right: BatteryPercent::new(60).unwrap(),
+        }));
+        assert_eq!(notices.len(), 1);
+        assert_eq!(notices[0].body, "Left earbud is at 10%.");
+    }
+
+    #[test]
+    fn codec() {
+        let mut notifier = Notifier::new(NotificationSettings {
+            codec: true,
+            ..Default::default()
+        });
+        assert!(
+            notifier
+                .on_payload(&Payload::Codec { codec: Codec::Aac })
+                .is_empty()
+        );
+        assert!(
+            notifier
+                .on_payload(&Payload::Codec { codec: Codec::Aac })
+                .is_empty()
+        );
+        assert_eq!(
+            notifier.on_payload(&Payload::Codec { codec: Codec::Ldac }),
+            [Notice {
+                summary: "Now using Ldac".to_string(),
+                body: "The headphones switched from Aac to Ldac.".to_string(),
+                urgent: false,
+            }]
+        );
+    }
+}
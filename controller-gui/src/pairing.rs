@@ -0,0 +1,135 @@
+//! Pairing new headphones from the app, so nothing like `bluetoothctl` is needed before the first connection.
+//!
+//! While pairing, the app is the BlueZ agent: if the headphones want a code confirmed or typed in (most pair
+//! without one), the request goes to the UI as a [PairingPrompt] and waits for the answer.
+
+use bluer::{
+    Adapter, Address, Device, Session,
+    agent::{Agent, ReqError, ReqResult},
+};
+use eframe::egui;
+use std::{future::Future, pin::Pin};
+use tokio::sync::{mpsc, oneshot};
+
+/// What the headphones ask for
+pub enum PairingRequest {
+    /// Whether the passkey is the one shown on the other side
+    Confirm {
+        passkey: u32,
+        reply: oneshot::Sender<Option<()>>,
+    },
+    PinCode {
+        reply: oneshot::Sender<Option<String>>,
+    },
+    Passkey {
+        reply: oneshot::Sender<Option<u32>>,
+    },
+    /// A code to type in on the other side
+    Display(String),
+}
+
+pub struct PairingPrompt {
+    pub device: Address,
+    pub request: PairingRequest,
+}
+
+type Prompts = mpsc::UnboundedSender<PairingPrompt>;
+type Reply<T> = Pin<Box<dyn Future<Output = ReqResult<T>> + Send>>;
+
+/// Where the agent sends its prompts
+#[derive(Clone)]
+struct PromptSender {
+    prompts: Prompts,
+    ctx: egui::Context,
+}
+
+impl PromptSender {
+    fn send(&self, device: Address, request: PairingRequest) -> ReqResult<()> {
+        let sent = self.prompts.send(PairingPrompt { device, request });
+        self.ctx.request_repaint();
+        sent.map_err(|_| ReqError::Canceled)
+    }
+
+    /// Send the prompt made by `request` and wait for the answer; no answer rejects the request
+    fn ask<T: Send + 'static>(
+        &self,
+        device: Address,
+        request: impl FnOnce(oneshot::Sender<Option<T>>) -> PairingRequest,
+    ) -> Reply<T> {
+        let (reply, answer) = oneshot::channel();
+        let sent = self.send(device, request(reply));
+        Box::pin(async move {
+            sent?;
+            answer.await.ok().flatten().ok_or(ReqError::Rejected)
+        })
+    }
+
+    /// Show `code`, without waiting for anything
+    fn show(&self, device: Address, code: String) -> Reply<()> {
+        let sent = self.send(device, PairingRequest::Display(code));
+        Box::pin(async move { sent })
+    }
+}
+
+fn agent(sender: PromptSender) -> Agent {
+    Agent {
+        request_default: true,
+        request_confirmation: Some({
+            let sender = sender.clone();
+            Box::new(move |request| {
+                sender.ask(request.device, |reply| PairingRequest::Confirm {
+                    passkey: request.passkey,
+                    reply,
+                })
+            })
+        }),
+        request_pin_code: Some({
+            let sender = sender.clone();
+            Box::new(move |request| {
+                sender.ask(request.device, |reply| PairingRequest::PinCode { reply })
+            })
+        }),
+        request_passkey: Some({
+            let sender = sender.clone();
+            Box::new(move |request| {
+                sender.ask(request.device, |reply| PairingRequest::Passkey { reply })
+            })
+        }),
+        display_pin_code: Some({
+            let sender = sender.clone();
+            Box::new(move |request| sender.show(request.device, request.pincode))
+        }),
+        display_passkey: Some(Box::new(move |request| {
+            sender.show(request.device, format!("{:06}", request.passkey))
+        })),
+        ..Default::default()
+    }
+}
+
+/// Pair with `device` and trust it, so it connects to us on its own from now on.
+/// The adapter is pairable and discoverable meanwhile, for headphones which pair from their side.
+pub async fn pair(
+    adapter: Adapter,
+    device: Device,
+    prompts: Prompts,
+    ctx: egui::Context,
+) -> bluer::Result<Device> {
+    let session = Session::new().await?;
+    // unregistered when dropped, at the end
+    let _agent = session
+        .register_agent(agent(PromptSender { prompts, ctx }))
+        .await?;
+    adapter.set_pairable(true).await?;
+    adapter.set_discoverable(true).await?;
+    let result = async {
+        if !device.is_paired().await? {
+            device.pair().await?;
+        }
+        device.set_trusted(true).await
+    }
+    .await;
+    if let Err(e) = adapter.set_discoverable(false).await {
+        log::warn!("couldn't make the adapter undiscoverable again: {e}");
+    }
+    result.map(|()| device)
+}
@@ -0,0 +1,271 @@
+//! What PipeWire does with the headphones: whether they're the default output, and which Bluetooth profile and
+//! codec it negotiated, which is what decides how they sound, whatever the headphones themselves report.
+//!
+//! The state comes from `pw-dump`, and changes go through `wpctl`, which come with PipeWire and WirePlumber.
+
+use serde_json::Value;
+
+/// A Bluetooth profile of the headphones, as PipeWire lists it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub index: u64,
+    /// e.g. `a2dp-sink` or `headset-head-unit`
+    pub name: String,
+    pub description: String,
+}
+
+impl Profile {
+    /// For music, in high quality but without the microphone
+    pub fn is_a2dp(&self) -> bool {
+        self.name.starts_with("a2dp-sink")
+    }
+
+    /// With the microphone, in low quality mono
+    pub fn is_headset(&self) -> bool {
+        self.name.starts_with("headset-head-unit")
+    }
+}
+
+/// The headphones as PipeWire sees them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AudioStatus {
+    /// The id of the device, `None` if PipeWire doesn't know the headphones
+    pub device: Option<u64>,
+    pub profiles: Vec<Profile>,
+    pub profile: Option<Profile>,
+    /// The id of the output of the headphones, `None` with no output, e.g. with the profile off
+    pub sink: Option<u64>,
+    /// e.g. `ldac`, `sbc` or `msbc`
+    pub codec: Option<String>,
+    pub is_default: bool,
+}
+
+impl AudioStatus {
+    /// The state of the headphones with `address` in the output of `pw-dump`
+    pub fn parse(json: &str, address: &str) -> Result<Self, serde_json::Error> {
+        let objects: Vec<Value> = serde_json::from_str(json)?;
+        let ours = |object: &&Value| {
+            object["info"]["props"]["api.bluez5.address"]
+                .as_str()
+                .is_some_and(|a| a.eq_ignore_ascii_case(address))
+        };
+        let mut status = Self::default();
+        if let Some(device) = objects
+            .iter()
+            .filter(|object| object["type"] == "PipeWire:Interface:Device")
+            .find(ours)
+        {
+            let params = &device["info"]["params"];
+            status.device = device["id"].as_u64();
+            status.profiles = params["EnumProfile"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(profile)
+                .collect();
+            status.profile = params["Profile"]
+                .as_array()
+                .and_then(|profiles| profiles.first())
+                .and_then(profile);
+        }
+        let default_sink = objects
+            .iter()
+            .filter(|object| object["type"] == "PipeWire:Interface:Metadata")
+            .filter(|object| object["props"]["metadata.name"] == "default")
+            .flat_map(|object| object["metadata"].as_array().into_iter().flatten())
+            .find(|entry| entry["key"] == "default.audio.sink")
+            .and_then(|entry| entry["value"]["name"].as_str());
+        if let Some(sink) = objects
+            .iter()
+            .filter(|object| object["type"] == "PipeWire:Interface:Node")
+            .filter(|object| object["info"]["props"]["media.class"] == "Audio/Sink")
+            .find(ours)
+        {
+            let props = &sink["info"]["props"];
+            status.sink = sink["id"].as_u64();
+            status.codec = props["api.bluez5.codec"].as_str().map(str::to_string);
+            status.is_default =
+                default_sink.is_some() && props["node.name"].as_str() == default_sink;
+        }
+        Ok(status)
+    }
+
+    /// The best profile for music, or for the microphone
+    pub fn find_profile(&self, headset: bool) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| {
+            if headset {
+                profile.is_headset()
+            } else {
+                profile.is_a2dp()
+            }
+        })
+    }
+
+    /// Why the headphones may sound worse than they could, `protocol_codec` being the one they report
+    pub fn advice(&self, protocol_codec: Option<&str>) -> Vec<String> {
+        let mut advice = Vec::new();
+        if self.device.is_none() {
+            advice.push(
+                "PipeWire doesn't know the headphones; they may be connected for control only."
+                    .to_string(),
+            );
+            return advice;
+        }
+        if self.profile.as_ref().is_some_and(Profile::is_headset) {
+            advice.push(
+                "The headset profile is on, so the microphone works, but playback is mono and \
+                 telephone quality. Switch back to high quality playback after the call."
+                    .to_string(),
+            );
+        }
+        if self.sink.is_some() && !self.is_default {
+            advice.push(
+                "Another output is the default, so most apps don't play on the headphones."
+                    .to_string(),
+            );
+        }
+        if let Some(codec) = self.codec.as_deref()
+            && codec.eq_ignore_ascii_case("sbc")
+            && self.profile.as_ref().is_some_and(Profile::is_a2dp)
+        {
+            advice.push(
+                "PipeWire picked SBC, the most basic codec; LDAC or AAC sound better, if this \
+                 PipeWire supports them and LDAC is enabled on the headphones."
+                    .to_string(),
+            );
+        }
+        if let (Some(ours), Some(theirs)) = (self.codec.as_deref(), protocol_codec)
+            // e.g. aptx_hd and AptxHd
+            && !ours.replace('_', "").eq_ignore_ascii_case(theirs)
+            && self.profile.as_ref().is_some_and(Profile::is_a2dp)
+        {
+            advice.push(format!(
+                "PipeWire says {ours} while the headphones say {theirs}; one of them is out of date."
+            ));
+        }
+        advice
+    }
+}
+
+fn profile(value: &Value) -> Option<Profile> {
+    Some(Profile {
+        index: value["index"].as_u64()?,
+        name: value["name"].as_str()?.to_string(),
+        description: value["description"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+    })
+}
+
+/// Run `program` with `args`, without blocking the UI; its output
+async fn run(program: &'static str, args: Vec<String>) -> Result<String, String> {
+    let output = tokio::task::spawn_blocking(move || {
+        std::process::Command::new(program).args(args).output()
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("couldn't run {program}: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{program} failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// All the objects PipeWire has, as JSON
+pub async fn pw_dump() -> Result<String, String> {
+    run("pw-dump", vec![]).await
+}
+
+pub async fn status(address: String) -> Result<AudioStatus, String> {
+    let json = pw_dump().await?;
+    AudioStatus::parse(&json, &address)
+        .map_err(|e| format!("couldn't read the output of pw-dump: {e}"))
+}
+
+/// Make the output with `sink` the one apps play on
+pub async fn set_default(sink: u64) -> Result<(), String> {
+    run("wpctl", vec!["set-default".to_string(), sink.to_string()])
+        .await
+        .map(|_| ())
+}
+
+pub async fn set_profile(device: u64, profile: u64) -> Result<(), String> {
+    run(
+        "wpctl",
+        vec![
+            "set-profile".to_string(),
+            device.to_string(),
+            profile.to_string(),
+        ],
+    )
+    .await
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DUMP: &str = r#"[
+        {"id": 35, "type": "PipeWire:Interface:Metadata", "props": {"metadata.name": "default"},
+         "metadata": [{"subject": 0, "key": "default.audio.sink", "type": "Spa:String:JSON",
+                       "value": {"name": "alsa_output.pci-0000_00_1f.3.analog-stereo"}}]},
+        {"id": 60, "type": "PipeWire:Interface:Device", "info": {
+            "props": {"device.api": "bluez5", "api.bluez5.address": "AC:80:0A:00:11:22"},
+            "params": {
+                "EnumProfile": [
+                    {"index": 0, "name": "off", "description": "Off"},
+                    {"index": 1, "name": "a2dp-sink", "description": "High Fidelity Playback (A2DP Sink)"},
+                    {"index": 2, "name": "headset-head-unit", "description": "Headset Head Unit (HSP/HFP)"}
+                ],
+                "Profile": [{"index": 1, "name": "a2dp-sink", "description": "High Fidelity Playback (A2DP Sink)"}]
+            }}},
+        {"id": 61, "type": "PipeWire:Interface:Node", "info": {"props": {
+            "media.class": "Audio/Sink", "node.name": "bluez_output.AC_80_0A_00_11_22.1",
+            "api.bluez5.address": "AC:80:0A:00:11:22", "api.bluez5.codec": "sbc",
+            "api.bluez5.profile": "a2dp-sink"}}},
+        {"id": 62, "type": "PipeWire:Interface:Node", "info": {"props": {
+            "media.class": "Audio/Sink", "node.name": "alsa_output.pci-0000_00_1f.3.analog-stereo"}}}
+    ]"#;
+
+    #[test]
+    fn parse() {
+        let status = AudioStatus::parse(DUMP, "ac:80:0a:00:11:22").unwrap();
+        assert_eq!(status.device, Some(60));
+        assert_eq!(status.profiles.len(), 3);
+        assert_eq!(status.profile.as_ref().unwrap().name, "a2dp-sink");
+        assert_eq!(status.sink, Some(61));
+        assert_eq!(status.codec.as_deref(), Some("sbc"));
+        assert!(!status.is_default);
+        assert_eq!(status.find_profile(true).unwrap().index, 2);
+        assert_eq!(status.find_profile(false).unwrap().index, 1);
+
+        assert_eq!(
+            AudioStatus::parse(DUMP, "00:00:00:00:00:00").unwrap(),
+            AudioStatus::default()
+        );
+        assert!(AudioStatus::parse("[", "").is_err());
+    }
+
+    #[test]
+    fn advice() {
+        let status = AudioStatus::parse(DUMP, "AC:80:0A:00:11:22").unwrap();
+        // not the default, SBC, and not what the headphones say
+        assert_eq!(status.advice(Some("ldac")).len(), 3);
+        let status = AudioStatus {
+            is_default: true,
+            codec: Some("msbc".to_string()),
+            profile: status.find_profile(true).cloned(),
+            ..status
+        };
+        let advice = status.advice(Some("ldac"));
+        assert_eq!(advice.len(), 1);
+        assert!(advice[0].starts_with("The headset profile is on"));
+        assert_eq!(AudioStatus::default().advice(None).len(), 1);
+    }
+}
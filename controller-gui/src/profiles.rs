@@ -0,0 +1,173 @@
+//! Named configurations, e.g. "Office" or "Commute", to switch to with one click.
+//!
+//! A profile holds the noise canceling mode with its ambient sound settings, and the equalizer preset with its bands,
+//! as a [SharedConfig]. DSEE isn't in it, since the library doesn't support it yet.
+
+use crate::share::SharedConfig;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    pub config: SharedConfig,
+}
+
+/// The saved profiles, in the order they were first saved
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profiles {
+    profiles: Vec<Profile>,
+    /// The name of the profile applied last
+    selected: Option<String>,
+}
+
+impl Profiles {
+    const KEY: &'static str = "PROFILES";
+    const SELECTED_KEY: &'static str = "SELECTED_PROFILE";
+
+    pub fn load(storage: &dyn eframe::Storage) -> Self {
+        let mut profiles: Self = storage
+            .get_string(Self::KEY)
+            .map(|json| Self::from_json(&json))
+            .unwrap_or_default();
+        if let Some(name) = storage.get_string(Self::SELECTED_KEY) {
+            profiles.select(&name);
+        }
+        profiles
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(Self::KEY, self.to_json());
+        storage.set_string(
+            Self::SELECTED_KEY,
+            self.selected.clone().unwrap_or_default(),
+        );
+    }
+
+    /// A list of `[name, share string]`, so the format of the configurations is the one of [SharedConfig::encode]
+    fn to_json(&self) -> String {
+        let profiles: Vec<(&str, String)> = self
+            .profiles
+            .iter()
+            .map(|profile| (profile.name.as_str(), profile.config.encode()))
+            .collect();
+        serde_json::to_string(&profiles).expect("profiles are always serializable")
+    }
+
+    /// Profiles which can't be read (e.g. saved by a newer version of the app) are skipped
+    fn from_json(json: &str) -> Self {
+        let profiles: Vec<(String, String)> = serde_json::from_str(json).unwrap_or_else(|e| {
+            log::warn!("couldn't read the profiles: {e}");
+            Vec::new()
+        });
+        Self {
+            profiles: profiles
+                .into_iter()
+                .filter_map(|(name, config)| match SharedConfig::decode(&config) {
+                    Ok(config) => Some(Profile { name, config }),
+                    Err(e) => {
+                        log::warn!("skipping profile {name}: {e}");
+                        None
+                    }
+                })
+                .collect(),
+            selected: None,
+        }
+    }
+
+    /// Save `config` as `name`, replacing the profile of that name if there is one
+    pub fn insert(&mut self, name: &str, config: SharedConfig) {
+        match self
+            .profiles
+            .iter_mut()
+            .find(|profile| profile.name == name)
+        {
+            Some(profile) => profile.config = config,
+            None => self.profiles.push(Profile {
+                name: name.to_string(),
+                config,
+            }),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.profiles.retain(|profile| profile.name != name);
+        if self.selected.as_deref() == Some(name) {
+            self.selected = None;
+        }
+    }
+
+    /// Remember `name` as the profile applied last, if there's one of that name
+    pub fn select(&mut self, name: &str) {
+        if self.profiles.iter().any(|profile| profile.name == name) {
+            self.selected = Some(name.to_string());
+        }
+    }
+
+    pub fn selected(&self) -> Option<&str> {
+        self.selected.as_deref()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Profile> {
+        self.profiles.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.profiles.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::share::{SharedAnc, SharedEqualizer};
+    use sony_wf1000xm5::command::{AncMode, EqualizerBands, EqualizerPreset};
+
+    #[test]
+    fn round_trip() {
+        let office = SharedConfig {
+            equalizer: Some(SharedEqualizer {
+                preset: EqualizerPreset::Custom1,
+                bands: EqualizerBands::new(2, 0, -1, 0, 3, 0).unwrap(),
+            }),
+            anc: Some(SharedAnc {
+                mode: AncMode::ActiveNoiseCanceling,
+                ambient_sound_level: 0,
+                voice_passthrough: false,
+            }),
+        };
+        let commute = SharedConfig {
+            equalizer: None,
+            anc: Some(SharedAnc {
+                mode: AncMode::AmbientSound,
+                ambient_sound_level: 15,
+                voice_passthrough: true,
+            }),
+        };
+        let mut profiles = Profiles::default();
+        profiles.insert("Office", commute);
+        profiles.insert("Commute", commute);
+        // replaces, keeping the order
+        profiles.insert("Office", office);
+        assert_eq!(
+            profiles.iter().map(|p| &p.name).collect::<Vec<_>>(),
+            ["Office", "Commute"]
+        );
+        assert_eq!(Profiles::from_json(&profiles.to_json()), profiles);
+
+        profiles.select("Office");
+        profiles.select("Home");
+        assert_eq!(profiles.selected(), Some("Office"));
+        profiles.remove("Office");
+        assert_eq!(profiles.iter().next().unwrap().config, commute);
+        assert_eq!(profiles.selected(), None);
+    }
+
+    #[test]
+    fn unreadable() {
+        assert!(Profiles::from_json("not json").is_empty());
+        let profiles = Profiles::from_json(r#"[["Gaming","xm5:AQA"],["Future","xm5:_wA"]]"#);
+        assert_eq!(
+            profiles.iter().map(|p| &p.name).collect::<Vec<_>>(),
+            ["Gaming"]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! The recent protocol events of a connection, for seeing what the app and the headphones said to each other
+//! without `RUST_LOG` and a terminal: every frame sent and received, decoded where the library knows the
+//! payload, and the warnings about what couldn't be parsed. Kept in memory only, and bounded.
+
+use chrono::{DateTime, Local};
+use eframe::egui::{self, Color32, RichText, Ui};
+use sony_wf1000xm5::{
+    MessageType,
+    payload::parse_payload_lenient,
+    trace::{Direction, TracedFrame},
+};
+use std::collections::VecDeque;
+
+/// The log keeps at most this many events
+const MAX_EVENTS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolEventKind {
+    Sent,
+    Received,
+    /// Something the session couldn't parse, e.g. a bad checksum
+    Warning,
+}
+
+impl ProtocolEventKind {
+    pub const ALL: [Self; 3] = [Self::Sent, Self::Received, Self::Warning];
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::Sent => "sent",
+            Self::Received => "received",
+            Self::Warning => "warnings",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProtocolEvent {
+    pub time: DateTime<Local>,
+    pub kind: ProtocolEventKind,
+    /// For frames, the frame as [TracedFrame] shows it, with the payload in hex
+    pub summary: String,
+    /// The payload of a frame the headphones sent, or why it couldn't be decoded
+    pub decoded: Option<String>,
+}
+
+impl ProtocolEvent {
+    pub fn frame(frame: &TracedFrame) -> Self {
+        let decoded = match (frame.direction, frame.message_type) {
+            (Direction::Received, Ok(message_type)) if message_type != MessageType::Ack => {
+                Some(match parse_payload_lenient(frame.payload, message_type) {
+                    Ok(payload) => format!("{payload:?}"),
+                    Err(e) => format!("not decoded: {e}"),
+                })
+            }
+            _ => None,
+        };
+        Self {
+            time: Local::now(),
+            kind: match frame.direction {
+                Direction::Sent => ProtocolEventKind::Sent,
+                Direction::Received => ProtocolEventKind::Received,
+            },
+            summary: frame.to_string(),
+            decoded,
+        }
+    }
+
+    pub fn warning(warning: String) -> Self {
+        Self {
+            time: Local::now(),
+            kind: ProtocolEventKind::Warning,
+            summary: warning,
+            decoded: None,
+        }
+    }
+}
+
+/// e.g. `12:00:01.250 <- Command1 seq 1 CodecGet 130002`, with the decoded payload on the next line
+impl std::fmt::Display for ProtocolEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.time.format("%H:%M:%S%.3f"), self.summary)?;
+        if let Some(decoded) = &self.decoded {
+            write!(f, "\n    {decoded}")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct ProtocolLog {
+    events: VecDeque<ProtocolEvent>,
+    /// Which kinds are shown, in the order of [ProtocolEventKind::ALL]
+    shown: [bool; 3],
+    paused: bool,
+}
+
+impl Default for ProtocolLog {
+    fn default() -> Self {
+        Self {
+            events: VecDeque::new(),
+            shown: [true; 3],
+            paused: false,
+        }
+    }
+}
+
+impl ProtocolLog {
+    /// Add `event`, dropping the oldest one if the log is full. Nothing is added while it's paused.
+    pub fn record(&mut self, event: ProtocolEvent) {
+        if self.paused {
+            return;
+        }
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    fn is_shown(&self, kind: ProtocolEventKind) -> bool {
+        let index = ProtocolEventKind::ALL
+            .iter()
+            .position(|shown| *shown == kind)
+            .expect("every kind is in ALL");
+        self.shown[index]
+    }
+
+    /// The events which pass the filter, oldest first
+    pub fn shown(&self) -> impl Iterator<Item = &ProtocolEvent> {
+        self.events.iter().filter(|event| self.is_shown(event.kind))
+    }
+
+    /// All the events, whatever is shown, oldest first
+    pub fn events(&self) -> impl Iterator<Item = &ProtocolEvent> {
+        self.events.iter()
+    }
+
+    /// The shown events as text, one per line
+    pub fn export(&self) -> String {
+        self.shown()
+            .map(ProtocolEvent::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn draw(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            for (kind, shown) in ProtocolEventKind::ALL.iter().zip(&mut self.shown) {
+                ui.checkbox(shown, kind.description());
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.paused, "pause");
+            if ui.button("clear").clicked() {
+                self.events.clear();
+            }
+            if ui.button("copy").clicked() {
+                ui.ctx().copy_text(self.export());
+            }
+        });
+        if self.events.is_empty() {
+            ui.label("Nothing yet.");
+            return;
+        }
+        egui::ScrollArea::vertical()
+            .max_height(300.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for event in self.shown() {
+                    let line = RichText::new(format!(
+                        "{} {}",
+                        event.time.format("%H:%M:%S%.3f"),
+                        event.summary
+                    ))
+                    .monospace();
+                    ui.label(match event.kind {
+                        ProtocolEventKind::Warning => line.color(Color32::YELLOW),
+                        _ => line,
+                    });
+                    if let Some(decoded) = &event.decoded {
+                        ui.indent(event.summary.as_str(), |ui| {
+                            ui.label(RichText::new(decoded).weak());
+                        });
+                    }
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::command::Command;
+
+    #[test]
+    fn frames() {
+        let reply = [0x13, 0x00, 0x02];
+        let received = ProtocolEvent::frame(&TracedFrame {
+            direction: Direction::Received,
+            message_type: Ok(MessageType::Command1),
+            seq_num: 1,
+            payload: &reply,
+            command: None,
+            retransmission: false,
+            checksum_ok: true,
+        });
+        assert_eq!(received.kind, ProtocolEventKind::Received);
+        assert!(received.summary.ends_with("130002"), "{}", received.summary);
+        assert!(
+            received
+                .decoded
+                .as_ref()
+                .is_some_and(|decoded| decoded.contains("Codec"))
+        );
+        let ack = ProtocolEvent::frame(&TracedFrame {
+            direction: Direction::Sent,
+            message_type: Ok(MessageType::Ack),
+            seq_num: 0,
+            payload: &[],
+            command: Some(&Command::Ack),
+            retransmission: false,
+            checksum_ok: true,
+        });
+        assert_eq!(ack.kind, ProtocolEventKind::Sent);
+        assert_eq!(ack.decoded, None);
+
+        let mut log = ProtocolLog::default();
+        for _ in 0..MAX_EVENTS {
+            log.record(ack.clone());
+        }
+        log.record(received);
+        log.record(ProtocolEvent::warning("bad checksum".to_string()));
+        assert_eq!(log.events.len(), MAX_EVENTS);
+
+        // only the warning
+        log.shown = [false, false, true];
+        assert_eq!(log.shown().count(), 1);
+        assert!(log.export().ends_with("bad checksum"));
+        log.shown = [false, true, false];
+        assert!(log.export().contains("\n    "));
+
+        log.paused = true;
+        log.record(ack);
+        assert_eq!(log.events.back().unwrap().kind, ProtocolEventKind::Warning);
+    }
+}
@@ -0,0 +1,104 @@
+//! Connecting again on our own when the link drops, e.g. when the buds go in the case or out of range
+
+use std::time::Duration;
+
+/// The delay before the first attempt; it doubles with every attempt which fails
+const FIRST_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Schedules reconnection attempts with exponential backoff.
+///
+/// Times are egui's (seconds, see [eframe::egui::InputState::time]), so it works the same on the web.
+/// A reconnection starts a new [crate::headphone_ui::HeadphoneUi], which asks for the whole state again.
+#[derive(Debug)]
+pub struct Reconnect {
+    /// The user visible toggle
+    pub enabled: bool,
+    attempts: u32,
+    retry_at: Option<f64>,
+}
+
+impl Reconnect {
+    /// Whether reconnecting automatically is on, in the app's storage
+    pub const ENABLED_KEY: &str = "auto_reconnect";
+
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            attempts: 0,
+            retry_at: None,
+        }
+    }
+
+    /// The connection failed (again) at `now`; returns when to try again, unless it's off
+    pub fn failed(&mut self, now: f64) -> Option<f64> {
+        if !self.enabled {
+            self.retry_at = None;
+            return None;
+        }
+        Some(*self.retry_at.get_or_insert_with(|| {
+            let delay = FIRST_DELAY
+                .saturating_mul(2u32.saturating_pow(self.attempts))
+                .min(MAX_DELAY);
+            self.attempts += 1;
+            now + delay.as_secs_f64()
+        }))
+    }
+
+    /// Whether it's time for the scheduled attempt. Returns true once per attempt.
+    pub fn due(&mut self, now: f64) -> bool {
+        let due = self.retry_at.is_some_and(|retry_at| now >= retry_at);
+        if due {
+            self.retry_at = None;
+        }
+        due
+    }
+
+    /// How many attempts were scheduled since the last connection
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// We're connected, or the user took over; the next drop starts over from the first delay
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+        self.retry_at = None;
+    }
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let mut reconnect = Reconnect::default();
+        let mut now = 0.0;
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            let retry_at = reconnect.failed(now).unwrap();
+            // asking again doesn't schedule another attempt
+            assert_eq!(reconnect.failed(now + 0.5), Some(retry_at));
+            assert!(!reconnect.due(retry_at - 0.1));
+            assert!(reconnect.due(retry_at));
+            assert!(!reconnect.due(retry_at));
+            delays.push(retry_at - now);
+            now = retry_at;
+        }
+        assert_eq!(delays, [1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 60.0, 60.0]);
+        assert_eq!(reconnect.attempts(), 8);
+
+        reconnect.reset();
+        assert_eq!(reconnect.failed(now), Some(now + 1.0));
+
+        reconnect.enabled = false;
+        assert_eq!(reconnect.failed(now), None);
+        assert!(!reconnect.due(now + 1.0));
+    }
+}
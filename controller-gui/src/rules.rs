@@ -0,0 +1,347 @@
+//! Automation rules, e.g. "at 22:00 set ambient sound, level 5" or "when the case battery drops below 20%, notify me".
+//!
+//! The rules are kept in `rules.json` in the app's storage directory, which can be edited by hand as well as under
+//! "Rules" once connected. They're evaluated against the payloads of the connection and the clock, so they only
+//! run while the app is connected to the headphones.
+
+use chrono::{NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use sony_wf1000xm5::{
+    command::{AncMode, EqualizerPreset},
+    payload::{BatteryLevel, BatteryPercent, Codec, Payload},
+};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RulesError {
+    #[error("Couldn't access the rules file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("The rules file is invalid: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Which battery a [Trigger::BatteryBelow] is about
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Battery {
+    /// Any of them, including the one of over-ear headphones
+    Any,
+    Left,
+    Right,
+    Case,
+}
+
+impl Battery {
+    pub const ALL: [Self; 4] = [Self::Any, Self::Left, Self::Right, Self::Case];
+}
+
+impl std::fmt::Display for Battery {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Any => "any",
+            Self::Left => "the left",
+            Self::Right => "the right",
+            Self::Case => "the case",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// Every day at this time
+    At(NaiveTime),
+    /// The headphones switched to this codec
+    Codec(Codec),
+    /// A battery dropped below `percent`
+    BatteryBelow { battery: Battery, percent: u8 },
+}
+
+impl std::fmt::Display for Trigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::At(time) => write!(f, "at {}", time.format("%H:%M")),
+            Self::Codec(codec) => write!(f, "when the codec switches to {codec:?}"),
+            Self::BatteryBelow { battery, percent } => {
+                write!(f, "when {battery} battery drops below {percent}%")
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Keeps voice passthrough as it is, and the ambient sound level if there's none
+    SetAnc {
+        mode: AncMode,
+        ambient_level: Option<usize>,
+    },
+    SetEqualizerPreset(EqualizerPreset),
+    /// Show a desktop notification
+    Notify(String),
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::SetAnc {
+                mode,
+                ambient_level,
+            } => {
+                let mode = match mode {
+                    AncMode::ActiveNoiseCanceling => "noise canceling",
+                    AncMode::AmbientSound => "ambient sound",
+                    AncMode::Off => "noise canceling off",
+                };
+                match ambient_level {
+                    Some(level) => write!(f, "set {mode}, level {level}"),
+                    None => write!(f, "set {mode}"),
+                }
+            }
+            Self::SetEqualizerPreset(preset) => write!(f, "switch the equalizer to {preset}"),
+            Self::Notify(message) => write!(f, "notify \"{message}\""),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    pub enabled: bool,
+    pub trigger: Trigger,
+    pub action: Action,
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.trigger, self.action)
+    }
+}
+
+/// The rules, in the order they run in
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rules {
+    pub rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Where the rules are kept
+    pub fn path() -> Option<PathBuf> {
+        eframe::storage_dir(crate::app::App::NAME).map(|dir| dir.join("rules.json"))
+    }
+
+    /// No rules if there's no file yet
+    pub fn load(path: &Path) -> Result<Self, RulesError> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => Ok(Self {
+                rules: serde_json::from_str(&json)?,
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RulesError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(&self.rules)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+/// Decides which rules the payloads of one connection and the clock set off
+#[derive(Debug, Default)]
+pub struct RuleEngine {
+    /// The last level of each battery, by the [Battery] it is
+    batteries: Vec<(Battery, BatteryPercent)>,
+    codec: Option<Codec>,
+    /// When the clock was last checked
+    last_tick: Option<NaiveDateTime>,
+}
+
+impl RuleEngine {
+    /// The enabled `rules` `payload` sets off
+    pub fn on_payload(&mut self, rules: &Rules, payload: &Payload) -> Vec<Rule> {
+        let fired: Vec<Trigger> = match payload {
+            Payload::Codec { codec } => {
+                let old = self.codec.replace(*codec);
+                if old.is_some_and(|old| old != *codec) {
+                    vec![Trigger::Codec(*codec)]
+                } else {
+                    vec![]
+                }
+            }
+            Payload::BatteryLevel(level) => {
+                let levels = match level {
+                    BatteryLevel::Single(level) => vec![(Battery::Any, *level)],
+                    BatteryLevel::Headphones { left, right } => {
+                        vec![(Battery::Left, *left), (Battery::Right, *right)]
+                    }
+                    BatteryLevel::Case(level) => vec![(Battery::Case, *level)],
+                };
+                return levels
+                    .into_iter()
+                    .flat_map(|(battery, level)| self.on_battery(rules, battery, level))
+                    .collect();
+            }
+            _ => vec![],
+        };
+        fired_rules(rules, |trigger| fired.contains(trigger))
+    }
+
+    /// The time rules due since the last tick; the first tick only starts the clock
+    pub fn on_tick(&mut self, rules: &Rules, now: NaiveDateTime) -> Vec<Rule> {
+        let Some(last) = self.last_tick.replace(now) else {
+            return vec![];
+        };
+        fired_rules(rules, |trigger| match trigger {
+            Trigger::At(time) => {
+                // the latest time of day at `time` which isn't after now
+                let mut at = now.date().and_time(*time);
+                if at > now {
+                    at -= chrono::Duration::days(1);
+                }
+                last < at
+            }
+            _ => false,
+        })
+    }
+
+    fn on_battery(&mut self, rules: &Rules, battery: Battery, level: BatteryPercent) -> Vec<Rule> {
+        let old = match self.batteries.iter_mut().find(|(b, _)| *b == battery) {
+            Some((_, old)) => Some(std::mem::replace(old, level)),
+            None => {
+                self.batteries.push((battery, level));
+                None
+            }
+        };
+        fired_rules(rules, |trigger| match trigger {
+            Trigger::BatteryBelow {
+                battery: wanted,
+                percent,
+            } => {
+                let below = |level: BatteryPercent| level.get() < *percent;
+                (*wanted == Battery::Any || *wanted == battery)
+                    && below(level)
+                    && !old.is_some_and(below)
+            }
+            _ => false,
+        })
+    }
+}
+
+fn fired_rules(rules: &Rules, fired: impl Fn(&Trigger) -> bool) -> Vec<Rule> {
+    rules
+        .rules
+        .iter()
+        .filter(|rule| rule.enabled && fired(&rule.trigger))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rules() -> Rules {
+        Rules {
+            rules: vec![
+                Rule {
+                    enabled: true,
+                    trigger: Trigger::At(NaiveTime::from_hms_opt(22, 0, 0).unwrap()),
+                    action: Action::SetAnc {
+                        mode: AncMode::AmbientSound,
+                        ambient_level: Some(5),
+                    },
+                },
+                Rule {
+                    enabled: true,
+                    trigger: Trigger::Codec(Codec::Sbc),
+                    action: Action::Notify("SBC".to_string()),
+                },
+                Rule {
+                    enabled: true,
+                    trigger: Trigger::BatteryBelow {
+                        battery: Battery::Case,
+                        percent: 20,
+                    },
+                    action: Action::Notify("case".to_string()),
+                },
+                Rule {
+                    enabled: false,
+                    trigger: Trigger::Codec(Codec::Sbc),
+                    action: Action::SetEqualizerPreset(EqualizerPreset::Off),
+                },
+            ],
+        }
+    }
+
+    fn at(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn time() {
+        let rules = rules();
+        let mut engine = RuleEngine::default();
+        assert!(engine.on_tick(&rules, at("2024-05-01 21:59:30")).is_empty());
+        assert!(engine.on_tick(&rules, at("2024-05-01 21:59:59")).is_empty());
+        assert_eq!(
+            engine.on_tick(&rules, at("2024-05-01 22:00:00")),
+            [rules.rules[0].clone()]
+        );
+        assert!(engine.on_tick(&rules, at("2024-05-01 22:00:30")).is_empty());
+        // the day after, with the app asleep over 22:00
+        assert!(engine.on_tick(&rules, at("2024-05-02 21:00:00")).is_empty());
+        assert_eq!(engine.on_tick(&rules, at("2024-05-02 23:00:00")).len(), 1);
+
+        // connecting after 22:00 doesn't catch up
+        let mut engine = RuleEngine::default();
+        assert!(engine.on_tick(&rules, at("2024-05-01 22:00:10")).is_empty());
+        assert!(engine.on_tick(&rules, at("2024-05-01 22:00:20")).is_empty());
+    }
+
+    #[test]
+    fn payloads() {
+        let rules = rules();
+        let mut engine = RuleEngine::default();
+        let codec = |codec| Payload::Codec { codec };
+        // the first codec is only us reading it
+        assert!(engine.on_payload(&rules, &codec(Codec::Sbc)).is_empty());
+        assert!(engine.on_payload(&rules, &codec(Codec::Ldac)).is_empty());
+        assert_eq!(
+            engine.on_payload(&rules, &codec(Codec::Sbc)),
+            [rules.rules[1].clone()]
+        );
+
+        let case =
+            |level| Payload::BatteryLevel(BatteryLevel::Case(BatteryPercent::new(level).unwrap()));
+        assert!(engine.on_payload(&rules, &case(40)).is_empty());
+        assert_eq!(engine.on_payload(&rules, &case(19)).len(), 1);
+        // only once per crossing
+        assert!(engine.on_payload(&rules, &case(10)).is_empty());
+        assert!(engine.on_payload(&rules, &case(30)).is_empty());
+        assert_eq!(engine.on_payload(&rules, &case(15)).len(), 1);
+        // the earbuds aren't the case
+        let earbuds = Payload::BatteryLevel(BatteryLevel::Headphones {
+            left: BatteryPercent::new(5).unwrap(),
+            right: BatteryPercent::new(5).unwrap(),
+        });
+        assert!(engine.on_payload(&rules, &earbuds).is_empty());
+    }
+
+    #[test]
+    fn file() {
+        let dir = std::env::temp_dir().join(format!("xm5-rules-test-{}", std::process::id()));
+        let path = dir.join("rules.json");
+        assert_eq!(Rules::load(&path).unwrap(), Rules::default());
+        rules().save(&path).unwrap();
+        assert_eq!(Rules::load(&path).unwrap(), rules());
+        std::fs::write(&path, "[{\"enabled\": true}]").unwrap();
+        assert!(matches!(Rules::load(&path), Err(RulesError::Json(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+//! The preferences of the app itself which don't belong to a section of the headphones' tab, edited on the
+//! settings page. The other ones (notifications, shortcuts, profiles, ...) are saved by their own modules.
+
+use crate::i18n::Language;
+use eframe::egui::ThemePreference;
+use std::{ops::RangeInclusive, time::Duration};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Theme {
+    /// Light or dark like the desktop
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub const ALL: [Self; 3] = [Self::System, Self::Light, Self::Dark];
+
+    /// As saved in the storage
+    fn key(self) -> &'static str {
+        match self {
+            Self::System => "system",
+            Self::Light => "light",
+            Self::Dark => "dark",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|theme| theme.key() == key)
+    }
+}
+
+impl From<Theme> for ThemePreference {
+    fn from(theme: Theme) -> Self {
+        match theme {
+            Theme::System => Self::System,
+            Theme::Light => Self::Light,
+            Theme::Dark => Self::Dark,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AppSettings {
+    /// How often the sound pressure is read while it's measured, in seconds
+    pub sound_pressure_interval_secs: u64,
+    /// Stop polling the headphones while the window is hidden or minimized
+    pub power_saving: bool,
+    /// Show the developer console, see [crate::developer_console]
+    pub developer: bool,
+    /// Show the controls which send commands whose bytes are guesses, see
+    /// [sony_wf1000xm5::command::Command::is_guessed]
+    pub guessed_commands: bool,
+    /// `None` follows the system, see [Language::detect]
+    pub language: Option<Language>,
+    pub theme: Theme,
+    /// The size of everything, in percent of egui's default
+    pub ui_scale_percent: u32,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            sound_pressure_interval_secs: 1,
+            power_saving: false,
+            developer: false,
+            guessed_commands: false,
+            language: None,
+            theme: Theme::System,
+            ui_scale_percent: 100,
+        }
+    }
+}
+
+impl AppSettings {
+    const SOUND_PRESSURE_INTERVAL_KEY: &'static str = "SOUND_PRESSURE_INTERVAL_SECS";
+    const POWER_SAVING_KEY: &'static str = "POWER_SAVING";
+    const DEVELOPER_KEY: &'static str = "DEVELOPER";
+    const GUESSED_COMMANDS_KEY: &'static str = "GUESSED_COMMANDS";
+    const LANGUAGE_KEY: &'static str = "LANGUAGE";
+    const THEME_KEY: &'static str = "THEME";
+    const UI_SCALE_KEY: &'static str = "UI_SCALE_PERCENT";
+    /// Longer ones would leave gaps in the [crate::exposure], which only bridges up to 5 seconds
+    pub const SOUND_PRESSURE_INTERVALS: RangeInclusive<u64> = 1..=5;
+    pub const UI_SCALES: RangeInclusive<u32> = 50..=300;
+
+    pub fn load(storage: &dyn eframe::Storage) -> Self {
+        let default = Self::default();
+        let flag = |key, default| {
+            storage
+                .get_string(key)
+                .map_or(default, |enabled| enabled == "true")
+        };
+        Self {
+            sound_pressure_interval_secs: storage
+                .get_string(Self::SOUND_PRESSURE_INTERVAL_KEY)
+                .and_then(|secs| secs.parse().ok())
+                .filter(|secs| Self::SOUND_PRESSURE_INTERVALS.contains(secs))
+                .unwrap_or(default.sound_pressure_interval_secs),
+            power_saving: flag(Self::POWER_SAVING_KEY, default.power_saving),
+            developer: flag(Self::DEVELOPER_KEY, default.developer),
+            guessed_commands: flag(Self::GUESSED_COMMANDS_KEY, default.guessed_commands),
+            language: storage
+                .get_string(Self::LANGUAGE_KEY)
+                .and_then(|code| Language::from_code(&code)),
+            theme: storage
+                .get_string(Self::THEME_KEY)
+                .and_then(|key| Theme::from_key(&key))
+                .unwrap_or(default.theme),
+            ui_scale_percent: storage
+                .get_string(Self::UI_SCALE_KEY)
+                .and_then(|percent| percent.parse().ok())
+                .filter(|percent| Self::UI_SCALES.contains(percent))
+                .unwrap_or(default.ui_scale_percent),
+        }
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(
+            Self::SOUND_PRESSURE_INTERVAL_KEY,
+            self.sound_pressure_interval_secs.to_string(),
+        );
+        storage.set_string(Self::POWER_SAVING_KEY, self.power_saving.to_string());
+        storage.set_string(Self::DEVELOPER_KEY, self.developer.to_string());
+        storage.set_string(
+            Self::GUESSED_COMMANDS_KEY,
+            self.guessed_commands.to_string(),
+        );
+        storage.set_string(
+            Self::LANGUAGE_KEY,
+            self.language
+                .map(Language::code)
+                .unwrap_or_default()
+                .to_string(),
+        );
+        storage.set_string(Self::THEME_KEY, self.theme.key().to_string());
+        storage.set_string(Self::UI_SCALE_KEY, self.ui_scale_percent.to_string());
+    }
+
+    pub fn sound_pressure_interval(&self) -> Duration {
+        Duration::from_secs(self.sound_pressure_interval_secs)
+    }
+
+    /// For [eframe::egui::Context::set_zoom_factor]
+    pub fn zoom_factor(&self) -> f32 {
+        self.ui_scale_percent as f32 / 100.0
+    }
+
+    /// The language to show, the system's unless one was picked
+    pub fn effective_language(&self) -> Language {
+        self.language.unwrap_or_else(Language::detect)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use eframe::Storage;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MemoryStorage(HashMap<String, String>);
+
+    impl eframe::Storage for MemoryStorage {
+        fn get_string(&self, key: &str) -> Option<String> {
+            self.0.get(key).cloned()
+        }
+
+        fn set_string(&mut self, key: &str, value: String) {
+            self.0.insert(key.to_string(), value);
+        }
+
+        fn flush(&mut self) {}
+    }
+
+    #[test]
+    fn storage() {
+        let mut storage = MemoryStorage::default();
+        assert_eq!(AppSettings::load(&storage), AppSettings::default());
+        let settings = AppSettings {
+            sound_pressure_interval_secs: 3,
+            power_saving: true,
+            developer: true,
+            guessed_commands: true,
+            language: Some(Language::English),
+            theme: Theme::Dark,
+            ui_scale_percent: 150,
+        };
+        settings.save(&mut storage);
+        assert_eq!(AppSettings::load(&storage), settings);
+        // e.g. edited by hand
+        storage.set_string(AppSettings::SOUND_PRESSURE_INTERVAL_KEY, "60".to_string());
+        assert_eq!(AppSettings::load(&storage).sound_pressure_interval_secs, 1);
+        storage.set_string(AppSettings::UI_SCALE_KEY, "1000".to_string());
+        storage.set_string(AppSettings::THEME_KEY, "sepia".to_string());
+        let loaded = AppSettings::load(&storage);
+        assert_eq!(loaded.ui_scale_percent, 100);
+        assert_eq!(loaded.theme, Theme::System);
+    }
+}
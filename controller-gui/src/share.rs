@@ -0,0 +1,239 @@
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use eframe::egui::{self, Color32, Sense, Ui, Vec2};
+use qrcode::{Color, QrCode};
+use sony_wf1000xm5::command::{AncMode, Command, EqualizerBands, EqualizerPreset};
+use thiserror::Error;
+
+/// Every share string starts with this, so we can tell it apart from random clipboard content.
+const SHARE_PREFIX: &str = "xm5:";
+/// Bumped whenever the byte layout below changes.
+const SHARE_VERSION: u8 = 1;
+const HAS_EQUALIZER: u8 = 0b01;
+const HAS_ANC: u8 = 0b10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SharedEqualizer {
+    pub preset: EqualizerPreset,
+    pub bands: EqualizerBands,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SharedAnc {
+    pub mode: AncMode,
+    pub ambient_sound_level: usize,
+    pub voice_passthrough: bool,
+}
+
+/// The part of the headphone configuration which can be shared with other users of the app.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SharedConfig {
+    pub equalizer: Option<SharedEqualizer>,
+    pub anc: Option<SharedAnc>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ShareDecodeError {
+    #[error("This is not a share string (it should start with \"{SHARE_PREFIX}\")")]
+    MissingPrefix,
+    #[error("The share string is not valid base64")]
+    InvalidBase64,
+    #[error("The share string was made by a newer version of the app (version {version})")]
+    UnsupportedVersion { version: u8 },
+    #[error("The share string is truncated")]
+    Truncated,
+    #[error("Unknown equalizer preset: 0x{preset:x}")]
+    UnknownEqualizerPreset { preset: u8 },
+    #[error("Unknown ANC mode: {mode}")]
+    UnknownAncMode { mode: u8 },
+    #[error("Value out of range: {value}")]
+    OutOfRange { value: i16 },
+}
+
+pub(crate) fn anc_mode_to_byte(mode: AncMode) -> u8 {
+    match mode {
+        AncMode::Off => 0,
+        AncMode::ActiveNoiseCanceling => 1,
+        AncMode::AmbientSound => 2,
+    }
+}
+
+pub(crate) fn anc_mode_from_byte(byte: u8) -> Option<AncMode> {
+    Some(match byte {
+        0 => AncMode::Off,
+        1 => AncMode::ActiveNoiseCanceling,
+        2 => AncMode::AmbientSound,
+        _ => return None,
+    })
+}
+
+impl SharedConfig {
+    /// Encode the configuration into a compact string which can be pasted into another instance of the app.
+    ///
+    /// Layout (before base64): version, flags, [preset, 6 bands as on the wire], [anc mode, ambient level, voice passthrough]
+    pub fn encode(&self) -> String {
+        let mut bytes = vec![SHARE_VERSION, 0];
+        if let Some(eq) = self.equalizer {
+            bytes[1] |= HAS_EQUALIZER;
+            bytes.push(eq.preset as u8);
+            bytes.extend(eq.bands.to_wire());
+        }
+        if let Some(anc) = self.anc {
+            bytes[1] |= HAS_ANC;
+            bytes.push(anc_mode_to_byte(anc.mode));
+            bytes.push(anc.ambient_sound_level as u8);
+            bytes.push(anc.voice_passthrough as u8);
+        }
+        format!("{SHARE_PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    pub fn decode(s: &str) -> Result<Self, ShareDecodeError> {
+        let encoded = s
+            .trim()
+            .strip_prefix(SHARE_PREFIX)
+            .ok_or(ShareDecodeError::MissingPrefix)?;
+        let bytes = URL_SAFE_NO_PAD
+            .decode(encoded)
+            .map_err(|_| ShareDecodeError::InvalidBase64)?;
+        let mut bytes = bytes.into_iter();
+        let mut next = || bytes.next().ok_or(ShareDecodeError::Truncated);
+
+        let version = next()?;
+        if version != SHARE_VERSION {
+            return Err(ShareDecodeError::UnsupportedVersion { version });
+        }
+        let flags = next()?;
+        let mut config = SharedConfig::default();
+        if flags & HAS_EQUALIZER != 0 {
+            let preset = next()?;
+            config.equalizer = Some(SharedEqualizer {
+                preset: EqualizerPreset::from_byte(preset)
+                    .ok_or(ShareDecodeError::UnknownEqualizerPreset { preset })?,
+                bands: EqualizerBands::from_wire([
+                    next()?,
+                    next()?,
+                    next()?,
+                    next()?,
+                    next()?,
+                    next()?,
+                ])
+                .map_err(|e| ShareDecodeError::OutOfRange { value: e.level })?,
+            });
+        }
+        if flags & HAS_ANC != 0 {
+            let mode = next()?;
+            let mode = anc_mode_from_byte(mode).ok_or(ShareDecodeError::UnknownAncMode { mode })?;
+            let ambient_sound_level = next()?;
+            if ambient_sound_level as usize > Command::MAX_AMBIENT_SOUND_LEVEL {
+                return Err(ShareDecodeError::OutOfRange {
+                    value: ambient_sound_level as i16,
+                });
+            }
+            config.anc = Some(SharedAnc {
+                mode,
+                ambient_sound_level: ambient_sound_level as usize,
+                voice_passthrough: next()? == 1,
+            });
+        }
+        Ok(config)
+    }
+
+    /// The commands which need to be sent to the headphones to apply this configuration.
+    pub fn to_commands(&self) -> Vec<Command> {
+        let mut commands = Vec::new();
+        if let Some(eq) = self.equalizer {
+            commands.push(Command::ChangeEqualizerPreset { preset: eq.preset });
+            // only the manual/custom presets have adjustable bands
+            if matches!(
+                eq.preset,
+                EqualizerPreset::Manual | EqualizerPreset::Custom1 | EqualizerPreset::Custom2
+            ) {
+                commands.push(Command::ChangeEqualizerSetting {
+                    preset: eq.preset,
+                    bands: eq.bands,
+                });
+            }
+        }
+        if let Some(anc) = self.anc {
+            commands.push(Command::AncSet {
+                dragging_ambient_sound_slider: false,
+                mode: anc.mode,
+                ambient_sound_voice_passthrough: anc.voice_passthrough,
+                ambient_sound_level: anc.ambient_sound_level,
+            });
+        }
+        commands
+    }
+}
+
+/// Draw `data` as a QR code, `module_size` points per module.
+pub fn draw_qr_code(ui: &mut Ui, data: &str, module_size: f32) {
+    let code = match QrCode::new(data.as_bytes()) {
+        Ok(code) => code,
+        Err(e) => {
+            ui.label(format!("Couldn't create a QR code: {e}"));
+            return;
+        }
+    };
+    // the spec asks for a 4 module wide light border around the code
+    let quiet_zone = 4;
+    let width = code.width();
+    let side = (width + 2 * quiet_zone) as f32 * module_size;
+    let (response, painter) = ui.allocate_painter(Vec2::splat(side), Sense::hover());
+    painter.rect_filled(response.rect, 0.0, Color32::WHITE);
+    for (idx, color) in code.to_colors().into_iter().enumerate() {
+        if color == Color::Dark {
+            let x = (idx % width + quiet_zone) as f32 * module_size;
+            let y = (idx / width + quiet_zone) as f32 * module_size;
+            let min = response.rect.min + Vec2::new(x, y);
+            painter.rect_filled(
+                egui::Rect::from_min_size(min, Vec2::splat(module_size)),
+                0.0,
+                Color32::BLACK,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let config = SharedConfig {
+            equalizer: Some(SharedEqualizer {
+                preset: EqualizerPreset::Custom1,
+                bands: EqualizerBands::new(-10, 3, 0, -2, 10, 7).unwrap(),
+            }),
+            anc: Some(SharedAnc {
+                mode: AncMode::AmbientSound,
+                ambient_sound_level: 17,
+                voice_passthrough: true,
+            }),
+        };
+        let encoded = config.encode();
+        assert!(encoded.starts_with(SHARE_PREFIX));
+        assert_eq!(SharedConfig::decode(&encoded), Ok(config));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert_eq!(
+            SharedConfig::decode("hello"),
+            Err(ShareDecodeError::MissingPrefix)
+        );
+        assert_eq!(
+            SharedConfig::decode("xm5:!!"),
+            Err(ShareDecodeError::InvalidBase64)
+        );
+        // version 1, claims an equalizer but has no bytes for it
+        let truncated = format!(
+            "{SHARE_PREFIX}{}",
+            URL_SAFE_NO_PAD.encode([1, HAS_EQUALIZER])
+        );
+        assert_eq!(
+            SharedConfig::decode(&truncated),
+            Err(ShareDecodeError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Only one instance of the app at a time: two would fight over the headphones' single Sony channel, and over
+//! the settings. The first instance listens on a Unix socket; a second one finds it there, asks it to show its
+//! window and exits.
+
+use eframe::egui;
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    sync::mpsc,
+};
+
+const SOCKET_NAME: &str = "sony-wf1000xm5-gui.sock";
+/// What a second instance sends to have the window shown
+const SHOW: &str = "show";
+
+/// Held by the instance which runs
+pub struct SingleInstance {
+    listener: UnixListener,
+}
+
+/// In the runtime directory, or else next to the settings
+fn socket_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| eframe::storage_dir(crate::app::App::NAME))
+        .map(|dir| dir.join(SOCKET_NAME))
+}
+
+impl SingleInstance {
+    /// Become the instance which runs, or `None` if another one does; that one is asked to show its window if
+    /// `show_running`
+    pub fn acquire(show_running: bool) -> io::Result<Option<Self>> {
+        let path = socket_path().ok_or_else(|| io::Error::other("no runtime directory"))?;
+        match UnixStream::connect(&path) {
+            Ok(mut running) => {
+                if show_running {
+                    writeln!(running, "{SHOW}")?;
+                }
+                return Ok(None);
+            }
+            // left behind by an instance which didn't exit cleanly
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => std::fs::remove_file(&path)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(Some(Self {
+            listener: UnixListener::bind(&path)?,
+        }))
+    }
+
+    /// Listen for other instances on a thread; the receiver gets a message whenever one wants the window shown
+    pub fn listen(self, ctx: &egui::Context) -> mpsc::Receiver<()> {
+        let (tx, rx) = mpsc::channel();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            for stream in self.listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("couldn't accept another instance: {e}");
+                        continue;
+                    }
+                };
+                let mut line = String::new();
+                if BufReader::new(stream).read_line(&mut line).is_ok() && line.trim() == SHOW {
+                    if tx.send(()).is_err() {
+                        return;
+                    }
+                    ctx.request_repaint();
+                }
+            }
+        });
+        rx
+    }
+}
@@ -0,0 +1,155 @@
+//! The strip at the bottom of a headphones' tab: whether they're connected, the codec, the signal strength and
+//! how long ago they last sent anything, so a link that went quiet isn't mistaken for values that don't change.
+
+#[cfg(target_os = "linux")]
+use crate::async_resource::{AsyncResource, ResourceStatus};
+use crate::tr;
+use chrono::{DateTime, Local};
+use eframe::egui;
+use sony_wf1000xm5::payload::Codec;
+use std::time::Duration;
+
+/// How often the signal strength is read while the tab is shown
+#[cfg(target_os = "linux")]
+const RSSI_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkState {
+    Connecting,
+    Connected,
+    /// Lost or failed, maybe about to be tried again
+    Disconnected,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalStrength {
+    Good,
+    Fair,
+    /// Audio starts dropping out around here
+    Weak,
+}
+
+impl SignalStrength {
+    pub fn of(rssi: i16) -> Self {
+        match rssi {
+            -65.. => Self::Good,
+            -80.. => Self::Fair,
+            _ => Self::Weak,
+        }
+    }
+}
+
+/// What the status bar shows
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkStatus {
+    pub state: LinkState,
+    pub codec: Option<Codec>,
+    /// In dBm, if the adapter reports it
+    pub rssi: Option<i16>,
+    pub last_payload_at: Option<DateTime<Local>>,
+}
+
+/// Reads the signal strength of connected headphones every [RSSI_INTERVAL]
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+pub struct RssiMonitor {
+    read: AsyncResource<Option<i16>>,
+    rssi: Option<i16>,
+    /// In egui's time
+    read_at: Option<f64>,
+}
+
+#[cfg(target_os = "linux")]
+impl RssiMonitor {
+    /// The last signal strength read, reading it again if it's due
+    pub fn update(&mut self, ctx: &egui::Context, device: &bluer::Device) -> Option<i16> {
+        match self.read.get() {
+            ResourceStatus::Ready(rssi) => {
+                self.rssi = *rssi;
+                self.read.clear();
+            }
+            ResourceStatus::Pending => return self.rssi,
+            ResourceStatus::NotInitialized => (),
+        }
+        let now = ctx.input(|i| i.time);
+        if self
+            .read_at
+            .is_none_or(|read_at| now - read_at >= RSSI_INTERVAL.as_secs_f64())
+        {
+            self.read_at = Some(now);
+            let device = device.clone();
+            let ctx = ctx.clone();
+            self.read.set(async move {
+                let rssi = device.rssi().await.unwrap_or_else(|e| {
+                    log::debug!("couldn't read the signal strength: {e}");
+                    None
+                });
+                ctx.request_repaint();
+                rssi
+            });
+        }
+        ctx.request_repaint_after(RSSI_INTERVAL);
+        self.rssi
+    }
+}
+
+/// e.g. `12 s` or `3 min`
+fn age_text(secs: i64) -> String {
+    match secs {
+        ..60 => tr!("status-age-seconds", seconds = secs.max(0)),
+        60..3600 => tr!("status-age-minutes", minutes = secs / 60),
+        _ => tr!("status-age-hours", hours = secs / 3600),
+    }
+}
+
+/// Draw the status bar at the bottom of the window; before the central panel
+pub fn draw(ctx: &egui::Context, status: &LinkStatus) {
+    egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            let (state, color) = match status.state {
+                LinkState::Connecting => (tr!("status-connecting"), ui.visuals().text_color()),
+                LinkState::Connected => (tr!("status-connected"), egui::Color32::GREEN),
+                LinkState::Disconnected => (tr!("status-disconnected"), egui::Color32::RED),
+            };
+            ui.colored_label(color, format!("● {state}"));
+            if let Some(codec) = status.codec {
+                ui.separator();
+                ui.label(codec.as_str());
+            }
+            if let Some(rssi) = status.rssi {
+                ui.separator();
+                let text = tr!("status-rssi", rssi = rssi);
+                match SignalStrength::of(rssi) {
+                    SignalStrength::Good | SignalStrength::Fair => ui.label(text),
+                    SignalStrength::Weak => ui.colored_label(egui::Color32::YELLOW, text),
+                }
+                .on_hover_text(tr!("status-rssi-hover"));
+            }
+            if status.state == LinkState::Connected
+                && let Some(last_payload_at) = status.last_payload_at
+            {
+                ui.separator();
+                let age = (Local::now() - last_payload_at).num_seconds();
+                ui.label(tr!("status-last-payload", age = age_text(age)));
+                ctx.request_repaint_after(Duration::from_secs(1));
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn link_quality() {
+        assert_eq!(SignalStrength::of(-50), SignalStrength::Good);
+        assert_eq!(SignalStrength::of(-65), SignalStrength::Good);
+        assert_eq!(SignalStrength::of(-79), SignalStrength::Fair);
+        assert_eq!(SignalStrength::of(-90), SignalStrength::Weak);
+        assert_eq!(age_text(-1), "0 s");
+        assert_eq!(age_text(59), "59 s");
+        assert_eq!(age_text(150), "2 min");
+        assert_eq!(age_text(7200), "2 h");
+    }
+}
@@ -0,0 +1,364 @@
+//! The tray icon (a StatusNotifierItem, which KDE, most bars and GNOME with the AppIndicator extension show),
+//! with the battery in its tooltip and the main settings in its menu, so the window can be closed.
+
+use crate::async_resource::{AsyncResource, ResourceStatus};
+use eframe::egui;
+use ksni::{
+    MenuItem, ToolTip, TrayMethods,
+    menu::{CheckmarkItem, RadioGroup, RadioItem, StandardItem, SubMenu},
+};
+use sony_wf1000xm5::{
+    command::{AncMode, EqualizerPreset},
+    payload::BatteryStatus,
+    snapshot::HeadphoneSnapshot,
+};
+use tokio::sync::mpsc;
+
+const ANC_MODES: [(AncMode, &str); 3] = [
+    (AncMode::ActiveNoiseCanceling, "Noise canceling"),
+    (AncMode::AmbientSound, "Ambient sound"),
+    (AncMode::Off, "Off"),
+];
+
+/// What the user picked from the tray menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayAction {
+    ShowWindow,
+    SetAnc(AncMode),
+    SetEqualizerPreset(EqualizerPreset),
+    Disconnect,
+    Settings(TraySettings),
+    Quit,
+}
+
+/// How the window and the tray icon go together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraySettings {
+    /// Closing the window hides it, keeping the connection, while the icon is shown
+    pub close_to_tray: bool,
+    /// Start with only the icon, e.g. for autostart; like `--minimized`
+    pub start_minimized: bool,
+}
+
+impl Default for TraySettings {
+    fn default() -> Self {
+        Self {
+            close_to_tray: true,
+            start_minimized: false,
+        }
+    }
+}
+
+impl TraySettings {
+    const CLOSE_TO_TRAY_KEY: &'static str = "CLOSE_TO_TRAY";
+    const START_MINIMIZED_KEY: &'static str = "START_MINIMIZED";
+
+    pub fn load(storage: &dyn eframe::Storage) -> Self {
+        let default = Self::default();
+        let flag = |key, default| {
+            storage
+                .get_string(key)
+                .map_or(default, |enabled| enabled == "true")
+        };
+        Self {
+            close_to_tray: flag(Self::CLOSE_TO_TRAY_KEY, default.close_to_tray),
+            start_minimized: flag(Self::START_MINIMIZED_KEY, default.start_minimized),
+        }
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        storage.set_string(Self::CLOSE_TO_TRAY_KEY, self.close_to_tray.to_string());
+        storage.set_string(Self::START_MINIMIZED_KEY, self.start_minimized.to_string());
+    }
+}
+
+/// What the tray shows; the default is not connected
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrayState {
+    pub connected: bool,
+    /// e.g. `L 80% R 70% case 50%`, empty until the headphones told us
+    pub battery: String,
+    pub anc_mode: Option<AncMode>,
+    pub preset: Option<EqualizerPreset>,
+}
+
+/// The batteries the headphones told us about, e.g. `L 80% R 70% case 50%`
+pub fn battery_summary(snapshot: &HeadphoneSnapshot) -> String {
+    let BatteryStatus { left, right, case } = snapshot.battery_status;
+    [("L", left), ("R", right), ("case", case)]
+        .into_iter()
+        .filter_map(|(name, level)| level.map(|level| format!("{name} {level}")))
+        .chain(snapshot.battery.map(|level| level.to_string()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl TrayState {
+    pub fn connected(snapshot: &HeadphoneSnapshot) -> Self {
+        Self {
+            connected: true,
+            battery: battery_summary(snapshot),
+            anc_mode: snapshot.anc.map(|anc| anc.mode),
+            preset: snapshot.equalizer.as_ref().map(|eq| eq.preset),
+        }
+    }
+
+    fn tool_tip(&self) -> String {
+        match (self.connected, self.battery.is_empty()) {
+            (false, _) => "Not connected".to_string(),
+            (true, true) => "Connected".to_string(),
+            (true, false) => format!("Battery: {}", self.battery),
+        }
+    }
+}
+
+struct SonyTray {
+    state: TrayState,
+    settings: TraySettings,
+    actions: mpsc::UnboundedSender<TrayAction>,
+    /// To run the app's update, which handles the action, even with the window hidden
+    ctx: egui::Context,
+}
+
+impl SonyTray {
+    fn act(&self, action: TrayAction) {
+        let _ = self.actions.send(action);
+        self.ctx.request_repaint();
+    }
+}
+
+impl ksni::Tray for SonyTray {
+    fn id(&self) -> String {
+        "sony-wf1000xm5-controller".to_string()
+    }
+
+    fn title(&self) -> String {
+        crate::app::App::NAME.to_string()
+    }
+
+    fn icon_name(&self) -> String {
+        "audio-headphones".to_string()
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        ToolTip {
+            title: crate::app::App::NAME.to_string(),
+            description: self.state.tool_tip(),
+            ..Default::default()
+        }
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        self.act(TrayAction::ShowWindow);
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let mut menu = vec![
+            StandardItem {
+                label: "Show window".to_string(),
+                activate: Box::new(|this: &mut Self| this.act(TrayAction::ShowWindow)),
+                ..Default::default()
+            }
+            .into(),
+        ];
+        if self.state.connected {
+            menu.push(MenuItem::Separator);
+            menu.push(
+                RadioGroup {
+                    // out of range selects none
+                    selected: ANC_MODES
+                        .iter()
+                        .position(|(mode, _)| Some(*mode) == self.state.anc_mode)
+                        .unwrap_or(usize::MAX),
+                    select: Box::new(|this: &mut Self, index| {
+                        this.act(TrayAction::SetAnc(ANC_MODES[index].0))
+                    }),
+                    options: ANC_MODES
+                        .iter()
+                        .map(|(_, label)| RadioItem {
+                            label: label.to_string(),
+                            ..Default::default()
+                        })
+                        .collect(),
+                }
+                .into(),
+            );
+            menu.push(
+                SubMenu {
+                    label: "Equalizer".to_string(),
+                    submenu: vec![
+                        RadioGroup {
+                            selected: EqualizerPreset::ALL
+                                .iter()
+                                .position(|preset| Some(*preset) == self.state.preset)
+                                .unwrap_or(usize::MAX),
+                            select: Box::new(|this: &mut Self, index| {
+                                this.act(TrayAction::SetEqualizerPreset(
+                                    EqualizerPreset::ALL[index],
+                                ))
+                            }),
+                            options: EqualizerPreset::ALL
+                                .iter()
+                                .map(|preset| RadioItem {
+                                    label: preset.to_string(),
+                                    ..Default::default()
+                                })
+                                .collect(),
+                        }
+                        .into(),
+                    ],
+                    ..Default::default()
+                }
+                .into(),
+            );
+            menu.push(MenuItem::Separator);
+            menu.push(
+                StandardItem {
+                    label: "Disconnect".to_string(),
+                    activate: Box::new(|this: &mut Self| this.act(TrayAction::Disconnect)),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        menu.push(MenuItem::Separator);
+        menu.push(
+            CheckmarkItem {
+                label: "Close to tray".to_string(),
+                checked: self.settings.close_to_tray,
+                activate: Box::new(|this: &mut Self| {
+                    this.settings.close_to_tray = !this.settings.close_to_tray;
+                    this.act(TrayAction::Settings(this.settings));
+                }),
+                ..Default::default()
+            }
+            .into(),
+        );
+        menu.push(
+            CheckmarkItem {
+                label: "Start minimized".to_string(),
+                checked: self.settings.start_minimized,
+                activate: Box::new(|this: &mut Self| {
+                    this.settings.start_minimized = !this.settings.start_minimized;
+                    this.act(TrayAction::Settings(this.settings));
+                }),
+                ..Default::default()
+            }
+            .into(),
+        );
+        menu.push(
+            StandardItem {
+                label: "Quit".to_string(),
+                icon_name: "application-exit".to_string(),
+                activate: Box::new(|this: &mut Self| this.act(TrayAction::Quit)),
+                ..Default::default()
+            }
+            .into(),
+        );
+        menu
+    }
+}
+
+/// The tray icon of the app, if the desktop shows one
+pub struct Tray {
+    /// `None` if there's no tray to show it in
+    handle: AsyncResource<Option<ksni::Handle<SonyTray>>>,
+    actions: mpsc::UnboundedReceiver<TrayAction>,
+    /// What the tray shows right now
+    shown: TrayState,
+}
+
+impl Tray {
+    pub fn spawn(ctx: &egui::Context, settings: TraySettings) -> Self {
+        let (actions_tx, actions) = mpsc::unbounded_channel();
+        let tray = SonyTray {
+            state: TrayState::default(),
+            settings,
+            actions: actions_tx,
+            ctx: ctx.clone(),
+        };
+        let handle = AsyncResource::default();
+        handle.set(async move {
+            tray.spawn()
+                .await
+                .inspect_err(|e| log::warn!("no tray icon: {e}"))
+                .ok()
+        });
+        Self {
+            handle,
+            actions,
+            shown: TrayState::default(),
+        }
+    }
+
+    /// Whether the icon is shown, so the window can be hidden instead of closed
+    pub fn is_shown(&self) -> bool {
+        match self.handle.get() {
+            ResourceStatus::Ready(handle) => handle.as_ref().is_some_and(|h| !h.is_closed()),
+            _ => false,
+        }
+    }
+
+    /// Whether there's no icon to show, because the desktop has no tray or it went away
+    pub fn is_unavailable(&self) -> bool {
+        match self.handle.get() {
+            ResourceStatus::Ready(handle) => handle.as_ref().is_none_or(|h| h.is_closed()),
+            _ => false,
+        }
+    }
+
+    pub fn poll_action(&mut self) -> Option<TrayAction> {
+        self.actions.try_recv().ok()
+    }
+
+    /// Show `settings` in the menu, e.g. after they were changed on the settings page
+    pub fn set_settings(&self, settings: TraySettings) {
+        if let ResourceStatus::Ready(handle) = self.handle.get()
+            && let Some(handle) = handle.clone()
+        {
+            tokio::task::spawn_local(async move {
+                handle.update(|tray| tray.settings = settings).await;
+            });
+        }
+    }
+
+    /// Show `state`, if it changed
+    pub fn update(&mut self, state: TrayState) {
+        if state == self.shown {
+            return;
+        }
+        if let ResourceStatus::Ready(handle) = self.handle.get()
+            && let Some(handle) = handle.clone()
+        {
+            self.shown = state.clone();
+            tokio::task::spawn_local(async move {
+                handle.update(|tray| tray.state = state).await;
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::payload::BatteryPercent;
+
+    #[test]
+    fn battery() {
+        let mut snapshot = HeadphoneSnapshot::default();
+        assert_eq!(TrayState::default().tool_tip(), "Not connected");
+        assert_eq!(TrayState::connected(&snapshot).tool_tip(), "Connected");
+        snapshot.battery_status = BatteryStatus {
+            left: BatteryPercent::new(80).ok(),
+            right: BatteryPercent::new(70).ok(),
+            case: None,
+        };
+        assert_eq!(
+            TrayState::connected(&snapshot).tool_tip(),
+            "Battery: L 80% R 70%"
+        );
+        snapshot.battery_status = BatteryStatus::default();
+        snapshot.battery = BatteryPercent::new(50).ok();
+        assert_eq!(TrayState::connected(&snapshot).battery, "50%");
+    }
+}
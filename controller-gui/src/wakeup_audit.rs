@@ -0,0 +1,110 @@
+use std::time::{Duration, Instant};
+
+/// Set this environment variable to log how often the event loop wakes up
+pub const AUDIT_ENV_VAR: &str = "CONTROLLER_GUI_AUDIT_WAKEUPS";
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wakeup {
+    /// winit had events for us (input, a repaint request from another thread, ...)
+    Event,
+    /// egui asked for a repaint at a certain time, and that time came
+    Deadline,
+    /// egui asked to be polled right away
+    Poll,
+}
+
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct WakeupCounts {
+    pub events: u32,
+    pub deadlines: u32,
+    pub polls: u32,
+}
+
+impl WakeupCounts {
+    pub fn total(&self) -> u32 {
+        self.events + self.deadlines + self.polls
+    }
+}
+
+/// Counts the wakeups of the winit+tokio pump and logs them once per second, to catch the loop burning CPU while idle.
+pub struct WakeupAudit {
+    enabled: bool,
+    window_start: Instant,
+    counts: WakeupCounts,
+}
+
+impl WakeupAudit {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            window_start: Instant::now(),
+            counts: WakeupCounts::default(),
+        }
+    }
+
+    /// Enabled if [AUDIT_ENV_VAR] is set
+    pub fn from_env() -> Self {
+        Self::new(std::env::var_os(AUDIT_ENV_VAR).is_some())
+    }
+
+    pub fn record(&mut self, wakeup: Wakeup) {
+        if !self.enabled {
+            return;
+        }
+        match wakeup {
+            Wakeup::Event => self.counts.events += 1,
+            Wakeup::Deadline => self.counts.deadlines += 1,
+            Wakeup::Poll => self.counts.polls += 1,
+        }
+        if let Some(counts) = self.take_report(Instant::now()) {
+            log::info!(
+                "wakeups/sec: {} (events: {}, repaint deadlines: {}, polls: {})",
+                counts.total(),
+                counts.events,
+                counts.deadlines,
+                counts.polls
+            );
+        }
+    }
+
+    /// The counts of the last window, if it is over
+    fn take_report(&mut self, now: Instant) -> Option<WakeupCounts> {
+        if now.duration_since(self.window_start) < REPORT_INTERVAL {
+            return None;
+        }
+        self.window_start = now;
+        Some(std::mem::take(&mut self.counts))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report_per_window() {
+        let mut audit = WakeupAudit::new(true);
+        let start = audit.window_start;
+        audit.record(Wakeup::Event);
+        audit.record(Wakeup::Poll);
+        audit.record(Wakeup::Poll);
+        assert_eq!(audit.take_report(start + REPORT_INTERVAL / 2), None);
+        assert_eq!(
+            audit.take_report(start + REPORT_INTERVAL),
+            Some(WakeupCounts {
+                events: 1,
+                deadlines: 0,
+                polls: 2,
+            })
+        );
+        assert_eq!(audit.counts.total(), 0);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut audit = WakeupAudit::new(false);
+        audit.record(Wakeup::Event);
+        assert_eq!(audit.counts.total(), 0);
+    }
+}
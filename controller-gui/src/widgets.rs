@@ -0,0 +1,373 @@
+//! The controls of the headphones as egui widgets, for every view which shows them: the batteries, the noise
+//! canceling modes and the equalizer. They edit their state and tell what changed; sending the commands is up to
+//! whoever shows them.
+
+use crate::tr;
+use eframe::egui::{self, RichText, Slider, Ui};
+use sony_wf1000xm5::{
+    command::{AncMode, Command, EqualizerBands, EqualizerPreset},
+    payload::{BatteryPercent, BatteryStatus},
+    snapshot::{AncSnapshot, HeadphoneSnapshot},
+};
+use std::ops::RangeInclusive;
+
+/// The ambient sound levels of the firmware before the headphones told the range
+pub const DEFAULT_AMBIENT_RANGE: RangeInclusive<usize> = 0..=20;
+
+pub fn preset_label(preset: EqualizerPreset) -> &'static str {
+    match preset {
+        EqualizerPreset::Off => "Off",
+        EqualizerPreset::Bright => "Bright",
+        EqualizerPreset::Excited => "Excited",
+        EqualizerPreset::Mellow => "Mellow",
+        EqualizerPreset::Relaxed => "Relaxed",
+        EqualizerPreset::Vocal => "Vocal",
+        EqualizerPreset::TrebleBoost => "Treble Boost",
+        EqualizerPreset::BassBoost => "Bass Boost",
+        EqualizerPreset::Speech => "Speech",
+        EqualizerPreset::Manual => "Manual",
+        EqualizerPreset::Custom1 => "Custom1",
+        EqualizerPreset::Custom2 => "Custom2",
+    }
+}
+
+pub fn anc_label(mode: AncMode) -> String {
+    match mode {
+        AncMode::Off => tr!("anc-off"),
+        AncMode::AmbientSound => tr!("anc-ambient-sound"),
+        AncMode::ActiveNoiseCanceling => tr!("anc-noise-canceling"),
+    }
+}
+
+/// The batteries, one line for the earbuds and their case and one for over-ear headphones
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BatteryRow {
+    pub status: BatteryStatus,
+    pub battery: Option<BatteryPercent>,
+    /// Strong and this large, as at the top of a tab, rather than as any label
+    pub text_size: Option<f32>,
+}
+
+impl BatteryRow {
+    pub fn new(snapshot: &HeadphoneSnapshot) -> Self {
+        Self {
+            status: snapshot.battery_status,
+            battery: snapshot.battery,
+            text_size: None,
+        }
+    }
+
+    pub fn text_size(self, size: f32) -> Self {
+        Self {
+            text_size: Some(size),
+            ..self
+        }
+    }
+
+    /// What it shows; a battery of the earbuds which wasn't reported yet is a `?`
+    pub fn lines(&self) -> Vec<String> {
+        let BatteryStatus { left, right, case } = self.status;
+        let level = |level: Option<BatteryPercent>| {
+            level.map_or_else(|| "?".to_string(), |level| level.to_string())
+        };
+        let mut lines = Vec::new();
+        if left.is_some() || right.is_some() || case.is_some() {
+            lines.push(tr!(
+                "headphones-batteries",
+                left = level(left),
+                right = level(right),
+                case = level(case)
+            ));
+        }
+        if let Some(battery) = self.battery {
+            lines.push(tr!("headphones-battery", battery = battery));
+        }
+        lines
+    }
+}
+
+impl egui::Widget for BatteryRow {
+    fn ui(self, ui: &mut Ui) -> egui::Response {
+        ui.vertical(|ui| {
+            for line in self.lines() {
+                let text = RichText::new(line);
+                ui.label(match self.text_size {
+                    Some(size) => text.size(size).strong(),
+                    None => text,
+                });
+            }
+        })
+        .response
+    }
+}
+
+/// The noise canceling settings an [AncControl] edits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AncState {
+    pub mode: AncMode,
+    pub ambient_level: usize,
+    pub voice_passthrough: bool,
+}
+
+impl From<AncSnapshot> for AncState {
+    fn from(anc: AncSnapshot) -> Self {
+        Self {
+            mode: anc.mode,
+            ambient_level: anc.ambient_sound_level as usize,
+            voice_passthrough: anc.ambient_sound_voice_passthrough,
+        }
+    }
+}
+
+impl AncState {
+    /// Applies the state as it is
+    pub fn command(&self) -> Command {
+        Command::AncSet {
+            dragging_ambient_sound_slider: false,
+            mode: self.mode,
+            ambient_sound_voice_passthrough: self.voice_passthrough,
+            ambient_sound_level: self.ambient_level,
+        }
+    }
+
+    /// Switches to `mode` as the Sony app does: with voice passthrough, and without the ambient sound settings
+    /// when turning it off
+    pub fn pick_command(&self, mode: AncMode) -> Command {
+        Command::AncSet {
+            dragging_ambient_sound_slider: false,
+            mode,
+            ambient_sound_voice_passthrough: mode != AncMode::Off,
+            ambient_sound_level: if mode == AncMode::Off {
+                0
+            } else {
+                self.ambient_level
+            },
+        }
+    }
+}
+
+/// A radio button per mode, with the ambient sound level and voice passthrough under ambient sound
+pub struct AncControl<'a> {
+    state: &'a mut AncState,
+    ambient_range: RangeInclusive<usize>,
+}
+
+impl<'a> AncControl<'a> {
+    pub fn new(state: &'a mut AncState, ambient_range: RangeInclusive<usize>) -> Self {
+        Self {
+            state,
+            ambient_range,
+        }
+    }
+
+    /// The command to send if the user changed something
+    pub fn show(self, ui: &mut Ui) -> Option<Command> {
+        let mut command = None;
+        let state = self.state;
+        for mode in [
+            AncMode::Off,
+            AncMode::AmbientSound,
+            AncMode::ActiveNoiseCanceling,
+        ] {
+            if ui
+                .radio_value(
+                    &mut state.mode,
+                    mode,
+                    RichText::new(anc_label(mode)).strong(),
+                )
+                .clicked()
+            {
+                command = Some(state.pick_command(mode));
+            }
+            if mode == AncMode::AmbientSound && state.mode == AncMode::AmbientSound {
+                ui.horizontal(|ui| {
+                    let slider = ui.add(
+                        Slider::new(&mut state.ambient_level, self.ambient_range.clone())
+                            .text(tr!("anc-ambient-level")),
+                    );
+                    // once let go of, or on every step made with the arrow keys
+                    let mut changed =
+                        slider.drag_stopped() || (slider.changed() && !slider.dragged());
+                    changed |= ui
+                        .checkbox(&mut state.voice_passthrough, tr!("anc-voice-passthrough"))
+                        .clicked();
+                    if changed {
+                        command = Some(state.command());
+                    }
+                });
+            }
+        }
+        command
+    }
+}
+
+/// A combo box of the equalizer presets; the response is changed when another one was picked
+pub struct PresetPicker<'a> {
+    preset: &'a mut EqualizerPreset,
+}
+
+impl<'a> PresetPicker<'a> {
+    pub fn new(preset: &'a mut EqualizerPreset) -> Self {
+        Self { preset }
+    }
+}
+
+impl egui::Widget for PresetPicker<'_> {
+    fn ui(self, ui: &mut Ui) -> egui::Response {
+        let before = *self.preset;
+        // a combo box rather than a menu, so a screen reader tells what the choice is about
+        let mut response = egui::ComboBox::from_label(tr!("equalizer-preset"))
+            .selected_text(preset_label(before))
+            .show_ui(ui, |ui| {
+                for choice in EqualizerPreset::ALL {
+                    ui.selectable_value(self.preset, choice, preset_label(choice));
+                }
+            })
+            .response;
+        if *self.preset != before {
+            response.mark_changed();
+        }
+        response
+    }
+}
+
+/// A vertical slider per band; the response is changed when any of them was moved
+pub struct EqualizerSliders<'a> {
+    bands: &'a mut EqualizerBands,
+}
+
+impl<'a> EqualizerSliders<'a> {
+    pub fn new(bands: &'a mut EqualizerBands) -> Self {
+        Self { bands }
+    }
+}
+
+impl egui::Widget for EqualizerSliders<'_> {
+    fn ui(self, ui: &mut Ui) -> egui::Response {
+        let bands = self.bands;
+        let inner = ui.horizontal(|ui| {
+            let mut levels = bands.levels();
+            let labels = [
+                tr!("equalizer-clear-bass"),
+                "400 Hz".to_string(),
+                "1000 Hz".to_string(),
+                "2500 Hz".to_string(),
+                "6300 Hz".to_string(),
+                "16000 Hz".to_string(),
+            ];
+            let mut changed = false;
+            // left to right, which is also the order Tab goes through them in
+            for (level, label) in levels.iter_mut().zip(labels) {
+                changed |= ui
+                    .add(
+                        Slider::new(level, EqualizerBands::MIN..=EqualizerBands::MAX)
+                            .vertical()
+                            .text(RichText::new(label).strong()),
+                    )
+                    .changed();
+            }
+            if changed {
+                *bands = EqualizerBands::from_levels(levels)
+                    .expect("the sliders keep the levels in range");
+            }
+            changed
+        });
+        let mut response = inner.response;
+        if inner.inner {
+            response.mark_changed();
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn batteries() {
+        let percent = |level| BatteryPercent::new(level).unwrap();
+        assert!(BatteryRow::default().lines().is_empty());
+        let row = BatteryRow {
+            status: BatteryStatus {
+                left: Some(percent(80)),
+                right: None,
+                case: None,
+            },
+            battery: None,
+            text_size: None,
+        };
+        let lines = row.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("80%"), "{}", lines[0]);
+        assert!(lines[0].contains('?'), "{}", lines[0]);
+
+        let over_ear = BatteryRow {
+            battery: Some(percent(55)),
+            ..BatteryRow::default()
+        };
+        assert_eq!(over_ear.lines().len(), 1);
+    }
+
+    #[test]
+    fn anc_commands() {
+        let state = AncState {
+            mode: AncMode::AmbientSound,
+            ambient_level: 12,
+            voice_passthrough: false,
+        };
+        assert_eq!(
+            state.command(),
+            Command::AncSet {
+                dragging_ambient_sound_slider: false,
+                mode: AncMode::AmbientSound,
+                ambient_sound_voice_passthrough: false,
+                ambient_sound_level: 12,
+            }
+        );
+        assert_eq!(
+            state.pick_command(AncMode::Off),
+            Command::AncSet {
+                dragging_ambient_sound_slider: false,
+                mode: AncMode::Off,
+                ambient_sound_voice_passthrough: false,
+                ambient_sound_level: 0,
+            }
+        );
+        assert_eq!(
+            state.pick_command(AncMode::ActiveNoiseCanceling),
+            Command::AncSet {
+                dragging_ambient_sound_slider: false,
+                mode: AncMode::ActiveNoiseCanceling,
+                ambient_sound_voice_passthrough: true,
+                ambient_sound_level: 12,
+            }
+        );
+    }
+
+    #[test]
+    fn untouched() {
+        let ctx = egui::Context::default();
+        let mut anc = AncState {
+            mode: AncMode::AmbientSound,
+            ambient_level: 5,
+            voice_passthrough: true,
+        };
+        let mut preset = EqualizerPreset::Bright;
+        let mut bands = EqualizerBands::default();
+        let _ = ctx.run(egui::RawInput::default(), |ctx| {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.add(BatteryRow::default().text_size(25.0));
+                assert!(
+                    AncControl::new(&mut anc, DEFAULT_AMBIENT_RANGE)
+                        .show(ui)
+                        .is_none()
+                );
+                assert!(!ui.add(PresetPicker::new(&mut preset)).changed());
+                assert!(!ui.add(EqualizerSliders::new(&mut bands)).changed());
+            });
+        });
+        assert_eq!(anc.ambient_level, 5);
+        assert_eq!(preset, EqualizerPreset::Bright);
+    }
+}
@@ -0,0 +1,290 @@
+use sony_wf1000xm5::{
+    MessageType,
+    command::{AncMode, BatteryType, EqualizerBands, EqualizerPreset, build_ack, build_message},
+    frame_parser::{FrameParser, FramerParserError},
+    payload::{BatteryLevel, BatteryPercent, Codec, Payload},
+};
+use std::collections::VecDeque;
+
+/// The state the emulated headphones report, and which the set commands change
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceState {
+    pub left_battery: BatteryPercent,
+    pub right_battery: BatteryPercent,
+    pub case_battery: BatteryPercent,
+    pub equalizer_preset: EqualizerPreset,
+    pub equalizer_bands: EqualizerBands,
+    pub anc_mode: AncMode,
+    pub ambient_sound_voice_passthrough: bool,
+    pub ambient_sound_level: u8,
+    pub codec: Codec,
+    pub model_name: String,
+    pub firmware_version: String,
+}
+
+impl Default for DeviceState {
+    fn default() -> Self {
+        Self {
+            left_battery: BatteryPercent::new(80).unwrap(),
+            right_battery: BatteryPercent::new(70).unwrap(),
+            case_battery: BatteryPercent::new(50).unwrap(),
+            equalizer_preset: EqualizerPreset::Off,
+            equalizer_bands: EqualizerBands::default(),
+            anc_mode: AncMode::ActiveNoiseCanceling,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: 10,
+            codec: Codec::Ldac,
+            model_name: "WF-1000XM5".to_string(),
+            // made up, like the rest of its state
+            firmware_version: "1.0.0".to_string(),
+        }
+    }
+}
+
+/// The headphone side of a connection, without any IO; the mirror image of [sony_wf1000xm5::session::Session].
+///
+/// Feed it the bytes the client wrote with [Emulator::feed], and send the client whatever [Emulator::poll_transmit] returns.
+/// Every command is acked, and the ones it knows are answered the way the headphones would.
+/// Commands it doesn't know get a [Payload::CommandError], which the library only parses with its
+/// `unstable-guessed-opcodes` feature.
+pub struct Emulator {
+    pub state: DeviceState,
+    frame_parser: FrameParser,
+    seq_number: u8,
+    transmit: VecDeque<Vec<u8>>,
+}
+
+impl Emulator {
+    pub fn new(state: DeviceState) -> Self {
+        Self {
+            state,
+            frame_parser: FrameParser::new(),
+            seq_number: 0,
+            transmit: VecDeque::new(),
+        }
+    }
+
+    /// Feed bytes the client wrote.
+    ///
+    /// A frame parser error means the stream is out of sync, and the connection should be dropped.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), FramerParserError> {
+        self.frame_parser.push_bytes(bytes);
+        while let Some(msg) = self.frame_parser.next_frame() {
+            let msg = msg?;
+            let (Ok(kind), Ok(_)) = (msg.kind, msg.checksum.as_ref()) else {
+                continue;
+            };
+            match kind {
+                // we don't wait for the client's acks
+                MessageType::Ack => (),
+                MessageType::Command1 | MessageType::Command2 => {
+                    let seq_num = msg.seq_num;
+                    let command = msg.payload.to_vec();
+                    let replies = self.handle(kind, &command);
+                    self.transmit.push_back(build_ack(seq_num));
+                    for reply in replies {
+                        self.transmit.push_back(build_message(
+                            reply.message_type(),
+                            self.seq_number,
+                            &reply.to_bytes(),
+                        ));
+                        self.seq_number = 1 - self.seq_number;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The next bytes which should be written to the client
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.transmit.pop_front()
+    }
+
+    /// The payloads to answer a command with
+    fn handle(&mut self, kind: MessageType, command: &[u8]) -> Vec<Payload> {
+        let state = &mut self.state;
+        let Some(&opcode) = command.first() else {
+            return Vec::new();
+        };
+        let reply = match (kind, opcode, &command[1..]) {
+            (MessageType::Command1, 0x00, _) => Payload::InitReply,
+            (MessageType::Command1, 0x22, [battery_type, ..]) => {
+                match BatteryType::from_byte(*battery_type) {
+                    Some(BatteryType::Case) => {
+                        Payload::BatteryLevel(BatteryLevel::Case(state.case_battery))
+                    }
+                    Some(BatteryType::Headphones) => {
+                        Payload::BatteryLevel(BatteryLevel::Headphones {
+                            left: state.left_battery,
+                            right: state.right_battery,
+                        })
+                    }
+                    // the emulated headphones are earbuds
+                    Some(BatteryType::Single) | None => return vec![command_error(opcode)],
+                }
+            }
+            (MessageType::Command1, 0x12, _) => Payload::Codec { codec: state.codec },
+            (MessageType::Command1, 0x56, _) => equalizer(state),
+            // preset change: [0x58, 0, preset, 0]; band change: [0x58, 0, preset, 6, bands...]
+            (MessageType::Command1, 0x58, [_, preset, rest @ ..]) => {
+                let Some(preset) = EqualizerPreset::from_byte(*preset) else {
+                    return vec![command_error(opcode)];
+                };
+                state.equalizer_preset = preset;
+                if let [6, bands @ ..] = rest {
+                    let Some(bands) = bands
+                        .try_into()
+                        .ok()
+                        .and_then(|bands| EqualizerBands::from_wire(bands).ok())
+                    else {
+                        return vec![command_error(opcode)];
+                    };
+                    state.equalizer_bands = bands;
+                }
+                equalizer(state)
+            }
+            (MessageType::Command1, 0x66, _) => anc_status(state),
+            // [0x68, 0x17, dragging, on, ambient, voice passthrough, level]
+            (MessageType::Command1, 0x68, [_, _, on, ambient, passthrough, level]) => {
+                state.anc_mode = match (on, ambient) {
+                    (0, _) => AncMode::Off,
+                    (_, 0) => AncMode::ActiveNoiseCanceling,
+                    _ => AncMode::AmbientSound,
+                };
+                state.ambient_sound_voice_passthrough = *passthrough == 1;
+                state.ambient_sound_level = *level;
+                anc_status(state)
+            }
+            (MessageType::Command1, 0x04, [0x01, ..]) => {
+                Payload::ModelName(state.model_name.clone())
+            }
+            (MessageType::Command1, 0x04, [0x02, ..]) => {
+                Payload::FirmwareVersion(state.firmware_version.clone())
+            }
+            _ => command_error(opcode),
+        };
+        vec![reply]
+    }
+}
+
+fn command_error(opcode: u8) -> Payload {
+    Payload::CommandError { opcode, code: 1 }
+}
+
+fn equalizer(state: &DeviceState) -> Payload {
+    Payload::Equalizer {
+        preset: state.equalizer_preset,
+        bands: state.equalizer_bands,
+    }
+}
+
+fn anc_status(state: &DeviceState) -> Payload {
+    Payload::AncStatus {
+        mode: state.anc_mode,
+        ambient_sound_voice_passthrough: state.ambient_sound_voice_passthrough,
+        ambient_sound_level: state.ambient_sound_level,
+    }
+}
+
+/// An emulator in `state` on the other end of an in-memory stream, for testing the frontends. The task ends with
+/// the emulator once the stream is dropped.
+#[cfg(feature = "tokio")]
+pub fn duplex(state: DeviceState) -> (tokio::io::DuplexStream, tokio::task::JoinHandle<Emulator>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (client, mut headphones) = tokio::io::duplex(1024);
+    let task = tokio::spawn(async move {
+        let mut emulator = Emulator::new(state);
+        let mut buffer = [0; 256];
+        while let Ok(read @ 1..) = headphones.read(&mut buffer).await {
+            emulator.feed(&buffer[..read]).unwrap();
+            while let Some(bytes) = emulator.poll_transmit() {
+                headphones.write_all(&bytes).await.unwrap();
+            }
+        }
+        emulator
+    });
+    (client, task)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sony_wf1000xm5::{
+        command::Command,
+        session::{Session, SessionEvent},
+    };
+
+    /// Run the client session against the emulator until neither has anything left to say
+    fn run(session: &mut Session, emulator: &mut Emulator) -> Vec<Payload> {
+        let mut payloads = Vec::new();
+        loop {
+            let mut idle = true;
+            while let Some(bytes) = session.poll_transmit() {
+                emulator.feed(&bytes).unwrap();
+                idle = false;
+            }
+            while let Some(bytes) = emulator.poll_transmit() {
+                session.feed(&bytes).unwrap();
+                idle = false;
+            }
+            while let Some(event) = session.poll_event() {
+                match event {
+                    SessionEvent::Payload(payload) | SessionEvent::Notification(payload) => {
+                        payloads.push(payload)
+                    }
+                    SessionEvent::Acked(_) => (),
+                    event => panic!("unexpected event: {event:?}"),
+                }
+            }
+            if idle {
+                return payloads;
+            }
+        }
+    }
+
+    #[test]
+    fn conversation() {
+        let mut session = Session::new();
+        let mut emulator = Emulator::new(DeviceState::default());
+        session
+            .send(Command::GetBatteryStatus {
+                battery_type: BatteryType::Headphones,
+            })
+            .unwrap();
+        session.send(Command::GetCodec).unwrap();
+        session
+            .send(Command::AncSet {
+                dragging_ambient_sound_slider: false,
+                mode: AncMode::AmbientSound,
+                ambient_sound_voice_passthrough: true,
+                ambient_sound_level: 15,
+            })
+            .unwrap();
+        // the emulator doesn't know about the sound pressure
+        session.send(Command::GetSoundPressure).unwrap();
+        let mut payloads = run(&mut session, &mut emulator);
+        // an unknown payload without the library's unstable-guessed-opcodes feature
+        let rejected = payloads.pop().unwrap();
+        assert_eq!(rejected.to_bytes(), [0xfe, 0x5a, 1]);
+        assert_eq!(
+            payloads,
+            [
+                Payload::InitReply,
+                // the setting jumps the queue
+                Payload::AncStatus {
+                    mode: AncMode::AmbientSound,
+                    ambient_sound_voice_passthrough: true,
+                    ambient_sound_level: 15,
+                },
+                Payload::BatteryLevel(BatteryLevel::Headphones {
+                    left: BatteryPercent::new(80).unwrap(),
+                    right: BatteryPercent::new(70).unwrap(),
+                }),
+                Payload::Codec { codec: Codec::Ldac },
+            ]
+        );
+        assert_eq!(emulator.state.anc_mode, AncMode::AmbientSound);
+    }
+}
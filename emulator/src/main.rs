@@ -0,0 +1,92 @@
+//! Pretends to be a pair of WF-1000XM5 on a local TCP socket, for development without the hardware.
+//!
+//! usage: emulator [--port PORT] [--battery LEFT,RIGHT,CASE] [--codec sbc|aac|ldac]
+
+use emulator::{DeviceState, Emulator};
+use sony_wf1000xm5::payload::{BatteryPercent, Codec};
+use std::{
+    io::{self, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+};
+
+const DEFAULT_PORT: u16 = 5555;
+
+fn main() -> io::Result<()> {
+    let (port, state) = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!(
+                "usage: emulator [--port PORT] [--battery LEFT,RIGHT,CASE] [--codec sbc|aac|ldac]"
+            );
+            std::process::exit(2);
+        }
+    };
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    eprintln!("emulating a WF-1000XM5 on {}", listener.local_addr()?);
+    // one client at a time, like the real headphones
+    for stream in listener.incoming() {
+        let stream = stream?;
+        eprintln!("client connected: {}", stream.peer_addr()?);
+        if let Err(e) = serve(stream, state.clone()) {
+            eprintln!("connection dropped: {e}");
+        }
+    }
+    Ok(())
+}
+
+fn serve(mut stream: TcpStream, state: DeviceState) -> io::Result<()> {
+    let mut emulator = Emulator::new(state);
+    let mut buffer = [0; 256];
+    loop {
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            eprintln!("client disconnected");
+            return Ok(());
+        }
+        emulator
+            .feed(&buffer[..read])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        while let Some(bytes) = emulator.poll_transmit() {
+            stream.write_all(&bytes)?;
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(u16, DeviceState), String> {
+    let mut port = DEFAULT_PORT;
+    let mut state = DeviceState::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--port" => {
+                let value = value()?;
+                port = value
+                    .parse()
+                    .map_err(|_| format!("invalid port: {value}"))?;
+            }
+            "--battery" => {
+                let value = value()?;
+                let levels: Vec<BatteryPercent> = value
+                    .split(',')
+                    .map(|level| BatteryPercent::new(level.parse().ok()?).ok())
+                    .collect::<Option<_>>()
+                    .ok_or(format!("invalid battery levels: {value}"))?;
+                let [left, right, case] = levels[..] else {
+                    return Err(format!("expected 3 battery levels, got: {value}"));
+                };
+                (state.left_battery, state.right_battery, state.case_battery) = (left, right, case);
+            }
+            "--codec" => {
+                state.codec = match value()?.to_lowercase().as_str() {
+                    "sbc" => Codec::Sbc,
+                    "aac" => Codec::Aac,
+                    "ldac" => Codec::Ldac,
+                    codec => return Err(format!("unknown codec: {codec}")),
+                };
+            }
+            _ => return Err(format!("unknown argument: {arg}")),
+        }
+    }
+    Ok((port, state))
+}
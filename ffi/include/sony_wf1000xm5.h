@@ -0,0 +1,120 @@
+#ifndef SONY_WF1000XM5_H
+#define SONY_WF1000XM5_H
+
+/* Generated by cbindgen from src/lib.rs; don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A pointer was null, a string wasn't UTF-8, or the JSON didn't describe a command
+ */
+#define SONY_ERROR_INVALID_ARGUMENT -1
+
+/**
+ * The command can't be sent, e.g. a level is out of range
+ */
+#define SONY_ERROR_INVALID_COMMAND -2
+
+/**
+ * The stream is out of sync; the connection should be dropped
+ */
+#define SONY_ERROR_MALFORMED_FRAME -3
+
+/**
+ * Splits the bytes read from the headphones into frames
+ */
+typedef struct SonyFrameParser SonyFrameParser;
+
+/**
+ * A frame from [sony_frame_parser_next]
+ */
+typedef struct SonyFrame {
+  /**
+   * 0x1 for an Ack, 0xc or 0xe for commands; anything else is a type nobody knows about
+   */
+  uint8_t message_type;
+  uint8_t seq_num;
+  bool checksum_ok;
+  /**
+   * Owned by the parser, and only valid until the parser is used again
+   */
+  const uint8_t *payload;
+  size_t payload_len;
+} SonyFrame;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Build the frame for `command_json` with `seq_num`, e.g. `"GetCodec"`.
+ *
+ * Returns the length of the frame, which is written to `out` if it fits in `out_len` bytes,
+ * so a call with a NULL `out` tells how big the buffer must be. A negative value is one of the `SONY_ERROR`s.
+ *
+ * # Safety
+ * `command_json` must be a NUL terminated string, and `out` must be NULL or valid for `out_len` bytes.
+ */
+ptrdiff_t sony_build_command(const char *command_json,
+                             uint8_t seq_num,
+                             uint8_t *out,
+                             size_t out_len);
+
+/**
+ * Create a frame parser, to be freed with [sony_frame_parser_free]
+ */
+struct SonyFrameParser *sony_frame_parser_new(void);
+
+/**
+ * # Safety
+ * `parser` must be NULL or come from [sony_frame_parser_new], and not be used afterwards.
+ */
+void sony_frame_parser_free(struct SonyFrameParser *parser);
+
+/**
+ * Queue bytes read from the headphones. They may contain any number of frames, including partial ones.
+ *
+ * # Safety
+ * `parser` must come from [sony_frame_parser_new], and `bytes` must be valid for `len` bytes.
+ */
+void sony_frame_parser_push(struct SonyFrameParser *parser,
+                            const uint8_t *bytes,
+                            size_t len);
+
+/**
+ * Take the next frame out of the queued bytes.
+ *
+ * Returns 1 if `frame` was filled in, 0 once more bytes are needed, or [SONY_ERROR_MALFORMED_FRAME].
+ * After an error, the following frames can still be taken out.
+ *
+ * # Safety
+ * `parser` must come from [sony_frame_parser_new], and `frame` must be valid for writes.
+ */
+ptrdiff_t sony_frame_parser_next(struct SonyFrameParser *parser,
+                                 struct SonyFrame *frame);
+
+/**
+ * Decode the payload of a frame with `message_type` as JSON, e.g. `{"Codec":{"codec":"Ldac"}}`.
+ *
+ * Returns a string to be freed with [sony_string_free], or NULL if the payload couldn't be parsed.
+ *
+ * # Safety
+ * `payload` must be valid for `len` bytes.
+ */
+char *sony_parse_payload(uint8_t message_type, const uint8_t *payload, size_t len);
+
+/**
+ * # Safety
+ * `string` must be NULL or come from this library, and not be used afterwards.
+ */
+void sony_string_free(char *string);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SONY_WF1000XM5_H */
@@ -0,0 +1,238 @@
+//! A C ABI for the protocol crate, so projects which aren't written in Rust can build commands,
+//! split the byte stream into frames and decode payloads. `include/sony_wf1000xm5.h` is the header.
+//!
+//! Commands and payloads cross the boundary as JSON, in the format of the `serde` feature of the
+//! library, e.g. `"GetCodec"` or `{"BatteryLevel":{"Single":70}}`.
+
+use sony_wf1000xm5::{
+    MessageType,
+    command::{Command, build_command},
+    frame_parser::FrameParser,
+    payload::parse_payload,
+};
+use std::{
+    ffi::{CStr, CString, c_char},
+    ptr,
+};
+
+/// A pointer was null, a string wasn't UTF-8, or the JSON didn't describe a command
+pub const SONY_ERROR_INVALID_ARGUMENT: isize = -1;
+/// The command can't be sent, e.g. a level is out of range
+pub const SONY_ERROR_INVALID_COMMAND: isize = -2;
+/// The stream is out of sync; the connection should be dropped
+pub const SONY_ERROR_MALFORMED_FRAME: isize = -3;
+
+/// Splits the bytes read from the headphones into frames
+pub struct SonyFrameParser(FrameParser);
+
+/// A frame from [sony_frame_parser_next]
+#[repr(C)]
+pub struct SonyFrame {
+    /// 0x1 for an Ack, 0xc or 0xe for commands; anything else is a type nobody knows about
+    pub message_type: u8,
+    pub seq_num: u8,
+    pub checksum_ok: bool,
+    /// Owned by the parser, and only valid until the parser is used again
+    pub payload: *const u8,
+    pub payload_len: usize,
+}
+
+/// Build the frame for `command_json` with `seq_num`, e.g. `"GetCodec"`.
+///
+/// Returns the length of the frame, which is written to `out` if it fits in `out_len` bytes,
+/// so a call with a NULL `out` tells how big the buffer must be. A negative value is one of the `SONY_ERROR`s.
+///
+/// # Safety
+/// `command_json` must be a NUL terminated string, and `out` must be NULL or valid for `out_len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sony_build_command(
+    command_json: *const c_char,
+    seq_num: u8,
+    out: *mut u8,
+    out_len: usize,
+) -> isize {
+    if command_json.is_null() {
+        return SONY_ERROR_INVALID_ARGUMENT;
+    }
+    let Ok(json) = unsafe { CStr::from_ptr(command_json) }.to_str() else {
+        return SONY_ERROR_INVALID_ARGUMENT;
+    };
+    let Ok(command) = serde_json::from_str::<Command>(json) else {
+        return SONY_ERROR_INVALID_ARGUMENT;
+    };
+    let Ok(frame) = build_command(&command, seq_num) else {
+        return SONY_ERROR_INVALID_COMMAND;
+    };
+    if !out.is_null() && frame.len() <= out_len {
+        unsafe { ptr::copy_nonoverlapping(frame.as_ptr(), out, frame.len()) };
+    }
+    frame.len() as isize
+}
+
+/// Create a frame parser, to be freed with [sony_frame_parser_free]
+#[unsafe(no_mangle)]
+pub extern "C" fn sony_frame_parser_new() -> *mut SonyFrameParser {
+    Box::into_raw(Box::new(SonyFrameParser(FrameParser::new())))
+}
+
+/// # Safety
+/// `parser` must be NULL or come from [sony_frame_parser_new], and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sony_frame_parser_free(parser: *mut SonyFrameParser) {
+    if !parser.is_null() {
+        drop(unsafe { Box::from_raw(parser) });
+    }
+}
+
+/// Queue bytes read from the headphones. They may contain any number of frames, including partial ones.
+///
+/// # Safety
+/// `parser` must come from [sony_frame_parser_new], and `bytes` must be valid for `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sony_frame_parser_push(
+    parser: *mut SonyFrameParser,
+    bytes: *const u8,
+    len: usize,
+) {
+    if parser.is_null() || bytes.is_null() {
+        return;
+    }
+    let parser = unsafe { &mut *parser };
+    parser
+        .0
+        .push_bytes(unsafe { std::slice::from_raw_parts(bytes, len) });
+}
+
+/// Take the next frame out of the queued bytes.
+///
+/// Returns 1 if `frame` was filled in, 0 once more bytes are needed, or [SONY_ERROR_MALFORMED_FRAME].
+/// After an error, the following frames can still be taken out.
+///
+/// # Safety
+/// `parser` must come from [sony_frame_parser_new], and `frame` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sony_frame_parser_next(
+    parser: *mut SonyFrameParser,
+    frame: *mut SonyFrame,
+) -> isize {
+    if parser.is_null() || frame.is_null() {
+        return SONY_ERROR_INVALID_ARGUMENT;
+    }
+    let parser = unsafe { &mut *parser };
+    match parser.0.next_frame() {
+        None => 0,
+        Some(Err(_)) => SONY_ERROR_MALFORMED_FRAME,
+        Some(Ok(msg)) => {
+            let message_type = match msg.kind {
+                Ok(kind) => kind as u8,
+                Err(kind) => kind,
+            };
+            unsafe {
+                frame.write(SonyFrame {
+                    message_type,
+                    seq_num: msg.seq_num,
+                    checksum_ok: msg.checksum.is_ok(),
+                    payload: msg.payload.as_ptr(),
+                    payload_len: msg.payload.len(),
+                })
+            };
+            1
+        }
+    }
+}
+
+/// Decode the payload of a frame with `message_type` as JSON, e.g. `{"Codec":{"codec":"Ldac"}}`.
+///
+/// Returns a string to be freed with [sony_string_free], or NULL if the payload couldn't be parsed.
+///
+/// # Safety
+/// `payload` must be valid for `len` bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sony_parse_payload(
+    message_type: u8,
+    payload: *const u8,
+    len: usize,
+) -> *mut c_char {
+    let Some(message_type) = MessageType::from_byte(message_type) else {
+        return ptr::null_mut();
+    };
+    if payload.is_null() {
+        return ptr::null_mut();
+    }
+    let payload = unsafe { std::slice::from_raw_parts(payload, len) };
+    let Ok(payload) = parse_payload(payload, message_type) else {
+        return ptr::null_mut();
+    };
+    // the JSON of a payload never contains a NUL
+    CString::new(serde_json::to_string(&payload).unwrap())
+        .unwrap()
+        .into_raw()
+}
+
+/// # Safety
+/// `string` must be NULL or come from this library, and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn sony_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn command_round_trip() {
+        let mut out = [0; 64];
+        let len =
+            unsafe { sony_build_command(c"\"GetCodec\"".as_ptr(), 0, out.as_mut_ptr(), out.len()) };
+        assert_eq!(
+            &out[..len as usize],
+            build_command(&Command::GetCodec, 0).unwrap()
+        );
+        // asking for the length only
+        assert_eq!(
+            unsafe { sony_build_command(c"\"GetCodec\"".as_ptr(), 0, ptr::null_mut(), 0) },
+            len
+        );
+        assert_eq!(
+            unsafe { sony_build_command(c"\"NoSuchCommand\"".as_ptr(), 0, ptr::null_mut(), 0) },
+            SONY_ERROR_INVALID_ARGUMENT
+        );
+
+        // the headphones' reply to GetCodec, followed by garbage
+        let reply = [
+            0x3e, 0xc, 0x1, 0x0, 0x0, 0x0, 0x3, 0x13, 0x0, 0x10, 0x33, 0x3c, 0x0,
+        ];
+        let parser = sony_frame_parser_new();
+        let mut frame = SonyFrame {
+            message_type: 0,
+            seq_num: 0,
+            checksum_ok: false,
+            payload: ptr::null(),
+            payload_len: 0,
+        };
+        unsafe {
+            assert_eq!(sony_frame_parser_next(parser, &mut frame), 0);
+            sony_frame_parser_push(parser, reply.as_ptr(), reply.len());
+            assert_eq!(sony_frame_parser_next(parser, &mut frame), 1);
+            assert_eq!(frame.message_type, MessageType::Command1 as u8);
+            assert_eq!(frame.seq_num, 1);
+            assert!(frame.checksum_ok);
+
+            let json = sony_parse_payload(frame.message_type, frame.payload, frame.payload_len);
+            assert_eq!(
+                CStr::from_ptr(json).to_str().unwrap(),
+                r#"{"Codec":{"codec":"Ldac"}}"#
+            );
+            sony_string_free(json);
+
+            assert_eq!(
+                sony_frame_parser_next(parser, &mut frame),
+                SONY_ERROR_MALFORMED_FRAME
+            );
+            sony_frame_parser_free(parser);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! The btsnoop capture format, as written by Android's "Bluetooth HCI snoop log" and `btmon -w`.
+use crate::Direction;
+use thiserror::Error;
+
+const MAGIC: &[u8] = b"btsnoop\0";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 16;
+const RECORD_HEADER_LEN: usize = 24;
+const FLAG_RECEIVED: u32 = 0b01;
+const FLAG_COMMAND_OR_EVENT: u32 = 0b10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Datalink {
+    /// HCI packets without a packet type; the record flags tell data from commands and events
+    Hci = 1001,
+    /// HCI UART (H4) packets, which start with the packet type
+    H4 = 1002,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BtSnoopError {
+    #[error("Not a btsnoop capture")]
+    NotBtSnoop,
+    #[error("Unsupported btsnoop version: {version}")]
+    UnsupportedVersion { version: u32 },
+    #[error("Unsupported datalink type: {datalink}")]
+    UnsupportedDatalink { datalink: u32 },
+    #[error("The capture is truncated at byte {offset}")]
+    Truncated { offset: usize },
+}
+
+/// One HCI packet
+#[derive(Debug)]
+pub struct Record<'a> {
+    /// Microseconds since midnight, January 1st, 0 AD
+    pub timestamp: u64,
+    pub direction: Direction,
+    /// An HCI command or event, rather than data
+    pub command_or_event: bool,
+    pub data: &'a [u8],
+}
+
+pub struct BtSnoop<'a> {
+    pub datalink: Datalink,
+    records: &'a [u8],
+}
+
+impl<'a> BtSnoop<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, BtSnoopError> {
+        if bytes.len() < HEADER_LEN || !bytes.starts_with(MAGIC) {
+            return Err(BtSnoopError::NotBtSnoop);
+        }
+        let version = be_u32(&bytes[8..]);
+        if version != VERSION {
+            return Err(BtSnoopError::UnsupportedVersion { version });
+        }
+        let datalink = match be_u32(&bytes[12..]) {
+            1001 => Datalink::Hci,
+            1002 => Datalink::H4,
+            datalink => return Err(BtSnoopError::UnsupportedDatalink { datalink }),
+        };
+        Ok(Self {
+            datalink,
+            records: &bytes[HEADER_LEN..],
+        })
+    }
+
+    /// The records of the capture.
+    /// A capture which was still being written usually ends with a truncated record, which is returned as an error.
+    pub fn records(&self) -> Records<'a> {
+        Records {
+            bytes: self.records,
+            offset: HEADER_LEN,
+        }
+    }
+}
+
+pub struct Records<'a> {
+    bytes: &'a [u8],
+    /// of `bytes` in the capture
+    offset: usize,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, BtSnoopError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        // original length, included length, flags, cumulative drops, timestamp
+        let record = self.bytes.get(..RECORD_HEADER_LEN).and_then(|header| {
+            let len = be_u32(&header[4..]) as usize;
+            let data = self.bytes.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len)?;
+            let flags = be_u32(&header[8..]);
+            Some(Record {
+                timestamp: u64::from_be_bytes(header[16..24].try_into().unwrap()),
+                direction: if flags & FLAG_RECEIVED == 0 {
+                    Direction::Sent
+                } else {
+                    Direction::Received
+                },
+                command_or_event: flags & FLAG_COMMAND_OR_EVENT != 0,
+                data,
+            })
+        });
+        let Some(record) = record else {
+            self.bytes = &[];
+            return Some(Err(BtSnoopError::Truncated {
+                offset: self.offset,
+            }));
+        };
+        let len = RECORD_HEADER_LEN + record.data.len();
+        self.bytes = &self.bytes[len..];
+        self.offset += len;
+        Some(Ok(record))
+    }
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header_and_records() {
+        assert_eq!(
+            BtSnoop::parse(b"btsnoo").err(),
+            Some(BtSnoopError::NotBtSnoop)
+        );
+        let mut capture = b"btsnoop\0\0\0\0\x01\0\0\x03\xea".to_vec();
+        // a 2 byte record, received
+        capture.extend_from_slice(&[0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0]);
+        capture.extend_from_slice(&0x00dcddb30f2f8000u64.to_be_bytes());
+        capture.extend_from_slice(&[0x02, 0x0b]);
+        // truncated
+        capture.extend_from_slice(&[0, 0, 0, 9, 0, 0, 0, 9]);
+
+        let snoop = BtSnoop::parse(&capture).unwrap();
+        assert_eq!(snoop.datalink, Datalink::H4);
+        let mut records = snoop.records();
+        let record = records.next().unwrap().unwrap();
+        assert_eq!(record.timestamp, 0x00dcddb30f2f8000);
+        assert_eq!(record.direction, Direction::Received);
+        assert!(!record.command_or_event);
+        assert_eq!(record.data, [0x02, 0x0b]);
+        assert_eq!(
+            records.next().unwrap().err(),
+            Some(BtSnoopError::Truncated { offset: 42 })
+        );
+        assert!(records.next().is_none());
+    }
+}
@@ -0,0 +1,361 @@
+pub mod btsnoop;
+
+use btsnoop::{Datalink, Record};
+use sony_wf1000xm5::{
+    MessageType,
+    frame_parser::{FrameParser, InvalidChecksum},
+    payload::{ParsePayloadError, Payload, parse_payload},
+};
+use std::collections::{HashMap, HashSet};
+
+const H4_ACL: u8 = 0x02;
+const H4_EVENT: u8 = 0x04;
+const EVENT_DISCONNECTION_COMPLETE: u8 = 0x05;
+/// ACL packet boundary flag of a fragment which continues an L2CAP packet
+const CONTINUING_FRAGMENT: u8 = 0b01;
+const SIGNALING_CID: u16 = 0x0001;
+const CONNECTION_REQUEST: u8 = 0x02;
+const CONNECTION_RESPONSE: u8 = 0x03;
+const CONNECTION_SUCCESSFUL: u16 = 0x0000;
+const RFCOMM_PSM: u16 = 0x0003;
+const RFCOMM_UIH: u8 = 0xef;
+const RFCOMM_POLL_FINAL: u8 = 0x10;
+/// The first byte of every message on the Sony channel
+const MESSAGE_HEADER: u8 = 0x3e;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// From the host which made the capture (e.g. the phone) to the headphones
+    Sent,
+    /// From the headphones to the host
+    Received,
+}
+
+impl Direction {
+    fn reverse(self) -> Self {
+        match self {
+            Self::Sent => Self::Received,
+            Self::Received => Self::Sent,
+        }
+    }
+}
+
+/// A message on the Sony channel
+#[derive(Debug)]
+pub struct SonyMessage {
+    /// As in [Record::timestamp]
+    pub timestamp: u64,
+    pub direction: Direction,
+    /// The RFCOMM server channel
+    pub channel: u8,
+    pub kind: Result<MessageType, u8>,
+    pub seq_num: u8,
+    pub payload: Vec<u8>,
+    pub checksum: Result<u8, InvalidChecksum>,
+}
+
+impl SonyMessage {
+    /// The payload the headphones sent.
+    /// `None` for acks and for the commands the host sent, which we can't parse.
+    pub fn decode(&self) -> Option<Result<Payload, ParsePayloadError>> {
+        match (self.direction, self.kind) {
+            (Direction::Received, Ok(kind @ (MessageType::Command1 | MessageType::Command2))) => {
+                Some(parse_payload(&self.payload, kind))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A connection handle, and which way the data goes on it
+type Link = (u16, Direction);
+
+enum Packet<'a> {
+    Acl(&'a [u8]),
+    Event(&'a [u8]),
+}
+
+/// Pulls the messages on the Sony channel out of the records of a btsnoop capture.
+///
+/// RFCOMM channels are found by following the L2CAP signaling, so the capture has to include the connection.
+/// Unless a channel is given, every RFCOMM channel whose first data starts with a message header is taken for the Sony channel.
+pub struct Decoder {
+    datalink: Datalink,
+    channel: Option<u8>,
+    /// L2CAP packets which are still missing fragments
+    fragments: HashMap<Link, Vec<u8>>,
+    /// The L2CAP channel IDs which carry RFCOMM
+    rfcomm_cids: HashSet<(Link, u16)>,
+    /// Per RFCOMM DLCI; `None` for the ones which aren't the Sony channel
+    frame_parsers: HashMap<(Link, u8), Option<FrameParser>>,
+}
+
+impl Decoder {
+    /// Only decode RFCOMM server channel `channel`, if given
+    pub fn new(datalink: Datalink, channel: Option<u8>) -> Self {
+        Self {
+            datalink,
+            channel,
+            fragments: HashMap::new(),
+            rfcomm_cids: HashSet::new(),
+            frame_parsers: HashMap::new(),
+        }
+    }
+
+    /// The messages completed by `record`
+    pub fn feed(&mut self, record: &Record) -> Vec<SonyMessage> {
+        let mut messages = Vec::new();
+        let packet = match self.datalink {
+            Datalink::H4 => match record.data.split_first() {
+                Some((&H4_ACL, acl)) => Packet::Acl(acl),
+                Some((&H4_EVENT, event)) => Packet::Event(event),
+                _ => return messages,
+            },
+            Datalink::Hci if !record.command_or_event => Packet::Acl(record.data),
+            Datalink::Hci if record.direction == Direction::Received => Packet::Event(record.data),
+            Datalink::Hci => return messages,
+        };
+        match packet {
+            Packet::Acl(acl) => self.handle_acl(record, acl, &mut messages),
+            Packet::Event(event) => self.handle_event(event),
+        }
+        messages
+    }
+
+    fn handle_event(&mut self, event: &[u8]) {
+        // a reconnection may reuse the handle and the channel IDs
+        if let [EVENT_DISCONNECTION_COMPLETE, _, 0x00, handle @ ..] = event
+            && let Some(handle) = handle.get(..2)
+        {
+            let handle = le_u16(handle) & 0x0fff;
+            self.fragments.retain(|link, _| link.0 != handle);
+            self.rfcomm_cids.retain(|(link, _)| link.0 != handle);
+            self.frame_parsers.retain(|(link, _), _| link.0 != handle);
+        }
+    }
+
+    fn handle_acl(&mut self, record: &Record, acl: &[u8], messages: &mut Vec<SonyMessage>) {
+        let [handle_0, handle_1, len_0, len_1, data @ ..] = acl else {
+            return;
+        };
+        let link = (le_u16(&[*handle_0, *handle_1]) & 0x0fff, record.direction);
+        let data = &data[..data.len().min(le_u16(&[*len_0, *len_1]) as usize)];
+        let mut packet = self.fragments.remove(&link).unwrap_or_default();
+        if (handle_1 >> 4) & 0b11 != CONTINUING_FRAGMENT {
+            packet.clear();
+        } else if packet.is_empty() {
+            // the start was before the capture
+            return;
+        }
+        packet.extend_from_slice(data);
+        let len = packet.get(..2).map(le_u16).unwrap_or(u16::MAX) as usize;
+        if packet.len() < 4 + len {
+            self.fragments.insert(link, packet);
+            return;
+        }
+        let cid = le_u16(&packet[2..4]);
+        let payload = &packet[4..4 + len];
+        if cid == SIGNALING_CID {
+            self.handle_signaling(link, payload);
+        } else if self.rfcomm_cids.contains(&(link, cid)) {
+            self.handle_rfcomm(record, link, payload, messages);
+        }
+    }
+
+    fn handle_signaling(&mut self, link: Link, mut commands: &[u8]) {
+        while let [code, _identifier, len_0, len_1, rest @ ..] = commands {
+            let len = le_u16(&[*len_0, *len_1]) as usize;
+            let Some(command) = rest.get(..len) else {
+                return;
+            };
+            match *code {
+                // PSM, source CID
+                CONNECTION_REQUEST
+                    if command.len() >= 4 && le_u16(&command[0..2]) == RFCOMM_PSM =>
+                {
+                    // the requester receives on its source CID
+                    self.rfcomm_cids
+                        .insert(((link.0, link.1.reverse()), le_u16(&command[2..4])));
+                }
+                // destination CID, source CID, result, status
+                CONNECTION_RESPONSE
+                    if command.len() >= 6
+                        && le_u16(&command[4..6]) == CONNECTION_SUCCESSFUL
+                        && self.rfcomm_cids.contains(&(link, le_u16(&command[2..4]))) =>
+                {
+                    // and sends to the destination CID, which the responder picked
+                    self.rfcomm_cids
+                        .insert(((link.0, link.1.reverse()), le_u16(&command[0..2])));
+                }
+                _ => (),
+            }
+            commands = &rest[len..];
+        }
+    }
+
+    fn handle_rfcomm(
+        &mut self,
+        record: &Record,
+        link: Link,
+        frame: &[u8],
+        messages: &mut Vec<SonyMessage>,
+    ) {
+        let [address, control, rest @ ..] = frame else {
+            return;
+        };
+        let dlci = address >> 2;
+        // DLCI 0 is the multiplexer's control channel
+        if dlci == 0 || control & !RFCOMM_POLL_FINAL != RFCOMM_UIH {
+            return;
+        }
+        let (len, rest) = match rest {
+            [len, rest @ ..] if len & 1 == 1 => ((len >> 1) as usize, rest),
+            [len_0, len_1, rest @ ..] => ((len_0 >> 1) as usize | (*len_1 as usize) << 7, rest),
+            _ => return,
+        };
+        // with credit based flow control, the poll/final bit means the frame starts with credits
+        let rest = if control & RFCOMM_POLL_FINAL != 0 {
+            rest.get(1..).unwrap_or_default()
+        } else {
+            rest
+        };
+        let channel = dlci >> 1;
+        let Some(data) = rest.get(..len).filter(|data| !data.is_empty()) else {
+            return;
+        };
+        if self.channel.is_some_and(|wanted| wanted != channel) {
+            return;
+        }
+        let parser = self.frame_parsers.entry((link, dlci)).or_insert_with(|| {
+            (self.channel.is_some() || data[0] == MESSAGE_HEADER).then(FrameParser::new)
+        });
+        let Some(parser) = parser else {
+            return;
+        };
+        parser.push_bytes(data);
+        while let Some(msg) = parser.next_frame() {
+            // garbage is skipped up to the next message header
+            let Ok(msg) = msg else {
+                continue;
+            };
+            messages.push(SonyMessage {
+                timestamp: record.timestamp,
+                direction: record.direction,
+                channel,
+                kind: msg.kind,
+                seq_num: msg.seq_num,
+                payload: msg.payload.to_vec(),
+                checksum: msg.checksum,
+            });
+        }
+    }
+}
+
+fn le_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use btsnoop::BtSnoop;
+    use sony_wf1000xm5::{
+        command::{BatteryType, Command, build_command, build_message},
+        payload::{BatteryLevel, BatteryPercent},
+    };
+
+    /// The RFCOMM channel the WF-1000XM5 serve the Sony service on
+    const CHANNEL: u8 = 9;
+
+    fn record(capture: &mut Vec<u8>, direction: Direction, data: &[u8]) {
+        let len = (data.len() as u32).to_be_bytes();
+        capture.extend_from_slice(&len);
+        capture.extend_from_slice(&len);
+        capture.extend_from_slice(&(direction as u32).to_be_bytes());
+        capture.extend_from_slice(&[0; 4]);
+        capture.extend_from_slice(&(capture.len() as u64).to_be_bytes());
+        capture.extend_from_slice(data);
+    }
+
+    fn acl(capture: &mut Vec<u8>, direction: Direction, cid: u16, payload: &[u8]) {
+        let mut l2cap = (payload.len() as u16).to_le_bytes().to_vec();
+        l2cap.extend_from_slice(&cid.to_le_bytes());
+        l2cap.extend_from_slice(payload);
+        // split in 2 fragments, to exercise the reassembly
+        let (start, continuation) = l2cap.split_at(l2cap.len() / 2);
+        for (flags, fragment) in [(0x20, start), (0x10, continuation)] {
+            let mut packet = vec![H4_ACL, 0x0b, flags];
+            packet.extend_from_slice(&(fragment.len() as u16).to_le_bytes());
+            packet.extend_from_slice(fragment);
+            record(capture, direction, &packet);
+        }
+    }
+
+    fn rfcomm(capture: &mut Vec<u8>, direction: Direction, cid: u16, channel: u8, data: &[u8]) {
+        let mut frame = vec![channel << 3 | 0b11, RFCOMM_UIH, (data.len() as u8) << 1 | 1];
+        frame.extend_from_slice(data);
+        // FCS, which we don't check
+        frame.push(0);
+        acl(capture, direction, cid, &frame);
+    }
+
+    #[test]
+    fn sony_channel() {
+        let mut capture = b"btsnoop\0\0\0\0\x01\0\0\x03\xea".to_vec();
+        // the phone connects to RFCOMM on its CID 0x40, the headphones pick 0x41
+        acl(
+            &mut capture,
+            Direction::Sent,
+            SIGNALING_CID,
+            &[CONNECTION_REQUEST, 1, 4, 0, 0x03, 0, 0x40, 0],
+        );
+        acl(
+            &mut capture,
+            Direction::Received,
+            SIGNALING_CID,
+            &[CONNECTION_RESPONSE, 1, 8, 0, 0x41, 0, 0x40, 0, 0, 0, 0, 0],
+        );
+        // another RFCOMM channel, e.g. HFP
+        rfcomm(&mut capture, Direction::Sent, 0x41, 2, b"AT+BRSF=0\r");
+        let command = build_command(
+            &Command::GetBatteryStatus {
+                battery_type: BatteryType::Headphones,
+            },
+            0,
+        )
+        .unwrap();
+        // the message is split across 2 RFCOMM frames
+        let (start, end) = command.split_at(4);
+        rfcomm(&mut capture, Direction::Sent, 0x41, CHANNEL, start);
+        rfcomm(&mut capture, Direction::Sent, 0x41, CHANNEL, end);
+        let ack = build_command(&Command::Ack, 0).unwrap();
+        let battery = Payload::BatteryLevel(BatteryLevel::Headphones {
+            left: BatteryPercent::new(80).unwrap(),
+            right: BatteryPercent::new(70).unwrap(),
+        });
+        let reply = build_message(battery.message_type(), 0, &battery.to_bytes());
+        // both in 1 RFCOMM frame
+        rfcomm(
+            &mut capture,
+            Direction::Received,
+            0x40,
+            CHANNEL,
+            &[ack, reply].concat(),
+        );
+
+        let snoop = BtSnoop::parse(&capture).unwrap();
+        let mut decoder = Decoder::new(snoop.datalink, None);
+        let messages: Vec<SonyMessage> = snoop
+            .records()
+            .flat_map(|record| decoder.feed(&record.unwrap()))
+            .collect();
+        assert_eq!(messages.len(), 3);
+        assert!(messages.iter().all(|message| message.channel == CHANNEL));
+        assert_eq!(messages[0].direction, Direction::Sent);
+        assert_eq!(messages[0].payload, [0x22, 0x01]);
+        assert!(messages[0].decode().is_none());
+        assert_eq!(messages[1].kind, Ok(MessageType::Ack));
+        assert!(messages[2].checksum.is_ok());
+        assert_eq!(messages[2].decode().unwrap().unwrap(), battery);
+    }
+}
@@ -0,0 +1,102 @@
+//! Prints the messages on the Sony channel of a btsnoop HCI capture, e.g. Android's `btsnoop_hci.log`.
+//! Payloads from the headphones are decoded; everything else is printed as hex.
+//!
+//! usage: hci-log [--channel CHANNEL] CAPTURE
+
+use hci_log::{Decoder, Direction, SonyMessage, btsnoop::BtSnoop};
+use std::{fmt::Write, process::ExitCode};
+
+const USAGE: &str = "usage: hci-log [--channel CHANNEL] CAPTURE";
+
+fn main() -> ExitCode {
+    let (path, channel) = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("couldn't read {path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let snoop = match BtSnoop::parse(&bytes) {
+        Ok(snoop) => snoop,
+        Err(e) => {
+            eprintln!("{path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut decoder = Decoder::new(snoop.datalink, channel);
+    let mut start = None;
+    for record in snoop.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("{e}; stopping");
+                break;
+            }
+        };
+        let start = *start.get_or_insert(record.timestamp);
+        for message in decoder.feed(&record) {
+            println!("{}", describe(&message, start));
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// e.g. `   12.345678 <- ch 9 Command1 seq 0 Codec { codec: Ldac }`
+fn describe(message: &SonyMessage, start: u64) -> String {
+    let seconds = message.timestamp.saturating_sub(start) as f64 / 1_000_000.0;
+    let arrow = match message.direction {
+        Direction::Sent => "->",
+        Direction::Received => "<-",
+    };
+    let kind = match message.kind {
+        Ok(kind) => format!("{kind:?}"),
+        Err(kind) => format!("0x{kind:x}"),
+    };
+    let mut line = format!(
+        "{seconds:12.6} {arrow} ch {} {kind} seq {}",
+        message.channel, message.seq_num
+    );
+    let hex: String = message
+        .payload
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    match message.decode() {
+        Some(Ok(payload)) => write!(line, " {payload:?}").unwrap(),
+        Some(Err(e)) => write!(line, " {hex} ({e})").unwrap(),
+        None if !hex.is_empty() => write!(line, " {hex}").unwrap(),
+        None => (),
+    }
+    if let Err(e) = &message.checksum {
+        write!(line, " ({e})").unwrap();
+    }
+    line
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<(String, Option<u8>), String> {
+    let mut path = None;
+    let mut channel = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--channel" => {
+                let value = args.next().ok_or("--channel needs a value")?;
+                channel = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid channel: {value}"))?,
+                );
+            }
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(format!("unexpected argument: {arg}")),
+        }
+    }
+    Ok((path.ok_or("no capture given")?, channel))
+}
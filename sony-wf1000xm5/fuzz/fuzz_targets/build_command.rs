@@ -0,0 +1,10 @@
+//! Commands built from untrusted input (e.g. the UI or IPC) are rejected, never a panic
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sony_wf1000xm5::command::{Command, build_command};
+
+fuzz_target!(|input: (Command, u8)| {
+    let (command, seq_num) = input;
+    let _ = build_command(&command, seq_num);
+});
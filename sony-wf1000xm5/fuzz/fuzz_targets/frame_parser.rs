@@ -0,0 +1,37 @@
+//! Whatever the headphones (or something pretending to be them) send must not panic the parsers
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sony_wf1000xm5::{
+    frame_parser::{FrameParser, FrameParserResult},
+    payload::parse_payload,
+};
+
+fuzz_target!(|data: &[u8]| {
+    // all at once, like Session::feed
+    let mut parser = FrameParser::new();
+    parser.push_bytes(data);
+    while let Some(msg) = parser.next_frame() {
+        if let Ok(msg) = msg
+            && let Ok(kind) = msg.kind
+        {
+            let _ = parse_payload(msg.payload, kind);
+        }
+    }
+
+    // frame by frame
+    let mut parser = FrameParser::new();
+    let mut bytes = data;
+    while !bytes.is_empty() {
+        match parser.parse(bytes) {
+            FrameParserResult::Ready { msg, consumed } => {
+                if let Ok(kind) = msg.kind {
+                    let _ = parse_payload(msg.payload, kind);
+                }
+                bytes = &bytes[consumed..];
+            }
+            FrameParserResult::Error { consumed, .. } => bytes = &bytes[consumed..],
+            FrameParserResult::Incomplete { .. } => break,
+        }
+    }
+});
@@ -0,0 +1,31 @@
+//! A framed payload comes out of the parser as it went in, whatever bytes need escaping
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use sony_wf1000xm5::{
+    MessageType, command::build_message, frame_parser::FrameParser, payload::Payload,
+    payload::parse_payload,
+};
+
+#[derive(Arbitrary, Debug)]
+struct Frame {
+    message_type: MessageType,
+    seq_num: u8,
+    payload: Payload,
+}
+
+fuzz_target!(|frames: Vec<Frame>| {
+    let mut parser = FrameParser::new();
+    for frame in &frames {
+        let payload = frame.payload.to_bytes();
+        parser.push_bytes(&build_message(frame.message_type, frame.seq_num, &payload));
+        let msg = parser.next_frame().unwrap().unwrap();
+        assert_eq!(msg.kind, Ok(frame.message_type));
+        assert_eq!(msg.seq_num, frame.seq_num);
+        assert_eq!(msg.payload, payload);
+        assert!(msg.checksum.is_ok());
+        let _ = parse_payload(msg.payload, frame.message_type);
+    }
+    assert!(parser.next_frame().is_none());
+});
@@ -1,6 +1,14 @@
-use crate::{ESCAPE_BYTE, ESCAPE_MASK, MESSAGE_HEADER, MESSAGE_TRAILER, MessageType, checksum};
+use thiserror::Error;
+
+use crate::{
+    ESCAPE_BYTE, ESCAPE_MASK, MESSAGE_HEADER, MESSAGE_TRAILER, MessageType, checksum,
+    payload::{BatteryLevel, Payload},
+    sequence::SequenceTracker,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum EqualizerPreset {
     Off = 0x0,
     Bright = 0x10,
@@ -17,6 +25,22 @@ pub enum EqualizerPreset {
 }
 
 impl EqualizerPreset {
+    /// In the order of their bytes
+    pub const ALL: [Self; 12] = [
+        Self::Off,
+        Self::Bright,
+        Self::Excited,
+        Self::Mellow,
+        Self::Relaxed,
+        Self::Vocal,
+        Self::TrebleBoost,
+        Self::BassBoost,
+        Self::Speech,
+        Self::Manual,
+        Self::Custom1,
+        Self::Custom2,
+    ];
+
     pub fn from_byte(byte: u8) -> Option<Self> {
         Some(match byte {
             0x0 => Self::Off,
@@ -42,15 +66,253 @@ impl std::fmt::Display for EqualizerPreset {
     }
 }
 
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error(
+    "Equalizer level {level} is out of range ({}..={})",
+    EqualizerBands::MIN,
+    EqualizerBands::MAX
+)]
+pub struct EqualizerLevelOutOfRange {
+    pub level: i16,
+}
+
+/// Why a command can't be sent, see [Command::try_to_bytes]
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CommandError {
+    #[error(
+        "Ambient sound level {level} is out of range (0..={})",
+        Command::MAX_AMBIENT_SOUND_LEVEL
+    )]
+    AmbientSoundLevelOutOfRange { level: usize },
+    #[error("The bands of the {preset} preset can't be changed")]
+    PresetNotCustomizable { preset: EqualizerPreset },
+    #[error(
+        "Sidetone level {level} is out of range (0..={})",
+        Command::MAX_SIDETONE_LEVEL
+    )]
+    SidetoneLevelOutOfRange { level: u8 },
+    #[error("A raw command needs at least the payload type")]
+    EmptyRawPayload,
+    #[error("Acks are sent by the session, not as raw commands")]
+    RawAck,
+    #[error(
+        "The opcode of {0:?} is a guess, so it's only sent with the unstable-guessed-opcodes feature"
+    )]
+    GuessedOpcode(Command),
+}
+
+/// The levels of Clear Bass and the five equalizer bands.
+/// Every level is in [EqualizerBands::MIN]..=[EqualizerBands::MAX]; the constructors make sure of it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "UncheckedEqualizerBands")
+)]
+pub struct EqualizerBands {
+    clear_bass: i8,
+    band_400: i8,
+    band_1000: i8,
+    band_2500: i8,
+    band_6300: i8,
+    band_16000: i8,
+}
+
+/// What deserializes into [EqualizerBands], before the levels are checked
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct UncheckedEqualizerBands {
+    clear_bass: i8,
+    band_400: i8,
+    band_1000: i8,
+    band_2500: i8,
+    band_6300: i8,
+    band_16000: i8,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<UncheckedEqualizerBands> for EqualizerBands {
+    type Error = EqualizerLevelOutOfRange;
+
+    fn try_from(bands: UncheckedEqualizerBands) -> Result<Self, Self::Error> {
+        Self::new(
+            bands.clear_bass,
+            bands.band_400,
+            bands.band_1000,
+            bands.band_2500,
+            bands.band_6300,
+            bands.band_16000,
+        )
+    }
+}
+
+impl EqualizerBands {
+    pub const MIN: i8 = -10;
+    pub const MAX: i8 = 10;
+    /// On the wire levels are offset so they are never negative
+    const WIRE_OFFSET: i16 = 10;
+
+    pub fn new(
+        clear_bass: i8,
+        band_400: i8,
+        band_1000: i8,
+        band_2500: i8,
+        band_6300: i8,
+        band_16000: i8,
+    ) -> Result<Self, EqualizerLevelOutOfRange> {
+        Self::from_levels([
+            clear_bass, band_400, band_1000, band_2500, band_6300, band_16000,
+        ])
+    }
+
+    /// `[clear bass, 400 Hz, 1000 Hz, 2500 Hz, 6300 Hz, 16000 Hz]`
+    pub fn from_levels(levels: [i8; 6]) -> Result<Self, EqualizerLevelOutOfRange> {
+        if let Some(level) = levels
+            .iter()
+            .find(|level| !(Self::MIN..=Self::MAX).contains(level))
+        {
+            return Err(EqualizerLevelOutOfRange {
+                level: *level as i16,
+            });
+        }
+        let [
+            clear_bass,
+            band_400,
+            band_1000,
+            band_2500,
+            band_6300,
+            band_16000,
+        ] = levels;
+        Ok(Self {
+            clear_bass,
+            band_400,
+            band_1000,
+            band_2500,
+            band_6300,
+            band_16000,
+        })
+    }
+
+    /// `[clear bass, 400 Hz, 1000 Hz, 2500 Hz, 6300 Hz, 16000 Hz]`
+    pub fn levels(&self) -> [i8; 6] {
+        [
+            self.clear_bass,
+            self.band_400,
+            self.band_1000,
+            self.band_2500,
+            self.band_6300,
+            self.band_16000,
+        ]
+    }
+
+    /// Parse the levels as sent by the headphones
+    pub fn from_wire(bytes: [u8; 6]) -> Result<Self, EqualizerLevelOutOfRange> {
+        let mut levels = [0; 6];
+        for (level, byte) in levels.iter_mut().zip(bytes) {
+            let value = byte as i16 - Self::WIRE_OFFSET;
+            *level = i8::try_from(value).map_err(|_| EqualizerLevelOutOfRange { level: value })?;
+        }
+        Self::from_levels(levels)
+    }
+
+    /// The levels as sent to the headphones
+    pub fn to_wire(&self) -> [u8; 6] {
+        self.levels()
+            .map(|level| (level as i16 + Self::WIRE_OFFSET) as u8)
+    }
+}
+
+/// Only levels in range, like the constructors
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for EqualizerBands {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut levels = [0; 6];
+        for level in &mut levels {
+            *level = u.int_in_range(Self::MIN..=Self::MAX)?;
+        }
+        Ok(Self::from_levels(levels).expect("the levels are in range"))
+    }
+}
+
+/// What a double/triple tap on the earbuds launches
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum QuickAccessApp {
+    None = 0x0,
+    Spotify = 0x1,
+    Endel = 0x2,
+}
+
+impl QuickAccessApp {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Some(match byte {
+            0x0 => Self::None,
+            0x1 => Self::Spotify,
+            0x2 => Self::Endel,
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for QuickAccessApp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// How long Speak-to-Chat keeps the music paused after you stop talking
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum SpeakToChatTimeout {
+    Short = 0x0,
+    Standard = 0x1,
+    Long = 0x2,
+    /// The music stays paused until you resume it
+    Off = 0x3,
+}
+
+impl SpeakToChatTimeout {
+    pub const ALL: [Self; 4] = [Self::Short, Self::Standard, Self::Long, Self::Off];
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Some(match byte {
+            0x0 => Self::Short,
+            0x1 => Self::Standard,
+            0x2 => Self::Long,
+            0x3 => Self::Off,
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for SpeakToChatTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Short => "short",
+            Self::Standard => "standard",
+            Self::Long => "long",
+            Self::Off => "no auto off",
+        })
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum AncMode {
     Off,
     ActiveNoiseCanceling,
     AmbientSound,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum BatteryType {
+    /// The one battery of over-ear headphones
+    Single = 0x0,
     Headphones = 0x1,
     Case = 0xa,
 }
@@ -58,6 +320,7 @@ pub enum BatteryType {
 impl BatteryType {
     pub fn from_byte(byte: u8) -> Option<Self> {
         Some(match byte {
+            0x0 => Self::Single,
             0x1 | 0x9 => Self::Headphones,
             0xa => Self::Case,
             _ => return None,
@@ -65,7 +328,10 @@ impl BatteryType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
 pub enum Command {
     Init,
     Ack,
@@ -76,6 +342,8 @@ pub enum Command {
         ambient_sound_level: usize,
     },
     GetAncStatus,
+    /// Ask for the range of the ambient sound level. Replied to with [crate::payload::Payload::AmbientSoundRange]
+    GetAmbientSoundRange,
 
     ChangeEqualizerPreset {
         preset: EqualizerPreset,
@@ -83,12 +351,7 @@ pub enum Command {
     ChangeEqualizerSetting {
         // the preset to change the equalizer settings for
         preset: EqualizerPreset,
-        bass_level: i8,
-        band_400: i8,
-        band_1000: i8,
-        band_2500: i8,
-        band_6300: i8,
-        band_16000: i8,
+        bands: EqualizerBands,
     },
     GetBatteryStatus {
         battery_type: BatteryType,
@@ -99,6 +362,44 @@ pub enum Command {
         on: bool,
     },
     GetSoundPressure,
+    /// Make the earbuds play the "find my earbuds" tone
+    PlayLocatorTone {
+        left: bool,
+        right: bool,
+    },
+    StopLocatorTone,
+    GetCallVoiceFocus,
+    /// Focus the ambient sound on voices during calls
+    SetCallVoiceFocus {
+        on: bool,
+    },
+    GetSidetoneLevel,
+    /// How much of your own voice you hear during calls
+    SetSidetoneLevel {
+        level: u8,
+    },
+    /// Ask the headphones which functions they support. Replied to with [crate::payload::Payload::SupportedFunctions]
+    GetSupportedFunctions,
+    GetModelName,
+    GetFirmwareVersion,
+    GetQuickAccess,
+    SetQuickAccess {
+        double_tap: QuickAccessApp,
+        triple_tap: QuickAccessApp,
+    },
+    /// Ask for the 360 Reality Audio setup status. Replied to with [crate::payload::Payload::SpatialAudioStatus]
+    GetSpatialAudioStatus,
+    GetSpeakToChatTimeout,
+    /// How long the music stays paused after you stop talking. Independent of Speak-to-Chat being on.
+    SetSpeakToChatTimeout {
+        timeout: SpeakToChatTimeout,
+    },
+    /// A payload typed in by hand, e.g. to find out what an unknown opcode does. It counts as a write since nothing
+    /// is known about it, and nothing waits for a reply; whatever the headphones answer arrives as a payload.
+    Raw {
+        message_type: MessageType,
+        payload: Vec<u8>,
+    },
 }
 
 impl Command {
@@ -109,8 +410,242 @@ impl Command {
     const GET_BATTERY_STATUS: u8 = 0x22;
     const EQUALIZER_GET: u8 = 0x56;
     const CODEC_GET: u8 = 0x12;
-    fn to_bytes(&self) -> Vec<u8> {
+    // not confirmed with hci logs yet
+    const LOCATOR_TONE_SET: u8 = 0x5c;
+    // not confirmed with hci logs yet
+    const CALL_VOICE_FOCUS_GET: u8 = 0x86;
+    const CALL_VOICE_FOCUS_SET: u8 = 0x88;
+    const SIDETONE_GET: u8 = 0x96;
+    const SIDETONE_SET: u8 = 0x98;
+    // not confirmed with hci logs yet
+    const SUPPORTED_FUNCTIONS_GET: u8 = 0x06;
+    // from gadgetbridge's SonyProtocolImplV1
+    const DEVICE_INFO_GET: u8 = 0x04;
+    // not confirmed with hci logs yet
+    const QUICK_ACCESS_GET: u8 = 0xa6;
+    const QUICK_ACCESS_SET: u8 = 0xa8;
+    // not confirmed with hci logs yet
+    const AMBIENT_SOUND_RANGE_GET: u8 = 0x6a;
+    // not confirmed with hci logs yet
+    const SPATIAL_AUDIO_STATUS_GET: u8 = 0xb6;
+    // not confirmed with hci logs yet
+    const SPEAK_TO_CHAT_CONFIG_GET: u8 = 0xfa;
+    const SPEAK_TO_CHAT_CONFIG_SET: u8 = 0xfc;
+    /// The highest ambient sound level any known firmware accepts; see [Command::GetAmbientSoundRange] for the actual range
+    pub const MAX_AMBIENT_SOUND_LEVEL: usize = 22;
+    /// The highest level [Command::SetSidetoneLevel] accepts
+    pub const MAX_SIDETONE_LEVEL: u8 = 10;
+
+    /// The message type the command is sent with. Most commands are [MessageType::Command1];
+    /// the sound pressure ones are [MessageType::Command2], and their replies come back the same way.
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Self::AncSet { .. }
+            | Self::GetCodec
+            | Self::GetAncStatus
+            | Self::GetAmbientSoundRange
+            | Self::ChangeEqualizerSetting { .. }
+            | Self::ChangeEqualizerPreset { .. }
+            | Self::Init
+            | Self::GetBatteryStatus { .. }
+            | Self::GetEqualizerSettings
+            | Self::PlayLocatorTone { .. }
+            | Self::StopLocatorTone
+            | Self::GetCallVoiceFocus
+            | Self::SetCallVoiceFocus { .. }
+            | Self::GetSidetoneLevel
+            | Self::SetSidetoneLevel { .. }
+            | Self::GetSupportedFunctions
+            | Self::GetModelName
+            | Self::GetFirmwareVersion
+            | Self::GetQuickAccess
+            | Self::SetQuickAccess { .. }
+            | Self::GetSpatialAudioStatus
+            | Self::GetSpeakToChatTimeout
+            | Self::SetSpeakToChatTimeout { .. } => MessageType::Command1,
+
+            // from hci logs: SoundPressureMeasure: 3e0e0000000004580301006e3c
+            // from hci log: GetSoundPressure: 3e0e01000000025a036e3c
+            Self::SoundPressureMeasure { .. } | Self::GetSoundPressure => MessageType::Command2,
+
+            Self::Ack => MessageType::Ack,
+
+            Self::Raw { message_type, .. } => *message_type,
+        }
+    }
+
+    /// Whether the command changes anything on the headphones (settings, sounds, connections),
+    /// as opposed to only reading from them. Turning sound pressure measurement on and off counts as reading.
+    pub fn is_write(&self) -> bool {
+        match self {
+            Self::AncSet { .. }
+            | Self::ChangeEqualizerPreset { .. }
+            | Self::ChangeEqualizerSetting { .. }
+            | Self::PlayLocatorTone { .. }
+            | Self::StopLocatorTone
+            | Self::SetCallVoiceFocus { .. }
+            | Self::SetSidetoneLevel { .. }
+            | Self::SetQuickAccess { .. }
+            | Self::SetSpeakToChatTimeout { .. }
+            | Self::Raw { .. } => true,
+            Self::Init
+            | Self::Ack
+            | Self::GetAncStatus
+            | Self::GetAmbientSoundRange
+            | Self::GetBatteryStatus { .. }
+            | Self::GetEqualizerSettings
+            | Self::GetCodec
+            | Self::SoundPressureMeasure { .. }
+            | Self::GetSoundPressure
+            | Self::GetCallVoiceFocus
+            | Self::GetSidetoneLevel
+            | Self::GetSupportedFunctions
+            | Self::GetModelName
+            | Self::GetFirmwareVersion
+            | Self::GetQuickAccess
+            | Self::GetSpatialAudioStatus
+            | Self::GetSpeakToChatTimeout => false,
+        }
+    }
+
+    /// Whether `later` changes the same setting as this command, so sending only `later` has the same effect.
+    /// Used to coalesce the commands a dragged slider produces. Playing and stopping the locator tone aren't
+    /// settings but presses of a button, so each of them is sent.
+    pub fn is_superseded_by(&self, later: &Command) -> bool {
+        match (self, later) {
+            (
+                Self::ChangeEqualizerSetting { preset, .. },
+                Self::ChangeEqualizerSetting {
+                    preset: later_preset,
+                    ..
+                },
+            ) => preset == later_preset,
+            (Self::AncSet { .. }, Self::AncSet { .. })
+            | (Self::ChangeEqualizerPreset { .. }, Self::ChangeEqualizerPreset { .. })
+            | (Self::SetCallVoiceFocus { .. }, Self::SetCallVoiceFocus { .. })
+            | (Self::SetSidetoneLevel { .. }, Self::SetSidetoneLevel { .. })
+            | (Self::SetQuickAccess { .. }, Self::SetQuickAccess { .. })
+            | (Self::SetSpeakToChatTimeout { .. }, Self::SetSpeakToChatTimeout { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether `payload` is the reply to this command. Settings which were changed are confirmed with a
+    /// notification rather than a reply, so only the commands which read something (and the system commands) get one.
+    /// A [Payload::CommandError] for the command's opcode counts as its reply, too.
+    ///
+    /// The headphones answer commands in order, so the oldest command `payload` answers is the one it replies to.
+    pub fn is_answered_by(&self, payload: &Payload) -> bool {
+        match (self, payload) {
+            (_, Payload::CommandError { opcode, .. }) => {
+                self.expects_reply()
+                    && self
+                        .try_to_bytes()
+                        .is_ok_and(|bytes| bytes.first() == Some(opcode))
+            }
+            (Self::GetBatteryStatus { battery_type }, Payload::BatteryLevel(level)) => matches!(
+                (battery_type, level),
+                (BatteryType::Single, BatteryLevel::Single(_))
+                    | (BatteryType::Headphones, BatteryLevel::Headphones { .. })
+                    | (BatteryType::Case, BatteryLevel::Case(_))
+            ),
+            (Self::Init, Payload::InitReply)
+            | (Self::GetAncStatus, Payload::AncStatus { .. })
+            | (Self::GetAmbientSoundRange, Payload::AmbientSoundRange { .. })
+            | (Self::GetEqualizerSettings, Payload::Equalizer { .. })
+            | (Self::GetCodec, Payload::Codec { .. })
+            | (Self::SoundPressureMeasure { .. }, Payload::SoundPressureMeasureReply { .. })
+            | (Self::GetSoundPressure, Payload::SoundPressure { .. })
+            | (Self::GetCallVoiceFocus, Payload::CallVoiceFocus { .. })
+            | (Self::GetSidetoneLevel, Payload::SidetoneLevel { .. })
+            | (Self::GetSupportedFunctions, Payload::SupportedFunctions(_))
+            | (Self::GetModelName, Payload::ModelName(_))
+            | (Self::GetFirmwareVersion, Payload::FirmwareVersion(_))
+            | (Self::GetQuickAccess, Payload::QuickAccess { .. })
+            | (Self::GetSpatialAudioStatus, Payload::SpatialAudioStatus { .. })
+            | (Self::GetSpeakToChatTimeout, Payload::SpeakToChatTimeout { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the headphones reply to the command with a payload, see [Command::is_answered_by]
+    pub fn expects_reply(&self) -> bool {
         match self {
+            Self::Init
+            | Self::GetAncStatus
+            | Self::GetAmbientSoundRange
+            | Self::GetBatteryStatus { .. }
+            | Self::GetEqualizerSettings
+            | Self::GetCodec
+            | Self::SoundPressureMeasure { .. }
+            | Self::GetSoundPressure
+            | Self::GetCallVoiceFocus
+            | Self::GetSidetoneLevel
+            | Self::GetSupportedFunctions
+            | Self::GetModelName
+            | Self::GetFirmwareVersion
+            | Self::GetQuickAccess
+            | Self::GetSpatialAudioStatus
+            | Self::GetSpeakToChatTimeout => true,
+            Self::Ack
+            | Self::AncSet { .. }
+            | Self::ChangeEqualizerPreset { .. }
+            | Self::ChangeEqualizerSetting { .. }
+            | Self::PlayLocatorTone { .. }
+            | Self::StopLocatorTone
+            | Self::SetCallVoiceFocus { .. }
+            | Self::SetSidetoneLevel { .. }
+            | Self::SetQuickAccess { .. }
+            | Self::SetSpeakToChatTimeout { .. }
+            | Self::Raw { .. } => false,
+        }
+    }
+
+    /// Whether the opcode of the command is a guess from the neighbouring ones, not confirmed with HCI logs of the
+    /// Sony app (or taken from a client which was). The headphones may take a guessed command for something else
+    /// entirely, so they're only sent with the unstable `unstable-guessed-opcodes` feature, and frontends which
+    /// enable it should still only send them when asked to. A [Command::Raw] payload is what the user typed in,
+    /// not a guess.
+    pub fn is_guessed(&self) -> bool {
+        match self {
+            Self::GetAmbientSoundRange
+            | Self::PlayLocatorTone { .. }
+            | Self::StopLocatorTone
+            | Self::GetCallVoiceFocus
+            | Self::SetCallVoiceFocus { .. }
+            | Self::GetSidetoneLevel
+            | Self::SetSidetoneLevel { .. }
+            | Self::GetSupportedFunctions
+            | Self::GetQuickAccess
+            | Self::SetQuickAccess { .. }
+            | Self::GetSpatialAudioStatus
+            | Self::GetSpeakToChatTimeout
+            | Self::SetSpeakToChatTimeout { .. } => true,
+            Self::Init
+            | Self::Ack
+            | Self::AncSet { .. }
+            | Self::GetAncStatus
+            | Self::ChangeEqualizerPreset { .. }
+            | Self::ChangeEqualizerSetting { .. }
+            | Self::GetBatteryStatus { .. }
+            | Self::GetEqualizerSettings
+            | Self::GetCodec
+            | Self::SoundPressureMeasure { .. }
+            | Self::GetSoundPressure
+            | Self::GetModelName
+            | Self::GetFirmwareVersion
+            | Self::Raw { .. } => false,
+        }
+    }
+
+    /// The payload of the command, or why it can't be sent (e.g. a level out of range, or a guessed opcode without
+    /// the `unstable-guessed-opcodes` feature)
+    pub fn try_to_bytes(&self) -> Result<Vec<u8>, CommandError> {
+        #[cfg(not(feature = "unstable-guessed-opcodes"))]
+        if self.is_guessed() {
+            return Err(CommandError::GuessedOpcode(self.clone()));
+        }
+        Ok(match self {
             Self::Init => {
                 vec![0, 0]
             }
@@ -125,8 +660,10 @@ impl Command {
                 ambient_sound_voice_passthrough,
                 ambient_sound_level,
             } => {
-                if *ambient_sound_level > 20 {
-                    panic!("ambient sound level should be less than or equal to 20");
+                if *ambient_sound_level > Self::MAX_AMBIENT_SOUND_LEVEL {
+                    return Err(CommandError::AmbientSoundLevelOutOfRange {
+                        level: *ambient_sound_level,
+                    });
                 }
                 let mut out = vec![
                     Self::ANC_SET,
@@ -157,43 +694,27 @@ impl Command {
             Self::GetAncStatus => {
                 vec![Self::ANC_STATUS_GET, Self::SUPPORTS_AMBIENT_SOUND_CONTROL_2]
             }
+            Self::GetAmbientSoundRange => {
+                vec![
+                    Self::AMBIENT_SOUND_RANGE_GET,
+                    Self::SUPPORTS_AMBIENT_SOUND_CONTROL_2,
+                ]
+            }
 
             Self::ChangeEqualizerPreset { preset } => {
                 vec![Self::EQUALIZER_SET, 0, *preset as u8, 0]
             }
-            Self::ChangeEqualizerSetting {
-                preset,
-                bass_level,
-                band_400,
-                band_1000,
-                band_2500,
-                band_6300,
-                band_16000,
-            } => {
-                assert!(bass_level.abs() <= 10);
-                assert!(band_400.abs() <= 10);
-                assert!(band_1000.abs() <= 10);
-                assert!(band_2500.abs() <= 10);
-                assert!(band_6300.abs() <= 10);
-                assert!(band_16000.abs() <= 10);
-                assert!(matches!(
+            Self::ChangeEqualizerSetting { preset, bands } => {
+                if !matches!(
                     preset,
                     EqualizerPreset::Manual | EqualizerPreset::Custom1 | EqualizerPreset::Custom2
-                ));
-
+                ) {
+                    return Err(CommandError::PresetNotCustomizable { preset: *preset });
+                }
                 let data_size = 6; // bass level + 5 bands
-                vec![
-                    Self::EQUALIZER_SET,
-                    0,
-                    *preset as u8,
-                    data_size,
-                    (bass_level + 10) as u8,
-                    (band_400 + 10) as u8,
-                    (band_1000 + 10) as u8,
-                    (band_2500 + 10) as u8,
-                    (band_6300 + 10) as u8,
-                    (band_16000 + 10) as u8,
-                ]
+                let mut out = vec![Self::EQUALIZER_SET, 0, *preset as u8, data_size];
+                out.extend(bands.to_wire());
+                out
             }
 
             Self::GetBatteryStatus { battery_type } => {
@@ -217,7 +738,81 @@ impl Command {
                 // from HCI logs: 3e0e01000000025a036e3c
                 vec![0x5a, 0x03]
             }
-        }
+
+            Self::PlayLocatorTone { left, right } => {
+                vec![Self::LOCATOR_TONE_SET, 0x01, *left as u8, *right as u8]
+            }
+            Self::StopLocatorTone => {
+                vec![Self::LOCATOR_TONE_SET, 0x01, 0, 0]
+            }
+
+            Self::GetCallVoiceFocus => {
+                vec![Self::CALL_VOICE_FOCUS_GET, 0x01]
+            }
+            Self::SetCallVoiceFocus { on } => {
+                vec![Self::CALL_VOICE_FOCUS_SET, 0x01, *on as u8]
+            }
+
+            Self::GetSidetoneLevel => {
+                vec![Self::SIDETONE_GET, 0x01]
+            }
+            Self::SetSidetoneLevel { level } => {
+                if *level > Self::MAX_SIDETONE_LEVEL {
+                    return Err(CommandError::SidetoneLevelOutOfRange { level: *level });
+                }
+                vec![Self::SIDETONE_SET, 0x01, *level]
+            }
+
+            Self::GetSupportedFunctions => {
+                vec![Self::SUPPORTED_FUNCTIONS_GET, 0x02]
+            }
+
+            Self::GetModelName => {
+                vec![Self::DEVICE_INFO_GET, 0x01]
+            }
+            Self::GetFirmwareVersion => {
+                vec![Self::DEVICE_INFO_GET, 0x02]
+            }
+
+            Self::GetQuickAccess => {
+                vec![Self::QUICK_ACCESS_GET, 0x01]
+            }
+            Self::SetQuickAccess {
+                double_tap,
+                triple_tap,
+            } => {
+                vec![
+                    Self::QUICK_ACCESS_SET,
+                    0x01,
+                    *double_tap as u8,
+                    *triple_tap as u8,
+                ]
+            }
+
+            Self::GetSpatialAudioStatus => {
+                vec![Self::SPATIAL_AUDIO_STATUS_GET, 0x01]
+            }
+
+            Self::GetSpeakToChatTimeout => {
+                vec![Self::SPEAK_TO_CHAT_CONFIG_GET, 0x0c]
+            }
+            Self::SetSpeakToChatTimeout { timeout } => {
+                vec![Self::SPEAK_TO_CHAT_CONFIG_SET, 0x0c, *timeout as u8]
+            }
+
+            Self::Raw {
+                message_type,
+                payload,
+            } => {
+                if *message_type == MessageType::Ack {
+                    return Err(CommandError::RawAck);
+                }
+                if payload.is_empty() {
+                    return Err(CommandError::EmptyRawPayload);
+                }
+                payload.clone()
+            }
+        })
     }
 }
 
@@ -246,33 +841,29 @@ fn push_escaped(vec: &mut Vec<u8>, byte: u8) {
  * following byte masked with MESSAGE_ESCAPE_MASK.
  */
 /// Build a command to send the headphones
-pub fn build_command(command: &Command, seq_number: u8) -> Vec<u8> {
-    let cmd = command.to_bytes();
-    let mut buf = Vec::with_capacity(cmd.len() + 7);
-    let message_type = match command {
-        Command::AncSet { .. }
-        | Command::GetCodec
-        | Command::GetAncStatus
-        | Command::ChangeEqualizerSetting { .. }
-        | Command::ChangeEqualizerPreset { .. }
-        | Command::Init
-        | Command::GetBatteryStatus { .. }
-        | Command::GetEqualizerSettings => MessageType::Command1,
-
-        // from hci logs: SoundPressureMeasure: 3e0e0000000004580301006e3c
-        // from hci log: GetSoundPressure: 3e0e01000000025a036e3c
-        Command::SoundPressureMeasure { .. } | Command::GetSoundPressure => MessageType::Command2,
-
-        Command::Ack => MessageType::Ack,
-    };
-    buf.push(message_type as u8);
+pub fn build_command(command: &Command, seq_number: u8) -> Result<Vec<u8>, CommandError> {
     if matches!(command, Command::Ack) {
-        buf.push(1u8.wrapping_sub(seq_number));
-    } else {
-        buf.push(seq_number);
+        return Ok(build_ack(seq_number));
     }
-    buf.extend((cmd.len() as u32).to_be_bytes());
-    buf.extend(cmd);
+    Ok(build_message(
+        command.message_type(),
+        seq_number,
+        &command.try_to_bytes()?,
+    ))
+}
+
+/// Build the Ack for a message with `seq_number`, which can't fail unlike [build_command]
+pub fn build_ack(seq_number: u8) -> Vec<u8> {
+    build_message(MessageType::Ack, SequenceTracker::ack_for(seq_number), &[])
+}
+
+/// Frame `payload` as described above. Used for both directions of the conversation.
+pub fn build_message(message_type: MessageType, seq_number: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(payload.len() + 7);
+    buf.push(message_type as u8);
+    buf.push(seq_number);
+    buf.extend((payload.len() as u32).to_be_bytes());
+    buf.extend(payload);
     buf.push(checksum(&buf));
     let mut out = Vec::with_capacity(buf.len() + 9);
     out.push(MESSAGE_HEADER);
@@ -287,13 +878,17 @@ pub fn build_command(command: &Command, seq_number: u8) -> Vec<u8> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{
+        frame_parser::{FrameParser, FrameParserResult},
+        payload::{BatteryPercent, parse_payload},
+    };
     #[test]
     fn init() {
         // taken from hci logs
         let bytes = [0x3e, 0xc, 0x0, 0x0, 0x0, 0x0, 0x2, 0x0, 0x0, 0xe, 0x3c];
         assert_eq!(
             bytes.as_slice(),
-            build_command(&Command::Init, 0).as_slice()
+            build_command(&Command::Init, 0).unwrap().as_slice()
         );
     }
     #[test]
@@ -301,7 +896,204 @@ mod test {
         // taken from hci logs
         let ack = [0x3e, 0x1, 0x0, 0x0, 0x0, 0x0, 0x0, 0x1, 0x3c];
         let init_seq_num = 1;
-        let our_ack = build_command(&Command::Ack, init_seq_num);
+        let our_ack = build_command(&Command::Ack, init_seq_num).unwrap();
         assert_eq!(ack.as_slice(), our_ack.as_slice());
     }
+    #[test]
+    fn command2() {
+        // taken from hci logs
+        let start = [
+            0x3e, 0xe, 0x0, 0x0, 0x0, 0x0, 0x4, 0x58, 0x3, 0x1, 0x0, 0x6e, 0x3c,
+        ];
+        let stop = [
+            0x3e, 0xe, 0x0, 0x0, 0x0, 0x0, 0x4, 0x58, 0x3, 0x1, 0x1, 0x6f, 0x3c,
+        ];
+        let get = [0x3e, 0xe, 0x1, 0x0, 0x0, 0x0, 0x2, 0x5a, 0x3, 0x6e, 0x3c];
+        assert_eq!(
+            build_command(&Command::SoundPressureMeasure { on: true }, 0).unwrap(),
+            start
+        );
+        assert_eq!(
+            build_command(&Command::SoundPressureMeasure { on: false }, 0).unwrap(),
+            stop
+        );
+        assert_eq!(build_command(&Command::GetSoundPressure, 1).unwrap(), get);
+    }
+
+    #[test]
+    fn command2_reply_round_trip() {
+        // the reply to starting the measurement, and a reading, both from hci logs
+        let measure_reply = [
+            0x3e, 0xe, 0x0, 0x0, 0x0, 0x0, 0x4, 0x59, 0x3, 0x1, 0x0, 0x6f, 0x3c,
+        ];
+        let reading = [
+            0x3e, 0xe, 0x1, 0x0, 0x0, 0x0, 0x4, 0x5b, 0x3, 0x42, 0x3, 0xb6, 0x3c,
+        ];
+        let mut parser = FrameParser::new();
+        let mut parse = |frame: &[u8]| {
+            let FrameParserResult::Ready { msg, consumed } = parser.parse(frame) else {
+                panic!("expected a whole frame");
+            };
+            assert_eq!(consumed, frame.len());
+            assert_eq!(msg.kind, Ok(MessageType::Command2));
+            assert!(msg.checksum.is_ok());
+            parse_payload(msg.payload, MessageType::Command2)
+        };
+        assert!(matches!(
+            parse(&measure_reply),
+            Ok(Payload::SoundPressureMeasureReply { is_on: true })
+        ));
+        assert!(matches!(
+            parse(&reading),
+            Ok(Payload::SoundPressure { db: 0x42 })
+        ));
+    }
+
+    #[test]
+    fn equalizer_bands() {
+        assert_eq!(
+            EqualizerBands::new(0, 11, 0, 0, 0, 0),
+            Err(EqualizerLevelOutOfRange { level: 11 })
+        );
+        let bands = EqualizerBands::new(-10, 0, 3, 10, -5, 0).unwrap();
+        assert_eq!(bands.to_wire(), [0, 10, 13, 20, 5, 10]);
+        assert_eq!(EqualizerBands::from_wire(bands.to_wire()), Ok(bands));
+        assert_eq!(
+            EqualizerBands::from_wire([0, 0, 0, 0, 0, 0xff]),
+            Err(EqualizerLevelOutOfRange { level: 245 })
+        );
+    }
+
+    #[test]
+    fn invalid_commands() {
+        let anc = Command::AncSet {
+            dragging_ambient_sound_slider: false,
+            mode: AncMode::AmbientSound,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: Command::MAX_AMBIENT_SOUND_LEVEL + 1,
+        };
+        assert_eq!(
+            build_command(&anc, 0),
+            Err(CommandError::AmbientSoundLevelOutOfRange { level: 23 })
+        );
+        assert_eq!(
+            Command::ChangeEqualizerSetting {
+                preset: EqualizerPreset::Bright,
+                bands: EqualizerBands::default(),
+            }
+            .try_to_bytes(),
+            Err(CommandError::PresetNotCustomizable {
+                preset: EqualizerPreset::Bright
+            })
+        );
+    }
+
+    #[test]
+    fn replies() {
+        let case = Command::GetBatteryStatus {
+            battery_type: BatteryType::Case,
+        };
+        assert!(
+            case.is_answered_by(&Payload::BatteryLevel(BatteryLevel::Case(
+                BatteryPercent::new(50).unwrap()
+            )))
+        );
+        assert!(
+            !case.is_answered_by(&Payload::BatteryLevel(BatteryLevel::Headphones {
+                left: BatteryPercent::new(50).unwrap(),
+                right: BatteryPercent::new(50).unwrap(),
+            }))
+        );
+        assert!(case.is_answered_by(&Payload::CommandError {
+            opcode: 0x22,
+            code: 1
+        }));
+        assert!(!Command::GetCodec.is_answered_by(&Payload::CommandError {
+            opcode: 0x22,
+            code: 1
+        }));
+        // settings are confirmed with notifications
+        assert!(!Command::SetCallVoiceFocus { on: true }.expects_reply());
+        assert!(
+            !Command::SetCallVoiceFocus { on: true }.is_answered_by(&Payload::CommandError {
+                opcode: 0x88,
+                code: 1
+            })
+        );
+    }
+
+    #[test]
+    fn superseded() {
+        assert!(
+            Command::SetSidetoneLevel { level: 0 }
+                .is_superseded_by(&Command::SetSidetoneLevel { level: 3 })
+        );
+        let play = Command::PlayLocatorTone {
+            left: true,
+            right: false,
+        };
+        assert!(!play.is_superseded_by(&Command::StopLocatorTone));
+        assert!(!Command::StopLocatorTone.is_superseded_by(&play));
+        assert!(!Command::StopLocatorTone.is_superseded_by(&Command::StopLocatorTone));
+    }
+
+    #[test]
+    fn guessed() {
+        assert!(Command::GetQuickAccess.is_guessed());
+        assert!(Command::GetSupportedFunctions.is_guessed());
+        assert!(!Command::GetAncStatus.is_guessed());
+        assert!(!Command::GetModelName.is_guessed());
+    }
+
+    #[cfg(not(feature = "unstable-guessed-opcodes"))]
+    #[test]
+    fn guessed_refused() {
+        let command = Command::SetSidetoneLevel { level: 3 };
+        assert_eq!(
+            build_command(&command, 0),
+            Err(CommandError::GuessedOpcode(command))
+        );
+    }
+
+    #[test]
+    fn raw() {
+        let raw = Command::Raw {
+            message_type: MessageType::Command2,
+            payload: vec![0x5a, 0x03],
+        };
+        assert_eq!(
+            build_command(&raw, 1).unwrap(),
+            build_command(&Command::GetSoundPressure, 1).unwrap()
+        );
+        assert!(raw.is_write());
+        assert!(!raw.expects_reply());
+        let empty = Command::Raw {
+            message_type: MessageType::Command1,
+            payload: vec![],
+        };
+        assert_eq!(empty.try_to_bytes(), Err(CommandError::EmptyRawPayload));
+        let ack = Command::Raw {
+            message_type: MessageType::Ack,
+            payload: vec![0],
+        };
+        assert_eq!(ack.try_to_bytes(), Err(CommandError::RawAck));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let command = Command::ChangeEqualizerSetting {
+            preset: EqualizerPreset::Custom1,
+            bands: EqualizerBands::new(-10, 0, 3, 10, -5, 0).unwrap(),
+        };
+        let json = serde_json::to_string(&command).unwrap();
+        let deserialized: Command = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            build_command(&deserialized, 0).unwrap(),
+            build_command(&command, 0).unwrap()
+        );
+        // the levels are checked like in the constructors
+        let out_of_range = json.replace("-10", "-11");
+        assert!(serde_json::from_str::<Command>(&out_of_range).is_err());
+    }
 }
@@ -0,0 +1,308 @@
+//! The queue of commands from the frontend to the connection. It's bounded, so a link which stopped taking frames
+//! (writing to it blocks, and the connection stops taking commands) can't make it grow without end. When it's
+//! full, a poll makes room by dropping the oldest poll, since the newer one asks the same or more; a change
+//! the user made is refused, so the frontend can tell them instead of pretending it went through. Like the session, the
+//! queue keeps only the last of the changes a dragged slider makes, so dragging doesn't fill it.
+//!
+//! In read-only mode the queue refuses every command which would change something (see [Command::is_write]),
+//! so whichever sender a command comes from, it can't get one through.
+
+use crate::{
+    command::Command,
+    connection::{Request, RequestError},
+};
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+use thiserror::Error;
+use tokio::sync::Notify;
+
+/// Commands waiting for the connection, at most; the GUI sends a dozen when connecting
+pub const CAPACITY: usize = 32;
+
+#[derive(Debug, Error)]
+pub enum QueueError {
+    #[error("Too many commands are waiting for the headphones; not sending {:?}", .0.command)]
+    Full(Request),
+    #[error("The connection to the headphones is closed")]
+    Closed(Request),
+    #[error("Not sending {:?} in read-only mode", .0.command)]
+    ReadOnly(Request),
+}
+
+struct Shared {
+    requests: Mutex<VecDeque<Request>>,
+    capacity: usize,
+    /// Wakes the receiver for a new request, or once the last sender is gone
+    notify: Notify,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+    read_only: AtomicBool,
+}
+
+/// The sending half, which the frontend and its tasks share
+pub struct CommandQueue {
+    shared: Arc<Shared>,
+}
+
+/// The receiving half, which the connection takes the commands from
+pub struct CommandReceiver {
+    shared: Arc<Shared>,
+}
+
+/// A queue of at most `capacity` requests
+pub fn command_queue(capacity: usize) -> (CommandQueue, CommandReceiver) {
+    let shared = Arc::new(Shared {
+        requests: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        notify: Notify::new(),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+        read_only: AtomicBool::new(false),
+    });
+    (
+        CommandQueue {
+            shared: shared.clone(),
+        },
+        CommandReceiver { shared },
+    )
+}
+
+/// Only reads the headphones' state; starting to measure doesn't change a setting, but the user asked for it
+fn is_poll(request: &Request) -> bool {
+    !request.command.is_write() && !matches!(request.command, Command::SoundPressureMeasure { .. })
+}
+
+/// Tell whoever waits for the reply to `poll` that it won't come
+fn drop_poll(poll: Request) {
+    log::debug!("command queue full; dropped {:?}", poll.command);
+    if let Some(reply_tx) = poll.reply_tx {
+        let _ = reply_tx.send(Err(RequestError::QueueFull));
+    }
+}
+
+impl CommandQueue {
+    /// Queue `request`, replacing a queued one it supersedes (see [Command::is_superseded_by]) unless someone
+    /// waits for that one's reply. When the queue is full, a poll replaces the oldest poll (or is dropped if all
+    /// the queued requests are changes), and a change is refused.
+    pub fn send(&self, request: Request) -> Result<(), QueueError> {
+        if self.is_closed() {
+            return Err(QueueError::Closed(request));
+        }
+        if !self.allows(&request.command) {
+            return Err(QueueError::ReadOnly(request));
+        }
+        let mut requests = self.shared.requests.lock().unwrap();
+        if let Some(queued) = requests.iter_mut().find(|queued| {
+            queued.reply_tx.is_none() && queued.command.is_superseded_by(&request.command)
+        }) {
+            *queued = request;
+            return Ok(());
+        }
+        if requests.len() >= self.shared.capacity {
+            if !is_poll(&request) {
+                return Err(QueueError::Full(request));
+            }
+            let Some(oldest) = requests.iter().position(is_poll) else {
+                drop_poll(request);
+                return Ok(());
+            };
+            drop_poll(requests.remove(oldest).unwrap());
+        }
+        requests.push_back(request);
+        drop(requests);
+        self.shared.notify.notify_one();
+        Ok(())
+    }
+}
+
+impl CommandQueue {
+    /// Whether the connection is gone, so nothing will be sent anymore
+    pub fn is_closed(&self) -> bool {
+        !self.shared.receiver_alive.load(Ordering::Acquire)
+    }
+
+    /// Refuse the commands which would change something from now on, for every sender of the queue
+    pub fn set_read_only(&self, read_only: bool) {
+        self.shared.read_only.store(read_only, Ordering::Release);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.shared.read_only.load(Ordering::Acquire)
+    }
+
+    /// Whether the queue takes `command` in its current mode
+    pub fn allows(&self, command: &Command) -> bool {
+        !(self.is_read_only() && command.is_write())
+    }
+}
+
+impl Clone for CommandQueue {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for CommandQueue {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.notify.notify_one();
+        }
+    }
+}
+
+impl CommandReceiver {
+    /// The oldest request, if there's one already
+    pub fn try_recv(&mut self) -> Option<Request> {
+        self.shared.requests.lock().unwrap().pop_front()
+    }
+
+    /// The oldest request, or `None` once the queue is empty and every sender is gone
+    pub async fn recv(&mut self) -> Option<Request> {
+        loop {
+            if let Some(request) = self.try_recv() {
+                return Some(request);
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return None;
+            }
+            self.shared.notify.notified().await;
+        }
+    }
+}
+
+impl Drop for CommandReceiver {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    fn commands(receiver: &mut CommandReceiver) -> Vec<Command> {
+        let mut commands = Vec::new();
+        while let Some(request) = receiver.try_recv() {
+            commands.push(request.command);
+        }
+        commands
+    }
+
+    #[test]
+    fn full() {
+        let (queue, mut receiver) = command_queue(2);
+        let (reply_tx, mut reply_rx) = oneshot::channel();
+        queue
+            .send(Request {
+                command: Command::GetCodec,
+                reply_tx: Some(reply_tx),
+            })
+            .unwrap();
+        queue
+            .send(Command::SetCallVoiceFocus { on: true }.into())
+            .unwrap();
+        // a change is refused
+        assert!(matches!(
+            queue.send(Command::StopLocatorTone.into()),
+            Err(QueueError::Full(_))
+        ));
+        // a poll replaces the oldest one, whose caller learns why
+        queue.send(Command::GetAncStatus.into()).unwrap();
+        assert!(matches!(
+            reply_rx.try_recv(),
+            Ok(Err(RequestError::QueueFull))
+        ));
+        assert_eq!(
+            commands(&mut receiver),
+            [
+                Command::SetCallVoiceFocus { on: true },
+                Command::GetAncStatus
+            ]
+        );
+
+        // with only changes queued, the new poll is dropped
+        queue
+            .send(Command::SetCallVoiceFocus { on: true }.into())
+            .unwrap();
+        queue.send(Command::StopLocatorTone.into()).unwrap();
+        queue.send(Command::GetAncStatus.into()).unwrap();
+        assert_eq!(
+            commands(&mut receiver),
+            [
+                Command::SetCallVoiceFocus { on: true },
+                Command::StopLocatorTone
+            ]
+        );
+    }
+
+    #[test]
+    fn superseded() {
+        let (queue, mut receiver) = command_queue(2);
+        let (reply_tx, _reply_rx) = oneshot::channel();
+        queue
+            .send(Request {
+                command: Command::SetSidetoneLevel { level: 0 },
+                reply_tx: Some(reply_tx),
+            })
+            .unwrap();
+        // a dragged slider
+        for level in 1..=3 {
+            queue
+                .send(Command::SetSidetoneLevel { level }.into())
+                .unwrap();
+        }
+        assert_eq!(
+            commands(&mut receiver),
+            [
+                Command::SetSidetoneLevel { level: 0 },
+                Command::SetSidetoneLevel { level: 3 }
+            ]
+        );
+    }
+
+    #[test]
+    fn read_only() {
+        let (queue, mut receiver) = command_queue(CAPACITY);
+        queue.set_read_only(true);
+        // a clone shares the mode
+        let other = queue.clone();
+        assert!(matches!(
+            other.send(Command::SetCallVoiceFocus { on: true }.into()),
+            Err(QueueError::ReadOnly(_))
+        ));
+        other.send(Command::GetCallVoiceFocus.into()).unwrap();
+        queue.set_read_only(false);
+        other.send(Command::StopLocatorTone.into()).unwrap();
+        assert_eq!(
+            commands(&mut receiver),
+            [Command::GetCallVoiceFocus, Command::StopLocatorTone]
+        );
+    }
+
+    #[tokio::test]
+    async fn closed() {
+        let (queue, mut receiver) = command_queue(CAPACITY);
+        let other = queue.clone();
+        queue.send(Command::GetCodec.into()).unwrap();
+        drop(queue);
+        assert_eq!(receiver.recv().await.unwrap().command, Command::GetCodec);
+        drop(other);
+        assert!(receiver.recv().await.is_none());
+
+        let (queue, receiver) = command_queue(CAPACITY);
+        drop(receiver);
+        assert!(matches!(
+            queue.send(Command::GetCodec.into()),
+            Err(QueueError::Closed(_))
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+use std::cmp::Ordering;
+
+use crate::{LAST_TESTED_FIRMWARE_VERSION, SUPPORTED_MODELS};
+
+/// What the headphones told us about themselves.
+/// See [crate::command::Command::GetModelName] and [crate::command::Command::GetFirmwareVersion]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeviceInfo {
+    pub model_name: Option<String>,
+    pub firmware_version: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CompatibilityWarning {
+    UnsupportedModel {
+        model_name: String,
+    },
+    NewerFirmware {
+        firmware_version: String,
+        last_tested: String,
+    },
+    UnknownFirmwareVersion,
+}
+
+impl std::fmt::Display for CompatibilityWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedModel { model_name } => {
+                write!(
+                    f,
+                    "{model_name} is not a supported model; things may not work"
+                )
+            }
+            Self::NewerFirmware {
+                firmware_version,
+                last_tested,
+            } => write!(
+                f,
+                "firmware {firmware_version} is newer than the last tested firmware ({last_tested})"
+            ),
+            Self::UnknownFirmwareVersion => write!(f, "the firmware version is unknown"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompatibilityReport {
+    pub warnings: Vec<CompatibilityWarning>,
+}
+
+impl CompatibilityReport {
+    pub fn is_compatible(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+/// Compare dotted version strings numerically, e.g. "2.10.0" > "2.9.1".
+/// Non numeric parts compare as 0.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |v: &str| -> Vec<u32> {
+        v.split('.')
+            .map(|p| p.trim().parse().unwrap_or(0))
+            .collect()
+    };
+    let (a, b) = (parse(a), parse(b));
+    for idx in 0..a.len().max(b.len()) {
+        let ord = a.get(idx).unwrap_or(&0).cmp(b.get(idx).unwrap_or(&0));
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Check the device against what this crate was tested with, so frontends can warn the user.
+pub fn compatibility_report(device_info: &DeviceInfo) -> CompatibilityReport {
+    report_against(device_info, LAST_TESTED_FIRMWARE_VERSION)
+}
+
+/// [compatibility_report], against the given tested firmware version
+fn report_against(device_info: &DeviceInfo, last_tested: Option<&str>) -> CompatibilityReport {
+    let mut warnings = Vec::new();
+    if let Some(model_name) = device_info.model_name.as_ref()
+        && !SUPPORTED_MODELS.contains(&model_name.as_str())
+    {
+        warnings.push(CompatibilityWarning::UnsupportedModel {
+            model_name: model_name.clone(),
+        });
+    }
+    match device_info.firmware_version.as_ref() {
+        Some(firmware_version) => {
+            if let Some(last_tested) = last_tested
+                && compare_versions(firmware_version, last_tested) == Ordering::Greater
+            {
+                warnings.push(CompatibilityWarning::NewerFirmware {
+                    firmware_version: firmware_version.clone(),
+                    last_tested: last_tested.to_string(),
+                });
+            }
+        }
+        None => warnings.push(CompatibilityWarning::UnknownFirmwareVersion),
+    }
+    CompatibilityReport { warnings }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let tested = DeviceInfo {
+            model_name: Some("WF-1000XM5".to_string()),
+            firmware_version: Some("2.0.1".to_string()),
+        };
+        assert!(report_against(&tested, Some("2.0.1")).is_compatible());
+
+        let newer = DeviceInfo {
+            model_name: Some("WH-1000XM5".to_string()),
+            firmware_version: Some("99.0".to_string()),
+        };
+        assert_eq!(
+            report_against(&newer, Some("2.0.1")).warnings,
+            vec![
+                CompatibilityWarning::UnsupportedModel {
+                    model_name: "WH-1000XM5".to_string()
+                },
+                CompatibilityWarning::NewerFirmware {
+                    firmware_version: "99.0".to_string(),
+                    last_tested: "2.0.1".to_string(),
+                },
+            ]
+        );
+        // without a tested version, no firmware is newer
+        assert_eq!(report_against(&newer, None).warnings.len(), 1);
+        assert!(
+            compatibility_report(&DeviceInfo {
+                model_name: Some("WF-1000XM5".to_string()),
+                firmware_version: Some("99.0".to_string()),
+            })
+            .is_compatible()
+        );
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(compare_versions("2.10.0", "2.9.1"), Ordering::Greater);
+        assert_eq!(compare_versions("2.0", "2.0.0"), Ordering::Equal);
+        assert_eq!(compare_versions("1.9", "2"), Ordering::Less);
+    }
+}
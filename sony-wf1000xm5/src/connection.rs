@@ -0,0 +1,868 @@
+//! A [Session] over an async stream: [run] reads and writes the frames, keeps the session's timers, takes the
+//! commands from a [ConnectionHandle] and hands what the headphones send to the frontend. The GUI, `sonyctl` and
+//! the daemon all connect through it, so they retransmit, keep the connection alive and close it the same way.
+
+use crate::{
+    MessageType,
+    command::{Command, CommandError},
+    command_queue::{CommandQueue, CommandReceiver, QueueError, command_queue},
+    frame_parser::FramerParserError,
+    payload::{ParsePayloadError, Payload},
+    session::{ACK_TIMEOUT, Instant, KEEP_ALIVE_INTERVAL, Session, SessionEvent},
+};
+use futures::future::OptionFuture;
+use log::debug;
+use std::{collections::VecDeque, time::Duration};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, oneshot, watch},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Why the connection to the headphones ended, once the stream to them was open
+#[derive(Debug, Error)]
+pub enum ConnectionError {
+    #[error("Lost the connection to the headphones: {0}")]
+    Transport(#[from] std::io::Error),
+    #[error("The headphones sent a malformed frame ({0}). Try reconnecting.")]
+    Protocol(#[from] FramerParserError),
+    #[error(
+        "The headphones didn't answer. Make sure they're out of the case and not connected to another device, then try again."
+    )]
+    InitTimeout,
+    #[error("The headphones closed the connection.")]
+    RemoteClosed,
+    #[error(
+        "The headphones stopped answering. They were probably put in the case or went out of range."
+    )]
+    Unresponsive,
+}
+
+impl ConnectionError {
+    /// The headphones went away on their own, e.g. into the case or out of range, rather than something going wrong
+    pub fn is_disconnect(&self) -> bool {
+        use std::io::ErrorKind;
+
+        match self {
+            Self::RemoteClosed | Self::Unresponsive => true,
+            Self::Transport(e) => matches!(
+                e.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::NotConnected
+                    | ErrorKind::UnexpectedEof
+            ),
+            Self::Protocol(_) | Self::InitTimeout => false,
+        }
+    }
+}
+
+/// A command for the connection to send
+#[derive(Debug)]
+pub struct Request {
+    pub command: Command,
+    /// Settled once the headphones took the command, see [ConnectionHandle::request]
+    pub reply_tx: Option<oneshot::Sender<Result<Option<Payload>, RequestError>>>,
+}
+
+impl From<Command> for Request {
+    fn from(command: Command) -> Self {
+        Self {
+            command,
+            reply_tx: None,
+        }
+    }
+}
+
+/// Why [ConnectionHandle::request] didn't get a reply
+#[derive(Debug, Error)]
+pub enum RequestError {
+    #[error("Not sending {0:?} in read-only mode")]
+    ReadOnly(Command),
+    #[error("The headphones didn't reply in time")]
+    Timeout,
+    #[error("The headphones didn't acknowledge the command")]
+    NotAcked,
+    /// Only with the `unstable-guessed-opcodes` feature, which parses [Payload::CommandError]
+    #[error("The headphones rejected the command with error 0x{code:x}")]
+    Rejected { code: u8 },
+    #[error("The connection to the headphones is closed")]
+    Disconnected,
+    #[error("Too many commands are waiting for the headphones")]
+    QueueFull,
+    #[error(transparent)]
+    Invalid(#[from] CommandError),
+}
+
+impl From<QueueError> for RequestError {
+    fn from(error: QueueError) -> Self {
+        match error {
+            QueueError::Full(_) => Self::QueueFull,
+            QueueError::Closed(_) => Self::Disconnected,
+            QueueError::ReadOnly(request) => Self::ReadOnly(request.command),
+        }
+    }
+}
+
+/// The commands the connection sends periodically, with their intervals, see [Session::set_periodic]
+pub type PeriodicCommands = Vec<(Command, Duration)>;
+
+/// The frontend's end of a connection: the queue of commands to it, the commands it sends periodically and the
+/// way to stop it. The connection can end at any time, e.g. when the headphones go away, so nothing here assumes
+/// it's still running.
+#[derive(Clone)]
+pub struct ConnectionHandle {
+    commands: CommandQueue,
+    periodic_tx: watch::Sender<PeriodicCommands>,
+    stop: CancellationToken,
+}
+
+/// The connection's end of a [ConnectionHandle], to [run] it with
+pub struct Commands {
+    requests: CommandReceiver,
+    periodic_rx: watch::Receiver<PeriodicCommands>,
+    stop: CancellationToken,
+}
+
+impl Commands {
+    /// The oldest request, if there's one already
+    pub fn try_recv(&mut self) -> Option<Request> {
+        self.requests.try_recv()
+    }
+
+    /// Once the connection was asked to stop, e.g. to give up while the stream is still opening
+    pub async fn stopped(&self) {
+        self.stop.cancelled().await
+    }
+}
+
+impl ConnectionHandle {
+    /// A handle whose queue takes at most `capacity` commands (see [crate::command_queue]), and the end of it
+    /// to [run] the connection with
+    pub fn new(capacity: usize) -> (Self, Commands) {
+        let (commands, requests) = command_queue(capacity);
+        let (periodic_tx, periodic_rx) = watch::channel(Vec::new());
+        let stop = CancellationToken::new();
+        (
+            Self {
+                commands,
+                periodic_tx,
+                stop: stop.clone(),
+            },
+            Commands {
+                requests,
+                periodic_rx,
+                stop,
+            },
+        )
+    }
+
+    pub fn send(&self, request: Request) -> Result<(), QueueError> {
+        self.commands.send(request)
+    }
+
+    /// Refuse the commands which would change something, see [crate::command_queue]. Every clone of the handle
+    /// shares the mode.
+    pub fn set_read_only(&self, read_only: bool) {
+        self.commands.set_read_only(read_only);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.commands.is_read_only()
+    }
+
+    /// Whether [Self::send] takes `command` in the current mode
+    pub fn allows(&self, command: &Command) -> bool {
+        self.commands.allows(command)
+    }
+
+    /// Send `command` and wait until the headphones took it: for the commands which read something, that's
+    /// their reply (see [Command::is_answered_by]), and for the others `None` once they acked it.
+    /// The command is queued right away; the reply is also passed to the frontend as usual.
+    pub fn request(
+        &self,
+        command: Command,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<Option<Payload>, RequestError>> + 'static {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let sent = self.send(Request {
+            command,
+            reply_tx: Some(reply_tx),
+        });
+        async move {
+            sent?;
+            tokio::select! {
+                reply = reply_rx => reply.unwrap_or(Err(RequestError::Disconnected)),
+                _ = sleep(timeout) => Err(RequestError::Timeout),
+            }
+        }
+    }
+
+    /// Have the connection send `command` right away and then every `interval`, or stop sending it with `None`.
+    /// The connection keeps time, so the frontend doesn't have to wake up for it.
+    pub fn set_periodic(
+        &self,
+        command: Command,
+        interval: Option<Duration>,
+    ) -> Result<(), RequestError> {
+        if self.is_closed() {
+            return Err(RequestError::Disconnected);
+        }
+        if !self.allows(&command) {
+            return Err(RequestError::ReadOnly(command));
+        }
+        self.periodic_tx.send_if_modified(|periodic| {
+            let index = periodic.iter().position(|(other, _)| *other == command);
+            match (index, interval) {
+                (Some(index), Some(interval)) if periodic[index].1 == interval => return false,
+                (Some(index), Some(interval)) => periodic[index].1 = interval,
+                (Some(index), None) => {
+                    periodic.remove(index);
+                }
+                (None, Some(interval)) => periodic.push((command, interval)),
+                (None, None) => return false,
+            }
+            true
+        });
+        Ok(())
+    }
+
+    /// Ask the connection to close; asking again while it's closing is fine
+    pub fn stop(&self) -> Result<(), RequestError> {
+        if self.is_closed() {
+            return Err(RequestError::Disconnected);
+        }
+        self.stop.cancel();
+        Ok(())
+    }
+
+    /// Whether the connection ended, so nothing will be sent anymore
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+}
+
+/// What the connection tells the frontend
+#[derive(Debug)]
+pub enum ConnectionEvent {
+    Payload(Payload),
+    /// The headphones never acked the command, even after it was retransmitted
+    CommandTimedOut(Command),
+    /// Something the headphones sent was ignored, e.g. a frame with a bad checksum. It's logged, too.
+    Warning(String),
+}
+
+/// What the connection needs from the frontend besides the events
+pub trait Frontend {
+    /// Events are waiting. Called once per batch of them, and before waiting for room in the event queue.
+    fn wake(&mut self) {}
+
+    /// Only payloads which stream in periodically are waiting, so the frontend may take them a bit later
+    fn wake_later(&mut self) {
+        self.wake()
+    }
+
+    /// The headphones sent a payload which couldn't be parsed, e.g. to save it for a bug report
+    fn invalid_payload(
+        &mut self,
+        _message_type: MessageType,
+        _payload: &[u8],
+        _error: &ParsePayloadError,
+    ) {
+    }
+}
+
+/// For a frontend which waits for the events anyway
+impl Frontend for () {}
+
+/// Events waiting for the frontend, at most. Warnings and the streaming payloads are dropped when it's full; the
+/// connection waits for room for the others, rather than letting them pile up while the frontend doesn't take them.
+pub const EVENT_QUEUE_SIZE: usize = 256;
+
+/// How long closing the connection may take, to send the commands still queued and wait for their acks
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Bytes read from the stream at once; the session takes any number of frames per read,
+/// so a burst of notifications is handled in one wakeup
+const READ_BUFFER_SIZE: usize = 512;
+
+/// How long to wait for the reply to Init before sending it again
+const INIT_RETRY_INTERVAL: Duration = Duration::from_millis(1500);
+
+/// On the runtime of the platform
+pub async fn sleep(duration: Duration) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        tokio::time::sleep(duration).await
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        gloo_timers::future::sleep(duration).await
+    }
+}
+
+/// The requests waiting for the headphones, oldest first, like the headphones answer them
+type Waiting = VecDeque<(
+    Command,
+    oneshot::Sender<Result<Option<Payload>, RequestError>>,
+)>;
+
+/// Hand `payload` to the oldest request it answers, if any
+fn answer(waiting: &mut Waiting, payload: &Payload) {
+    // the caller stopped waiting (e.g. it timed out)
+    waiting.retain(|(_, reply_tx)| !reply_tx.is_closed());
+    let Some(index) = waiting
+        .iter()
+        .position(|(command, _)| command.is_answered_by(payload))
+    else {
+        return;
+    };
+    let (_, reply_tx) = waiting.remove(index).unwrap();
+    let reply = match payload {
+        Payload::CommandError { code, .. } => Err(RequestError::Rejected { code: *code }),
+        _ => Ok(Some(payload.clone())),
+    };
+    let _ = reply_tx.send(reply);
+}
+
+/// Settle the oldest request for `command`, which the headphones acked or never acked, and the ones `command`
+/// replaced in the session before it was sent (see [Session::send]) with it
+fn settle(
+    waiting: &mut Waiting,
+    command: &Command,
+    reply: impl Fn() -> Result<Option<Payload>, RequestError>,
+) {
+    waiting.retain(|(_, reply_tx)| !reply_tx.is_closed());
+    let Some(index) = waiting.iter().position(|(waiting, _)| waiting == command) else {
+        return;
+    };
+    let superseded: Vec<_> = (0..index)
+        .filter(|&i| waiting[i].0.is_superseded_by(command))
+        .collect();
+    for i in superseded.into_iter().chain([index]).rev() {
+        let (_, reply_tx) = waiting.remove(i).unwrap();
+        let _ = reply_tx.send(reply());
+    }
+}
+
+/// Payloads which stream in periodically, and don't need the frontend right away
+fn is_streaming(payload: &Payload) -> bool {
+    matches!(payload, Payload::SoundPressure { .. })
+}
+
+/// Hand `event` to the frontend unless its queue is full, waking it then so it makes room; `Err` once it's gone
+fn try_send_event<E: From<ConnectionEvent>>(
+    event_tx: &mpsc::Sender<E>,
+    frontend: &mut impl Frontend,
+    event: ConnectionEvent,
+) -> Result<(), ()> {
+    match event_tx.try_send(event.into()) {
+        Ok(()) => Ok(()),
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            frontend.wake();
+            Ok(())
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => Err(()),
+    }
+}
+
+/// Hand `event` to the frontend, waiting for room in its queue; it's woken first, in case it only takes events
+/// when woken. `Err` once it's gone.
+async fn send_event<E: From<ConnectionEvent>>(
+    event_tx: &mpsc::Sender<E>,
+    frontend: &mut impl Frontend,
+    event: ConnectionEvent,
+) -> Result<(), ()> {
+    match event_tx.try_send(event.into()) {
+        Ok(()) => Ok(()),
+        Err(mpsc::error::TrySendError::Full(event)) => {
+            frontend.wake();
+            event_tx.send(event).await.map_err(|_| ())
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => Err(()),
+    }
+}
+
+fn warn<E: From<ConnectionEvent>>(
+    event_tx: &mpsc::Sender<E>,
+    frontend: &mut impl Frontend,
+    warning: String,
+) {
+    log::warn!("{warning}");
+    let _ = try_send_event(event_tx, frontend, ConnectionEvent::Warning(warning));
+}
+
+/// Write everything the session wants to send
+async fn flush(
+    session: &mut Session,
+    stream: &mut (impl AsyncWrite + Unpin),
+) -> std::io::Result<()> {
+    while let Some(bytes) = session.poll_transmit() {
+        debug!("sending: {:x?}", bytes);
+        stream.write_all(&bytes).await?;
+    }
+    Ok(())
+}
+
+/// Close the connection in order, rather than dropping it halfway through a command: the commands the frontend
+/// queued before asking to stop are still sent, and the headphones get [SHUTDOWN_TIMEOUT] to ack them, then the
+/// stream is closed. Nothing else is taken from the frontend.
+async fn shut_down(
+    session: &mut Session,
+    stream: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    mut requests: CommandReceiver,
+    buffer: &mut [u8],
+) -> Result<(), ConnectionError> {
+    // nobody waits for their replies anymore
+    while let Some(request) = requests.try_recv() {
+        if let Err(e) = session.send(request.command.clone()) {
+            log::warn!("not sending {:?}: {e}", request.command);
+        }
+    }
+    drop(requests);
+    let flushed = async {
+        loop {
+            flush(session, stream).await?;
+            if session.is_flushed() {
+                return Ok(());
+            }
+            match stream.read(buffer).await? {
+                0 => return Err(ConnectionError::RemoteClosed),
+                read => session.feed(&buffer[..read])?,
+            }
+        }
+    };
+    let flushed = tokio::select! {
+        flushed = flushed => flushed,
+        _ = sleep(SHUTDOWN_TIMEOUT) => {
+            log::warn!("the headphones didn't ack the last commands in {SHUTDOWN_TIMEOUT:?}; closing anyway");
+            Ok(())
+        }
+    };
+    stream.shutdown().await?;
+    debug!("connection closed");
+    flushed
+}
+
+/// Run the connection over `stream` until it's stopped (see [ConnectionHandle::stop]), every handle is gone or
+/// the connection ends. `session` is a new one, which queued Init; it's sent again until the headphones answer.
+///
+/// Everything the headphones send goes to `event_tx`, replies and notifications alike. The connection waits for
+/// room in it, so the frontend has to keep taking the events, and it ends once the frontend dropped the receiver.
+pub async fn run<E: From<ConnectionEvent>>(
+    stream: impl AsyncRead + AsyncWrite,
+    commands: Commands,
+    event_tx: mpsc::Sender<E>,
+    mut session: Session,
+    mut frontend: impl Frontend,
+) -> Result<(), ConnectionError> {
+    let Commands {
+        mut requests,
+        mut periodic_rx,
+        stop,
+    } = commands;
+    let mut tries = 3;
+    tokio::pin!(stream);
+    flush(&mut session, &mut stream).await?;
+    let mut buffer = [0; READ_BUFFER_SIZE];
+
+    let mut read = loop {
+        tokio::select! {
+            _ = stop.cancelled() => {
+                return Ok(());
+            }
+
+            read = stream.read(&mut buffer) => {
+                // stream is alive
+                match read? {
+                    0 => return Err(ConnectionError::RemoteClosed),
+                    read => break read,
+                }
+            }
+
+            _ = sleep(INIT_RETRY_INTERVAL) => {
+                if tries == 0 {
+                    return Err(ConnectionError::InitTimeout);
+                }
+                debug!("init failed; retrying...");
+                session.retransmit();
+                flush(&mut session, &mut stream).await?;
+                tries -= 1;
+            }
+        }
+    };
+    // the headphones answered, so from now on their silence means something
+    session.set_keep_alive(true);
+    // restarted whenever the session (re)sends a command
+    let mut ack_timer = None;
+    // restarted whenever something is read
+    let mut idle_timer = Box::pin(sleep(KEEP_ALIVE_INTERVAL));
+    // restarted whenever the periodic commands change or some were sent
+    let mut periodic_timer = None;
+    let mut waiting = Waiting::new();
+
+    'eventloop: loop {
+        if let Err(err) = session.feed(&buffer[..read]) {
+            log::warn!("frame parser returned an error: {err}");
+            return Err(err.into());
+        }
+        // one wakeup per batch of payloads, not one per payload
+        let mut wake_now = false;
+        let mut wake_later = false;
+        while let Some(event) = session.poll_event() {
+            let is_notification = matches!(event, SessionEvent::Notification(_));
+            match event {
+                // the frontend works out who changed what itself
+                SessionEvent::Payload(payload) | SessionEvent::Notification(payload) => {
+                    debug!("payload: {:x?}", payload);
+                    if let Payload::Unknown {
+                        message_type,
+                        payload_type,
+                        raw,
+                    } = &payload
+                    {
+                        log::info!("unknown payload type: 0x{payload_type:x}");
+                        let error = ParsePayloadError::UnknownPayloadType {
+                            kind: *payload_type,
+                        };
+                        frontend.invalid_payload(*message_type, raw, &error);
+                    }
+                    if !is_notification {
+                        answer(&mut waiting, &payload);
+                    }
+                    let sent = if is_streaming(&payload) {
+                        wake_later = true;
+                        // the next one comes soon enough
+                        try_send_event(&event_tx, &mut frontend, ConnectionEvent::Payload(payload))
+                    } else {
+                        wake_now = true;
+                        send_event(&event_tx, &mut frontend, ConnectionEvent::Payload(payload))
+                            .await
+                    };
+                    if sent.is_err() {
+                        break 'eventloop;
+                    }
+                }
+                SessionEvent::InvalidPayload {
+                    error,
+                    message_type,
+                    payload,
+                } => {
+                    warn(&event_tx, &mut frontend, format!("bad payload: {error}"));
+                    frontend.invalid_payload(message_type, &payload, &error);
+                }
+                SessionEvent::UnknownMessageType {
+                    message_type,
+                    seq_num,
+                    payload,
+                } => warn(
+                    &event_tx,
+                    &mut frontend,
+                    format!(
+                        "unknown message type: 0x{message_type:x} (seq {seq_num}, payload {payload:02x?}); ignoring"
+                    ),
+                ),
+                SessionEvent::InvalidChecksum(e) => warn(
+                    &event_tx,
+                    &mut frontend,
+                    format!("bad checksum: {e}; ignoring"),
+                ),
+                // the commands which read something are settled by their reply
+                SessionEvent::Acked(command) if command.expects_reply() => (),
+                SessionEvent::Acked(command) => settle(&mut waiting, &command, || Ok(None)),
+                SessionEvent::AckTimeout(command) => {
+                    warn(
+                        &event_tx,
+                        &mut frontend,
+                        format!("no ack for {command:?}; dropped it"),
+                    );
+                    settle(&mut waiting, &command, || Err(RequestError::NotAcked));
+                    wake_now = true;
+                    let timed_out = ConnectionEvent::CommandTimedOut(command);
+                    if send_event(&event_tx, &mut frontend, timed_out)
+                        .await
+                        .is_err()
+                    {
+                        break 'eventloop;
+                    }
+                }
+                SessionEvent::Disconnected => {
+                    log::warn!("the headphones stopped answering; closing the connection");
+                    return Err(ConnectionError::Unresponsive);
+                }
+            }
+        }
+        if wake_now {
+            frontend.wake();
+        } else if wake_later {
+            frontend.wake_later();
+        }
+        flush(&mut session, &mut stream).await?;
+        if session.poll_ack_timer() {
+            ack_timer = Some(Box::pin(sleep(ACK_TIMEOUT)));
+        } else if !session.waiting_for_ack() {
+            ack_timer = None;
+        }
+        if session.poll_periodic_timer() {
+            periodic_timer = session.periodic_deadline().map(|deadline| {
+                Box::pin(sleep(deadline.saturating_duration_since(Instant::now())))
+            });
+        }
+        read = 0;
+        // the commands wait in the bounded queue until the session sent the previous ones, so a link which
+        // stopped taking them makes the queue refuse more instead of the session piling them up
+        let idle = session.is_flushed();
+
+        tokio::select! {
+            _ = stop.cancelled() => {
+                debug!("event loop received stop");
+                return shut_down(&mut session, &mut stream, requests, &mut buffer).await;
+            }
+
+            n = stream.read(&mut buffer) => {
+                read = n?;
+                if read == 0 {
+                    return Err(ConnectionError::RemoteClosed);
+                }
+                idle_timer = Box::pin(sleep(KEEP_ALIVE_INTERVAL));
+            }
+
+            // everything queued by then goes to the session at once, which coalesces and orders them
+            request = requests.recv(), if idle => {
+                let Some(request) = request else {
+                    debug!("every handle is gone");
+                    return shut_down(&mut session, &mut stream, requests, &mut buffer).await;
+                };
+                let mut request = Some(request);
+                while let Some(request) = request.take().or_else(|| requests.try_recv()) {
+                    debug!("queueing: {:?}", request.command);
+                    match session.send(request.command.clone()) {
+                        Ok(()) => {
+                            if let Some(reply_tx) = request.reply_tx {
+                                waiting.push_back((request.command, reply_tx));
+                            }
+                        }
+                        Err(e) => {
+                            log::warn!("not sending {:?}: {e}", request.command);
+                            if let Some(reply_tx) = request.reply_tx {
+                                let _ = reply_tx.send(Err(e.into()));
+                            }
+                        }
+                    }
+                }
+            }
+
+            Ok(()) = periodic_rx.changed() => {
+                let periodic = periodic_rx.borrow_and_update().clone();
+                let now = Instant::now();
+                let stopped: Vec<_> = session
+                    .periodic_commands()
+                    .filter(|command| !periodic.iter().any(|(other, _)| other == *command))
+                    .cloned()
+                    .collect();
+                for command in stopped {
+                    let _ = session.set_periodic(command, None, now);
+                }
+                for (command, interval) in periodic {
+                    if let Err(e) = session.set_periodic(command.clone(), Some(interval), now) {
+                        log::warn!("not sending {command:?} periodically: {e}");
+                    }
+                }
+            }
+
+            Some(()) = OptionFuture::from(periodic_timer.as_mut()) => {
+                periodic_timer = None;
+                session.handle_periodic_timeout(Instant::now());
+            }
+
+            Some(()) = OptionFuture::from(ack_timer.as_mut()) => {
+                debug!("ack timed out");
+                ack_timer = None;
+                session.handle_ack_timeout();
+            }
+
+            _ = idle_timer.as_mut() => {
+                debug!("nothing received for {KEEP_ALIVE_INTERVAL:?}");
+                idle_timer = Box::pin(sleep(KEEP_ALIVE_INTERVAL));
+                session.handle_idle_timeout();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::{AncMode, EqualizerBands, EqualizerPreset, build_ack, build_command};
+    use crate::command_queue::CAPACITY;
+
+    /// A change which the headphones only ack
+    fn anc(mode: AncMode) -> Command {
+        Command::AncSet {
+            dragging_ambient_sound_slider: false,
+            mode,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: 10,
+        }
+    }
+
+    /// Read what the connection sent, which should be `command` with `seq_num`
+    async fn expect(headphones: &mut tokio::io::DuplexStream, command: &Command, seq_num: u8) {
+        let mut buffer = [0; 64];
+        let read = headphones.read(&mut buffer).await.unwrap();
+        assert_eq!(buffer[..read], build_command(command, seq_num).unwrap());
+    }
+
+    #[tokio::test]
+    async fn shuts_down_in_order() {
+        let (mut ours, mut headphones) = tokio::io::duplex(64);
+        let mut session = Session::new();
+        let (handle, commands) = ConnectionHandle::new(CAPACITY);
+        // queued before the frontend asked to stop
+        handle.send(Command::GetCodec.into()).unwrap();
+
+        // the headphones ack every command until the stream is closed
+        let acking = async {
+            let mut received = Vec::new();
+            let mut buffer = [0; 64];
+            loop {
+                let read = headphones.read(&mut buffer).await.unwrap();
+                if read == 0 {
+                    return received;
+                }
+                received.extend_from_slice(&buffer[..read]);
+                let ack = build_command(&Command::Ack, buffer[2]).unwrap();
+                headphones.write_all(&ack).await.unwrap();
+            }
+        };
+        let mut buffer = [0; READ_BUFFER_SIZE];
+        let (result, received) = tokio::join!(
+            shut_down(&mut session, &mut ours, commands.requests, &mut buffer),
+            acking
+        );
+        result.unwrap();
+        assert_eq!(
+            received,
+            [
+                build_command(&Command::Init, 0).unwrap(),
+                build_command(&Command::GetCodec, 1).unwrap(),
+            ]
+            .concat()
+        );
+        assert!(session.is_flushed());
+        // nothing else is taken
+        assert!(handle.is_closed());
+    }
+
+    #[tokio::test]
+    async fn backpressure() {
+        let (ours, mut headphones) = tokio::io::duplex(64);
+        let (event_tx, _event_rx) = mpsc::channel::<ConnectionEvent>(EVENT_QUEUE_SIZE);
+        let (handle, commands) = ConnectionHandle::new(2);
+        let connection = run(ours, commands, event_tx, Session::new(), ());
+
+        let headphones = async {
+            expect(&mut headphones, &Command::Init, 0).await;
+            headphones.write_all(&build_ack(0)).await.unwrap();
+            handle.send(Command::GetCodec.into()).unwrap();
+            expect(&mut headphones, &Command::GetCodec, 1).await;
+
+            // not acked yet, so these wait in the queue until it's full
+            let preset = Command::ChangeEqualizerPreset {
+                preset: EqualizerPreset::BassBoost,
+            };
+            handle.send(preset.clone().into()).unwrap();
+            handle.send(anc(AncMode::Off).into()).unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            assert!(matches!(
+                handle.send(
+                    Command::ChangeEqualizerSetting {
+                        preset: EqualizerPreset::Custom1,
+                        bands: EqualizerBands::default(),
+                    }
+                    .into()
+                ),
+                Err(QueueError::Full(_))
+            ));
+
+            headphones.write_all(&build_ack(1)).await.unwrap();
+            expect(&mut headphones, &preset, 0).await;
+        };
+        tokio::select! {
+            result = connection => panic!("the connection ended: {result:?}"),
+            () = headphones => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn acked() {
+        let (ours, mut headphones) = tokio::io::duplex(64);
+        let (event_tx, _event_rx) = mpsc::channel::<ConnectionEvent>(EVENT_QUEUE_SIZE);
+        let (handle, commands) = ConnectionHandle::new(CAPACITY);
+        let connection = run(ours, commands, event_tx, Session::new(), ());
+
+        let headphones = async {
+            expect(&mut headphones, &Command::Init, 0).await;
+            headphones.write_all(&build_ack(0)).await.unwrap();
+            let set = anc(AncMode::AmbientSound);
+            let reply = handle.request(set.clone(), Duration::from_secs(1));
+            tokio::pin!(reply);
+            expect(&mut headphones, &set, 1).await;
+            // queued, but not taken yet
+            assert!(
+                tokio::time::timeout(Duration::from_millis(10), reply.as_mut())
+                    .await
+                    .is_err()
+            );
+            headphones.write_all(&build_ack(1)).await.unwrap();
+            assert!(matches!(reply.await, Ok(None)));
+        };
+        tokio::select! {
+            result = connection => panic!("the connection ended: {result:?}"),
+            () = headphones => {}
+        }
+    }
+
+    #[tokio::test]
+    async fn dead_connection() {
+        let (handle, commands) = ConnectionHandle::new(2);
+        assert!(!handle.is_closed());
+        // the second click on disconnect while it's closing
+        handle.stop().unwrap();
+        handle.stop().unwrap();
+        assert!(commands.stop.is_cancelled());
+
+        // the connection ended, e.g. the headphones went into the case
+        drop(commands);
+        assert!(handle.is_closed());
+        assert!(matches!(
+            handle.send(Command::GetCodec.into()),
+            Err(QueueError::Closed(_))
+        ));
+        assert!(matches!(handle.stop(), Err(RequestError::Disconnected)));
+        assert!(matches!(
+            handle.set_periodic(Command::GetSoundPressure, Some(Duration::from_secs(1))),
+            Err(RequestError::Disconnected)
+        ));
+        assert!(matches!(
+            handle
+                .request(Command::GetCodec, Duration::from_secs(1))
+                .await,
+            Err(RequestError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn disconnects() {
+        let transport = |kind| ConnectionError::Transport(std::io::Error::from(kind));
+        assert!(ConnectionError::RemoteClosed.is_disconnect());
+        assert!(ConnectionError::Unresponsive.is_disconnect());
+        assert!(transport(std::io::ErrorKind::ConnectionReset).is_disconnect());
+        assert!(!transport(std::io::ErrorKind::PermissionDenied).is_disconnect());
+        assert!(!ConnectionError::InitTimeout.is_disconnect());
+    }
+}
@@ -2,13 +2,30 @@ use thiserror::Error;
 
 use crate::{MessageType, checksum};
 
+/// The longest payload the parser accepts. The longest known payloads are the device info strings, at 258 bytes.
+pub const MAX_PAYLOAD_LEN: usize = 1024;
+/// The payload, plus the 7 bytes before it and the 2 after
+const MAX_FRAME_LEN: usize = MAX_PAYLOAD_LEN + 7 + 2;
+/// What [FrameParser::push_bytes] holds before it has to move or allocate anything: a frame with every byte escaped
+const PENDING_CAPACITY: usize = 2 * MAX_FRAME_LEN;
+
 /// A parser which can parse the message format of headphones
 /// and return a Message struct containing the payload.
+///
+/// Either hand it bytes with [FrameParser::parse] until it has consumed them all,
+/// or queue any number of bytes with [FrameParser::push_bytes] and take the frames out with [FrameParser::next_frame].
+///
+/// Frames are unescaped into a fixed buffer which is reused, so [FrameParser::parse] never allocates.
 pub struct FrameParser {
     msg_len: Option<usize>,
-    buf: Vec<u8>,
+    buf: [u8; MAX_FRAME_LEN],
+    /// How much of `buf` is filled
+    len: usize,
     need_escape: bool,
     got_an_error: bool,
+    /// Bytes from [FrameParser::push_bytes]; the ones from `read` on weren't parsed yet
+    pending: Vec<u8>,
+    read: usize,
 }
 
 pub enum FrameParserResult<'a> {
@@ -43,43 +60,98 @@ pub struct Message<'a> {
 pub enum FramerParserError {
     #[error("The given bytes do not start with the MESSAGE_HEADER value.")]
     NoMessageHeader,
+    #[error(
+        "The frame claims a payload of {len} bytes, more than the {MAX_PAYLOAD_LEN} we accept."
+    )]
+    PayloadTooLong { len: usize },
+}
+
+/// Like [FrameParserResult], without borrowing the parser
+enum Progress {
+    Ready,
+    Incomplete,
+    Error(FramerParserError),
 }
 impl FrameParser {
     pub fn new() -> Self {
         Self {
             msg_len: None,
-            buf: Vec::new(),
+            buf: [0; MAX_FRAME_LEN],
+            len: 0,
             need_escape: false,
             got_an_error: false,
+            pending: Vec::with_capacity(PENDING_CAPACITY),
+            read: 0,
         }
     }
 
+    /// Parse bytes up to the end of the first frame in them.
+    /// The bytes after it aren't consumed, and have to be passed again.
     pub fn parse<'a>(&'a mut self, bytes: &[u8]) -> FrameParserResult<'a> {
+        match self.advance(bytes) {
+            (Progress::Ready, consumed) => FrameParserResult::Ready {
+                msg: Self::parse_message(&self.buf[..self.len]),
+                consumed,
+            },
+            (Progress::Incomplete, _) => FrameParserResult::Incomplete {
+                bytes_needed: self.bytes_needed(),
+            },
+            (Progress::Error(err), consumed) => FrameParserResult::Error { err, consumed },
+        }
+    }
+
+    /// Queue bytes for [FrameParser::next_frame]. They may contain any number of frames, including partial ones.
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        // the parsed bytes are only dropped once they take up room, so each byte is moved at most once
+        if self.pending.len() + bytes.len() > self.pending.capacity() {
+            self.pending.drain(..self.read);
+            self.read = 0;
+        }
+        self.pending.extend_from_slice(bytes);
+    }
+
+    /// The next frame in the pushed bytes, or `None` once more bytes are needed.
+    /// After an error, the following frames can still be taken out.
+    pub fn next_frame(&mut self) -> Option<Result<Message<'_>, FramerParserError>> {
+        if self.read == self.pending.len() {
+            return None;
+        }
+        let pending = std::mem::take(&mut self.pending);
+        let (progress, consumed) = self.advance(&pending[self.read..]);
+        self.pending = pending;
+        self.read += consumed;
+        if self.read == self.pending.len() {
+            self.pending.clear();
+            self.read = 0;
+        }
+        match progress {
+            Progress::Ready => Some(Ok(Self::parse_message(&self.buf[..self.len]))),
+            Progress::Incomplete => None,
+            Progress::Error(err) => Some(Err(err)),
+        }
+    }
+
+    /// Parse bytes up to the end of the first frame in them, returning how many were consumed
+    fn advance(&mut self, bytes: &[u8]) -> (Progress, usize) {
         if self.done() {
             self.reset_state();
         }
         for (idx, byte) in bytes.iter().enumerate() {
             if let Err(err) = self.parse_byte(*byte) {
                 self.got_an_error = true;
-                return FrameParserResult::Error {
-                    err,
-                    consumed: idx + 1,
-                };
+                return (Progress::Error(err), idx + 1);
             }
             if self.done() {
-                return FrameParserResult::Ready {
-                    msg: Self::parse_message(&self.buf),
-                    consumed: idx + 1,
-                };
+                return (Progress::Ready, idx + 1);
             }
         }
-        FrameParserResult::Incomplete {
-            bytes_needed: self.bytes_needed(),
-        }
+        (Progress::Incomplete, bytes.len())
     }
 
     fn reset_state(&mut self) {
-        self.buf.clear();
+        self.len = 0;
+        self.msg_len = None;
+        self.need_escape = false;
         self.got_an_error = false;
     }
 
@@ -112,7 +184,7 @@ impl FrameParser {
     fn bytes_needed(&self) -> Option<usize> {
         let msg_len = self.msg_len?;
         // +7 for the 7 bytes before the len, +2 for the 2 bytes after the payload
-        Some(msg_len + 7 + 2 - self.buf.len())
+        Some(msg_len + 7 + 2 - self.len)
     }
     fn parse_byte(&mut self, mut byte: u8) -> std::result::Result<(), FramerParserError> {
         if self.need_escape {
@@ -122,25 +194,22 @@ impl FrameParser {
             self.need_escape = true;
             return Ok(());
         }
-        if self.buf.is_empty() {
+        if self.len == 0 {
             // byte must be Header
             if byte != crate::MESSAGE_HEADER {
                 return Err(FramerParserError::NoMessageHeader);
             }
-            self.buf.push(byte);
-        } else if self.buf.len() == 1 || self.buf.len() == 2 {
-            // we read the header, we now read the message type and seq number
-            self.buf.push(byte);
-        } else if self.buf.len() >= 3 && self.buf.len() <= 6 {
-            // we already read the message type and seq number, now we read the length
-            self.buf.push(byte);
-            if self.buf.len() == 7 {
-                // we read all the length
-                let len = u32::from_be_bytes([self.buf[3], self.buf[4], self.buf[5], self.buf[6]]);
-                self.msg_len = Some(len as usize);
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len == 7 {
+            // we read the header, the message type, the seq number and all the length
+            let len =
+                u32::from_be_bytes([self.buf[3], self.buf[4], self.buf[5], self.buf[6]]) as usize;
+            if len > MAX_PAYLOAD_LEN {
+                return Err(FramerParserError::PayloadTooLong { len });
             }
-        } else {
-            self.buf.push(byte);
+            self.msg_len = Some(len);
         }
         Ok(())
     }
@@ -156,30 +225,32 @@ impl Default for FrameParser {
 mod test {
     use crate::{
         MESSAGE_HEADER, MESSAGE_TRAILER,
-        command::{AncMode, build_command},
+        command::{AncMode, build_command, build_message},
     };
 
     use super::*;
     #[test]
     fn basic_messages() {
         let good_messages = vec![
-            build_command(&crate::command::Command::GetAncStatus, 0),
-            build_command(&crate::command::Command::GetEqualizerSettings, 0x69),
+            build_command(&crate::command::Command::GetAncStatus, 0).unwrap(),
+            build_command(&crate::command::Command::GetEqualizerSettings, 0x69).unwrap(),
             build_command(
                 &crate::command::Command::GetBatteryStatus {
                     battery_type: crate::command::BatteryType::Headphones,
                 },
                 0x22,
-            ),
+            )
+            .unwrap(),
             build_command(
                 &crate::command::Command::AncSet {
                     dragging_ambient_sound_slider: true,
                     mode: AncMode::AmbientSound,
-                    ambient_sound_voice_filtering: false,
+                    ambient_sound_voice_passthrough: false,
                     ambient_sound_level: 15,
                 },
                 0xe,
-            ),
+            )
+            .unwrap(),
         ];
         let mut parser = FrameParser::new();
         for bytes in good_messages {
@@ -189,7 +260,7 @@ mod test {
                     assert_eq!(msg.kind, Ok(MessageType::from_byte(bytes[1]).unwrap()));
                     assert_eq!(msg.seq_num, bytes[2]);
                     assert_eq!(consumed, bytes.len());
-                    assert_eq!(bytes, parser.buf);
+                    assert_eq!(bytes, parser.buf[..parser.len]);
                 }
                 _ => panic!(
                     "bad; shouldn't have panicked! this message is theoritcally fine as far as the 'frame'  is concerned."
@@ -277,4 +348,83 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn concatenated_frames() {
+        let ack = build_command(&crate::command::Command::Ack, 0).unwrap();
+        let get_anc = build_command(&crate::command::Command::GetAncStatus, 1).unwrap();
+        let bytes = [ack.as_slice(), &[0x00], &get_anc, &get_anc[..4]].concat();
+        let mut parser = FrameParser::new();
+        assert!(parser.next_frame().is_none());
+        parser.push_bytes(&bytes);
+        assert_eq!(
+            parser.next_frame().unwrap().unwrap().kind,
+            Ok(MessageType::Ack)
+        );
+        // garbage between frames is an error, but the next frame is still there
+        assert_eq!(
+            parser.next_frame().unwrap().err(),
+            Some(FramerParserError::NoMessageHeader)
+        );
+        assert_eq!(parser.next_frame().unwrap().unwrap().payload, [0x66, 0x17]);
+        assert!(parser.next_frame().is_none());
+        // the rest of the last frame
+        parser.push_bytes(&get_anc[4..]);
+        assert_eq!(parser.next_frame().unwrap().unwrap().seq_num, 1);
+        assert!(parser.next_frame().is_none());
+    }
+
+    #[test]
+    fn many_pushed_frames() {
+        let get_anc = build_command(&crate::command::Command::GetAncStatus, 1).unwrap();
+        let mut parser = FrameParser::new();
+        parser.push_bytes(&get_anc.repeat(1000));
+        let mut frames = 0;
+        while let Some(frame) = parser.next_frame() {
+            assert!(frame.is_ok());
+            frames += 1;
+        }
+        assert_eq!(frames, 1000);
+
+        // a frame at a time, always a frame behind, fits without growing
+        let mut parser = FrameParser::new();
+        parser.push_bytes(&get_anc);
+        for _ in 0..1000 {
+            parser.push_bytes(&get_anc);
+            assert!(parser.next_frame().unwrap().is_ok());
+        }
+        assert!(parser.next_frame().unwrap().is_ok());
+        assert!(parser.next_frame().is_none());
+        assert_eq!(parser.pending.capacity(), PENDING_CAPACITY);
+    }
+
+    #[test]
+    fn length_of_the_next_frame() {
+        let get_anc = build_command(&crate::command::Command::GetAncStatus, 1).unwrap();
+        let mut parser = FrameParser::new();
+        assert!(matches!(
+            parser.parse(&get_anc),
+            FrameParserResult::Ready { .. }
+        ));
+        // the length of the previous frame doesn't apply to the next one
+        assert!(matches!(
+            parser.parse(&get_anc[..3]),
+            FrameParserResult::Incomplete { bytes_needed: None }
+        ));
+
+        // more than fits in the buffer
+        let mut parser = FrameParser::new();
+        assert!(matches!(
+            parser.parse(&[MESSAGE_HEADER, 0x0c, 0, 0xff, 0xff, 0xff, 0xff, 0]),
+            FrameParserResult::Error {
+                err: FramerParserError::PayloadTooLong { len: 0xffffffff },
+                consumed: 7
+            }
+        ));
+        let longest = build_message(MessageType::Command1, 0, &[0; MAX_PAYLOAD_LEN]);
+        assert!(matches!(
+            parser.parse(&longest),
+            FrameParserResult::Ready { .. }
+        ));
+    }
 }
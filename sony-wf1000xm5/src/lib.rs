@@ -1,6 +1,26 @@
 pub mod command;
+#[cfg(feature = "connection")]
+pub mod command_queue;
+pub mod compatibility;
+#[cfg(feature = "connection")]
+pub mod connection;
 pub mod frame_parser;
+pub mod model;
 pub mod payload;
+pub mod sequence;
+pub mod session;
+pub mod snapshot;
+pub mod trace;
+
+/// The version of this crate
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Bumped whenever the wire format produced/understood by this crate changes in a way frontends should know about
+pub const PROTOCOL_REVISION: u32 = 1;
+/// The models this crate was tested against, as reported by [command::Command::GetModelName]
+pub const SUPPORTED_MODELS: &[&str] = &["WF-1000XM5"];
+/// The newest firmware version this crate was tested against. Unset until a test on a known firmware version is
+/// recorded, so no firmware counts as newer than the tested one.
+pub const LAST_TESTED_FIRMWARE_VERSION: Option<&str> = None;
 
 const MESSAGE_HEADER: u8 = 0x3e;
 const MESSAGE_TRAILER: u8 = 0x3c;
@@ -12,6 +32,8 @@ fn checksum(bytes: &[u8]) -> u8 {
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum MessageType {
     Ack = 0x1,
     Command1 = 0xc,
@@ -26,4 +48,13 @@ impl MessageType {
             _ => return None,
         })
     }
+
+    /// Whether a frame of this type must be acked by whoever receives it.
+    /// Both command types are, whatever their payload; an Ack itself never is.
+    pub fn needs_ack(&self) -> bool {
+        match self {
+            Self::Ack => false,
+            Self::Command1 | Self::Command2 => true,
+        }
+    }
 }
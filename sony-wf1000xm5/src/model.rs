@@ -0,0 +1,61 @@
+//! The headphones this crate implements the protocol of.
+//! Only the WF-1000XM5 is implemented. Other Sony headphones (e.g. the WH-1000XM5, WF-1000XM4 or LinkBuds S) may
+//! speak the same protocol, but none of their layouts or features were captured, so they aren't recognized; one
+//! gets its own variant here once a capture shows what it sends.
+
+use crate::command::BatteryType;
+
+/// See [crate::SUPPORTED_MODELS]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Model {
+    #[default]
+    Wf1000xm5,
+}
+
+impl Model {
+    pub const ALL: [Self; 1] = [Self::Wf1000xm5];
+
+    /// The name the headphones report, see [crate::command::Command::GetModelName]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Wf1000xm5 => "WF-1000XM5",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|model| model.name() == name.trim())
+    }
+
+    /// The batteries to ask for with [crate::command::Command::GetBatteryStatus]
+    pub fn battery_types(&self) -> &'static [BatteryType] {
+        match self {
+            Self::Wf1000xm5 => &[BatteryType::Headphones, BatteryType::Case],
+        }
+    }
+}
+
+impl std::fmt::Display for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names() {
+        for model in Model::ALL {
+            assert_eq!(Model::from_name(model.name()), Some(model));
+        }
+        assert_eq!(Model::from_name("WH-1000XM5"), None);
+        assert_eq!(
+            Model::from_name(crate::SUPPORTED_MODELS[0]),
+            Some(Model::default())
+        );
+    }
+}
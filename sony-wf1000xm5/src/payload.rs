@@ -2,10 +2,14 @@ use thiserror::Error;
 
 use crate::{
     MessageType,
-    command::{AncMode, BatteryType, EqualizerPreset},
+    command::{
+        AncMode, BatteryType, Command, EqualizerBands, EqualizerLevelOutOfRange, EqualizerPreset,
+        QuickAccessApp, SpeakToChatTimeout,
+    },
 };
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum PayloadType {
     InitReply,
     BatteryLevel,
@@ -18,9 +22,62 @@ pub enum PayloadType {
     CodecNotify,
     SoundPressureMeasureReply,
     PressureGet,
+    CallVoiceFocus,
+    CallVoiceFocusNotify,
+    SidetoneLevel,
+    SidetoneLevelNotify,
+    SupportedFunctions,
+    DeviceInfo,
+    QuickAccess,
+    QuickAccessNotify,
+    BatteryLowNotify,
+    CommandError,
+    AmbientSoundRange,
+    SpatialAudioStatus,
+    SpeakToChatTimeout,
+    SpeakToChatTimeoutNotify,
 }
 
 impl PayloadType {
+    /// Whether the headphones send this on their own, e.g. when a setting was changed from another device,
+    /// rather than in reply to a command
+    pub fn is_notification(&self) -> bool {
+        matches!(
+            self,
+            Self::BatteryLevelNotify
+                | Self::EqualizerNotify
+                | Self::AncStatusNotify
+                | Self::CodecNotify
+                | Self::CallVoiceFocusNotify
+                | Self::SidetoneLevelNotify
+                | Self::QuickAccessNotify
+                | Self::BatteryLowNotify
+                | Self::SpeakToChatTimeoutNotify
+        )
+    }
+
+    /// Whether the payload type is a guess, like the opcodes of [Command::is_guessed], not confirmed with HCI logs.
+    /// Without the unstable `unstable-guessed-opcodes` feature they aren't parsed, so they arrive as unknown
+    /// payloads.
+    pub fn is_guessed(&self) -> bool {
+        matches!(
+            self,
+            Self::CallVoiceFocus
+                | Self::CallVoiceFocusNotify
+                | Self::SidetoneLevel
+                | Self::SidetoneLevelNotify
+                | Self::SupportedFunctions
+                | Self::QuickAccess
+                | Self::QuickAccessNotify
+                | Self::BatteryLowNotify
+                | Self::AmbientSoundRange
+                | Self::SpatialAudioStatus
+                | Self::SpeakToChatTimeout
+                | Self::SpeakToChatTimeoutNotify
+                | Self::CommandError
+        )
+    }
+
     pub fn from_byte(msg_type: MessageType, byte: u8) -> Option<Self> {
         Some(match msg_type {
             MessageType::Ack => return None,
@@ -34,6 +91,21 @@ impl PayloadType {
                 0x59 => Self::EqualizerNotify,
                 0x67 => Self::AncStatus,
                 0x69 => Self::AncStatusNotify,
+                // not confirmed with hci logs yet
+                0x87 => Self::CallVoiceFocus,
+                0x89 => Self::CallVoiceFocusNotify,
+                0x97 => Self::SidetoneLevel,
+                0x99 => Self::SidetoneLevelNotify,
+                0x07 => Self::SupportedFunctions,
+                0x05 => Self::DeviceInfo,
+                0xa7 => Self::QuickAccess,
+                0xa9 => Self::QuickAccessNotify,
+                0x27 => Self::BatteryLowNotify,
+                0xfe => Self::CommandError,
+                0x6b => Self::AmbientSoundRange,
+                0xb7 => Self::SpatialAudioStatus,
+                0xfb => Self::SpeakToChatTimeout,
+                0xfd => Self::SpeakToChatTimeoutNotify,
                 _ => return None,
             },
             MessageType::Command2 => {
@@ -49,13 +121,147 @@ impl PayloadType {
     }
 }
 
-#[derive(Debug)]
+/// A battery charge, always in 0..=100; use [BatteryPercent::new] to make sure of it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u8", into = "u8")
+)]
+pub struct BatteryPercent(u8);
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("Battery level {level} is out of range (0..=100)")]
+pub struct BatteryPercentOutOfRange {
+    pub level: u8,
+}
+
+impl BatteryPercent {
+    pub const FULL: Self = Self(100);
+
+    pub fn new(percent: u8) -> Result<Self, BatteryPercentOutOfRange> {
+        if percent > 100 {
+            return Err(BatteryPercentOutOfRange { level: percent });
+        }
+        Ok(Self(percent))
+    }
+
+    pub fn get(&self) -> u8 {
+        self.0
+    }
+}
+
+impl TryFrom<u8> for BatteryPercent {
+    type Error = BatteryPercentOutOfRange;
+
+    fn try_from(percent: u8) -> Result<Self, Self::Error> {
+        Self::new(percent)
+    }
+}
+
+impl From<BatteryPercent> for u8 {
+    fn from(percent: BatteryPercent) -> Self {
+        percent.0
+    }
+}
+
+/// e.g. `70%`
+impl std::fmt::Display for BatteryPercent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+/// Only generates valid percentages, like [BatteryPercent::new] allows
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for BatteryPercent {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self(u.int_in_range(0..=100)?))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum BatteryLevel {
-    Case(usize),
-    Headphones { left: usize, right: usize },
+    /// Headphones with one battery, rather than earbuds and a case; none of the implemented [crate::model::Model]s
+    Single(BatteryPercent),
+    Case(BatteryPercent),
+    Headphones {
+        left: BatteryPercent,
+        right: BatteryPercent,
+    },
+}
+
+/// The batteries of earbuds, put together from the replies to both [crate::command::Command::GetBatteryStatus] queries
+/// and the low battery warnings. `None` means we haven't been told yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BatteryStatus {
+    pub left: Option<BatteryPercent>,
+    pub right: Option<BatteryPercent>,
+    pub case: Option<BatteryPercent>,
+}
+
+impl BatteryStatus {
+    /// Take the levels from `payload`, returning whether it had any
+    pub fn update(&mut self, payload: &Payload) -> bool {
+        match payload {
+            Payload::BatteryLevel(BatteryLevel::Headphones { left, right }) => {
+                self.left = Some(*left);
+                self.right = Some(*right);
+            }
+            Payload::BatteryLevel(BatteryLevel::Case(level)) => self.case = Some(*level),
+            Payload::BatteryLow { component, level } => match component {
+                BatteryComponent::Left => self.left = Some(*level),
+                BatteryComponent::Right => self.right = Some(*level),
+                BatteryComponent::Case => self.case = Some(*level),
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    /// Whether both queries were answered
+    pub fn is_complete(&self) -> bool {
+        self.left.is_some() && self.right.is_some() && self.case.is_some()
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+/// A part of the headphones which has its own battery
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum BatteryComponent {
+    Left = 0x1,
+    Right = 0x2,
+    Case = 0xa,
+}
+
+impl BatteryComponent {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Some(match byte {
+            0x1 => Self::Left,
+            0x2 => Self::Right,
+            0xa => Self::Case,
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for BatteryComponent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Left => "left earbud",
+            Self::Right => "right earbud",
+            Self::Case => "case",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Codec {
     Unknown = 0,
     Sbc = 0x1,
@@ -89,18 +295,126 @@ impl Codec {
     }
 }
 
-#[derive(Debug)]
+/// The functions the headphones report supporting, see [Command::GetSupportedFunctions].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct Capabilities {
+    pub noise_cancelling: bool,
+    pub ambient_sound_control: bool,
+    pub equalizer: bool,
+    pub speak_to_chat: bool,
+    pub multipoint: bool,
+    pub head_gestures: bool,
+    pub sound_pressure: bool,
+    pub locator_tone: bool,
+    pub call_settings: bool,
+    pub quick_access: bool,
+    pub spatial_audio: bool,
+}
+
+impl Capabilities {
+    // bit positions in the reply bitmap, not confirmed with hci logs yet
+    const NOISE_CANCELLING_BIT: usize = 0;
+    const AMBIENT_SOUND_CONTROL_BIT: usize = 1;
+    const EQUALIZER_BIT: usize = 2;
+    const SPEAK_TO_CHAT_BIT: usize = 3;
+    const MULTIPOINT_BIT: usize = 4;
+    const HEAD_GESTURES_BIT: usize = 5;
+    const SOUND_PRESSURE_BIT: usize = 6;
+    const LOCATOR_TONE_BIT: usize = 7;
+    const CALL_SETTINGS_BIT: usize = 8;
+    const QUICK_ACCESS_BIT: usize = 9;
+    const SPATIAL_AUDIO_BIT: usize = 10;
+
+    /// Bit `n` of the bitmap is bit `n % 8` of byte `n / 8`. Missing bytes mean unsupported.
+    pub fn from_bitmap(bitmap: &[u8]) -> Self {
+        let bit = |n: usize| {
+            bitmap
+                .get(n / 8)
+                .is_some_and(|byte| byte >> (n % 8) & 1 == 1)
+        };
+        Self {
+            noise_cancelling: bit(Self::NOISE_CANCELLING_BIT),
+            ambient_sound_control: bit(Self::AMBIENT_SOUND_CONTROL_BIT),
+            equalizer: bit(Self::EQUALIZER_BIT),
+            speak_to_chat: bit(Self::SPEAK_TO_CHAT_BIT),
+            multipoint: bit(Self::MULTIPOINT_BIT),
+            head_gestures: bit(Self::HEAD_GESTURES_BIT),
+            sound_pressure: bit(Self::SOUND_PRESSURE_BIT),
+            locator_tone: bit(Self::LOCATOR_TONE_BIT),
+            call_settings: bit(Self::CALL_SETTINGS_BIT),
+            quick_access: bit(Self::QUICK_ACCESS_BIT),
+            spatial_audio: bit(Self::SPATIAL_AUDIO_BIT),
+        }
+    }
+
+    /// The inverse of [Capabilities::from_bitmap]
+    pub fn to_bitmap(&self) -> Vec<u8> {
+        let bits = [
+            (Self::NOISE_CANCELLING_BIT, self.noise_cancelling),
+            (Self::AMBIENT_SOUND_CONTROL_BIT, self.ambient_sound_control),
+            (Self::EQUALIZER_BIT, self.equalizer),
+            (Self::SPEAK_TO_CHAT_BIT, self.speak_to_chat),
+            (Self::MULTIPOINT_BIT, self.multipoint),
+            (Self::HEAD_GESTURES_BIT, self.head_gestures),
+            (Self::SOUND_PRESSURE_BIT, self.sound_pressure),
+            (Self::LOCATOR_TONE_BIT, self.locator_tone),
+            (Self::CALL_SETTINGS_BIT, self.call_settings),
+            (Self::QUICK_ACCESS_BIT, self.quick_access),
+            (Self::SPATIAL_AUDIO_BIT, self.spatial_audio),
+        ];
+        let mut bitmap = vec![0; Self::SPATIAL_AUDIO_BIT / 8 + 1];
+        for (n, _) in bits.iter().filter(|(_, supported)| *supported) {
+            bitmap[n / 8] |= 1 << (n % 8);
+        }
+        bitmap
+    }
+
+    /// Whether sending `command` makes sense for headphones with these capabilities.
+    pub fn supports(&self, command: &Command) -> bool {
+        match command {
+            Command::AncSet { .. } | Command::GetAncStatus => {
+                self.noise_cancelling || self.ambient_sound_control
+            }
+            Command::GetAmbientSoundRange => self.ambient_sound_control,
+            Command::ChangeEqualizerPreset { .. }
+            | Command::ChangeEqualizerSetting { .. }
+            | Command::GetEqualizerSettings => self.equalizer,
+            Command::SoundPressureMeasure { .. } | Command::GetSoundPressure => self.sound_pressure,
+            Command::PlayLocatorTone { .. } | Command::StopLocatorTone => self.locator_tone,
+            Command::GetCallVoiceFocus
+            | Command::SetCallVoiceFocus { .. }
+            | Command::GetSidetoneLevel
+            | Command::SetSidetoneLevel { .. } => self.call_settings,
+            Command::Init
+            | Command::Ack
+            | Command::GetBatteryStatus { .. }
+            | Command::GetCodec
+            | Command::GetSupportedFunctions
+            | Command::GetModelName
+            | Command::GetFirmwareVersion => true,
+            // trying what's unknown is the point of a raw payload
+            Command::Raw { .. } => true,
+            Command::GetQuickAccess | Command::SetQuickAccess { .. } => self.quick_access,
+            Command::GetSpatialAudioStatus => self.spatial_audio,
+            Command::GetSpeakToChatTimeout | Command::SetSpeakToChatTimeout { .. } => {
+                self.speak_to_chat
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
 pub enum Payload {
     InitReply,
     BatteryLevel(BatteryLevel),
     Equalizer {
         preset: EqualizerPreset,
-        clear_bass: i8,
-        band_400: i8,
-        band_1000: i8,
-        band_2500: i8,
-        band_6300: i8,
-        band_16000: i8,
+        bands: EqualizerBands,
     },
     AncStatus {
         mode: AncMode,
@@ -116,6 +430,184 @@ pub enum Payload {
     SoundPressure {
         db: usize,
     },
+    CallVoiceFocus {
+        on: bool,
+    },
+    SidetoneLevel {
+        level: u8,
+    },
+    SupportedFunctions(Capabilities),
+    ModelName(String),
+    FirmwareVersion(String),
+    QuickAccess {
+        double_tap: QuickAccessApp,
+        triple_tap: QuickAccessApp,
+    },
+    /// Sent unprompted when the battery of `component` drops below one of the warning thresholds
+    BatteryLow {
+        component: BatteryComponent,
+        level: BatteryPercent,
+    },
+    /// The headphones rejected the command with the given opcode (the first byte of its payload),
+    /// usually because the firmware doesn't support it. Not confirmed with hci logs yet, so only parsed with the
+    /// `unstable-guessed-opcodes` feature.
+    CommandError {
+        opcode: u8,
+        code: u8,
+    },
+    /// The 360 Reality Audio setup status, see [Command::GetSpatialAudioStatus]
+    SpatialAudioStatus {
+        /// Whether the ears were measured (with the phone's camera) to personalize 360 Reality Audio
+        ear_measured: bool,
+    },
+    /// The valid ambient sound levels, see [Command::GetAmbientSoundRange]
+    AmbientSoundRange {
+        min: u8,
+        max: u8,
+    },
+    /// Also sent unprompted when the timeout is changed from another device
+    SpeakToChatTimeout {
+        timeout: SpeakToChatTimeout,
+    },
+    /// A payload type nobody knows about yet, see [parse_payload_lenient]
+    Unknown {
+        message_type: MessageType,
+        payload_type: u8,
+        /// The whole payload, including the type
+        raw: Vec<u8>,
+    },
+}
+
+impl Payload {
+    /// The message type the payload is sent with, see [Command::message_type]
+    pub fn message_type(&self) -> MessageType {
+        match self {
+            Self::SoundPressureMeasureReply { .. } | Self::SoundPressure { .. } => {
+                MessageType::Command2
+            }
+            Self::Unknown { message_type, .. } => *message_type,
+            _ => MessageType::Command1,
+        }
+    }
+
+    /// The type of the reply carrying this payload; `None` for [Payload::Unknown].
+    ///
+    /// [Payload] and [PayloadType] get new variants as payloads are reverse engineered, so match on them
+    /// with a `_` arm; this helps with handling groups of payloads without listing every variant.
+    pub fn kind(&self) -> Option<PayloadType> {
+        Some(match self {
+            Self::InitReply => PayloadType::InitReply,
+            Self::BatteryLevel(_) => PayloadType::BatteryLevel,
+            Self::Equalizer { .. } => PayloadType::Equalizer,
+            Self::AncStatus { .. } => PayloadType::AncStatus,
+            Self::Codec { .. } => PayloadType::CodecGet,
+            Self::SoundPressureMeasureReply { .. } => PayloadType::SoundPressureMeasureReply,
+            Self::SoundPressure { .. } => PayloadType::PressureGet,
+            Self::CallVoiceFocus { .. } => PayloadType::CallVoiceFocus,
+            Self::SidetoneLevel { .. } => PayloadType::SidetoneLevel,
+            Self::SupportedFunctions(_) => PayloadType::SupportedFunctions,
+            Self::ModelName(_) | Self::FirmwareVersion(_) => PayloadType::DeviceInfo,
+            Self::QuickAccess { .. } => PayloadType::QuickAccess,
+            Self::BatteryLow { .. } => PayloadType::BatteryLowNotify,
+            Self::CommandError { .. } => PayloadType::CommandError,
+            Self::SpatialAudioStatus { .. } => PayloadType::SpatialAudioStatus,
+            Self::AmbientSoundRange { .. } => PayloadType::AmbientSoundRange,
+            Self::SpeakToChatTimeout { .. } => PayloadType::SpeakToChatTimeout,
+            Self::Unknown { .. } => return None,
+        })
+    }
+
+    /// Whether the headphones may send this payload on their own, e.g. when a setting was changed
+    /// from another device, so a frontend showing it has to expect changes it didn't ask for.
+    /// See [PayloadType::is_notification] for whether a received one was such a notification.
+    pub fn is_notify(&self) -> bool {
+        matches!(
+            self,
+            Self::BatteryLevel(_)
+                | Self::Equalizer { .. }
+                | Self::AncStatus { .. }
+                | Self::Codec { .. }
+                | Self::CallVoiceFocus { .. }
+                | Self::SidetoneLevel { .. }
+                | Self::QuickAccess { .. }
+                | Self::BatteryLow { .. }
+                | Self::SpeakToChatTimeout { .. }
+        )
+    }
+
+    /// Encode the payload the way the headphones send it, i.e. the inverse of [parse_payload].
+    ///
+    /// Replies are encoded rather than notifications where both exist.
+    /// Bytes the parser ignores are zero, and numbers are truncated to the byte they are sent as.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::InitReply => vec![0x01],
+            Self::BatteryLevel(BatteryLevel::Single(level)) => {
+                vec![0x23, BatteryType::Single as u8, level.get(), 0]
+            }
+            Self::BatteryLevel(BatteryLevel::Case(level)) => {
+                vec![0x23, BatteryType::Case as u8, level.get(), 0, 0]
+            }
+            Self::BatteryLevel(BatteryLevel::Headphones { left, right }) => {
+                vec![
+                    0x23,
+                    BatteryType::Headphones as u8,
+                    left.get(),
+                    0,
+                    right.get(),
+                    0,
+                ]
+            }
+            Self::Equalizer { preset, bands } => {
+                let mut out = vec![0x57, 0, *preset as u8, 6];
+                out.extend(bands.to_wire());
+                out
+            }
+            Self::AncStatus {
+                mode,
+                ambient_sound_voice_passthrough,
+                ambient_sound_level,
+            } => vec![
+                0x67,
+                0x17,
+                1,
+                (*mode != AncMode::Off) as u8,
+                (*mode == AncMode::AmbientSound) as u8,
+                *ambient_sound_voice_passthrough as u8,
+                *ambient_sound_level,
+            ],
+            Self::Codec { codec } => vec![0x13, 0, *codec as u8],
+            Self::SoundPressureMeasureReply { is_on } => vec![0x59, 0x03, 0x01, !is_on as u8],
+            Self::SoundPressure { db } => vec![0x5b, 0x03, *db as u8, 0x03],
+            Self::CallVoiceFocus { on } => vec![0x87, 0x01, *on as u8],
+            Self::SidetoneLevel { level } => vec![0x97, 0x01, *level],
+            Self::SupportedFunctions(capabilities) => {
+                let mut out = vec![0x07, 0x02];
+                out.extend(capabilities.to_bitmap());
+                out
+            }
+            Self::ModelName(value) => encode_device_info(0x01, value),
+            Self::FirmwareVersion(value) => encode_device_info(0x02, value),
+            Self::QuickAccess {
+                double_tap,
+                triple_tap,
+            } => vec![0xa7, 0x01, *double_tap as u8, *triple_tap as u8],
+            Self::BatteryLow { component, level } => vec![0x27, *component as u8, level.get()],
+            Self::CommandError { opcode, code } => vec![0xfe, *opcode, *code],
+            Self::SpatialAudioStatus { ear_measured } => vec![0xb7, 0x01, *ear_measured as u8],
+            Self::AmbientSoundRange { min, max } => vec![0x6b, 0x17, *min, *max],
+            Self::SpeakToChatTimeout { timeout } => vec![0xfb, 0x0c, *timeout as u8],
+            Self::Unknown { raw, .. } => raw.clone(),
+        }
+    }
+}
+
+/// [0x05, info type, string length, string...]; strings longer than 255 bytes are truncated
+fn encode_device_info(kind: u8, value: &str) -> Vec<u8> {
+    let value = &value.as_bytes()[..value.len().min(u8::MAX as usize)];
+    let mut out = vec![0x05, kind, value.len() as u8];
+    out.extend(value);
+    out
 }
 
 #[derive(Debug, Error)]
@@ -126,12 +618,43 @@ pub enum ParsePayloadError {
     UnknownPayloadType { kind: u8 },
     #[error("Unknown battery type: 0x{battery:x}")]
     UnknownBatteryType { battery: u8 },
+    #[error(transparent)]
+    BatteryPercentOutOfRange(#[from] BatteryPercentOutOfRange),
     #[error("Unknown equalizer preset: 0x{preset:x}")]
     UnknownEqualizerPreset { preset: u8 },
+    #[error(transparent)]
+    EqualizerLevelOutOfRange(#[from] EqualizerLevelOutOfRange),
     #[error("Unknown codec: 0x{codec:x}")]
     UnknownCodec { codec: u8 },
     #[error("Payload is too small for payload of type {payload_type:?}")]
     PayloadTooSmall { payload_type: PayloadType },
+    #[error("Unknown device info type: 0x{kind:x}")]
+    UnknownDeviceInfoType { kind: u8 },
+    #[error("Unknown quick access app: 0x{app:x}")]
+    UnknownQuickAccessApp { app: u8 },
+    #[error("Invalid ambient sound range: {min}..={max}")]
+    InvalidAmbientSoundRange { min: u8, max: u8 },
+    #[error("Unknown battery component: 0x{component:x}")]
+    UnknownBatteryComponent { component: u8 },
+    #[error("Unknown Speak-to-Chat timeout: 0x{timeout:x}")]
+    UnknownSpeakToChatTimeout { timeout: u8 },
+}
+
+/// Like [parse_payload], but a payload type nobody knows about yet is a [Payload::Unknown] rather than an error,
+/// so frontends can capture it for reverse engineering.
+/// Payloads of a known type which don't parse are still errors.
+pub fn parse_payload_lenient(
+    payload: &[u8],
+    message_type: MessageType,
+) -> std::result::Result<Payload, ParsePayloadError> {
+    match parse_payload(payload, message_type) {
+        Err(ParsePayloadError::UnknownPayloadType { kind }) => Ok(Payload::Unknown {
+            message_type,
+            payload_type: kind,
+            raw: payload.to_vec(),
+        }),
+        result => result,
+    }
 }
 
 pub fn parse_payload(
@@ -144,11 +667,15 @@ pub fn parse_payload(
 
     let payload_type = PayloadType::from_byte(message_type, payload[0])
         .ok_or(ParsePayloadError::UnknownPayloadType { kind: payload[0] })?;
+    #[cfg(not(feature = "unstable-guessed-opcodes"))]
+    if payload_type.is_guessed() {
+        return Err(ParsePayloadError::UnknownPayloadType { kind: payload[0] });
+    }
 
     Ok(match payload_type {
         PayloadType::InitReply => Payload::InitReply,
         PayloadType::BatteryLevel | PayloadType::BatteryLevelNotify => {
-            if payload.len() < 5 {
+            if payload.len() < 2 {
                 return Err(ParsePayloadError::PayloadTooSmall { payload_type });
             }
             let battery_type = BatteryType::from_byte(payload[1]).ok_or(
@@ -156,12 +683,24 @@ pub fn parse_payload(
                     battery: payload[1],
                 },
             )?;
+            // [0x23, single, level, charging]; the others are at least 5 bytes
+            let min_len = match battery_type {
+                BatteryType::Single => 4,
+                BatteryType::Headphones | BatteryType::Case => 5,
+            };
+            if payload.len() < min_len {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
             match battery_type {
-                BatteryType::Case => Payload::BatteryLevel(BatteryLevel::Case(payload[2] as usize)),
-
+                BatteryType::Single => {
+                    Payload::BatteryLevel(BatteryLevel::Single(BatteryPercent::new(payload[2])?))
+                }
+                BatteryType::Case => {
+                    Payload::BatteryLevel(BatteryLevel::Case(BatteryPercent::new(payload[2])?))
+                }
                 BatteryType::Headphones => Payload::BatteryLevel(BatteryLevel::Headphones {
-                    left: payload[2] as usize,
-                    right: payload[4] as usize,
+                    left: BatteryPercent::new(payload[2])?,
+                    right: BatteryPercent::new(payload[4])?,
                 }),
             }
         }
@@ -170,21 +709,10 @@ pub fn parse_payload(
             if payload.len() < 10 {
                 return Err(ParsePayloadError::PayloadTooSmall { payload_type });
             }
-            let clear_bass = payload[4] as i8 - 10;
-            let band_400 = payload[5] as i8 - 10;
-            let band_1000 = payload[6] as i8 - 10;
-            let band_2500 = payload[7] as i8 - 10;
-            let band_6300 = payload[8] as i8 - 10;
-            let band_16000 = payload[9] as i8 - 10;
             Payload::Equalizer {
                 preset: EqualizerPreset::from_byte(payload[2])
                     .ok_or(ParsePayloadError::UnknownEqualizerPreset { preset: payload[2] })?,
-                clear_bass,
-                band_400,
-                band_1000,
-                band_2500,
-                band_6300,
-                band_16000,
+                bands: EqualizerBands::from_wire(payload[4..10].try_into().unwrap())?,
             }
         }
 
@@ -244,5 +772,406 @@ pub fn parse_payload(
                 is_on: payload[3] == 0,
             }
         }
+
+        PayloadType::CallVoiceFocus | PayloadType::CallVoiceFocusNotify => {
+            if payload.len() < 3 {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
+            Payload::CallVoiceFocus {
+                on: payload[2] == 1,
+            }
+        }
+
+        PayloadType::SidetoneLevel | PayloadType::SidetoneLevelNotify => {
+            if payload.len() < 3 {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
+            Payload::SidetoneLevel { level: payload[2] }
+        }
+
+        PayloadType::SupportedFunctions => {
+            if payload.len() < 2 {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
+            Payload::SupportedFunctions(Capabilities::from_bitmap(&payload[2..]))
+        }
+
+        // [0x05, info type, string length, string...]
+        PayloadType::DeviceInfo => {
+            if payload.len() < 3 || payload.len() < 3 + payload[2] as usize {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
+            let value = String::from_utf8_lossy(&payload[3..3 + payload[2] as usize]).into_owned();
+            match payload[1] {
+                0x01 => Payload::ModelName(value),
+                0x02 => Payload::FirmwareVersion(value),
+                kind => return Err(ParsePayloadError::UnknownDeviceInfoType { kind }),
+            }
+        }
+
+        PayloadType::QuickAccess | PayloadType::QuickAccessNotify => {
+            if payload.len() < 4 {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
+            let app = |byte: u8| {
+                QuickAccessApp::from_byte(byte)
+                    .ok_or(ParsePayloadError::UnknownQuickAccessApp { app: byte })
+            };
+            Payload::QuickAccess {
+                double_tap: app(payload[2])?,
+                triple_tap: app(payload[3])?,
+            }
+        }
+
+        // [0xb7, 0x01, ear measured]
+        PayloadType::SpatialAudioStatus => {
+            if payload.len() < 3 {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
+            Payload::SpatialAudioStatus {
+                ear_measured: payload[2] == 1,
+            }
+        }
+
+        // [0x6b, 0x17, min, max]
+        PayloadType::AmbientSoundRange => {
+            if payload.len() < 4 {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
+            let (min, max) = (payload[2], payload[3]);
+            if min > max || max as usize > Command::MAX_AMBIENT_SOUND_LEVEL {
+                return Err(ParsePayloadError::InvalidAmbientSoundRange { min, max });
+            }
+            Payload::AmbientSoundRange { min, max }
+        }
+
+        // [0xfb/0xfd, 0x0c, timeout]
+        PayloadType::SpeakToChatTimeout | PayloadType::SpeakToChatTimeoutNotify => {
+            if payload.len() < 3 {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
+            Payload::SpeakToChatTimeout {
+                timeout: SpeakToChatTimeout::from_byte(payload[2]).ok_or(
+                    ParsePayloadError::UnknownSpeakToChatTimeout {
+                        timeout: payload[2],
+                    },
+                )?,
+            }
+        }
+
+        // [0xfe, opcode of the rejected command, error code]
+        PayloadType::CommandError => {
+            if payload.len() < 3 {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
+            Payload::CommandError {
+                opcode: payload[1],
+                code: payload[2],
+            }
+        }
+
+        // [0x27, component, level]
+        PayloadType::BatteryLowNotify => {
+            if payload.len() < 3 {
+                return Err(ParsePayloadError::PayloadTooSmall { payload_type });
+            }
+            Payload::BatteryLow {
+                component: BatteryComponent::from_byte(payload[1]).ok_or(
+                    ParsePayloadError::UnknownBatteryComponent {
+                        component: payload[1],
+                    },
+                )?,
+                level: BatteryPercent::new(payload[2])?,
+            }
+        }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "unstable-guessed-opcodes")]
+    #[test]
+    fn supported_functions() {
+        // noise cancelling, equalizer and call settings
+        let payload = [0x07, 0x02, 0b0000_0101, 0b0000_0001];
+        let Ok(Payload::SupportedFunctions(capabilities)) =
+            parse_payload(&payload, MessageType::Command1)
+        else {
+            panic!("expected SupportedFunctions");
+        };
+        assert!(capabilities.noise_cancelling);
+        assert!(capabilities.equalizer);
+        assert!(capabilities.call_settings);
+        assert!(!capabilities.locator_tone);
+        assert!(!capabilities.supports(&Command::StopLocatorTone));
+        assert!(capabilities.supports(&Command::GetEqualizerSettings));
+
+        // a short bitmap just means the rest isn't supported
+        let payload = [0x07, 0x02];
+        let Ok(Payload::SupportedFunctions(capabilities)) =
+            parse_payload(&payload, MessageType::Command1)
+        else {
+            panic!("expected SupportedFunctions");
+        };
+        assert_eq!(capabilities, Capabilities::default());
+    }
+
+    #[test]
+    fn battery_status() {
+        assert!(matches!(
+            parse_payload(&[0x23, 0xa, 101, 0, 0], MessageType::Command1),
+            Err(ParsePayloadError::BatteryPercentOutOfRange(
+                BatteryPercentOutOfRange { level: 101 }
+            ))
+        ));
+        let mut status = BatteryStatus::default();
+        for bytes in [[0x23, 0x1, 70, 0, 80, 0].as_slice(), &[0x23, 0xa, 60, 0, 0]] {
+            assert!(!status.is_complete());
+            assert!(status.update(&parse_payload(bytes, MessageType::Command1).unwrap()));
+        }
+        assert!(status.is_complete());
+        assert_eq!(status.case.unwrap().to_string(), "60%");
+        // over-ear headphones have nothing to do with it
+        let single = Payload::BatteryLevel(BatteryLevel::Single(BatteryPercent::FULL));
+        assert!(!status.update(&single));
+    }
+
+    #[cfg(feature = "unstable-guessed-opcodes")]
+    #[test]
+    fn battery_low() {
+        let Ok(Payload::BatteryLow { component, level }) =
+            parse_payload(&[0x27, 0xa, 10], MessageType::Command1)
+        else {
+            panic!("expected BatteryLow");
+        };
+        assert_eq!(component, BatteryComponent::Case);
+        assert_eq!(level.get(), 10);
+        assert!(matches!(
+            parse_payload(&[0x27, 0x5, 10], MessageType::Command1),
+            Err(ParsePayloadError::UnknownBatteryComponent { component: 0x5 })
+        ));
+        assert!(matches!(
+            parse_payload(&[0x27, 0x1], MessageType::Command1),
+            Err(ParsePayloadError::PayloadTooSmall { .. })
+        ));
+    }
+
+    #[test]
+    fn equalizer() {
+        let payload = [0x57, 0x00, 0xa1, 0x06, 0, 10, 13, 20, 5, 10];
+        let Ok(Payload::Equalizer { preset, bands }) =
+            parse_payload(&payload, MessageType::Command1)
+        else {
+            panic!("expected Equalizer");
+        };
+        assert_eq!(preset, EqualizerPreset::Custom1);
+        assert_eq!(bands, EqualizerBands::new(-10, 0, 3, 10, -5, 0).unwrap());
+
+        let payload = [0x57, 0x00, 0xa1, 0x06, 0, 10, 13, 21, 5, 10];
+        assert!(matches!(
+            parse_payload(&payload, MessageType::Command1),
+            Err(ParsePayloadError::EqualizerLevelOutOfRange(
+                EqualizerLevelOutOfRange { level: 11 }
+            ))
+        ));
+    }
+
+    #[cfg(feature = "unstable-guessed-opcodes")]
+    #[test]
+    fn command_error() {
+        assert!(matches!(
+            parse_payload(&[0xfe, 0x86, 0x01], MessageType::Command1),
+            Ok(Payload::CommandError {
+                opcode: 0x86,
+                code: 0x01
+            })
+        ));
+    }
+
+    #[cfg(feature = "unstable-guessed-opcodes")]
+    #[test]
+    fn ambient_sound_range() {
+        assert!(matches!(
+            parse_payload(&[0x6b, 0x17, 0, 22], MessageType::Command1),
+            Ok(Payload::AmbientSoundRange { min: 0, max: 22 })
+        ));
+        assert!(matches!(
+            parse_payload(&[0x6b, 0x17, 0, 23], MessageType::Command1),
+            Err(ParsePayloadError::InvalidAmbientSoundRange { min: 0, max: 23 })
+        ));
+    }
+
+    #[cfg(feature = "unstable-guessed-opcodes")]
+    #[test]
+    fn spatial_audio_status() {
+        assert!(matches!(
+            parse_payload(&[0xb7, 0x01, 0x01], MessageType::Command1),
+            Ok(Payload::SpatialAudioStatus { ear_measured: true })
+        ));
+        assert!(matches!(
+            parse_payload(&[0xb7, 0x01], MessageType::Command1),
+            Err(ParsePayloadError::PayloadTooSmall { .. })
+        ));
+    }
+
+    #[cfg(feature = "unstable-guessed-opcodes")]
+    #[test]
+    fn speak_to_chat_timeout() {
+        // changed from the phone
+        assert!(matches!(
+            parse_payload(&[0xfd, 0x0c, 0x02], MessageType::Command1),
+            Ok(Payload::SpeakToChatTimeout {
+                timeout: SpeakToChatTimeout::Long
+            })
+        ));
+        assert!(matches!(
+            parse_payload(&[0xfb, 0x0c, 0x03], MessageType::Command1),
+            Ok(Payload::SpeakToChatTimeout {
+                timeout: SpeakToChatTimeout::Off
+            })
+        ));
+        assert!(matches!(
+            parse_payload(&[0xfb, 0x0c, 0x04], MessageType::Command1),
+            Err(ParsePayloadError::UnknownSpeakToChatTimeout { timeout: 4 })
+        ));
+    }
+
+    #[test]
+    fn round_trip() {
+        let payloads = [
+            Payload::InitReply,
+            Payload::BatteryLevel(BatteryLevel::Single(BatteryPercent::new(90).unwrap())),
+            Payload::BatteryLevel(BatteryLevel::Case(BatteryPercent::new(80).unwrap())),
+            Payload::BatteryLevel(BatteryLevel::Headphones {
+                left: BatteryPercent::new(70).unwrap(),
+                right: BatteryPercent::new(60).unwrap(),
+            }),
+            Payload::Equalizer {
+                preset: EqualizerPreset::Custom1,
+                bands: EqualizerBands::new(-10, 0, 3, 10, -5, 0).unwrap(),
+            },
+            Payload::AncStatus {
+                mode: AncMode::AmbientSound,
+                ambient_sound_voice_passthrough: true,
+                ambient_sound_level: 17,
+            },
+            Payload::AncStatus {
+                mode: AncMode::ActiveNoiseCanceling,
+                ambient_sound_voice_passthrough: false,
+                ambient_sound_level: 3,
+            },
+            Payload::Codec { codec: Codec::Ldac },
+            Payload::SoundPressureMeasureReply { is_on: false },
+            Payload::SoundPressure { db: 66 },
+            Payload::ModelName("WF-1000XM5".to_string()),
+            Payload::FirmwareVersion("2.0.1".to_string()),
+        ];
+        #[cfg(feature = "unstable-guessed-opcodes")]
+        let payloads = payloads.into_iter().chain(guessed_payloads());
+        for payload in payloads {
+            let bytes = payload.to_bytes();
+            assert_eq!(
+                parse_payload(&bytes, payload.message_type()).unwrap(),
+                payload,
+                "{bytes:x?}"
+            );
+            assert_eq!(
+                payload.kind(),
+                PayloadType::from_byte(payload.message_type(), bytes[0])
+            );
+        }
+    }
+
+    /// One of every payload whose type is a guess, see [PayloadType::is_guessed]
+    fn guessed_payloads() -> [Payload; 9] {
+        [
+            Payload::CallVoiceFocus { on: true },
+            Payload::SidetoneLevel { level: 7 },
+            Payload::SupportedFunctions(Capabilities {
+                equalizer: true,
+                spatial_audio: true,
+                ..Default::default()
+            }),
+            Payload::QuickAccess {
+                double_tap: QuickAccessApp::Endel,
+                triple_tap: QuickAccessApp::None,
+            },
+            Payload::BatteryLow {
+                component: BatteryComponent::Right,
+                level: BatteryPercent::new(10).unwrap(),
+            },
+            Payload::SpatialAudioStatus { ear_measured: true },
+            Payload::CommandError {
+                opcode: 0x86,
+                code: 1,
+            },
+            Payload::AmbientSoundRange { min: 1, max: 20 },
+            Payload::SpeakToChatTimeout {
+                timeout: SpeakToChatTimeout::Long,
+            },
+        ]
+    }
+
+    #[cfg(not(feature = "unstable-guessed-opcodes"))]
+    #[test]
+    fn guessed_unknown() {
+        for payload in guessed_payloads() {
+            let bytes = payload.to_bytes();
+            assert!(payload.kind().unwrap().is_guessed());
+            assert_eq!(
+                parse_payload_lenient(&bytes, payload.message_type()).unwrap(),
+                Payload::Unknown {
+                    message_type: payload.message_type(),
+                    payload_type: bytes[0],
+                    raw: bytes,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_payload() {
+        let bytes = [0x70, 0x01, 0x02];
+        assert!(matches!(
+            parse_payload(&bytes, MessageType::Command2),
+            Err(ParsePayloadError::UnknownPayloadType { kind: 0x70 })
+        ));
+        let payload = parse_payload_lenient(&bytes, MessageType::Command2).unwrap();
+        assert_eq!(
+            payload,
+            Payload::Unknown {
+                message_type: MessageType::Command2,
+                payload_type: 0x70,
+                raw: bytes.to_vec(),
+            }
+        );
+        assert_eq!(payload.message_type(), MessageType::Command2);
+        assert_eq!(payload.to_bytes(), bytes);
+        assert_eq!(payload.kind(), None);
+        assert!(!payload.is_notify());
+        // a known type which doesn't parse is still an error
+        assert!(matches!(
+            parse_payload_lenient(&[0x13], MessageType::Command1),
+            Err(ParsePayloadError::PayloadTooSmall { .. })
+        ));
+    }
+
+    #[test]
+    fn encode_hci_logs() {
+        // from hci logs, see parse_payload
+        assert_eq!(
+            Payload::SoundPressure { db: 0x42 }.to_bytes(),
+            [0x5b, 0x03, 0x42, 0x03]
+        );
+        assert_eq!(
+            Payload::SoundPressureMeasureReply { is_on: true }.to_bytes(),
+            [0x59, 0x03, 0x01, 0x00]
+        );
+        assert_eq!(
+            Payload::SoundPressureMeasureReply { is_on: false }.to_bytes(),
+            [0x59, 0x03, 0x01, 0x01]
+        );
+    }
+}
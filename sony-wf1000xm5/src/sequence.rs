@@ -0,0 +1,83 @@
+//! The sequence number rules, so every integration gets them the same way.
+//!
+//! Sequence numbers alternate between 0 and 1:
+//! - the Ack of a frame has the other number than the frame,
+//! - the Ack tells the sender which number to use for its next command, and a command which isn't acked keeps its number.
+
+/// The sequence numbers of the commands we send. [crate::session::Session] keeps one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SequenceTracker {
+    next: u8,
+}
+
+impl SequenceTracker {
+    /// The first command goes out with 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of the next command, and of retransmissions of the last one until it's acked
+    pub fn next_command(&self) -> u8 {
+        self.next
+    }
+
+    /// Our command was acked with `ack_seq_num`; the next command uses it
+    pub fn acked(&mut self, ack_seq_num: u8) {
+        self.next = ack_seq_num;
+    }
+
+    /// The number of the Ack for a frame with `seq_num`
+    pub fn ack_for(seq_num: u8) -> u8 {
+        1u8.wrapping_sub(seq_num)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame_parser::{FrameParser, FrameParserResult};
+
+    fn seq_num(frame: &[u8]) -> u8 {
+        let mut parser = FrameParser::new();
+        let FrameParserResult::Ready { msg, .. } = parser.parse(frame) else {
+            panic!("expected a whole frame");
+        };
+        msg.seq_num
+    }
+
+    #[test]
+    fn our_commands() {
+        // SoundPressureMeasure and the GetSoundPressure after it, from hci logs
+        let measure = [
+            0x3e, 0xe, 0x0, 0x0, 0x0, 0x0, 0x4, 0x58, 0x3, 0x1, 0x0, 0x6e, 0x3c,
+        ];
+        let ack = [0x3e, 0x1, 0x1, 0x0, 0x0, 0x0, 0x0, 0x2, 0x3c];
+        let get = [0x3e, 0xe, 0x1, 0x0, 0x0, 0x0, 0x2, 0x5a, 0x3, 0x6e, 0x3c];
+
+        let mut tracker = SequenceTracker::new();
+        assert_eq!(tracker.next_command(), seq_num(&measure));
+        assert_eq!(SequenceTracker::ack_for(seq_num(&measure)), seq_num(&ack));
+        tracker.acked(seq_num(&ack));
+        assert_eq!(tracker.next_command(), seq_num(&get));
+        // not acked yet, so a retransmission keeps the number
+        assert_eq!(tracker.next_command(), seq_num(&get));
+    }
+
+    #[test]
+    fn their_frames() {
+        // from hci logs: the headphones' measurement reply and a reading, which are acked with the other number
+        let measure_reply = [
+            0x3e, 0xe, 0x0, 0x0, 0x0, 0x0, 0x4, 0x59, 0x3, 0x1, 0x0, 0x6f, 0x3c,
+        ];
+        let reading = [
+            0x3e, 0xe, 0x1, 0x0, 0x0, 0x0, 0x4, 0x5b, 0x3, 0x42, 0x3, 0xb6, 0x3c,
+        ];
+        let ack_0 = [0x3e, 0x1, 0x0, 0x0, 0x0, 0x0, 0x0, 0x1, 0x3c];
+        let ack_1 = [0x3e, 0x1, 0x1, 0x0, 0x0, 0x0, 0x0, 0x2, 0x3c];
+        assert_eq!(
+            SequenceTracker::ack_for(seq_num(&measure_reply)),
+            seq_num(&ack_1)
+        );
+        assert_eq!(SequenceTracker::ack_for(seq_num(&reading)), seq_num(&ack_0));
+    }
+}
@@ -0,0 +1,897 @@
+use std::{collections::VecDeque, time::Duration};
+
+/// std's, except in browsers, where it doesn't work
+pub use web_time::Instant;
+
+use crate::{
+    MessageType,
+    command::{Command, CommandError, build_ack, build_message},
+    frame_parser::{FrameParser, FramerParserError, InvalidChecksum},
+    payload::{ParsePayloadError, Payload, PayloadType, parse_payload_lenient},
+    sequence::SequenceTracker,
+    trace::{Direction, FrameTracer, TracedFrame},
+};
+
+#[derive(Debug)]
+pub enum SessionEvent {
+    /// The headphones replied to a command.
+    /// Payload types nobody knows about yet come as [Payload::Unknown], since they might be a reply.
+    Payload(Payload),
+    /// The headphones sent a payload on their own, e.g. because a setting was changed from another device.
+    /// Setting something often leads to a notification too.
+    Notification(Payload),
+    /// The headphones sent a payload of a known type which we couldn't parse. It was still acked.
+    InvalidPayload {
+        error: ParsePayloadError,
+        message_type: MessageType,
+        /// The raw payload, for reporting
+        payload: Vec<u8>,
+    },
+    /// A frame with a message type we don't know about. It wasn't acked, since we don't know if it should be.
+    UnknownMessageType {
+        message_type: u8,
+        seq_num: u8,
+        /// The raw payload, for reporting
+        payload: Vec<u8>,
+    },
+    /// A frame with a bad checksum. It was ignored.
+    InvalidChecksum(InvalidChecksum),
+    /// The headphones acked the command, so they took it. The ones which read something are answered later.
+    Acked(Command),
+    /// The headphones never acked the command, even after it was retransmitted. It was dropped.
+    AckTimeout(Command),
+    /// With keep-alive on, a command was never acked, so the headphones are gone (e.g. they were put in the case).
+    /// Nothing is sent anymore; the connection should be closed.
+    Disconnected,
+}
+
+/// How long to wait for an Ack before calling [Session::handle_ack_timeout]
+pub const ACK_TIMEOUT: Duration = Duration::from_millis(1500);
+/// How often a command is retransmitted before giving up on it, unless set with [Session::set_max_retries]
+pub const DEFAULT_MAX_RETRIES: u32 = 2;
+/// How long nothing may be received before calling [Session::handle_idle_timeout]
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The state machine of a connection to the headphones, without any IO.
+///
+/// Feed it the bytes you read with [Session::feed], write whatever [Session::poll_transmit] returns,
+/// and handle whatever [Session::poll_event] returns.
+///
+/// Communication must be done sequentially, so after a command we must wait for an Ack before sending the next one;
+/// the session queues commands until then.
+///
+/// The session has no clock: whoever does the IO (re)starts a timer whenever [Session::poll_ack_timer] says so,
+/// and calls [Session::handle_ack_timeout] if it runs out.
+/// With keep-alive on (see [Session::set_keep_alive]), it also restarts a timer whenever it reads bytes,
+/// and calls [Session::handle_idle_timeout] if that runs out. With periodic commands (see [Session::set_periodic]),
+/// it restarts a timer until [Session::periodic_deadline] whenever [Session::poll_periodic_timer] says so,
+/// and calls [Session::handle_periodic_timeout] when it's reached. Those take the current time, as an [Instant].
+pub struct Session {
+    frame_parser: FrameParser,
+    sequence: SequenceTracker,
+    waiting_for_ack: bool,
+    last_command: Option<Vec<u8>>,
+    /// The command waiting for an Ack, with its payload
+    in_flight: Option<(Command, Vec<u8>)>,
+    retries: u32,
+    max_retries: u32,
+    restart_ack_timer: bool,
+    /// With their payloads, which were checked in [Session::send]
+    pending_commands: VecDeque<(Command, Vec<u8>)>,
+    transmit: VecDeque<Vec<u8>>,
+    events: VecDeque<SessionEvent>,
+    tracer: Option<Box<dyn FrameTracer + Send>>,
+    keep_alive: bool,
+    disconnected: bool,
+    periodic: Vec<Periodic>,
+    restart_periodic_timer: bool,
+}
+
+/// A command which is sent every `interval`, see [Session::set_periodic]
+struct Periodic {
+    command: Command,
+    interval: Duration,
+    /// When it was last queued
+    sent_at: Instant,
+}
+
+impl Periodic {
+    fn due_at(&self) -> Instant {
+        self.sent_at + self.interval
+    }
+}
+
+impl Session {
+    /// Create a new session. The Init command is queued for transmission right away.
+    pub fn new() -> Self {
+        let mut session = Self {
+            frame_parser: FrameParser::new(),
+            sequence: SequenceTracker::new(),
+            waiting_for_ack: false,
+            last_command: None,
+            in_flight: None,
+            retries: 0,
+            max_retries: DEFAULT_MAX_RETRIES,
+            restart_ack_timer: false,
+            pending_commands: VecDeque::new(),
+            transmit: VecDeque::new(),
+            events: VecDeque::new(),
+            tracer: None,
+            keep_alive: false,
+            disconnected: false,
+            periodic: Vec::new(),
+            restart_periodic_timer: false,
+        };
+        session
+            .send(Command::Init)
+            .expect("Init is a valid command");
+        session
+    }
+
+    /// Queue a command. It is sent once every command before it has been acked.
+    ///
+    /// A queued command which changes the same setting is replaced by `command` (see [Command::is_superseded_by]),
+    /// so dragging a slider doesn't pile up commands. Commands which change something are sent before queued reads,
+    /// so polling doesn't delay what the user does.
+    ///
+    /// A command which can't be sent (e.g. with a level out of range) is rejected right away.
+    pub fn send(&mut self, command: Command) -> Result<(), CommandError> {
+        let payload = command.try_to_bytes()?;
+        if let Some(pending) = self
+            .pending_commands
+            .iter_mut()
+            .find(|(pending, _)| pending.is_superseded_by(&command))
+        {
+            *pending = (command, payload);
+        } else if command.is_write() {
+            let first_read = self
+                .pending_commands
+                .iter()
+                .position(|(pending, _)| !pending.is_write())
+                .unwrap_or(self.pending_commands.len());
+            self.pending_commands.insert(first_read, (command, payload));
+        } else {
+            self.pending_commands.push_back((command, payload));
+        }
+        self.send_next_command();
+        Ok(())
+    }
+
+    /// Whether a command was sent and is still waiting for an Ack
+    pub fn waiting_for_ack(&self) -> bool {
+        self.waiting_for_ack
+    }
+
+    /// Whether every queued command was sent and acked, so the connection can be closed without leaving the
+    /// headphones halfway through a command
+    pub fn is_flushed(&self) -> bool {
+        !self.waiting_for_ack && self.pending_commands.is_empty() && self.transmit.is_empty()
+    }
+
+    /// Send the last command again, e.g. if the headphones didn't answer in time.
+    pub fn retransmit(&mut self) {
+        if let Some(command) = self.last_command.clone() {
+            self.transmit.push_back(command);
+            self.restart_ack_timer = true;
+            if let (Some(tracer), Some((command, payload))) =
+                (self.tracer.as_mut(), self.in_flight.as_ref())
+            {
+                tracer.trace(&TracedFrame {
+                    direction: Direction::Sent,
+                    message_type: Ok(command.message_type()),
+                    seq_num: self.sequence.next_command(),
+                    payload,
+                    command: Some(command),
+                    retransmission: true,
+                    checksum_ok: true,
+                });
+            }
+        }
+    }
+
+    /// Have `tracer` see every frame from now on, e.g. to log them
+    pub fn set_tracer(&mut self, tracer: impl FrameTracer + Send + 'static) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    /// How often a command is retransmitted on [Session::handle_ack_timeout] before it is dropped
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.max_retries = max_retries;
+    }
+
+    /// Notice when the headphones stop answering: quiet headphones are asked something every [KEEP_ALIVE_INTERVAL],
+    /// and a command which is never acked ends the session with [SessionEvent::Disconnected]. Off by default.
+    pub fn set_keep_alive(&mut self, on: bool) {
+        self.keep_alive = on;
+    }
+
+    /// Whether the session ended with [SessionEvent::Disconnected]
+    pub fn is_disconnected(&self) -> bool {
+        self.disconnected
+    }
+
+    /// Nothing was received for [KEEP_ALIVE_INTERVAL]. With keep-alive on, the codec is asked for
+    /// (its reply comes as a [SessionEvent::Payload] as usual), so the ack times out if the headphones are gone.
+    pub fn handle_idle_timeout(&mut self) {
+        if !self.keep_alive
+            || self.disconnected
+            || self.waiting_for_ack
+            || !self.pending_commands.is_empty()
+        {
+            return;
+        }
+        self.send(Command::GetCodec)
+            .expect("GetCodec is a valid command");
+    }
+
+    /// Send `command` right away and then every `interval`, e.g. to read the sound pressure while it's measured,
+    /// or stop sending it with `None`. Setting the interval a command already has changes nothing; a new interval
+    /// counts from when the command was last sent, and the other periodic commands keep their schedule.
+    ///
+    /// A periodic command isn't queued again while it still waits to be sent, so a slow link doesn't pile them up.
+    /// Its replies come as [SessionEvent::Payload] as usual.
+    pub fn set_periodic(
+        &mut self,
+        command: Command,
+        interval: Option<Duration>,
+        now: Instant,
+    ) -> Result<(), CommandError> {
+        let index = self
+            .periodic
+            .iter()
+            .position(|periodic| periodic.command == command);
+        match (index, interval) {
+            (Some(index), Some(interval)) if self.periodic[index].interval == interval => {
+                return Ok(());
+            }
+            (Some(index), Some(interval)) => {
+                self.periodic[index].interval = interval;
+            }
+            (Some(index), None) => {
+                self.periodic.remove(index);
+            }
+            (None, Some(interval)) => {
+                self.send_periodic(command.clone())?;
+                self.periodic.push(Periodic {
+                    command,
+                    interval,
+                    sent_at: now,
+                });
+            }
+            (None, None) => return Ok(()),
+        }
+        self.restart_periodic_timer = true;
+        Ok(())
+    }
+
+    /// The commands sent periodically, see [Session::set_periodic]
+    pub fn periodic_commands(&self) -> impl Iterator<Item = &Command> {
+        self.periodic.iter().map(|periodic| &periodic.command)
+    }
+
+    /// Whether the periodic commands changed or were sent since the last call, so the periodic timer should
+    /// restart until [Session::periodic_deadline], or stop if that's `None`
+    pub fn poll_periodic_timer(&mut self) -> bool {
+        std::mem::take(&mut self.restart_periodic_timer)
+    }
+
+    /// When the next periodic command is due, if there's any
+    pub fn periodic_deadline(&self) -> Option<Instant> {
+        if self.disconnected {
+            return None;
+        }
+        self.periodic.iter().map(Periodic::due_at).min()
+    }
+
+    /// The periodic timer reached [Session::periodic_deadline]: the commands which are due are queued
+    pub fn handle_periodic_timeout(&mut self, now: Instant) {
+        let mut due = Vec::new();
+        for periodic in &mut self.periodic {
+            if periodic.due_at() <= now {
+                periodic.sent_at = now;
+                due.push(periodic.command.clone());
+            }
+        }
+        for command in due {
+            // it was checked when it was set
+            let _ = self.send_periodic(command);
+        }
+        self.restart_periodic_timer = true;
+    }
+
+    /// Queue a periodic `command`, unless it still waits to be sent from last time
+    fn send_periodic(&mut self, command: Command) -> Result<(), CommandError> {
+        if self.disconnected
+            || self
+                .pending_commands
+                .iter()
+                .any(|(pending, _)| *pending == command)
+        {
+            // still checked, so a bad command isn't accepted while nothing is sent
+            return command.try_to_bytes().map(|_| ());
+        }
+        self.send(command)
+    }
+
+    /// Whether a command was (re)sent since the last call, so the ack timer should (re)start
+    pub fn poll_ack_timer(&mut self) -> bool {
+        std::mem::take(&mut self.restart_ack_timer) && self.waiting_for_ack
+    }
+
+    /// No Ack came within [ACK_TIMEOUT] of the timer (re)starting.
+    /// The command is retransmitted, or dropped with [SessionEvent::AckTimeout] once it ran out of retries.
+    pub fn handle_ack_timeout(&mut self) {
+        if !self.waiting_for_ack {
+            return;
+        }
+        if self.retries < self.max_retries {
+            self.retries += 1;
+            self.retransmit();
+            return;
+        }
+        // the headphones never acked it, so the next command keeps its sequence number
+        self.waiting_for_ack = false;
+        if let Some((command, _)) = self.in_flight.take() {
+            self.events.push_back(SessionEvent::AckTimeout(command));
+        }
+        if self.keep_alive {
+            self.disconnected = true;
+            self.pending_commands.clear();
+            self.events.push_back(SessionEvent::Disconnected);
+            return;
+        }
+        self.send_next_command();
+    }
+
+    fn send_next_command(&mut self) {
+        if self.waiting_for_ack || self.disconnected {
+            return;
+        }
+        if let Some((command, payload)) = self.pending_commands.pop_front() {
+            let bytes = build_message(
+                command.message_type(),
+                self.sequence.next_command(),
+                &payload,
+            );
+            self.last_command = Some(bytes.clone());
+            self.transmit.push_back(bytes);
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.trace(&TracedFrame {
+                    direction: Direction::Sent,
+                    message_type: Ok(command.message_type()),
+                    seq_num: self.sequence.next_command(),
+                    payload: &payload,
+                    command: Some(&command),
+                    retransmission: false,
+                    checksum_ok: true,
+                });
+            }
+            self.waiting_for_ack = true;
+            self.in_flight = Some((command, payload));
+            self.retries = 0;
+            self.restart_ack_timer = true;
+        }
+    }
+
+    /// Feed bytes read from the headphones.
+    ///
+    /// A frame parser error means the stream is out of sync, and the connection should be dropped.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<(), FramerParserError> {
+        self.frame_parser.push_bytes(bytes);
+        while let Some(msg) = self.frame_parser.next_frame() {
+            let msg = msg?;
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.trace(&TracedFrame {
+                    direction: Direction::Received,
+                    message_type: msg.kind,
+                    seq_num: msg.seq_num,
+                    payload: msg.payload,
+                    command: None,
+                    retransmission: false,
+                    checksum_ok: msg.checksum.is_ok(),
+                });
+            }
+            let kind = match msg.kind {
+                Ok(kind) => kind,
+                Err(message_type) => {
+                    self.events.push_back(SessionEvent::UnknownMessageType {
+                        message_type,
+                        seq_num: msg.seq_num,
+                        payload: msg.payload.to_vec(),
+                    });
+                    continue;
+                }
+            };
+            if let Err(e) = msg.checksum {
+                self.events.push_back(SessionEvent::InvalidChecksum(e));
+                continue;
+            }
+            if kind.needs_ack() {
+                self.transmit.push_back(build_ack(msg.seq_num));
+                if let Some(tracer) = self.tracer.as_mut() {
+                    tracer.trace(&TracedFrame {
+                        direction: Direction::Sent,
+                        message_type: Ok(MessageType::Ack),
+                        seq_num: SequenceTracker::ack_for(msg.seq_num),
+                        payload: &[],
+                        command: Some(&Command::Ack),
+                        retransmission: false,
+                        checksum_ok: true,
+                    });
+                }
+            }
+            match kind {
+                MessageType::Ack => {
+                    // a late or duplicate Ack, e.g. for both copies of a retransmitted command, would otherwise
+                    // ack the command sent after it
+                    if !self.waiting_for_ack
+                        || msg.seq_num != SequenceTracker::ack_for(self.sequence.next_command())
+                    {
+                        continue;
+                    }
+                    self.sequence.acked(msg.seq_num);
+                    self.waiting_for_ack = false;
+                    if let Some((command, _)) = self.in_flight.take() {
+                        self.events.push_back(SessionEvent::Acked(command));
+                    }
+                }
+                MessageType::Command1 | MessageType::Command2 => {
+                    let payload = parse_payload_lenient(msg.payload, kind);
+                    let notification = msg
+                        .payload
+                        .first()
+                        .and_then(|byte| PayloadType::from_byte(kind, *byte))
+                        .is_some_and(|payload_type| payload_type.is_notification());
+                    self.events.push_back(match payload {
+                        Ok(payload) if notification => SessionEvent::Notification(payload),
+                        Ok(payload) => SessionEvent::Payload(payload),
+                        Err(error) => SessionEvent::InvalidPayload {
+                            error,
+                            message_type: kind,
+                            payload: msg.payload.to_vec(),
+                        },
+                    });
+                }
+            }
+        }
+        self.send_next_command();
+        Ok(())
+    }
+
+    /// The next bytes which should be written to the headphones
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.transmit.pop_front()
+    }
+
+    pub fn poll_event(&mut self) -> Option<SessionEvent> {
+        self.events.pop_front()
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::{AncMode, EqualizerPreset, build_command, build_message};
+
+    #[test]
+    fn waits_for_ack() {
+        let mut session = Session::new();
+        session.send(Command::GetCodec).unwrap();
+        assert_eq!(
+            session.poll_transmit(),
+            Some(build_command(&Command::Init, 0).unwrap())
+        );
+        // GetCodec must wait for the Ack of Init
+        assert_eq!(session.poll_transmit(), None);
+
+        // the Ack for Init; the next command reuses its sequence number
+        let ack = [0x3e, 0x1, 0x1, 0x0, 0x0, 0x0, 0x0, 0x2, 0x3c];
+        session.feed(&ack).unwrap();
+        assert_eq!(
+            session.poll_transmit(),
+            Some(build_command(&Command::GetCodec, 1).unwrap())
+        );
+        assert!(session.waiting_for_ack());
+        assert!(!session.is_flushed());
+
+        session
+            .feed(&build_command(&Command::Ack, 1).unwrap())
+            .unwrap();
+        assert!(session.is_flushed());
+    }
+
+    #[test]
+    fn duplicate_ack() {
+        let mut session = Session::new();
+        session.send(Command::GetCodec).unwrap();
+        let init = session.poll_transmit().unwrap();
+        // Init wasn't acked in time, so it goes out again, and both copies get acked
+        session.handle_ack_timeout();
+        assert_eq!(session.poll_transmit(), Some(init));
+        let ack = build_command(&Command::Ack, 0).unwrap();
+        session.feed(&ack).unwrap();
+        assert_eq!(
+            session.poll_transmit(),
+            Some(build_command(&Command::GetCodec, 1).unwrap())
+        );
+        session.feed(&ack).unwrap();
+        // the second one doesn't ack GetCodec
+        assert!(session.waiting_for_ack());
+        assert_eq!(session.poll_transmit(), None);
+
+        session
+            .feed(&build_command(&Command::Ack, 1).unwrap())
+            .unwrap();
+        assert!(session.is_flushed());
+        // nor does an Ack change the sequence while nothing waits for one
+        session.feed(&ack).unwrap();
+        session.send(Command::GetCodec).unwrap();
+        assert_eq!(
+            session.poll_transmit(),
+            Some(build_command(&Command::GetCodec, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn invalid_payload() {
+        let mut session = Session::new();
+        session.poll_transmit();
+        // a payload type nobody knows about yet
+        let frame = [0x3e, 0xc, 0x0, 0x0, 0x0, 0x0, 0x2, 0x70, 0x1, 0x7f, 0x3c];
+        session.feed(&frame).unwrap();
+        assert!(matches!(
+            session.poll_event(),
+            Some(SessionEvent::Payload(Payload::Unknown {
+                message_type: MessageType::Command1,
+                payload_type: 0x70,
+                raw,
+            })) if raw == [0x70, 0x1]
+        ));
+        assert_eq!(
+            session.poll_transmit(),
+            Some(build_command(&Command::Ack, 0).unwrap())
+        );
+
+        // a codec reply without the codec
+        session
+            .feed(&build_message(MessageType::Command1, 1, &[0x13]))
+            .unwrap();
+        assert!(matches!(
+            session.poll_event(),
+            Some(SessionEvent::InvalidPayload {
+                error: ParsePayloadError::PayloadTooSmall { .. },
+                message_type: MessageType::Command1,
+                payload,
+            }) if payload == [0x13]
+        ));
+        // it's still acked
+        assert_eq!(
+            session.poll_transmit(),
+            Some(build_command(&Command::Ack, 1).unwrap())
+        );
+    }
+
+    #[test]
+    fn command2_is_acked() {
+        let mut session = Session::new();
+        session.poll_transmit();
+        // a sound pressure reading, from hci logs
+        let reading = [
+            0x3e, 0xe, 0x1, 0x0, 0x0, 0x0, 0x4, 0x5b, 0x3, 0x42, 0x3, 0xb6, 0x3c,
+        ];
+        session.feed(&reading).unwrap();
+        assert!(matches!(
+            session.poll_event(),
+            Some(SessionEvent::Payload(Payload::SoundPressure { db: 0x42 }))
+        ));
+        assert_eq!(
+            session.poll_transmit(),
+            Some(build_command(&Command::Ack, 1).unwrap())
+        );
+    }
+
+    #[test]
+    fn unknown_message_type() {
+        let mut session = Session::new();
+        session.poll_transmit();
+        // a frame with a message type nobody knows about
+        let mut frame = build_message(MessageType::Command1, 1, &[0x12, 0x34]);
+        frame[1] = 0x9;
+        let checksum = frame.len() - 2;
+        frame[checksum] = crate::checksum(&frame[1..checksum]);
+        session.feed(&frame).unwrap();
+        assert!(matches!(
+            session.poll_event(),
+            Some(SessionEvent::UnknownMessageType {
+                message_type: 0x9,
+                seq_num: 1,
+                payload,
+            }) if payload == [0x12, 0x34]
+        ));
+        // not acked
+        assert_eq!(session.poll_transmit(), None);
+    }
+
+    #[test]
+    fn ack_timeout() {
+        let mut session = Session::new();
+        session.set_max_retries(1);
+        session.send(Command::GetCodec).unwrap();
+        let init = session.poll_transmit();
+        assert!(session.poll_ack_timer());
+        assert!(!session.poll_ack_timer());
+
+        // retransmitted once
+        session.handle_ack_timeout();
+        assert_eq!(session.poll_transmit(), init);
+        assert!(session.poll_ack_timer());
+        assert!(session.poll_event().is_none());
+
+        // then dropped, and the next command goes out
+        session.handle_ack_timeout();
+        assert!(matches!(
+            session.poll_event(),
+            Some(SessionEvent::AckTimeout(Command::Init))
+        ));
+        assert_eq!(
+            session.poll_transmit(),
+            Some(build_command(&Command::GetCodec, 0).unwrap())
+        );
+        assert!(session.poll_ack_timer());
+
+        // acked in time
+        let ack = [0x3e, 0x1, 0x1, 0x0, 0x0, 0x0, 0x0, 0x2, 0x3c];
+        session.feed(&ack).unwrap();
+        assert!(!session.waiting_for_ack());
+        assert!(matches!(
+            session.poll_event(),
+            Some(SessionEvent::Acked(Command::GetCodec))
+        ));
+        session.handle_ack_timeout();
+        assert_eq!(session.poll_transmit(), None);
+        assert!(session.poll_event().is_none());
+    }
+
+    #[test]
+    fn keep_alive() {
+        let mut session = Session::new();
+        session.set_keep_alive(true);
+        session.set_max_retries(0);
+        session.poll_transmit();
+        let ack = [0x3e, 0x1, 0x1, 0x0, 0x0, 0x0, 0x0, 0x2, 0x3c];
+        session.feed(&ack).unwrap();
+        assert!(matches!(
+            session.poll_event(),
+            Some(SessionEvent::Acked(Command::Init))
+        ));
+
+        // quiet headphones are asked for the codec
+        session.handle_idle_timeout();
+        assert_eq!(
+            session.poll_transmit(),
+            Some(build_command(&Command::GetCodec, 1).unwrap())
+        );
+        // but only once
+        session.handle_idle_timeout();
+        assert_eq!(session.poll_transmit(), None);
+
+        // which is never acked
+        session.send(Command::GetEqualizerSettings).unwrap();
+        session.handle_ack_timeout();
+        assert!(matches!(
+            session.poll_event(),
+            Some(SessionEvent::AckTimeout(Command::GetCodec))
+        ));
+        assert!(matches!(
+            session.poll_event(),
+            Some(SessionEvent::Disconnected)
+        ));
+        assert!(session.is_disconnected());
+        // nothing is sent anymore
+        assert_eq!(session.poll_transmit(), None);
+        session.handle_idle_timeout();
+        assert_eq!(session.poll_transmit(), None);
+    }
+
+    #[test]
+    fn periodic() {
+        let second = Duration::from_secs(1);
+        let start = Instant::now();
+        let mut session = Session::new();
+        session.poll_transmit();
+        session
+            .set_periodic(Command::GetSoundPressure, Some(2 * second), start)
+            .unwrap();
+        session
+            .set_periodic(Command::GetCodec, Some(3 * second), start)
+            .unwrap();
+        // the same interval again changes nothing
+        session
+            .set_periodic(Command::GetCodec, Some(3 * second), start + second)
+            .unwrap();
+        assert!(session.poll_periodic_timer());
+        assert!(!session.poll_periodic_timer());
+        assert_eq!(session.periodic_deadline(), Some(start + 2 * second));
+
+        // the sound pressure is due, but Init is still waiting for its ack, and both wait behind it
+        session.handle_periodic_timeout(start + 2 * second);
+        assert!(session.poll_periodic_timer());
+        assert_eq!(session.periodic_deadline(), Some(start + 3 * second));
+        let mut sent = Vec::new();
+        let ack = [0x3e, 0x1, 0x1, 0x0, 0x0, 0x0, 0x0, 0x2, 0x3c];
+        session.feed(&ack).unwrap();
+        while let Some(bytes) = session.poll_transmit() {
+            let seq_num = bytes[2];
+            sent.push(bytes);
+            session
+                .feed(&build_command(&Command::Ack, seq_num).unwrap())
+                .unwrap();
+        }
+        assert_eq!(
+            sent,
+            [
+                build_command(&Command::GetSoundPressure, 1).unwrap(),
+                build_command(&Command::GetCodec, 0).unwrap(),
+            ]
+        );
+
+        // the codec is due
+        session.handle_periodic_timeout(start + 3 * second);
+        assert_eq!(
+            session.poll_transmit(),
+            Some(build_command(&Command::GetCodec, 1).unwrap())
+        );
+
+        session
+            .set_periodic(Command::GetCodec, None, start + 3 * second)
+            .unwrap();
+        assert_eq!(
+            session.periodic_commands().collect::<Vec<_>>(),
+            [&Command::GetSoundPressure]
+        );
+        assert!(session.poll_periodic_timer());
+        assert!(
+            session
+                .set_periodic(
+                    Command::AncSet {
+                        dragging_ambient_sound_slider: false,
+                        mode: AncMode::AmbientSound,
+                        ambient_sound_voice_passthrough: false,
+                        ambient_sound_level: 200,
+                    },
+                    Some(second),
+                    start + 3 * second
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn periodic_restart() {
+        let second = Duration::from_secs(1);
+        let start = Instant::now();
+        let mut session = Session::new();
+        session
+            .set_periodic(Command::GetSoundPressure, Some(10 * second), start)
+            .unwrap();
+        assert!(session.poll_periodic_timer());
+        // a command added in the middle of the interval doesn't push the first one back
+        session
+            .set_periodic(Command::GetCodec, Some(10 * second), start + 6 * second)
+            .unwrap();
+        assert!(session.poll_periodic_timer());
+        assert_eq!(session.periodic_deadline(), Some(start + 10 * second));
+        // nor does the timer firing early
+        session.handle_periodic_timeout(start + 8 * second);
+        assert_eq!(session.periodic_deadline(), Some(start + 10 * second));
+
+        session.handle_periodic_timeout(start + 10 * second);
+        assert_eq!(session.periodic_deadline(), Some(start + 16 * second));
+        // a shorter interval counts from the last time it was sent
+        session
+            .set_periodic(Command::GetCodec, Some(5 * second), start + 12 * second)
+            .unwrap();
+        assert_eq!(session.periodic_deadline(), Some(start + 11 * second));
+    }
+
+    #[test]
+    fn coalescing() {
+        let mut session = Session::new();
+        let anc = |ambient_sound_level| Command::AncSet {
+            dragging_ambient_sound_slider: true,
+            mode: AncMode::AmbientSound,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level,
+        };
+        session.send(Command::GetCodec).unwrap();
+        for level in 0..=20 {
+            session.send(anc(level)).unwrap();
+        }
+        session
+            .send(Command::ChangeEqualizerPreset {
+                preset: EqualizerPreset::BassBoost,
+            })
+            .unwrap();
+        let mut sent = Vec::new();
+        // ack everything right away
+        while let Some(bytes) = session.poll_transmit() {
+            let seq_num = bytes[2];
+            sent.push(bytes);
+            session
+                .feed(&build_command(&Command::Ack, seq_num).unwrap())
+                .unwrap();
+        }
+        assert_eq!(
+            sent,
+            [
+                build_command(&Command::Init, 0).unwrap(),
+                build_command(&anc(20), 1).unwrap(),
+                build_command(
+                    &Command::ChangeEqualizerPreset {
+                        preset: EqualizerPreset::BassBoost
+                    },
+                    0
+                )
+                .unwrap(),
+                build_command(&Command::GetCodec, 1).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn notifications() {
+        let mut session = Session::new();
+        session.poll_transmit();
+        let anc = Payload::AncStatus {
+            mode: AncMode::Off,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: 0,
+        };
+        let reply = anc.to_bytes();
+        let mut notification = reply.clone();
+        notification[0] = 0x69;
+        session
+            .feed(&build_message(MessageType::Command1, 0, &reply))
+            .unwrap();
+        session
+            .feed(&build_message(MessageType::Command1, 1, &notification))
+            .unwrap();
+        assert!(
+            matches!(session.poll_event(), Some(SessionEvent::Payload(payload)) if payload == anc)
+        );
+        assert!(
+            matches!(session.poll_event(), Some(SessionEvent::Notification(payload)) if payload == anc)
+        );
+    }
+
+    #[test]
+    fn tracing() {
+        let frames = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut session = Session::new();
+        let traced = frames.clone();
+        session.set_tracer(move |frame: &TracedFrame| {
+            traced.lock().unwrap().push(frame.to_string());
+        });
+        session.retransmit();
+        let codec = Payload::Codec {
+            codec: crate::payload::Codec::Ldac,
+        };
+        session
+            .feed(&build_message(MessageType::Command1, 1, &codec.to_bytes()))
+            .unwrap();
+        assert_eq!(
+            *frames.lock().unwrap(),
+            [
+                "-> Command1 seq 0 Init 0000 (retransmitted)",
+                "<- Command1 seq 1 CodecGet 130010",
+                "-> Ack seq 0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use crate::{
+    command::{
+        AncMode, Command, EqualizerBands, EqualizerPreset, QuickAccessApp, SpeakToChatTimeout,
+    },
+    compatibility::DeviceInfo,
+    payload::{BatteryLevel, BatteryPercent, BatteryStatus, Capabilities, Codec, Payload},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EqualizerSnapshot {
+    pub preset: EqualizerPreset,
+    pub bands: EqualizerBands,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AncSnapshot {
+    pub mode: AncMode,
+    pub ambient_sound_voice_passthrough: bool,
+    pub ambient_sound_level: u8,
+}
+
+/// Where the data in a [HeadphoneSnapshot] comes from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SnapshotSource {
+    /// The Sony protocol; everything the headphones support is available
+    #[default]
+    Protocol,
+    /// A generic data source, e.g. the OS bluetooth stack when another device owns the Sony channel.
+    /// Only what [FallbackReading] covers is available.
+    Fallback,
+}
+
+/// A reading from a fallback data source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FallbackReading {
+    /// A single battery percentage for the whole device
+    Battery(BatteryPercent),
+}
+
+/// A part of the [HeadphoneSnapshot] which can change
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SnapshotField {
+    Battery,
+    CaseBattery,
+    LeftBattery,
+    RightBattery,
+    Equalizer,
+    Anc,
+    Codec,
+    CallVoiceFocus,
+    SidetoneLevel,
+    Capabilities,
+    ModelName,
+    FirmwareVersion,
+    QuickAccess,
+    AmbientSoundRange,
+    SpatialAudioEarMeasured,
+    SpeakToChatTimeout,
+}
+
+impl SnapshotField {
+    /// The field a command changes on the headphones, if any
+    pub fn changed_by(command: &Command) -> Option<Self> {
+        Some(match command {
+            Command::AncSet { .. } => Self::Anc,
+            Command::ChangeEqualizerPreset { .. } | Command::ChangeEqualizerSetting { .. } => {
+                Self::Equalizer
+            }
+            Command::SetCallVoiceFocus { .. } => Self::CallVoiceFocus,
+            Command::SetSidetoneLevel { .. } => Self::SidetoneLevel,
+            Command::SetQuickAccess { .. } => Self::QuickAccess,
+            Command::SetSpeakToChatTimeout { .. } => Self::SpeakToChatTimeout,
+            _ => return None,
+        })
+    }
+
+    /// The field of [Self::changed_by] a payload tells the value of, which confirms a command changing it
+    pub fn reported_by(payload: &Payload) -> Option<Self> {
+        Some(match payload {
+            Payload::AncStatus { .. } => Self::Anc,
+            Payload::Equalizer { .. } => Self::Equalizer,
+            Payload::CallVoiceFocus { .. } => Self::CallVoiceFocus,
+            Payload::SidetoneLevel { .. } => Self::SidetoneLevel,
+            Payload::QuickAccess { .. } => Self::QuickAccess,
+            Payload::SpeakToChatTimeout { .. } => Self::SpeakToChatTimeout,
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for SnapshotField {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Battery => "Battery",
+            Self::CaseBattery => "Case battery",
+            Self::LeftBattery => "Left battery",
+            Self::RightBattery => "Right battery",
+            Self::Equalizer => "Equalizer",
+            Self::Anc => "ANC",
+            Self::Codec => "Codec",
+            Self::CallVoiceFocus => "Call voice focus",
+            Self::SidetoneLevel => "Sidetone level",
+            Self::Capabilities => "Capabilities",
+            Self::ModelName => "Model",
+            Self::FirmwareVersion => "Firmware",
+            Self::QuickAccess => "Quick access",
+            Self::AmbientSoundRange => "Ambient sound range",
+            Self::SpatialAudioEarMeasured => "360 Reality Audio ear measurement",
+            Self::SpeakToChatTimeout => "Speak-to-Chat auto off",
+        })
+    }
+}
+
+/// A change of one field of the snapshot, with human readable values (`None` meaning unknown).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotChange {
+    pub field: SnapshotField,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl std::fmt::Display for SnapshotChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "?".to_string());
+        write!(
+            f,
+            "{}: {}→{}",
+            self.field,
+            value(&self.old),
+            value(&self.new)
+        )
+    }
+}
+
+fn describe_anc(anc: &AncSnapshot) -> String {
+    match anc.mode {
+        AncMode::Off => "Off".to_string(),
+        AncMode::ActiveNoiseCanceling => "Noise canceling".to_string(),
+        AncMode::AmbientSound => format!(
+            "Ambient (level {}{})",
+            anc.ambient_sound_level,
+            if anc.ambient_sound_voice_passthrough {
+                ", voice passthrough"
+            } else {
+                ""
+            }
+        ),
+    }
+}
+
+fn describe_equalizer(eq: &EqualizerSnapshot) -> String {
+    format!("{} {:?}", eq.preset, eq.bands.levels())
+}
+
+/// Everything we know about the state of the headphones, built from the payloads they sent us.
+/// `None` means we haven't been told yet.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HeadphoneSnapshot {
+    pub source: SnapshotSource,
+    /// A single battery level for the whole device, reported by over-ear headphones or a fallback source
+    pub battery: Option<BatteryPercent>,
+    /// The earbuds and their case
+    pub battery_status: BatteryStatus,
+    pub equalizer: Option<EqualizerSnapshot>,
+    pub anc: Option<AncSnapshot>,
+    pub codec: Option<Codec>,
+    pub sound_pressure_db: Option<usize>,
+    pub call_voice_focus: Option<bool>,
+    pub sidetone_level: Option<u8>,
+    pub capabilities: Option<Capabilities>,
+    pub device_info: DeviceInfo,
+    pub quick_access: Option<(QuickAccessApp, QuickAccessApp)>,
+    /// The valid ambient sound levels, inclusive
+    pub ambient_sound_range: Option<(u8, u8)>,
+    /// Whether the ears were measured for 360 Reality Audio
+    pub spatial_audio_ear_measured: Option<bool>,
+    pub speak_to_chat_timeout: Option<SpeakToChatTimeout>,
+}
+
+impl HeadphoneSnapshot {
+    /// Update the snapshot with a payload the headphones sent, returning what changed.
+    /// Sound pressure readings are not reported as changes since they stream in constantly.
+    pub fn apply(&mut self, payload: &Payload) -> Vec<SnapshotChange> {
+        let old = self.clone();
+        self.source = SnapshotSource::Protocol;
+        self.update(payload);
+        self.diff(&old)
+    }
+
+    /// Update the snapshot with a reading from a fallback data source, returning what changed.
+    pub fn apply_fallback(&mut self, reading: &FallbackReading) -> Vec<SnapshotChange> {
+        let old = self.clone();
+        self.source = SnapshotSource::Fallback;
+        match reading {
+            FallbackReading::Battery(level) => self.battery = Some(*level),
+        }
+        self.diff(&old)
+    }
+
+    /// Whether only the little a fallback data source provides is available
+    pub fn is_limited(&self) -> bool {
+        self.source == SnapshotSource::Fallback
+    }
+
+    /// The changes from `old` to `self`
+    pub fn diff(&self, old: &HeadphoneSnapshot) -> Vec<SnapshotChange> {
+        let mut changes = Vec::new();
+        let mut check = |field, old: Option<String>, new: Option<String>| {
+            if old != new {
+                changes.push(SnapshotChange { field, old, new });
+            }
+        };
+        let percent = |level: Option<BatteryPercent>| level.map(|level| level.to_string());
+        check(
+            SnapshotField::Battery,
+            percent(old.battery),
+            percent(self.battery),
+        );
+        check(
+            SnapshotField::CaseBattery,
+            percent(old.battery_status.case),
+            percent(self.battery_status.case),
+        );
+        check(
+            SnapshotField::LeftBattery,
+            percent(old.battery_status.left),
+            percent(self.battery_status.left),
+        );
+        check(
+            SnapshotField::RightBattery,
+            percent(old.battery_status.right),
+            percent(self.battery_status.right),
+        );
+        check(
+            SnapshotField::Equalizer,
+            old.equalizer.as_ref().map(describe_equalizer),
+            self.equalizer.as_ref().map(describe_equalizer),
+        );
+        check(
+            SnapshotField::Anc,
+            old.anc.as_ref().map(describe_anc),
+            self.anc.as_ref().map(describe_anc),
+        );
+        check(
+            SnapshotField::Codec,
+            old.codec.map(|c| c.as_str().to_string()),
+            self.codec.map(|c| c.as_str().to_string()),
+        );
+        check(
+            SnapshotField::CallVoiceFocus,
+            old.call_voice_focus.map(|on| on.to_string()),
+            self.call_voice_focus.map(|on| on.to_string()),
+        );
+        check(
+            SnapshotField::SidetoneLevel,
+            old.sidetone_level.map(|level| level.to_string()),
+            self.sidetone_level.map(|level| level.to_string()),
+        );
+        check(
+            SnapshotField::Capabilities,
+            old.capabilities.map(|c| format!("{c:?}")),
+            self.capabilities.map(|c| format!("{c:?}")),
+        );
+        check(
+            SnapshotField::ModelName,
+            old.device_info.model_name.clone(),
+            self.device_info.model_name.clone(),
+        );
+        check(
+            SnapshotField::FirmwareVersion,
+            old.device_info.firmware_version.clone(),
+            self.device_info.firmware_version.clone(),
+        );
+        check(
+            SnapshotField::QuickAccess,
+            old.quick_access
+                .map(|(double, triple)| format!("double: {double}, triple: {triple}")),
+            self.quick_access
+                .map(|(double, triple)| format!("double: {double}, triple: {triple}")),
+        );
+        check(
+            SnapshotField::AmbientSoundRange,
+            old.ambient_sound_range
+                .map(|(min, max)| format!("{min}..={max}")),
+            self.ambient_sound_range
+                .map(|(min, max)| format!("{min}..={max}")),
+        );
+        check(
+            SnapshotField::SpatialAudioEarMeasured,
+            old.spatial_audio_ear_measured.map(|done| done.to_string()),
+            self.spatial_audio_ear_measured.map(|done| done.to_string()),
+        );
+        check(
+            SnapshotField::SpeakToChatTimeout,
+            old.speak_to_chat_timeout.map(|timeout| timeout.to_string()),
+            self.speak_to_chat_timeout
+                .map(|timeout| timeout.to_string()),
+        );
+        changes
+    }
+
+    fn update(&mut self, payload: &Payload) {
+        if self.battery_status.update(payload) {
+            return;
+        }
+        match payload {
+            Payload::InitReply | Payload::CommandError { .. } | Payload::Unknown { .. } => (),
+            Payload::BatteryLevel(BatteryLevel::Single(level)) => self.battery = Some(*level),
+            // in battery_status
+            Payload::BatteryLevel(BatteryLevel::Case(_) | BatteryLevel::Headphones { .. })
+            | Payload::BatteryLow { .. } => (),
+            Payload::Equalizer { preset, bands } => {
+                self.equalizer = Some(EqualizerSnapshot {
+                    preset: *preset,
+                    bands: *bands,
+                })
+            }
+            Payload::AncStatus {
+                mode,
+                ambient_sound_voice_passthrough,
+                ambient_sound_level,
+            } => {
+                self.anc = Some(AncSnapshot {
+                    mode: *mode,
+                    ambient_sound_voice_passthrough: *ambient_sound_voice_passthrough,
+                    ambient_sound_level: *ambient_sound_level,
+                })
+            }
+            Payload::Codec { codec } => self.codec = Some(*codec),
+            Payload::SoundPressureMeasureReply { is_on } => {
+                if !is_on {
+                    self.sound_pressure_db = None;
+                }
+            }
+            Payload::SoundPressure { db } => self.sound_pressure_db = Some(*db),
+            Payload::AmbientSoundRange { min, max } => {
+                self.ambient_sound_range = Some((*min, *max))
+            }
+            Payload::SpatialAudioStatus { ear_measured } => {
+                self.spatial_audio_ear_measured = Some(*ear_measured)
+            }
+            Payload::SpeakToChatTimeout { timeout } => self.speak_to_chat_timeout = Some(*timeout),
+            Payload::CallVoiceFocus { on } => self.call_voice_focus = Some(*on),
+            Payload::SidetoneLevel { level } => self.sidetone_level = Some(*level),
+            Payload::SupportedFunctions(capabilities) => self.capabilities = Some(*capabilities),
+            Payload::ModelName(model_name) => {
+                self.device_info.model_name = Some(model_name.clone())
+            }
+            Payload::FirmwareVersion(firmware_version) => {
+                self.device_info.firmware_version = Some(firmware_version.clone())
+            }
+            Payload::QuickAccess {
+                double_tap,
+                triple_tap,
+            } => self.quick_access = Some((*double_tap, *triple_tap)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn diff() {
+        let mut snapshot = HeadphoneSnapshot::default();
+        let changes = snapshot.apply(&Payload::AncStatus {
+            mode: AncMode::AmbientSound,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: 10,
+        });
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].to_string(), "ANC: ?→Ambient (level 10)");
+
+        let changes = snapshot.apply(&Payload::AncStatus {
+            mode: AncMode::Off,
+            ambient_sound_voice_passthrough: false,
+            ambient_sound_level: 10,
+        });
+        assert_eq!(changes[0].to_string(), "ANC: Ambient (level 10)→Off");
+
+        // nothing changed
+        assert!(
+            snapshot
+                .apply(&Payload::AncStatus {
+                    mode: AncMode::Off,
+                    ambient_sound_voice_passthrough: false,
+                    ambient_sound_level: 10,
+                })
+                .is_empty()
+        );
+        assert!(
+            snapshot
+                .apply(&Payload::SoundPressure { db: 60 })
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn fields() {
+        let command = Command::ChangeEqualizerPreset {
+            preset: EqualizerPreset::Bright,
+        };
+        let payload = Payload::Equalizer {
+            preset: EqualizerPreset::Bright,
+            bands: EqualizerBands::default(),
+        };
+        assert_eq!(
+            SnapshotField::changed_by(&command),
+            SnapshotField::reported_by(&payload)
+        );
+        assert_eq!(SnapshotField::changed_by(&Command::GetCodec), None);
+        assert_eq!(SnapshotField::reported_by(&Payload::InitReply), None);
+    }
+
+    #[test]
+    fn fallback() {
+        let mut snapshot = HeadphoneSnapshot::default();
+        assert!(!snapshot.is_limited());
+        let changes =
+            snapshot.apply_fallback(&FallbackReading::Battery(BatteryPercent::new(40).unwrap()));
+        assert!(snapshot.is_limited());
+        assert_eq!(changes[0].to_string(), "Battery: ?→40%");
+
+        snapshot.apply(&Payload::InitReply);
+        assert!(!snapshot.is_limited());
+    }
+}
@@ -0,0 +1,92 @@
+//! Hooks to see every frame a [crate::session::Session] sends and receives, for debugging
+//! replies which differ between firmware versions, and for decoding new payloads.
+
+use std::fmt::Write;
+
+use crate::{MessageType, command::Command, payload::PayloadType};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// From us to the headphones
+    Sent,
+    /// From the headphones to us
+    Received,
+}
+
+/// A frame as it went over the wire, without the framing and escaping
+#[derive(Debug)]
+pub struct TracedFrame<'a> {
+    pub direction: Direction,
+    pub message_type: Result<MessageType, u8>,
+    pub seq_num: u8,
+    pub payload: &'a [u8],
+    /// The command, for frames we sent
+    pub command: Option<&'a Command>,
+    /// The frame was sent before, but not acked
+    pub retransmission: bool,
+    /// Whether the checksum matched. Always true for frames we sent.
+    pub checksum_ok: bool,
+}
+
+impl TracedFrame<'_> {
+    /// What the payload is, for frames the headphones sent
+    pub fn payload_type(&self) -> Option<PayloadType> {
+        match (self.direction, self.message_type) {
+            (Direction::Received, Ok(message_type)) => {
+                PayloadType::from_byte(message_type, *self.payload.first()?)
+            }
+            _ => None,
+        }
+    }
+
+    /// The payload as lowercase hex, without separators
+    pub fn hex(&self) -> String {
+        self.payload.iter().fold(String::new(), |mut hex, byte| {
+            write!(hex, "{byte:02x}").unwrap();
+            hex
+        })
+    }
+}
+
+/// e.g. `<- Command1 seq 1 CodecGet 130010`
+impl std::fmt::Display for TracedFrame<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let arrow = match self.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+        match self.message_type {
+            Ok(message_type) => write!(f, "{arrow} {message_type:?} seq {}", self.seq_num)?,
+            Err(byte) => write!(f, "{arrow} 0x{byte:x} seq {}", self.seq_num)?,
+        }
+        match (self.command, self.payload_type()) {
+            (Some(Command::Ack), _) => (),
+            (Some(command), _) => write!(f, " {command:?}")?,
+            (None, Some(payload_type)) => write!(f, " {payload_type:?}")?,
+            (None, None) => (),
+        }
+        if !self.payload.is_empty() {
+            write!(f, " {}", self.hex())?;
+        }
+        if self.retransmission {
+            write!(f, " (retransmitted)")?;
+        }
+        if !self.checksum_ok {
+            write!(f, " (bad checksum)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Gets every frame the session sends and receives, see [crate::session::Session::set_tracer].
+///
+/// It's called as frames are queued and fed, so that's when to take the time.
+pub trait FrameTracer {
+    fn trace(&mut self, frame: &TracedFrame);
+}
+
+impl<F: FnMut(&TracedFrame)> FrameTracer for F {
+    fn trace(&mut self, frame: &TracedFrame) {
+        self(frame)
+    }
+}
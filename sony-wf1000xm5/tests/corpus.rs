@@ -0,0 +1,65 @@
+//! Parses every frame in the capture corpus (see tests/corpus) and checks the decoded payload.
+//!
+//! A corpus file is a list of frames the headphones sent, as hex, each with the [Payload] it must decode to,
+//! as its serde JSON. Only frames captured from real headphones belong here, never ones written by hand or
+//! produced by the emulator, and `source` records where they were captured (the model, its firmware, what
+//! was done in the Sony app). To add a capture, append it to the file for the same capture, or start a new file.
+//!
+//! [Payload]: sony_wf1000xm5::payload::Payload
+
+mod common;
+
+use common::decode_hex;
+use serde::Deserialize;
+use sony_wf1000xm5::{
+    frame_parser::{FrameParser, FrameParserResult},
+    payload::parse_payload,
+};
+
+#[derive(Deserialize)]
+struct Sample {
+    hex: String,
+    payload: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct Corpus {
+    source: String,
+    samples: Vec<Sample>,
+}
+
+#[test]
+fn corpus() {
+    let dir = format!("{}/tests/corpus", env!("CARGO_MANIFEST_DIR"));
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no corpus in {dir}");
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let corpus: Corpus =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(!corpus.source.trim().is_empty(), "{name}: no source");
+        for (idx, sample) in corpus.samples.iter().enumerate() {
+            let frame = decode_hex(&sample.hex);
+            let mut parser = FrameParser::new();
+            let FrameParserResult::Ready { msg, consumed } = parser.parse(&frame) else {
+                panic!("{name} sample {idx}: not a whole frame");
+            };
+            assert_eq!(consumed, frame.len(), "{name} sample {idx}: trailing bytes");
+            assert!(msg.checksum.is_ok(), "{name} sample {idx}: bad checksum");
+            let kind = msg
+                .kind
+                .unwrap_or_else(|kind| panic!("{name} sample {idx}: message type 0x{kind:x}"));
+            let payload = parse_payload(msg.payload, kind)
+                .unwrap_or_else(|e| panic!("{name} sample {idx}: {e}"));
+            assert_eq!(
+                serde_json::to_value(&payload).unwrap(),
+                sample.payload,
+                "{name} sample {idx}: decoded differently"
+            );
+        }
+    }
+}